@@ -22,8 +22,7 @@
 #include "hw/irq.h"
 #include "qapi/error.h"
 
-static NamedGPIOList *qdev_get_named_gpio_list(DeviceState *dev,
-                                               const char *name)
+NamedGPIOList *qdev_get_named_gpio_list(DeviceState *dev, const char *name)
 {
     NamedGPIOList *ngl;
 
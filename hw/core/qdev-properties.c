@@ -484,11 +484,17 @@ static void set_string(Object *obj, Visitor *v, const char *name,
     *ptr = str;
 }
 
+static void set_default_value_string(ObjectProperty *op, const Property *prop)
+{
+    object_property_set_default_str(op, prop->defval.str);
+}
+
 const PropertyInfo qdev_prop_string = {
     .name  = "str",
     .release = release_string,
     .get   = get_string,
     .set   = set_string,
+    .set_default_value = set_default_value_string,
 };
 
 /* --- on/off/auto --- */
@@ -1009,7 +1015,9 @@ static void qdev_class_add_property(DeviceClass *klass, const char *name,
     if (prop->set_default) {
         prop->info->set_default_value(op, prop);
     }
-    object_class_property_set_description(oc, name, prop->info->description);
+    object_class_property_set_description(oc, name,
+                                          prop->description ?:
+                                          prop->info->description);
 }
 
 /**
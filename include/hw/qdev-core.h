@@ -647,6 +647,21 @@ qemu_irq qdev_get_gpio_in(DeviceState *dev, int n);
  */
 qemu_irq qdev_get_gpio_in_named(DeviceState *dev, const char *name, int n);
 
+/**
+ * qdev_get_named_gpio_list: Get the list of a device's GPIO lines
+ * @dev: Device whose GPIO list we want
+ * @name: Name of the GPIO array, or NULL for the anonymous lines
+ *
+ * Returns the structure that records the input and output GPIO lines
+ * of @dev called @name, creating an empty one if the device has no
+ * such lines yet.  This is mostly useful for bindings to other
+ * languages, which need to know how many lines a device already has
+ * before they add more; device models should not need it.
+ *
+ * Return: the NamedGPIOList for @name
+ */
+NamedGPIOList *qdev_get_named_gpio_list(DeviceState *dev, const char *name);
+
 /**
  * qdev_connect_gpio_out: Connect one of a device's anonymous output GPIO lines
  * @dev: Device whose GPIO to connect
@@ -5,6 +5,8 @@
 
 /**
  * Property:
+ * @description: help text of the property; if NULL, the description
+ *    of @info is used
  * @set_default: true if the default value should be set from @defval,
  *    in which case @info->set_default_value must not be NULL
  *    (if false then no default value is set by the property system
@@ -14,6 +16,7 @@
  */
 struct Property {
     const char   *name;
+    const char   *description;
     const PropertyInfo *info;
     ptrdiff_t    offset;
     const char   *link_type;
@@ -21,6 +24,7 @@ struct Property {
     union {
         int64_t i;
         uint64_t u;
+        const char *str;
     } defval;
     const PropertyInfo *arrayinfo;
     int          arrayoffset;
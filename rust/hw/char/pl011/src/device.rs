@@ -2,32 +2,31 @@
 // Author(s): Manos Pitsidianakis <manos.pitsidianakis@linaro.org>
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use core::ptr::{addr_of, addr_of_mut, NonNull};
-use std::{
-    ffi::CStr,
-    os::raw::{c_int, c_void},
-};
+use core::ptr::{addr_of_mut, NonNull};
+use std::{ffi::CStr, os::raw::c_void};
 
 use qemu_api::{
+    assert_device_layout,
     bindings::{
-        error_fatal, hwaddr, memory_region_init_io, qdev_init_clock_in, qdev_new,
-        qdev_prop_set_chr, qemu_chr_fe_accept_input, qemu_chr_fe_ioctl, qemu_chr_fe_set_handlers,
-        qemu_chr_fe_write_all, qemu_irq, sysbus_connect_irq, sysbus_mmio_map,
-        sysbus_realize_and_unref, CharBackend, Chardev, Clock, ClockEvent, MemoryRegion,
-        QEMUChrEvent, CHR_IOCTL_SERIAL_SET_BREAK,
+        error_fatal, hwaddr, qdev_init_clock_in, qdev_new, qdev_prop_set_chr, qemu_irq,
+        sysbus_connect_irq, sysbus_realize_and_unref, Clock, ClockEvent,
     },
-    c_str, impl_vmstate_forward,
+    c_str,
+    chardev::{CharBackend, CharBackendHandlers, Chardev, QEMUChrEvent},
+    impl_vmstate_forward,
     irq::InterruptSource,
+    log_guest_error, log_unimp,
+    memory::{MemoryRegion, MmioConfig, MmioOps},
     prelude::*,
-    qdev::{DeviceImpl, DeviceState, Property},
+    qdev::{DeviceImpl, DeviceState, Property, ResetType, ResettablePhasesImpl},
     qom::{ClassInitImpl, ObjectImpl, ParentField},
-    sysbus::{SysBusDevice, SysBusDeviceClass},
+    sysbus::{SysBusDevice, SysBusDeviceClass, SysBusDeviceImpl},
+    trace,
     vmstate::VMStateDescription,
 };
 
 use crate::{
     device_class,
-    memory_ops::PL011_OPS,
     registers::{self, Interrupt},
     RegisterOffset,
 };
@@ -137,6 +136,7 @@ pub struct PL011State {
 }
 
 qom_isa!(PL011State : SysBusDevice, DeviceState, Object);
+assert_device_layout!(PL011State, parent_obj);
 
 #[repr(C)]
 pub struct PL011Class {
@@ -171,10 +171,65 @@ fn properties() -> &'static [Property] {
     fn vmsd() -> Option<&'static VMStateDescription> {
         Some(&device_class::VMSTATE_PL011)
     }
-    const REALIZE: Option<fn(&Self)> = Some(Self::realize);
-    const RESET: Option<fn(&Self)> = Some(Self::reset);
+    const REALIZE: Option<fn(&Self) -> qemu_api::Result<()>> = Some(Self::realize);
+}
+
+impl MmioOps for PL011State {
+    const CONFIG: MmioConfig = MmioConfig::DEFAULT.with_impl_sizes(4, 4);
+
+    fn read(&self, offset: hwaddr, _size: u32) -> u64 {
+        match RegisterOffset::try_from(offset) {
+            Err(v) if (0x3f8..0x400).contains(&(v >> 2)) => {
+                let device_id = self.get_class().device_id;
+                u64::from(device_id[(offset - 0xfe0) >> 2])
+            }
+            Err(_) => {
+                log_guest_error!("pl011_read: Bad offset {:#x}", offset);
+                0
+            }
+            Ok(field) => {
+                let (update_irq, result) = self.regs.borrow_mut().read(field);
+                if update_irq {
+                    self.update();
+                    self.char_backend.accept_input();
+                }
+                result.into()
+            }
+        }
+    }
+
+    fn write(&self, offset: hwaddr, _size: u32, value: u64) {
+        let mut update_irq = false;
+        if let Ok(field) = RegisterOffset::try_from(offset) {
+            // write_all() calls into the can_receive callback, so handle
+            // writes before entering PL011Registers.
+            if field == RegisterOffset::DR {
+                // ??? Check if transmitter is enabled.
+                let ch: u8 = value as u8;
+                // XXX this blocks entire thread. Rewrite to use
+                // write() and background I/O callbacks
+                let _ = self.char_backend.write_all(&[ch]);
+            }
+
+            update_irq = self
+                .regs
+                .borrow_mut()
+                .write(field, value as u32, &self.char_backend);
+        } else {
+            log_guest_error!("pl011_write: Bad offset {:#x}", offset);
+        }
+        if update_irq {
+            self.update();
+        }
+    }
 }
 
+impl ResettablePhasesImpl for PL011State {
+    const HOLD: Option<fn(&Self, ResetType)> = Some(Self::reset_hold);
+}
+
+impl SysBusDeviceImpl for PL011State {}
+
 impl PL011Registers {
     pub(self) fn read(&mut self, offset: RegisterOffset) -> (bool, u32) {
         use RegisterOffset::*;
@@ -225,7 +280,7 @@ pub(self) fn write(
         &mut self,
         offset: RegisterOffset,
         value: u32,
-        char_backend: *mut CharBackend,
+        char_backend: &CharBackend,
     ) -> bool {
         // eprintln!("write offset {offset} value {value}");
         use RegisterOffset::*;
@@ -259,17 +314,9 @@ pub(self) fn write(
                     self.reset_tx_fifo();
                 }
                 let update = (self.line_control.send_break() != new_val.send_break()) && {
-                    let mut break_enable: c_int = new_val.send_break().into();
-                    // SAFETY: self.char_backend is a valid CharBackend instance after it's been
-                    // initialized in realize().
-                    unsafe {
-                        qemu_chr_fe_ioctl(
-                            char_backend,
-                            CHR_IOCTL_SERIAL_SET_BREAK as i32,
-                            addr_of_mut!(break_enable).cast::<c_void>(),
-                        );
-                    }
-                    self.loopback_break(break_enable > 0)
+                    let break_enable = new_val.send_break();
+                    char_backend.send_break(break_enable);
+                    self.loopback_break(break_enable)
                 };
                 self.line_control = new_val;
                 self.set_read_trigger();
@@ -297,8 +344,7 @@ pub(self) fn write(
             DMACR => {
                 self.dmacr = value;
                 if value & 3 > 0 {
-                    // qemu_log_mask(LOG_UNIMP, "pl011: DMA not implemented\n");
-                    eprintln!("pl011: DMA not implemented");
+                    log_unimp!("pl011: DMA not implemented");
                 }
             }
         }
@@ -487,19 +533,13 @@ impl PL011State {
     unsafe fn init(&mut self) {
         const CLK_NAME: &CStr = c_str!("clk");
 
+        let owner = addr_of_mut!(*self);
         // SAFETY:
         //
         // self and self.iomem are guaranteed to be valid at this point since callers
         // must make sure the `self` reference is valid.
         unsafe {
-            memory_region_init_io(
-                addr_of_mut!(self.iomem),
-                addr_of_mut!(*self).cast::<Object>(),
-                &PL011_OPS,
-                addr_of_mut!(*self).cast::<c_void>(),
-                Self::TYPE_NAME.as_ptr(),
-                0x1000,
-            );
+            self.iomem.init_io(owner, Self::TYPE_NAME, 0x1000);
         }
 
         self.regs = Default::default();
@@ -531,67 +571,48 @@ fn post_init(&self) {
         }
     }
 
-    pub fn read(&mut self, offset: hwaddr, _size: u32) -> u64 {
-        match RegisterOffset::try_from(offset) {
-            Err(v) if (0x3f8..0x400).contains(&(v >> 2)) => {
-                let device_id = self.get_class().device_id;
-                u64::from(device_id[(offset - 0xfe0) >> 2])
-            }
-            Err(_) => {
-                // qemu_log_mask(LOG_GUEST_ERROR, "pl011_read: Bad offset 0x%x\n", (int)offset);
-                0
-            }
-            Ok(field) => {
-                let (update_irq, result) = self.regs.borrow_mut().read(field);
-                if update_irq {
-                    self.update();
-                    unsafe {
-                        qemu_chr_fe_accept_input(&mut self.char_backend);
-                    }
-                }
-                result.into()
-            }
-        }
+    pub fn realize(&self) -> qemu_api::Result<()> {
+        self.char_backend.set_handlers(self);
+        Ok(())
     }
 
-    pub fn write(&mut self, offset: hwaddr, value: u64) {
-        let mut update_irq = false;
-        if let Ok(field) = RegisterOffset::try_from(offset) {
-            // qemu_chr_fe_write_all() calls into the can_receive
-            // callback, so handle writes before entering PL011Registers.
-            if field == RegisterOffset::DR {
-                // ??? Check if transmitter is enabled.
-                let ch: u8 = value as u8;
-                // SAFETY: char_backend is a valid CharBackend instance after it's been
-                // initialized in realize().
-                // XXX this blocks entire thread. Rewrite to use
-                // qemu_chr_fe_write and background I/O callbacks
-                unsafe {
-                    qemu_chr_fe_write_all(&mut self.char_backend, &ch, 1);
-                }
-            }
+    pub fn reset_hold(&self, _type: ResetType) {
+        self.regs.borrow_mut().reset();
+    }
 
-            update_irq = self
-                .regs
-                .borrow_mut()
-                .write(field, value as u32, &mut self.char_backend);
-        } else {
-            eprintln!("write bad offset {offset} value {value}");
-        }
-        if update_irq {
-            self.update();
+    pub fn update(&self) {
+        let regs = self.regs.borrow();
+        let flags = regs.int_level & regs.int_enabled;
+        for (irq, i) in self.interrupts.iter().zip(IRQMASK) {
+            irq.set(flags & i != 0);
         }
     }
 
-    pub fn can_receive(&self) -> bool {
-        // trace_pl011_can_receive(s->lcr, s->read_count, r);
+    pub fn post_load(&self, _version_id: u32) -> Result<(), i32> {
+        self.regs.borrow_mut().post_load().map_err(|()| -1)
+    }
+}
+
+impl CharBackendHandlers for PL011State {
+    fn can_receive(&self) -> u32 {
         let regs = self.regs.borrow();
-        regs.read_count < regs.fifo_depth()
+        let r = regs.read_count < regs.fifo_depth();
+        trace!(pl011_can_receive(
+            u32::from(regs.line_control),
+            regs.read_count as i32,
+            r.into()
+        ));
+        r.into()
     }
 
-    pub fn receive(&self, ch: u32) {
+    fn receive(&self, buf: &[u8]) {
         let mut regs = self.regs.borrow_mut();
-        let update_irq = !regs.loopback_enabled() && regs.put_fifo(ch);
+        let mut update_irq = false;
+        if !regs.loopback_enabled() {
+            for &ch in buf {
+                update_irq |= regs.put_fifo(ch.into());
+            }
+        }
         // Release the BqlRefCell before calling self.update()
         drop(regs);
 
@@ -600,7 +621,7 @@ pub fn receive(&self, ch: u32) {
         }
     }
 
-    pub fn event(&self, event: QEMUChrEvent) {
+    fn event(&self, event: QEMUChrEvent) {
         let mut update_irq = false;
         let mut regs = self.regs.borrow_mut();
         if event == QEMUChrEvent::CHR_EVENT_BREAK && !regs.loopback_enabled() {
@@ -613,39 +634,6 @@ pub fn event(&self, event: QEMUChrEvent) {
             self.update()
         }
     }
-
-    pub fn realize(&self) {
-        // SAFETY: self.char_backend has the correct size and alignment for a
-        // CharBackend object, and its callbacks are of the correct types.
-        unsafe {
-            qemu_chr_fe_set_handlers(
-                addr_of!(self.char_backend) as *mut CharBackend,
-                Some(pl011_can_receive),
-                Some(pl011_receive),
-                Some(pl011_event),
-                None,
-                addr_of!(*self).cast::<c_void>() as *mut c_void,
-                core::ptr::null_mut(),
-                true,
-            );
-        }
-    }
-
-    pub fn reset(&self) {
-        self.regs.borrow_mut().reset();
-    }
-
-    pub fn update(&self) {
-        let regs = self.regs.borrow();
-        let flags = regs.int_level & regs.int_enabled;
-        for (irq, i) in self.interrupts.iter().zip(IRQMASK) {
-            irq.set(flags & i != 0);
-        }
-    }
-
-    pub fn post_load(&self, _version_id: u32) -> Result<(), ()> {
-        self.regs.borrow_mut().post_load()
-    }
 }
 
 /// Which bits in the interrupt status matter for each outbound IRQ line ?
@@ -659,43 +647,6 @@ pub fn post_load(&self, _version_id: u32) -> Result<(), ()> {
     Interrupt::E.0,
 ];
 
-/// # Safety
-///
-/// We expect the FFI user of this function to pass a valid pointer, that has
-/// the same size as [`PL011State`]. We also expect the device is
-/// readable/writeable from one thread at any time.
-pub unsafe extern "C" fn pl011_can_receive(opaque: *mut c_void) -> c_int {
-    let state = NonNull::new(opaque).unwrap().cast::<PL011State>();
-    unsafe { state.as_ref().can_receive().into() }
-}
-
-/// # Safety
-///
-/// We expect the FFI user of this function to pass a valid pointer, that has
-/// the same size as [`PL011State`]. We also expect the device is
-/// readable/writeable from one thread at any time.
-///
-/// The buffer and size arguments must also be valid.
-pub unsafe extern "C" fn pl011_receive(opaque: *mut c_void, buf: *const u8, size: c_int) {
-    let state = NonNull::new(opaque).unwrap().cast::<PL011State>();
-    unsafe {
-        if size > 0 {
-            debug_assert!(!buf.is_null());
-            state.as_ref().receive(u32::from(buf.read_volatile()));
-        }
-    }
-}
-
-/// # Safety
-///
-/// We expect the FFI user of this function to pass a valid pointer, that has
-/// the same size as [`PL011State`]. We also expect the device is
-/// readable/writeable from one thread at any time.
-pub unsafe extern "C" fn pl011_event(opaque: *mut c_void, event: QEMUChrEvent) {
-    let state = NonNull::new(opaque).unwrap().cast::<PL011State>();
-    unsafe { state.as_ref().event(event) }
-}
-
 /// # Safety
 ///
 /// We expect the FFI user of this function to pass a valid pointer for `chr`.
@@ -711,7 +662,8 @@ pub fn post_load(&self, _version_id: u32) -> Result<(), ()> {
 
         qdev_prop_set_chr(dev, c_str!("chardev").as_ptr(), chr);
         sysbus_realize_and_unref(sysbus, addr_of_mut!(error_fatal));
-        sysbus_mmio_map(sysbus, 0, addr);
+        let sysbus_ref: &SysBusDevice = &*sysbus;
+        sysbus_ref.mmio_map(0, addr);
         sysbus_connect_irq(sysbus, 0, irq);
         dev
     }
@@ -743,3 +695,7 @@ impl ObjectImpl for PL011Luminary {
 }
 
 impl DeviceImpl for PL011Luminary {}
+
+impl ResettablePhasesImpl for PL011Luminary {}
+
+impl SysBusDeviceImpl for PL011Luminary {}
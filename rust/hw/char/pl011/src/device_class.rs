@@ -2,20 +2,20 @@
 // Author(s): Manos Pitsidianakis <manos.pitsidianakis@linaro.org>
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use core::ptr::NonNull;
-use std::os::raw::{c_int, c_void};
-
 use qemu_api::{
-    bindings::*, c_str, prelude::*, vmstate_clock, vmstate_fields, vmstate_of, vmstate_struct,
-    vmstate_subsections, vmstate_unused, zeroable::Zeroable,
+    bindings::*,
+    c_str,
+    prelude::*,
+    vmstate::{vmstate_needed, vmstate_post_load},
+    vmstate_clock, vmstate_fields, vmstate_of, vmstate_struct, vmstate_subsections, vmstate_unused,
+    zeroable::Zeroable,
 };
 
 use crate::device::{PL011Registers, PL011State};
 
 #[allow(clippy::missing_const_for_fn)]
-extern "C" fn pl011_clock_needed(opaque: *mut c_void) -> bool {
-    let state = NonNull::new(opaque).unwrap().cast::<PL011State>();
-    unsafe { state.as_ref().migrate_clock }
+fn pl011_clock_needed(state: &PL011State) -> bool {
+    state.migrate_clock
 }
 
 /// Migration subsection for [`PL011State`] clock.
@@ -23,23 +23,13 @@ extern "C" fn pl011_clock_needed(opaque: *mut c_void) -> bool {
     name: c_str!("pl011/clock").as_ptr(),
     version_id: 1,
     minimum_version_id: 1,
-    needed: Some(pl011_clock_needed),
+    needed: vmstate_needed(&pl011_clock_needed),
     fields: vmstate_fields! {
         vmstate_clock!(PL011State, clock),
     },
     ..Zeroable::ZERO
 };
 
-extern "C" fn pl011_post_load(opaque: *mut c_void, version_id: c_int) -> c_int {
-    let state = NonNull::new(opaque).unwrap().cast::<PL011State>();
-    let result = unsafe { state.as_ref().post_load(version_id as u32) };
-    if result.is_err() {
-        -1
-    } else {
-        0
-    }
-}
-
 static VMSTATE_PL011_REGS: VMStateDescription = VMStateDescription {
     name: c_str!("pl011/regs").as_ptr(),
     version_id: 2,
@@ -68,7 +58,7 @@ extern "C" fn pl011_post_load(opaque: *mut c_void, version_id: c_int) -> c_int {
     name: c_str!("pl011").as_ptr(),
     version_id: 2,
     minimum_version_id: 2,
-    post_load: Some(pl011_post_load),
+    post_load: vmstate_post_load(&PL011State::post_load),
     fields: vmstate_fields! {
         vmstate_unused!(core::mem::size_of::<u32>()),
         vmstate_struct!(PL011State, regs, &VMSTATE_PL011_REGS, BqlRefCell<PL011Registers>),
@@ -81,18 +71,12 @@ extern "C" fn pl011_post_load(opaque: *mut c_void, version_id: c_int) -> c_int {
 
 qemu_api::declare_properties! {
     PL011_PROPERTIES,
-    qemu_api::define_property!(
-        c_str!("chardev"),
-        PL011State,
-        char_backend,
-        unsafe { &qdev_prop_chr },
-        CharBackend
-    ),
+    qemu_api::define_chardev_property!(c_str!("chardev"), PL011State, char_backend),
     qemu_api::define_property!(
         c_str!("migrate-clk"),
         PL011State,
         migrate_clock,
-        unsafe { &qdev_prop_bool },
+        PropKind::Bool,
         bool,
         default = true
     ),
@@ -29,7 +29,7 @@
 
 mod device;
 mod device_class;
-mod memory_ops;
+mod trace;
 
 pub use device::pl011_create;
 
@@ -0,0 +1,11 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Trace events used by the PL011 device model.  The functions are
+//! generated by tracetool from the events in `hw/char/trace-events` that
+//! have the `rust` property.
+
+#[cfg(MESON)]
+include!("trace-hw_char.rs");
+#[cfg(not(MESON))]
+include!(concat!(env!("MESON_BUILD_ROOT"), "/trace/trace-hw_char.rs"));
@@ -5,8 +5,9 @@
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::{
-    parse_macro_input, parse_quote, punctuated::Punctuated, spanned::Spanned, token::Comma, Data,
-    DeriveInput, Field, Fields, Ident, Meta, Path, Token, Type, Variant, Visibility,
+    parse_macro_input, parse_quote, punctuated::Punctuated, spanned::Spanned, token::Comma,
+    Attribute, Data, DeriveInput, Field, Fields, Ident, LitInt, LitStr, Meta, Path, Token, Type,
+    Variant, Visibility,
 };
 
 mod utils;
@@ -53,12 +54,15 @@ fn derive_object_or_error(input: DeriveInput) -> Result<proc_macro2::TokenStream
     let parent = &get_fields(&input, "#[derive(Object)]")?[0].ident;
 
     Ok(quote! {
+        // SAFETY: is_c_repr() checked that the struct is #[repr(C)]
+        unsafe impl ::qemu_api::assertions::ReprC for #name {}
+
         ::qemu_api::assert_field_type!(#name, #parent,
             ::qemu_api::qom::ParentField<<#name as ::qemu_api::qom::ObjectImpl>::ParentType>);
 
         ::qemu_api::module_init! {
-            MODULE_INIT_QOM => unsafe {
-                ::qemu_api::bindings::type_register_static(&<#name as ::qemu_api::qom::ObjectImpl>::TYPE_INFO);
+            MODULE_INIT_QOM => {
+                ::qemu_api::qom::type_register::<#name>();
             }
         }
     })
@@ -168,3 +172,141 @@ pub fn derive_tryinto(input: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+/// Options for `#[derive(Migrate)]`, from `#[migrate(...)]` attributes on the
+/// struct or on one of its fields.
+#[derive(Default)]
+struct MigrateAttrs {
+    skip: bool,
+    name: Option<LitStr>,
+    version: Option<i32>,
+    minimum_version: Option<i32>,
+    priority: Option<Ident>,
+    early_setup: bool,
+}
+
+fn get_migrate_attrs(attrs: &[Attribute], is_field: bool) -> Result<MigrateAttrs, MacroError> {
+    let mut result = MigrateAttrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("migrate")) {
+        attr.parse_nested_meta(|meta| {
+            if is_field && meta.path.is_ident("skip") {
+                result.skip = true;
+            } else if meta.path.is_ident("version") {
+                result.version = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if !is_field && meta.path.is_ident("name") {
+                result.name = Some(meta.value()?.parse()?);
+            } else if !is_field && meta.path.is_ident("minimum_version") {
+                result.minimum_version = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if !is_field && meta.path.is_ident("priority") {
+                result.priority = Some(meta.value()?.parse()?);
+            } else if !is_field && meta.path.is_ident("early_setup") {
+                result.early_setup = true;
+            } else {
+                return Err(meta.error("unsupported #[migrate] attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(result)
+}
+
+/// Return whether `ty` is one of the types that `#[derive(Migrate)]`
+/// knows how to migrate: integers, `bool` and arrays of them.
+fn is_migratable_type(ty: &Type) -> bool {
+    const SCALARS: &[&str] = &["bool", "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64"];
+    match ty {
+        Type::Path(p) if p.qself.is_none() => {
+            matches!(p.path.get_ident(), Some(id) if SCALARS.iter().any(|s| id == s))
+        }
+        Type::Array(a) => is_migratable_type(&a.elem),
+        Type::Group(g) => is_migratable_type(&g.elem),
+        Type::Paren(p) => is_migratable_type(&p.elem),
+        _ => false,
+    }
+}
+
+#[rustfmt::skip::macros(quote)]
+fn derive_migrate_or_error(input: DeriveInput) -> Result<proc_macro2::TokenStream, MacroError> {
+    let name = &input.ident;
+    let struct_attrs = get_migrate_attrs(&input.attrs, false)?;
+    let fields = get_fields(&input, "#[derive(Migrate)]")?;
+
+    let mut vmstate_fields = vec![];
+    let mut version_id = struct_attrs.version.unwrap_or(1);
+    for field in fields {
+        let attrs = get_migrate_attrs(&field.attrs, true)?;
+        if attrs.skip {
+            continue;
+        }
+        let ty = &field.ty;
+        if !is_migratable_type(ty) {
+            return Err(MacroError::Message(
+                format!(
+                    "unsupported type {} for #[derive(Migrate)]; use #[migrate(skip)] to exclude the field",
+                    quote!(#ty)
+                ),
+                ty.span(),
+            ));
+        }
+        let field_name = field.ident.as_ref().unwrap();
+        let with_version = attrs.version.map(|v| {
+            version_id = version_id.max(v);
+            quote! { .with_version_id(#v) }
+        });
+        vmstate_fields.push(quote! {
+            ::qemu_api::vmstate_of!(#name, #field_name)#with_version
+        });
+    }
+
+    let minimum_version_id = struct_attrs.minimum_version.unwrap_or(1);
+    if minimum_version_id > version_id {
+        return Err(MacroError::Message(
+            format!(
+                "minimum_version {} is greater than the version {} of the migration stream",
+                minimum_version_id, version_id
+            ),
+            input.ident.span(),
+        ));
+    }
+    // a variant of MigrationPriority, such as MIG_PRI_IOMMU
+    let priority = struct_attrs
+        .priority
+        .unwrap_or_else(|| Ident::new("MIG_PRI_DEFAULT", name.span()));
+    let early_setup = struct_attrs.early_setup;
+    let vmsd_name = struct_attrs
+        .name
+        .unwrap_or_else(|| LitStr::new(&name.to_string(), name.span()));
+
+    Ok(quote! {
+	impl ::qemu_api::vmstate::Migrate for #name {
+	    fn vmsd() -> &'static ::qemu_api::bindings::VMStateDescription {
+		static FIELDS: &[::qemu_api::bindings::VMStateField] = &[
+		    #(#vmstate_fields,)*
+		    ::qemu_api::bindings::VMStateField {
+			flags: ::qemu_api::bindings::VMStateFlags::VMS_END,
+			..::qemu_api::zeroable::Zeroable::ZERO
+		    }
+		];
+		static VMSD: ::qemu_api::bindings::VMStateDescription =
+		    ::qemu_api::bindings::VMStateDescription {
+			name: ::qemu_api::c_str!(#vmsd_name).as_ptr(),
+			version_id: #version_id,
+			minimum_version_id: #minimum_version_id,
+			priority: ::qemu_api::bindings::MigrationPriority::#priority,
+			early_setup: #early_setup,
+			fields: FIELDS.as_ptr(),
+			..::qemu_api::zeroable::Zeroable::ZERO
+		    };
+		&VMSD
+	    }
+	}
+    })
+}
+
+#[proc_macro_derive(Migrate, attributes(migrate))]
+pub fn derive_migrate(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let expanded = derive_migrate_or_error(input).unwrap_or_else(Into::into);
+
+    TokenStream::from(expanded)
+}
@@ -77,14 +77,98 @@ macro_rules! assert_field_type {
     ($t:ty, $i:tt, $ti:ty) => {
         const _: () = {
             #[allow(unused)]
-            fn assert_field_type(v: $t) {
+            fn assert_field_type(v: &$t) {
                 fn types_must_be_equal<T, U>(_: T)
                 where
                     T: $crate::assertions::EqType<Itself = U>,
                 {
                 }
-                types_must_be_equal::<_, $ti>(v.$i);
+                // borrow the field, so that `$t` can implement `Drop`
+                types_must_be_equal::<_, &$ti>(&v.$i);
             }
         };
     };
 }
+
+/// Marker for structs that are declared with `#[repr(C)]`, whose layout
+/// is therefore predictable by C code.  It is implemented by
+/// `#[derive(Object)]`, which rejects structs without `#[repr(C)]`.
+///
+/// # Safety
+///
+/// The struct must be declared with `#[repr(C)]`.
+pub unsafe trait ReprC {}
+
+#[doc(hidden)]
+pub const fn assert_repr_c<T: ReprC>() {}
+
+#[doc(hidden)]
+pub const fn assert_parent_field<T: crate::qom::ObjectType>(
+    _: std::marker::PhantomData<crate::qom::ParentField<T>>,
+) {
+}
+
+/// Assert that the struct for a QOM object can be accessed from C code:
+/// it is declared with `#[repr(C)]`, so that the offsets used by
+/// properties and migration are meaningful, and the parent object is
+/// the field at offset 0, as QOM requires.
+///
+/// The first argument is the struct; the second is the name of the
+/// [`ParentField`](crate::qom::ParentField) that embeds the parent.
+///
+/// # Examples
+///
+/// ```
+/// # use qemu_api::{assert_device_layout, assertions::ReprC};
+/// # use qemu_api::{qdev::DeviceState, qom::ParentField};
+/// #[repr(C)]
+/// pub struct Foo {
+///     parent: ParentField<DeviceState>,
+///     level: u32,
+/// }
+/// # // implemented by #[derive(Object)]
+/// # unsafe impl ReprC for Foo {}
+///
+/// assert_device_layout!(Foo, parent);
+/// ```
+///
+/// A parent that is not the first field will cause a compile failure
+///
+/// ```compile_fail
+/// # use qemu_api::{assert_device_layout, assertions::ReprC};
+/// # use qemu_api::{qdev::DeviceState, qom::ParentField};
+/// #[repr(C)]
+/// pub struct Foo {
+///     level: u32,
+///     parent: ParentField<DeviceState>,
+/// }
+/// # unsafe impl ReprC for Foo {}
+///
+/// assert_device_layout!(Foo, parent);
+/// ```
+///
+/// and so will a struct that is not known to be `#[repr(C)]`
+///
+/// ```compile_fail
+/// # use qemu_api::assert_device_layout;
+/// # use qemu_api::{qdev::DeviceState, qom::ParentField};
+/// pub struct Foo {
+///     parent: ParentField<DeviceState>,
+///     level: u32,
+/// }
+///
+/// assert_device_layout!(Foo, parent);
+/// ```
+#[macro_export]
+macro_rules! assert_device_layout {
+    ($t:ty, $parent:ident) => {
+        const _: () = {
+            $crate::assertions::assert_repr_c::<$t>();
+            $crate::call_func_with_field!($crate::assertions::assert_parent_field, $t, $parent);
+            assert!(
+                $crate::offset_of!($t, $parent) == 0,
+                "the QOM parent must be the first field"
+            );
+        };
+    };
+}
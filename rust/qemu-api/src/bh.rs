@@ -0,0 +1,147 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for bottom halves (`QEMUBH`).
+//!
+//! A bottom half defers work from a callback, for example an MMIO write,
+//! to the next iteration of the main loop.  Like a [`Timer`], a
+//! [`BottomHalf`] is embedded in the state of a device and initialized with
+//! the function to run and the object that is passed to it:
+//!
+//! ```ignore
+//! self.bh.init(&Self::bh_cb, self);
+//! ...
+//! self.bh.schedule();
+//! ```
+//!
+//! The bottom half is deleted when the `BottomHalf` is dropped, which for
+//! QOM objects happens when the object is finalized.  Because scheduling
+//! needs a reference to the `BottomHalf`, it cannot happen after that.
+//!
+//! [`Timer`]: crate::timer::Timer
+
+use std::{
+    cell::UnsafeCell,
+    os::raw::c_void,
+    ptr::{self, NonNull},
+};
+
+use crate::{
+    bindings::{self, QEMUBH},
+    c_str,
+    callbacks::{abort_on_panic, FnCall},
+    cell::bql_locked,
+};
+
+/// A bottom half that runs in the main loop, and therefore always under
+/// the Big QEMU Lock.
+#[derive(Debug)]
+pub struct BottomHalf(UnsafeCell<*mut QEMUBH>);
+
+// SAFETY: the pointer is only accessed with the BQL taken
+unsafe impl Send for BottomHalf {}
+unsafe impl Sync for BottomHalf {}
+
+unsafe extern "C" fn rust_bh_cb<T, F: for<'a> FnCall<(&'a T,)>>(opaque: *mut c_void) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque was passed as a reference to `T` in
+        // BottomHalf::init
+        F::call((unsafe { state.as_ref() },));
+    })
+}
+
+impl BottomHalf {
+    /// Create a bottom half that does nothing yet.  It must be initialized
+    /// with [`init`](BottomHalf::init) before it is scheduled.
+    pub const fn new() -> Self {
+        BottomHalf(UnsafeCell::new(ptr::null_mut()))
+    }
+
+    /// Make the bottom half call `cb` with `opaque` as the argument.
+    ///
+    /// The `_cb` parameter is unused; it only selects the function to call,
+    /// which must be a function item or a closure that captures nothing.
+    ///
+    /// `opaque` must live as long as the bottom half; usually the bottom
+    /// half is a field of `opaque` itself.  If the bottom half was already
+    /// initialized, the previous one is deleted first, including any
+    /// pending call.
+    pub fn init<T, F: for<'a> FnCall<(&'a T,)>>(&self, _cb: &F, opaque: &T) {
+        assert!(bql_locked());
+        self.delete();
+        let cb: unsafe extern "C" fn(*mut c_void) = rust_bh_cb::<T, F>;
+        let name = c_str!("rust_bh_cb");
+        // SAFETY: the name is a static string, and opaque outlives the
+        // bottom half
+        unsafe {
+            *self.0.get() = bindings::qemu_bh_new_full(
+                Some(cb),
+                ptr::addr_of!(*opaque).cast::<c_void>() as *mut c_void,
+                name.as_ptr(),
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    /// Run the callback in the next iteration of the main loop.  Scheduling
+    /// the bottom half again before the callback has run has no effect.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bottom half was not initialized.
+    pub fn schedule(&self) {
+        assert!(bql_locked());
+        let bh = self.as_mut_ptr();
+        assert!(!bh.is_null(), "bottom half not initialized");
+        // SAFETY: the bottom half was created by init()
+        unsafe {
+            bindings::qemu_bh_schedule(bh);
+        }
+    }
+
+    /// Cancel a call that was scheduled and has not run yet.  This does
+    /// nothing if the bottom half is not scheduled.
+    pub fn cancel(&self) {
+        assert!(bql_locked());
+        let bh = self.as_mut_ptr();
+        if !bh.is_null() {
+            // SAFETY: the bottom half was created by init()
+            unsafe {
+                bindings::qemu_bh_cancel(bh);
+            }
+        }
+    }
+
+    fn delete(&self) {
+        let bh = self.as_mut_ptr();
+        if !bh.is_null() {
+            // SAFETY: the bottom half was created by init(), and is
+            // forgotten right after it is deleted
+            unsafe {
+                bindings::qemu_bh_delete(bh);
+                *self.0.get() = ptr::null_mut();
+            }
+        }
+    }
+
+    /// Return a raw pointer to the C `QEMUBH`, or null if the bottom half
+    /// was not initialized, for use with C functions.
+    pub fn as_mut_ptr(&self) -> *mut QEMUBH {
+        // SAFETY: the pointer is only written by init() and delete()
+        unsafe { *self.0.get() }
+    }
+}
+
+impl Default for BottomHalf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for BottomHalf {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
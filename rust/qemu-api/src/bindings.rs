@@ -23,6 +23,7 @@
 
 unsafe impl Send for Property {}
 unsafe impl Sync for Property {}
+unsafe impl Sync for PropertyInfo {}
 unsafe impl Sync for TypeInfo {}
 unsafe impl Sync for VMStateDescription {}
 unsafe impl Sync for VMStateField {}
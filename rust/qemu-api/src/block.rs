@@ -0,0 +1,166 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for block backends.
+//!
+//! A storage device has a [`Drive`] field, which is connected to a
+//! [`BlockBackend`] by a `drive` property, defined with
+//! [`define_drive_property!`](crate::define_drive_property).  As with
+//! `-device foo,drive=disk0`, the property names a drive created with
+//! `-drive` or `-blockdev`.  The device then performs synchronous I/O on
+//! the backend:
+//!
+//! ```ignore
+//! let blk = self.drive.backend().ok_or_else(|| Error::msg("drive property not set"))?;
+//! let mut sector = [0u8; 512];
+//! blk.read(lba * 512, &mut sector)?;
+//! ```
+//!
+//! The `drive` property detaches the backend from the device when the
+//! device is finalized.
+
+use std::{
+    cell::UnsafeCell,
+    io,
+    os::raw::c_void,
+    ptr::{self, addr_of},
+};
+
+pub use bindings::BlockBackend;
+
+use crate::bindings;
+
+/// Return an error unless `len` bytes starting at `offset` are within
+/// a drive of `size` bytes.  On success, return `len` as expected by the
+/// block layer.
+fn check_range(size: u64, offset: u64, len: usize) -> io::Result<i64> {
+    match offset.checked_add(len as u64) {
+        // the size of a drive fits in an i64, and so does the request
+        Some(end) if end <= size => Ok(len as i64),
+        _ => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "request past the end of the drive",
+        )),
+    }
+}
+
+impl BlockBackend {
+    /// Return the size of the medium in bytes.
+    pub fn size(&self) -> io::Result<u64> {
+        // SAFETY: the backend is valid, even if it has no medium
+        let ret = unsafe { bindings::blk_getlength(self.as_mut_ptr()) };
+        u64::try_from(ret).map_err(|_| io::Error::from_raw_os_error(-ret as i32))
+    }
+
+    /// Fill `buf` with the contents of the drive at `offset`.  This blocks
+    /// until the request completes.
+    ///
+    /// Reads that extend past the end of the drive fail with
+    /// [`io::ErrorKind::UnexpectedEof`] without accessing the drive.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let len = check_range(self.size()?, offset, buf.len())?;
+        // SAFETY: the buffer is valid for `len` bytes
+        let ret = unsafe {
+            bindings::blk_pread(
+                self.as_mut_ptr(),
+                offset as i64,
+                len,
+                buf.as_mut_ptr().cast::<c_void>(),
+                0,
+            )
+        };
+        errno_result(ret)
+    }
+
+    /// Write the contents of `buf` to the drive at `offset`.  This blocks
+    /// until the request completes.
+    ///
+    /// Writes that extend past the end of the drive fail with
+    /// [`io::ErrorKind::UnexpectedEof`] without accessing the drive.
+    pub fn write(&self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let len = check_range(self.size()?, offset, buf.len())?;
+        // SAFETY: the buffer is valid for `len` bytes
+        let ret = unsafe {
+            bindings::blk_pwrite(
+                self.as_mut_ptr(),
+                offset as i64,
+                len,
+                buf.as_ptr().cast::<c_void>(),
+                0,
+            )
+        };
+        errno_result(ret)
+    }
+
+    /// Return a raw pointer to the C `BlockBackend`, for use with C
+    /// functions.
+    pub const fn as_mut_ptr(&self) -> *mut BlockBackend {
+        addr_of!(*self) as *mut _
+    }
+}
+
+/// Convert the return value of a block layer function, which is a
+/// negative errno value on failure.
+fn errno_result(ret: i32) -> io::Result<()> {
+    if ret < 0 {
+        Err(io::Error::from_raw_os_error(-ret))
+    } else {
+        Ok(())
+    }
+}
+
+/// A field of a storage device that holds the block backend attached by
+/// its `drive` property, if any.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct Drive(UnsafeCell<*mut BlockBackend>);
+
+// SAFETY: the pointer is only written by the drive property, before the
+// device is realized; the block layer takes care of concurrent requests
+unsafe impl Send for Drive {}
+unsafe impl Sync for Drive {}
+
+impl Drive {
+    /// Return the block backend, or `None` if the `drive` property was
+    /// not set.
+    pub fn backend(&self) -> Option<&BlockBackend> {
+        // SAFETY: the backend, if any, stays attached to the device until
+        // the device is finalized
+        unsafe { (*self.0.get()).as_ref() }
+    }
+}
+
+impl Default for Drive {
+    fn default() -> Self {
+        Drive(UnsafeCell::new(ptr::null_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_range() {
+        assert_eq!(check_range(1024, 512, 512).unwrap(), 512);
+        assert_eq!(check_range(1024, 1024, 0).unwrap(), 0);
+        assert_eq!(
+            check_range(1024, 1023, 2).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            check_range(0, 0, 1).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            check_range(1024, u64::MAX, 2).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_errno_result() {
+        errno_result(0).unwrap();
+        assert_eq!(errno_result(-5).unwrap_err().raw_os_error(), Some(5));
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Taking the Big QEMU Lock from Rust.
+//!
+//! Code that runs in a thread other than the main loop or a vCPU, and
+//! that needs to call into QEMU, takes the Big QEMU Lock with a
+//! [`BqlGuard`]:
+//!
+//! ```ignore
+//! let _guard = BqlGuard::new();
+//! // ... code that needs the BQL ...
+//! // the BQL is released when _guard goes out of scope
+//! ```
+//!
+//! The BQL is not recursive; taking it when the current thread already
+//! holds it is a programming error and panics.
+
+use std::{marker::PhantomData, os::raw::c_int, panic::Location};
+
+use crate::{bindings, c_str, cell::bql_locked};
+
+/// Panic if the current thread does not hold the Big QEMU Lock.  The
+/// check is only performed in debug builds.
+///
+/// The trampolines that this crate installs for MMIO and port I/O
+/// accesses, memory listeners, PCI config space accesses, realize and
+/// reset, GPIO inputs and clocks, migration (both `VMStateDescription`
+/// callbacks and [`SaveVmImpl`](crate::savevm::SaveVmImpl) handlers) and
+/// QMP commands call this function before entering Rust code.  Timers,
+/// bottom halves and the character and network backends check the BQL in
+/// release builds too.
+///
+/// IOMMU translation and RCU callbacks are not checked, because they run
+/// without the BQL.  Neither are the QOM type, instance and property
+/// callbacks, because QOM is also used by code that does not take the
+/// BQL, such as the tools.
+#[track_caller]
+pub fn assert_bql_locked() {
+    debug_assert!(bql_locked(), "BQL not held");
+}
+
+/// A RAII guard for the Big QEMU Lock.  The lock is taken by
+/// [`BqlGuard::new`] and released when the guard is dropped, including
+/// during unwinding.
+///
+/// The guard cannot be sent to other threads, because the BQL must be
+/// released by the thread that took it.
+#[derive(Debug)]
+#[must_use = "the BQL is released as soon as the guard is dropped"]
+pub struct BqlGuard {
+    _not_send: PhantomData<*mut ()>,
+}
+
+// Taking a lock is not a sensible default value.
+#[allow(clippy::new_without_default)]
+impl BqlGuard {
+    /// Take the Big QEMU Lock, waiting until it is available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current thread already holds the lock.
+    #[track_caller]
+    pub fn new() -> Self {
+        if cfg!(MESON) {
+            assert!(!bql_locked(), "BQL already held by this thread");
+            // Lock profiling keeps a pointer to the file name, so it cannot
+            // point to the non-NUL-terminated string in `Location`.
+            let line = Location::caller().line();
+            let file = c_str!("<rust>");
+            // SAFETY: the BQL is not held, so this cannot deadlock
+            unsafe {
+                bindings::bql_lock_impl(file.as_ptr(), line as c_int);
+            }
+        }
+        BqlGuard {
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for BqlGuard {
+    fn drop(&mut self) {
+        if cfg!(MESON) {
+            // SAFETY: the lock was taken by this thread in BqlGuard::new()
+            unsafe {
+                bindings::bql_unlock();
+            }
+        }
+    }
+}
@@ -2,9 +2,18 @@
 // Author(s): Paolo Bonzini <pbonzini@redhat.com>
 // SPDX-License-Identifier: GPL-2.0-or-later
 
+use std::os::raw::c_char;
+
+use crate::{bindings, g_strndup};
+
 #[macro_export]
 /// Given a string constant _without_ embedded or trailing NULs, return
-/// a `CStr`.
+/// a `CStr`.  The absence of NULs is checked at compile time:
+///
+/// ```compile_fail
+/// # use qemu_api::c_str;
+/// let name = c_str!("foo\0bar");
+/// ```
 ///
 /// Needed for compatibility with Rust <1.77.
 macro_rules! c_str {
@@ -24,19 +33,66 @@ const fn f(b: &[u8], i: usize) {
                 f(b, i + 1)
             }
         }
-        f(BYTES, 0);
+        const _: () = f(BYTES, 0);
 
         // SAFETY: absence of NULs apart from the final byte was checked above
         unsafe { std::ffi::CStr::from_bytes_with_nul_unchecked(BYTES) }
     }};
 }
 
+/// Return the name of a QOM type or property, which must be a string
+/// literal, as a `&'static CStr`.  This is what the `TYPE_NAME` of
+/// [`ObjectType`](crate::qom::ObjectType), [`define_property!`] and the
+/// other macros that register names with QOM expect:
+///
+/// ```
+/// # use qemu_api::qom_name;
+/// # use std::ffi::CStr;
+/// const TYPE_PL011: &CStr = qom_name!("pl011");
+/// assert_eq!(TYPE_PL011.to_bytes_with_nul(), b"pl011\0");
+/// ```
+///
+/// Names that contain a NUL character are rejected at compile time:
+///
+/// ```compile_fail
+/// # use qemu_api::qom_name;
+/// let name = qom_name!("chardev\0");
+/// ```
+///
+/// and so are strings that are not literals, which would otherwise need
+/// a conversion, and a check for NULs, at run time:
+///
+/// ```compile_fail
+/// # use qemu_api::qom_name;
+/// let s: &str = "chardev";
+/// let name = qom_name!(s);
+/// ```
+#[macro_export]
+macro_rules! qom_name {
+    ($name:literal) => {
+        $crate::c_str!($name)
+    };
+}
+
+/// Copy `s` into a C string allocated by glib, as expected by C functions
+/// that take ownership of a string and free it with `g_free()`, for
+/// example the getters of string properties.
+///
+/// Strings returned to C must not be allocated by Rust, for example with
+/// [`CString::into_raw`](std::ffi::CString::into_raw), because freeing them
+/// with `g_free()` would mix two allocators.
+///
+/// The copy stops at the first NUL character of `s`, if any.
+pub fn glib_strdup(s: &str) -> *mut c_char {
+    // SAFETY: `s` is valid for `s.len()` bytes; g_strndup() adds the
+    // terminating NUL
+    unsafe { g_strndup(s.as_ptr().cast::<c_char>(), s.len() as bindings::gsize) }
+}
+
 #[cfg(test)]
 mod tests {
     use std::ffi::CStr;
 
-    use crate::c_str;
-
     #[test]
     fn test_cstr_macro() {
         let good = c_str!("🦀");
@@ -50,4 +106,10 @@ fn test_cstr_macro_const() {
         const GOOD_BYTES: &[u8] = b"\xf0\x9f\xa6\x80\0";
         assert_eq!(GOOD.to_bytes_with_nul(), GOOD_BYTES);
     }
+
+    #[test]
+    fn test_qom_name() {
+        const NAME: &CStr = qom_name!("migrate-clk");
+        assert_eq!(NAME, c_str!("migrate-clk"));
+    }
 }
@@ -2,7 +2,12 @@
 
 //! Utility functions to deal with callbacks from C to Rust.
 
-use std::{mem, ptr::NonNull};
+use std::{
+    mem,
+    panic::{self, AssertUnwindSafe},
+    process,
+    ptr::NonNull,
+};
 
 /// Trait for functions (types implementing [`Fn`]) that can be used as
 /// callbacks. These include both zero-capture closures and function pointers.
@@ -128,6 +133,36 @@ fn call(a: ($($args,)*)) -> R {
 impl_call!(_1,);
 impl_call!();
 
+/// Run `f` and return its result, aborting the process if it panics.
+///
+/// Unwinding out of an `extern "C"` function into C code is undefined
+/// behavior, because C frames do not know how to run destructors or
+/// release the locks that they hold.  Therefore, C callbacks that are
+/// implemented in Rust must wrap the Rust code in this function, so that
+/// a panic turns into a controlled `abort()` instead.  The panic message
+/// is printed by the panic hook before the process is aborted.
+///
+/// ```
+/// # use qemu_api::callbacks::abort_on_panic;
+/// unsafe extern "C" fn rust_cb(value: u32) -> u32 {
+///     abort_on_panic(|| value.checked_mul(2).expect("overflow"))
+/// }
+///
+/// assert_eq!(unsafe { rust_cb(21) }, 42);
+/// ```
+#[inline]
+pub fn abort_on_panic<R, F: FnOnce() -> R>(f: F) -> R {
+    // The state of the device is not inspected again after a panic,
+    // so there is no need to check for unwind safety.
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(_) => {
+            eprintln!("qemu: panic in a Rust callback called from C, aborting");
+            process::abort()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,222 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for character device frontends.
+//!
+//! A [`CharBackend`] is embedded in the state of a device, and is
+//! connected to a `Chardev` by a `chardev` property, defined with
+//! [`define_chardev_property!`](crate::define_chardev_property).  When
+//! the device is realized, it registers itself as the recipient of
+//! incoming data by implementing [`CharBackendHandlers`]:
+//!
+//! ```ignore
+//! impl CharBackendHandlers for MyUartState {
+//!     fn can_receive(&self) -> u32 { ... }
+//!     fn receive(&self, buf: &[u8]) { ... }
+//!     fn event(&self, event: QEMUChrEvent) { ... }
+//! }
+//!
+//! self.char_backend.set_handlers(self);
+//! ```
+//!
+//! The handlers are removed when the `CharBackend` is dropped, which for
+//! QOM objects happens when the object is finalized.
+
+use std::{
+    cell::UnsafeCell,
+    io,
+    os::raw::{c_int, c_void},
+    ptr::{self, addr_of_mut, NonNull},
+    slice,
+};
+
+pub use bindings::{Chardev, QEMUChrEvent};
+
+use crate::{bindings, callbacks::abort_on_panic, cell::bql_locked, zeroable::Zeroable};
+
+/// A wrapper around the C `CharBackend` struct, i.e. the frontend side
+/// of a character device.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct CharBackend(UnsafeCell<bindings::CharBackend>);
+
+// SAFETY: the `CharBackend` is only modified under the Big QEMU Lock,
+// and writing to the chardev is thread-safe.
+unsafe impl Send for CharBackend {}
+unsafe impl Sync for CharBackend {}
+
+/// Callbacks that a frontend provides to receive data and events from
+/// its chardev.  They are registered with
+/// [`CharBackend::set_handlers`], and they always run under the Big QEMU
+/// Lock.
+pub trait CharBackendHandlers {
+    /// Return how many bytes the frontend is ready to accept.  Returning
+    /// zero stops the flow of data; when it can accept more bytes, the
+    /// frontend must call [`CharBackend::accept_input`] to restart it.
+    fn can_receive(&self) -> u32;
+
+    /// Process bytes coming from the chardev.  `buf` is never longer
+    /// than the last value returned by [`can_receive`], but it may be
+    /// shorter.
+    ///
+    /// [`can_receive`]: CharBackendHandlers::can_receive
+    fn receive(&self, buf: &[u8]);
+
+    /// Process an event, such as the chardev being opened or a break
+    /// condition.  The default implementation ignores all events.
+    fn event(&self, _event: QEMUChrEvent) {}
+}
+
+unsafe extern "C" fn rust_can_receive_cb<T: CharBackendHandlers>(opaque: *mut c_void) -> c_int {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let owner = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque was passed as a reference to `T` in set_handlers()
+        let size = unsafe { owner.as_ref() }.can_receive();
+        size.try_into().unwrap_or(c_int::MAX)
+    })
+}
+
+unsafe extern "C" fn rust_receive_cb<T: CharBackendHandlers>(
+    opaque: *mut c_void,
+    buf: *const u8,
+    size: c_int,
+) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let owner = NonNull::new(opaque).unwrap().cast::<T>();
+        let buf = match usize::try_from(size) {
+            // SAFETY: the chardev passes a buffer of `size` bytes
+            Ok(len) if len > 0 => unsafe { slice::from_raw_parts(buf, len) },
+            _ => &[],
+        };
+        // SAFETY: the opaque was passed as a reference to `T` in set_handlers()
+        unsafe { owner.as_ref() }.receive(buf);
+    })
+}
+
+unsafe extern "C" fn rust_event_cb<T: CharBackendHandlers>(
+    opaque: *mut c_void,
+    event: QEMUChrEvent,
+) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let owner = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque was passed as a reference to `T` in set_handlers()
+        unsafe { owner.as_ref() }.event(event);
+    })
+}
+
+impl CharBackend {
+    /// Create a frontend that is not connected to any chardev.  The
+    /// connection is usually made by the `chardev` property of the device.
+    pub const fn new() -> Self {
+        CharBackend(UnsafeCell::new(Zeroable::ZERO))
+    }
+
+    /// Register the [`CharBackendHandlers`] of `owner`, and open the
+    /// frontend.  If the chardev is already open, `owner` receives a
+    /// `CHR_EVENT_OPENED` event immediately.
+    ///
+    /// `owner` must live as long as the `CharBackend`; usually the
+    /// `CharBackend` is a field of `owner` itself.  This does nothing if
+    /// no chardev is connected.
+    pub fn set_handlers<T: CharBackendHandlers>(&self, owner: &T) {
+        assert!(bql_locked());
+        // SAFETY: the callbacks match the type of the opaque, and the
+        // chardev is not made to use a different GMainContext
+        unsafe {
+            bindings::qemu_chr_fe_set_handlers(
+                self.as_mut_ptr(),
+                Some(rust_can_receive_cb::<T>),
+                Some(rust_receive_cb::<T>),
+                Some(rust_event_cb::<T>),
+                None,
+                ptr::addr_of!(*owner).cast::<c_void>() as *mut c_void,
+                ptr::null_mut(),
+                true,
+            );
+        }
+    }
+
+    /// Return whether the frontend is connected to a chardev.
+    pub fn is_connected(&self) -> bool {
+        // SAFETY: the frontend is valid even if it is not connected
+        unsafe { bindings::qemu_chr_fe_backend_connected(self.as_mut_ptr()) }
+    }
+
+    /// Tell the chardev that the frontend can accept more data, after
+    /// [`can_receive`](CharBackendHandlers::can_receive) returned zero.
+    pub fn accept_input(&self) {
+        assert!(bql_locked());
+        // SAFETY: the frontend is valid even if it is not connected
+        unsafe {
+            bindings::qemu_chr_fe_accept_input(self.as_mut_ptr());
+        }
+    }
+
+    /// Write as many bytes of `buf` as the chardev can accept without
+    /// blocking, and return how many were written.  This can be less than
+    /// `buf.len()`, and it is zero if no chardev is connected.
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().try_into().unwrap_or(c_int::MAX);
+        // SAFETY: the buffer is valid for `len` bytes
+        let ret = unsafe { bindings::qemu_chr_fe_write(self.as_mut_ptr(), buf.as_ptr(), len) };
+        ret.try_into().map_err(|_| io::Error::last_os_error())
+    }
+
+    /// Write all of `buf`, blocking the current thread until the chardev
+    /// has accepted it.  If no chardev is connected, the data is dropped.
+    pub fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        for chunk in buf.chunks(c_int::MAX as usize) {
+            // SAFETY: the buffer is valid for `chunk.len()` bytes
+            let ret = unsafe {
+                bindings::qemu_chr_fe_write_all(
+                    self.as_mut_ptr(),
+                    chunk.as_ptr(),
+                    chunk.len() as c_int,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Start or stop sending a break condition on a serial chardev.  Other
+    /// kinds of chardev ignore the request.
+    pub fn send_break(&self, enable: bool) {
+        let mut enable: c_int = enable.into();
+        // SAFETY: CHR_IOCTL_SERIAL_SET_BREAK takes a pointer to an int
+        unsafe {
+            bindings::qemu_chr_fe_ioctl(
+                self.as_mut_ptr(),
+                bindings::CHR_IOCTL_SERIAL_SET_BREAK as c_int,
+                addr_of_mut!(enable).cast::<c_void>(),
+            );
+        }
+    }
+
+    /// Return a raw pointer to the C `CharBackend`, for use with C
+    /// functions.
+    pub const fn as_mut_ptr(&self) -> *mut bindings::CharBackend {
+        self.0.get()
+    }
+}
+
+impl Default for CharBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for CharBackend {
+    fn drop(&mut self) {
+        // SAFETY: qemu_chr_fe_deinit() does nothing if the frontend is not
+        // connected, for example because the qdev property already released it
+        unsafe {
+            bindings::qemu_chr_fe_deinit(self.as_mut_ptr(), false);
+        }
+    }
+}
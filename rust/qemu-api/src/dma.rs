@@ -0,0 +1,459 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! DMA accesses to guest memory.
+//!
+//! Bus-master devices read and write guest memory through an
+//! [`AddressSpace`], usually [`address_space_memory`]:
+//!
+//! ```ignore
+//! let mut desc = [0u8; 16];
+//! address_space_memory().read(self.desc_addr, &mut desc)?;
+//! ```
+//!
+//! As with `dma_memory_read()` and `dma_memory_write()` in C, each
+//! access is ordered with respect to the device's other memory accesses.
+//!
+//! Devices that move a lot of data can instead map guest memory and access
+//! it in place, as with `dma_memory_map()`.  The mapping is released when
+//! the [`DmaMapping`] is dropped:
+//!
+//! ```ignore
+//! if let Some(mut map) = address_space_memory().map_write(self.buf_addr, len) {
+//!     let n = map.len();
+//!     map.write(0, &self.frame[..n]);
+//! }
+//! ```
+//!
+//! Devices that have their own view of memory, such as IOMMUs, create an
+//! address space on top of one of their memory regions with
+//! [`AddressSpace::new`].
+//!
+//! Board and firmware-loading code that is not a bus master, and does not
+//! need the ordering guarantees of DMA, can use [`phys_mem_read`] and
+//! [`phys_mem_write`] instead, like `cpu_physical_memory_read()` and
+//! `cpu_physical_memory_write()` in C.
+
+use std::{
+    error,
+    ffi::CStr,
+    fmt,
+    ops::Deref,
+    os::raw::c_void,
+    ptr::{self, addr_of_mut, NonNull},
+    sync::atomic::{fence, Ordering},
+};
+
+pub use bindings::AddressSpace;
+
+use crate::{
+    bindings::{self, MemTxAttrs, MemTxResult},
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    memory::{hwaddr, MemoryRegion},
+    zeroable::Zeroable,
+};
+
+/// The reason why a DMA access failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DmaError {
+    /// The device at the address returned an error.
+    DeviceError,
+    /// Nothing is mapped at the address.
+    Decode,
+    /// The access was denied, for example because of its attributes.
+    Access,
+}
+
+impl DmaError {
+    const fn check(result: MemTxResult) -> Result<(), DmaError> {
+        // several bits can be set if the access spans more than one region;
+        // report the one that is most specific
+        if result == bindings::MEMTX_OK {
+            Ok(())
+        } else if result & bindings::MEMTX_DECODE_ERROR != 0 {
+            Err(DmaError::Decode)
+        } else if result & bindings::MEMTX_ACCESS_ERROR != 0 {
+            Err(DmaError::Access)
+        } else {
+            Err(DmaError::DeviceError)
+        }
+    }
+}
+
+impl fmt::Display for DmaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DmaError::DeviceError => "device returned an error",
+            DmaError::Decode => "nothing mapped at the address",
+            DmaError::Access => "access denied",
+        })
+    }
+}
+
+impl error::Error for DmaError {}
+
+/// `MEMTXATTRS_UNSPECIFIED` in C.
+fn unspecified_attrs() -> MemTxAttrs {
+    let mut attrs = MemTxAttrs::default();
+    attrs.set_unspecified(1);
+    attrs
+}
+
+impl AddressSpace {
+    /// Create an address space whose accesses are routed by `root`, as in
+    /// `address_space_init()`.  The name is only used for debugging.
+    ///
+    /// The address space keeps `root` alive, through a reference to its
+    /// owner, until the returned [`OwnedAddressSpace`] is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `root` has no owner, since nothing would then keep it
+    /// alive.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(root: &MemoryRegion, name: &CStr) -> OwnedAddressSpace {
+        assert!(bql_locked());
+        assert!(root.has_owner());
+        let storage = NonNull::from(Box::leak(Box::new(AddressSpaceStorage {
+            rcu: Zeroable::ZERO,
+            space: Zeroable::ZERO,
+        })));
+        // SAFETY: the storage is not moved or freed until the address
+        // space has been destroyed, and the name is copied
+        unsafe {
+            bindings::address_space_init(
+                addr_of_mut!((*storage.as_ptr()).space),
+                root.as_mut_ptr(),
+                name.as_ptr(),
+            );
+        }
+        OwnedAddressSpace(storage)
+    }
+
+    /// Fill `buf` with the contents of guest memory at `addr`.  If part
+    /// of the access fails, the remaining bytes of `buf` are unspecified.
+    pub fn read(&self, addr: hwaddr, buf: &mut [u8]) -> Result<(), DmaError> {
+        fence(Ordering::SeqCst);
+        // SAFETY: the buffer is valid for `buf.len()` bytes
+        let result = unsafe {
+            bindings::address_space_read_full(
+                self.as_mut_ptr(),
+                addr,
+                unspecified_attrs(),
+                buf.as_mut_ptr().cast::<c_void>(),
+                buf.len() as hwaddr,
+            )
+        };
+        DmaError::check(result)
+    }
+
+    /// Write the contents of `buf` to guest memory at `addr`.
+    pub fn write(&self, addr: hwaddr, buf: &[u8]) -> Result<(), DmaError> {
+        fence(Ordering::SeqCst);
+        // SAFETY: the buffer is valid for `buf.len()` bytes
+        let result = unsafe {
+            bindings::address_space_write(
+                self.as_mut_ptr(),
+                addr,
+                unspecified_attrs(),
+                buf.as_ptr().cast::<c_void>(),
+                buf.len() as hwaddr,
+            )
+        };
+        DmaError::check(result)
+    }
+
+    /// Map up to `len` bytes of guest memory at `addr` so that the device
+    /// can read them in place, as in `address_space_map()`.
+    ///
+    /// The mapping can be shorter than `len`, for example if the range
+    /// crosses the end of a RAM region.  If the range is not RAM, the
+    /// memory is copied to a bounce buffer; `None` is returned if nothing
+    /// could be mapped, for example because the bounce buffer is in use.
+    /// In both cases, [`read`](AddressSpace::read) still works.
+    pub fn map_read(&self, addr: hwaddr, len: hwaddr) -> Option<DmaMapping<'_>> {
+        DmaMapping::new(self, addr, len, false)
+    }
+
+    /// Map up to `len` bytes of guest memory at `addr` so that the device
+    /// can write them in place, as in `address_space_map()`.  The memory is
+    /// marked dirty, and a bounce buffer is written back to guest memory,
+    /// when the mapping is dropped.
+    ///
+    /// As with [`map_read`](AddressSpace::map_read), the mapping can be
+    /// shorter than `len`, and `None` is returned if nothing could be
+    /// mapped.
+    pub fn map_write(&self, addr: hwaddr, len: hwaddr) -> Option<DmaMapping<'_>> {
+        DmaMapping::new(self, addr, len, true)
+    }
+
+    /// Return a raw pointer to the C `AddressSpace`, for use with C
+    /// functions.
+    pub const fn as_mut_ptr(&self) -> *mut AddressSpace {
+        self as *const AddressSpace as *mut AddressSpace
+    }
+}
+
+/// Guest memory that was mapped with [`AddressSpace::map_read`] or
+/// [`AddressSpace::map_write`].  The memory is unmapped when the
+/// `DmaMapping` is dropped.
+///
+/// The guest can access the memory while it is mapped, so devices should
+/// not expect it to stay the same between two reads.
+pub struct DmaMapping<'a> {
+    space: &'a AddressSpace,
+    buf: NonNull<u8>,
+    len: usize,
+    is_write: bool,
+    access_len: usize,
+}
+
+impl<'a> DmaMapping<'a> {
+    fn new(space: &'a AddressSpace, addr: hwaddr, len: hwaddr, is_write: bool) -> Option<Self> {
+        fence(Ordering::SeqCst);
+        let mut plen = len;
+        // SAFETY: plen is updated with the length of the mapping, which
+        // is then valid until address_space_unmap()
+        let buf = unsafe {
+            bindings::address_space_map(
+                space.as_mut_ptr(),
+                addr,
+                addr_of_mut!(plen),
+                is_write,
+                unspecified_attrs(),
+            )
+        };
+        let buf = NonNull::new(buf.cast::<u8>())?;
+        // the mapping is at most as long as requested, and fits in memory
+        let mapped_len = plen as usize;
+        Some(DmaMapping {
+            space,
+            buf,
+            len: mapped_len,
+            is_write,
+            access_len: mapped_len,
+        })
+    }
+
+    /// Return the length of the mapping, which can be shorter than the
+    /// requested length.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return whether the mapping is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Panic unless `len` bytes at `offset` are within the mapping.
+    fn check_range(&self, offset: usize, len: usize) {
+        assert!(
+            offset <= self.len && len <= self.len - offset,
+            "access past the end of the mapping"
+        );
+    }
+
+    /// Copy the `buf.len()` bytes at `offset` within the mapping to `buf`.
+    ///
+    /// The guest and other devices can access the memory while it is
+    /// mapped, so it is only ever copied, and never exposed as a slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range does not fit in the mapping.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) {
+        self.check_range(offset, buf.len());
+        // SAFETY: the mapping is valid for `len` bytes until it is dropped,
+        // and `buf` cannot be part of it
+        unsafe {
+            ptr::copy_nonoverlapping(self.buf.as_ptr().add(offset), buf.as_mut_ptr(), buf.len());
+        }
+    }
+
+    /// Copy `data` to the mapping, starting at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the memory was mapped with
+    /// [`map_read`](AddressSpace::map_read), or if the range does not fit
+    /// in the mapping.
+    pub fn write(&mut self, offset: usize, data: &[u8]) {
+        assert!(self.is_write, "memory was mapped for reading");
+        self.check_range(offset, data.len());
+        // SAFETY: as above
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.buf.as_ptr().add(offset), data.len());
+        }
+    }
+
+    /// Record that only the first `access_len` bytes of the mapping were
+    /// accessed.  By default, the whole mapping is assumed to be accessed;
+    /// for writes, only the bytes that were accessed are marked dirty and
+    /// copied back from a bounce buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `access_len` is longer than the mapping.
+    pub fn set_access_len(&mut self, access_len: usize) {
+        assert!(access_len <= self.len);
+        self.access_len = access_len;
+    }
+}
+
+impl fmt::Debug for DmaMapping<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DmaMapping")
+            .field("buf", &self.buf)
+            .field("len", &self.len)
+            .field("is_write", &self.is_write)
+            .finish()
+    }
+}
+
+impl Drop for DmaMapping<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the buffer and length are those returned by
+        // address_space_map(), and the mapping is not used after this
+        unsafe {
+            bindings::address_space_unmap(
+                self.space.as_mut_ptr(),
+                self.buf.as_ptr().cast::<c_void>(),
+                self.len as hwaddr,
+                self.is_write,
+                self.access_len as hwaddr,
+            );
+        }
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// The memory for an [`OwnedAddressSpace`].  `address_space_destroy()`
+/// finishes the teardown of the address space in an RCU callback, so the
+/// memory is freed by a second RCU callback, which runs after it.
+#[repr(C)]
+struct AddressSpaceStorage {
+    // first, so that a pointer to it is also a pointer to the storage
+    rcu: bindings::rcu_head,
+    space: AddressSpace,
+}
+
+unsafe extern "C" fn rust_address_space_free(rcu: *mut bindings::rcu_head) {
+    abort_on_panic(|| {
+        let storage = NonNull::new(rcu).unwrap().cast::<AddressSpaceStorage>();
+        // SAFETY: the storage was leaked by AddressSpace::new(), and the
+        // address space is not used anymore
+        unsafe {
+            drop(Box::from_raw(storage.as_ptr()));
+        }
+    });
+}
+
+/// An address space created with [`AddressSpace::new`].  The address space
+/// is destroyed, and the reference to its root region released, when the
+/// `OwnedAddressSpace` is dropped.
+pub struct OwnedAddressSpace(NonNull<AddressSpaceStorage>);
+
+impl Deref for OwnedAddressSpace {
+    type Target = AddressSpace;
+
+    fn deref(&self) -> &AddressSpace {
+        // SAFETY: the address space is alive until self is dropped
+        unsafe { &(*self.0.as_ptr()).space }
+    }
+}
+
+impl fmt::Debug for OwnedAddressSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("OwnedAddressSpace").field(&self.0).finish()
+    }
+}
+
+impl Drop for OwnedAddressSpace {
+    fn drop(&mut self) {
+        assert!(bql_locked());
+        let storage = self.0.as_ptr();
+        // SAFETY: RCU callbacks run in the order in which they are queued,
+        // so the storage is freed after the address space is torn down
+        unsafe {
+            bindings::address_space_destroy(addr_of_mut!((*storage).space));
+            bindings::call_rcu1(addr_of_mut!((*storage).rcu), Some(rust_address_space_free));
+        }
+    }
+}
+
+/// Return the address space of the system bus, i.e. the memory that
+/// is seen by devices that do not sit behind an IOMMU.
+pub fn address_space_memory() -> &'static AddressSpace {
+    // SAFETY: the address space is created at startup and never freed;
+    // it is only modified through the memory API, which takes care of
+    // concurrent accesses
+    unsafe { &*addr_of_mut!(bindings::address_space_memory) }
+}
+
+/// Fill `buf` with the contents of guest physical memory at `addr`, as
+/// `cpu_physical_memory_read()` does, but report whether the access
+/// succeeded.  Unlike [`AddressSpace::read`], the access is not ordered
+/// with respect to other memory accesses.
+pub fn phys_mem_read(addr: hwaddr, buf: &mut [u8]) -> Result<(), DmaError> {
+    // SAFETY: the buffer is valid for `buf.len()` bytes, and is only
+    // written because is_write is false
+    let result = unsafe {
+        bindings::address_space_rw(
+            address_space_memory().as_mut_ptr(),
+            addr,
+            unspecified_attrs(),
+            buf.as_mut_ptr().cast::<c_void>(),
+            buf.len() as hwaddr,
+            false,
+        )
+    };
+    DmaError::check(result)
+}
+
+/// Write the contents of `buf` to guest physical memory at `addr`, as
+/// `cpu_physical_memory_write()` does, but report whether the access
+/// succeeded.  Unlike [`AddressSpace::write`], the access is not ordered
+/// with respect to other memory accesses.
+pub fn phys_mem_write(addr: hwaddr, buf: &[u8]) -> Result<(), DmaError> {
+    // SAFETY: the buffer is valid for `buf.len()` bytes, and is only read
+    // because is_write is true
+    let result = unsafe {
+        bindings::address_space_rw(
+            address_space_memory().as_mut_ptr(),
+            addr,
+            unspecified_attrs(),
+            buf.as_ptr().cast::<c_void>() as *mut c_void,
+            buf.len() as hwaddr,
+            true,
+        )
+    };
+    DmaError::check(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memtx_result() {
+        assert_eq!(DmaError::check(bindings::MEMTX_OK), Ok(()));
+        assert_eq!(
+            DmaError::check(bindings::MEMTX_ERROR),
+            Err(DmaError::DeviceError)
+        );
+        assert_eq!(
+            DmaError::check(bindings::MEMTX_DECODE_ERROR),
+            Err(DmaError::Decode)
+        );
+        assert_eq!(
+            DmaError::check(bindings::MEMTX_ACCESS_ERROR),
+            Err(DmaError::Access)
+        );
+        assert_eq!(
+            DmaError::check(bindings::MEMTX_ERROR | bindings::MEMTX_DECODE_ERROR),
+            Err(DmaError::Decode)
+        );
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Error reporting for QEMU Rust code.
+//!
+//! QEMU functions that can fail take an `Error **errp` argument and store
+//! a newly-allocated `Error` object in it on failure.  In Rust, fallible
+//! functions instead return a [`Result`], whose error type wraps the
+//! C object.  The conversion to `errp` happens at the boundary between
+//! Rust and C, for example in the `realize` callback of a device.
+
+use std::{
+    ffi::CStr,
+    fmt,
+    mem::ManuallyDrop,
+    os::raw::{c_char, c_int},
+    panic::Location,
+    ptr::{self, addr_of, addr_of_mut, NonNull},
+};
+
+use crate::{bindings, c_str};
+
+/// An owned QEMU `Error` object.
+///
+/// The C object is freed when the `Error` is dropped, unless ownership
+/// was passed back to C code.
+pub struct Error(NonNull<bindings::Error>);
+
+/// A `Result` whose error type is a QEMU [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Create a new generic error with the given message.  The line of
+    /// the caller is recorded in the error.
+    ///
+    /// To record the source file as well, and to format the message,
+    /// use [`error_setg!`](crate::error_setg).
+    #[track_caller]
+    pub fn new(msg: &str) -> Self {
+        // The C object stores the source file name by reference, so it
+        // cannot point to the non-NUL-terminated string in `Location`.
+        let src = c_str!("<rust>");
+        Self::with_location(msg, src, Location::caller().line(), src)
+    }
+
+    /// Create a new generic error, recording `src`, `line` and `func` as
+    /// its location.  This is used by [`error_setg!`](crate::error_setg).
+    #[doc(hidden)]
+    pub fn with_location(msg: &str, src: &'static CStr, line: u32, func: &'static CStr) -> Self {
+        let fmt = c_str!("%.*s");
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the format string consumes exactly the two arguments
+        // that follow it, and `msg` is valid for `msg.len()` bytes.
+        unsafe {
+            bindings::error_setg_internal(
+                addr_of_mut!(err),
+                src.as_ptr(),
+                line as c_int,
+                func.as_ptr(),
+                fmt.as_ptr(),
+                msg.len() as c_int,
+                msg.as_ptr().cast::<c_char>(),
+            );
+        }
+        Error(NonNull::new(err).unwrap())
+    }
+
+    /// Add `hint` to the explanation that is printed after the message
+    /// of the error.  The hint should end with a newline.
+    pub fn append_hint(&mut self, hint: &str) {
+        let err = self.0.as_ptr();
+        // SAFETY: `err` is a valid error object
+        unsafe {
+            append_hint(addr_of!(err), hint);
+        }
+    }
+
+    /// Return the human-readable message of the error.
+    pub fn message(&self) -> &CStr {
+        // SAFETY: the message lives as long as the error object
+        unsafe { CStr::from_ptr(bindings::error_get_pretty(self.0.as_ptr())) }
+    }
+
+    /// Convert the error returned by a C function into a `Result`.  If
+    /// `err` is null the function succeeded and `value` is returned.
+    ///
+    /// # Safety
+    ///
+    /// `err` must be null or point to an `Error` that was not freed; the
+    /// `Error` is owned by the result.
+    pub(crate) unsafe fn err_or_else<T>(err: *mut bindings::Error, value: T) -> Result<T> {
+        match NonNull::new(err) {
+            None => Ok(value),
+            Some(err) => Err(Error(err)),
+        }
+    }
+
+    /// Pass ownership of `err` to `errp`, as in `error_propagate()`.
+    /// If `errp` is null, or it already holds an error, `err` is freed.
+    ///
+    /// # Safety
+    ///
+    /// `errp` must be valid as the destination argument of
+    /// `error_propagate()`; in particular it can be null or one of
+    /// `&error_abort` and `&error_fatal`.
+    pub unsafe fn setg(errp: *mut *mut bindings::Error, err: Self) {
+        let err = ManuallyDrop::new(err);
+        // SAFETY: error_propagate() takes ownership of the object
+        unsafe {
+            bindings::error_propagate(errp, err.0.as_ptr());
+        }
+    }
+}
+
+/// Convert the result of a Rust function to the C convention, storing
+/// the error if any in `errp`.  This is meant for callbacks that are
+/// invoked by C code with the BQL held, such as `realize`.
+///
+/// Return `Some` with the successful value, or `None` after
+/// the error has been passed to `errp`.
+///
+/// # Safety
+///
+/// `errp` must be valid as the destination argument of
+/// `error_propagate()`; see [`Error::setg`].
+pub unsafe fn bql_error_propagate<T>(
+    result: Result<T>,
+    errp: *mut *mut bindings::Error,
+) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            // SAFETY: forwarded to the caller
+            unsafe {
+                Error::setg(errp, err);
+            }
+            None
+        }
+    }
+}
+
+/// Add `hint` to the explanation of the error in `*errp`, as in
+/// `error_append_hint()`.  This is used by
+/// [`error_append_hint!`](crate::error_append_hint).
+///
+/// # Safety
+///
+/// `errp` must be null or point to a valid error; it must not be
+/// `&error_abort` or `&error_fatal`.
+#[doc(hidden)]
+pub unsafe fn append_hint(errp: *const *mut bindings::Error, hint: &str) {
+    let fmt = c_str!("%.*s");
+    // SAFETY: the format string consumes exactly the two arguments that
+    // follow it; the validity of `errp` is forwarded to the caller
+    unsafe {
+        bindings::error_append_hint(
+            errp,
+            fmt.as_ptr(),
+            hint.len() as c_int,
+            hint.as_ptr().cast::<c_char>(),
+        );
+    }
+}
+
+/// Store a new error in `errp`, formatting the message with
+/// [`format!`].  The source file, line and module of the caller are
+/// recorded in the error.  As with [`Error::setg`], if `errp` already
+/// holds an error, the new one is freed.
+///
+/// The macro must be invoked in an `unsafe` block, because `errp` must
+/// be valid as the destination argument of `error_propagate()`:
+///
+/// ```ignore
+/// unsafe {
+///     error_setg!(errp, "value {} out of range", x);
+/// }
+/// ```
+///
+/// Functions that return a [`Result`] can instead create the error with
+/// `Error::new(&format!(...))`.
+#[macro_export]
+macro_rules! error_setg {
+    ($errp:expr, $($arg:tt)+) => {
+        $crate::error::Error::setg(
+            $errp,
+            $crate::error::Error::with_location(
+                &::std::format!($($arg)+),
+                $crate::c_str!(::core::file!()),
+                ::core::line!(),
+                $crate::c_str!(::core::module_path!()),
+            ),
+        )
+    };
+}
+
+/// Return an error from the enclosing function, which must return a
+/// [`Result`], unless `cond` is true.  The message is formatted with
+/// [`format!`], and the location of the caller is recorded in the error
+/// as in [`error_setg!`](crate::error_setg).
+///
+/// This is handy to validate the properties of a device in its `realize`
+/// function, where all of them have been set:
+///
+/// ```ignore
+/// fn realize(&self) -> Result<()> {
+///     ensure!(self.size.is_power_of_two(), "size {} is not a power of two", self.size);
+///     ensure!(
+///         self.size >= self.block_size,
+///         "size {} is smaller than block-size {}",
+///         self.size,
+///         self.block_size
+///     );
+///     Ok(())
+/// }
+/// ```
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return ::core::result::Result::Err($crate::error::Error::with_location(
+                &::std::format!($($arg)+),
+                $crate::c_str!(::core::file!()),
+                ::core::line!(),
+                $crate::c_str!(::core::module_path!()),
+            )
+            .into());
+        }
+    };
+}
+
+/// Add a hint, formatted with [`format!`], to the error in `errp`.  The
+/// hint is printed after the message of the error, and should end with
+/// a newline.
+///
+/// As with `error_append_hint()` in C, `errp` must be null or point to an
+/// error; it must not be `&error_abort` or `&error_fatal`.  Therefore,
+/// the macro must be invoked in an `unsafe` block.
+#[macro_export]
+macro_rules! error_append_hint {
+    ($errp:expr, $($arg:tt)+) => {
+        $crate::error::append_hint($errp, &::std::format!($($arg)+))
+    };
+}
+
+impl Drop for Error {
+    fn drop(&mut self) {
+        unsafe {
+            bindings::error_free(self.0.as_ptr());
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Error").field(&self.message()).finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message().to_string_lossy())
+    }
+}
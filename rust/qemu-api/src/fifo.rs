@@ -0,0 +1,121 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for `Fifo8`, the byte FIFO that models the receive and transmit
+//! queues of many devices.
+//!
+//! A [`Fifo8`] has a fixed capacity, chosen when it is created:
+//!
+//! ```ignore
+//! let mut rx = Fifo8::new(16);
+//! if rx.is_full() {
+//!     self.overrun = true;
+//! } else {
+//!     rx.push(byte);
+//! }
+//! let next = rx.pop();
+//! ```
+//!
+//! The contents are migrated with [`vmstate_fifo8!`](crate::vmstate_fifo8);
+//! the destination must have created a FIFO with the same capacity.
+
+use std::{mem::MaybeUninit, ptr::addr_of_mut};
+
+use crate::bindings;
+
+/// A wrapper around the C `Fifo8` struct, which owns its buffer.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct Fifo8(bindings::Fifo8);
+
+// SAFETY: the FIFO owns its buffer and is only accessed through `&mut self`
+unsafe impl Send for Fifo8 {}
+unsafe impl Sync for Fifo8 {}
+
+impl Fifo8 {
+    /// Create an empty FIFO that can hold up to `capacity` bytes.
+    pub fn new(capacity: u32) -> Self {
+        let mut fifo = MaybeUninit::<bindings::Fifo8>::uninit();
+        // SAFETY: fifo8_create initializes all the fields
+        unsafe {
+            bindings::fifo8_create(fifo.as_mut_ptr(), capacity);
+            Fifo8(fifo.assume_init())
+        }
+    }
+
+    /// Return the maximum number of bytes in the FIFO.
+    pub const fn capacity(&self) -> u32 {
+        self.0.capacity
+    }
+
+    /// Return the number of bytes in the FIFO.
+    pub const fn len(&self) -> u32 {
+        self.0.num
+    }
+
+    /// Return the number of bytes that can be pushed before the FIFO is
+    /// full.
+    pub const fn num_free(&self) -> u32 {
+        self.0.capacity - self.0.num
+    }
+
+    /// Return true if the FIFO holds no data.
+    pub const fn is_empty(&self) -> bool {
+        self.0.num == 0
+    }
+
+    /// Return true if no more bytes can be pushed into the FIFO.
+    pub const fn is_full(&self) -> bool {
+        self.0.num == self.0.capacity
+    }
+
+    /// Add a byte at the end of the FIFO.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the FIFO is full.
+    pub fn push(&mut self, data: u8) {
+        assert!(!self.is_full(), "push to a full Fifo8");
+        // SAFETY: the FIFO was created by new() and has room for one byte
+        unsafe {
+            bindings::fifo8_push(addr_of_mut!(self.0), data);
+        }
+    }
+
+    /// Remove the byte at the head of the FIFO and return it, or
+    /// return `None` if the FIFO is empty.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        // SAFETY: the FIFO was created by new() and is not empty
+        Some(unsafe { bindings::fifo8_pop(addr_of_mut!(self.0)) })
+    }
+
+    /// Return the byte at the head of the FIFO without removing it, or
+    /// `None` if the FIFO is empty.
+    pub fn peek(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        // SAFETY: the FIFO was created by new() and is not empty
+        Some(unsafe { bindings::fifo8_peek(addr_of_mut!(self.0)) })
+    }
+
+    /// Discard the contents of the FIFO.
+    pub fn reset(&mut self) {
+        // SAFETY: the FIFO was created by new()
+        unsafe {
+            bindings::fifo8_reset(addr_of_mut!(self.0));
+        }
+    }
+}
+
+impl Drop for Fifo8 {
+    fn drop(&mut self) {
+        // SAFETY: the FIFO was created by new() and not destroyed yet
+        unsafe {
+            bindings::fifo8_destroy(addr_of_mut!(self.0));
+        }
+    }
+}
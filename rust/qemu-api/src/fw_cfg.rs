@@ -0,0 +1,77 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Passing data to the firmware through the `fw_cfg` device.
+//!
+//! Boards and some devices add entries to the firmware configuration
+//! device, either as numbered items or as named files that the firmware
+//! finds through the file directory:
+//!
+//! ```ignore
+//! if let Some(fw_cfg) = fw_cfg_find() {
+//!     fw_cfg.add_file(c_str!("etc/my-device/config"), config.to_vec());
+//!     fw_cfg.add_u32(FW_CFG_NB_CPUS as u16, 4);
+//! }
+//! ```
+
+use std::{ffi::CStr, os::raw::c_void};
+
+pub use bindings::FWCfgState;
+
+use crate::{bindings, cell::bql_locked};
+
+/// Return the `fw_cfg` device of the machine, if it has one.
+pub fn fw_cfg_find() -> Option<&'static FWCfgState> {
+    assert!(bql_locked());
+    // SAFETY: the device, if any, lives as long as the machine
+    unsafe { bindings::fw_cfg_find().as_ref() }
+}
+
+impl FWCfgState {
+    /// Add a file called `name` whose contents are `data`, as in
+    /// `fw_cfg_add_file()`.
+    ///
+    /// The device only stores a pointer to the contents, so `data` is
+    /// leaked and stays alive as long as QEMU runs.  Files are never
+    /// removed from `fw_cfg`, therefore this is not a problem in practice.
+    /// QEMU exits if there is already a file with the same name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` does not fit in the file directory in the device,
+    /// or if `data` is larger than 4 GiB.
+    pub fn add_file(&self, name: &CStr, data: impl Into<Box<[u8]>>) {
+        assert!(bql_locked());
+        assert!(
+            name.to_bytes().len() < bindings::FW_CFG_MAX_FILE_PATH as usize,
+            "fw_cfg file name too long"
+        );
+        let data: &'static mut [u8] = Box::leak(data.into());
+        assert!(u32::try_from(data.len()).is_ok(), "fw_cfg file too large");
+        // SAFETY: the name is copied, and the data lives forever
+        unsafe {
+            bindings::fw_cfg_add_file(
+                self.as_mut_ptr(),
+                name.as_ptr(),
+                data.as_mut_ptr().cast::<c_void>(),
+                data.len(),
+            );
+        }
+    }
+
+    /// Add a 32-bit item with selector `key`, as in `fw_cfg_add_i32()`.
+    /// The firmware reads the value in little-endian format.
+    pub fn add_u32(&self, key: u16, value: u32) {
+        assert!(bql_locked());
+        // SAFETY: the value is copied into the device
+        unsafe {
+            bindings::fw_cfg_add_i32(self.as_mut_ptr(), key, value);
+        }
+    }
+
+    /// Return a raw pointer to the C `FWCfgState`, for use with C
+    /// functions.
+    pub const fn as_mut_ptr(&self) -> *mut FWCfgState {
+        self as *const FWCfgState as *mut FWCfgState
+    }
+}
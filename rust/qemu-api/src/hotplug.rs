@@ -0,0 +1,157 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for the QOM `HotplugHandler` interface.
+//!
+//! The device or machine that controls a hotpluggable bus implements the
+//! `HotplugHandler` interface, and is notified when devices are plugged into
+//! the bus or removed from it.  A Rust type does so by listing the interface
+//! in [`ObjectImpl::INTERFACES`](crate::qom::ObjectImpl::INTERFACES),
+//! implementing [`HotplugHandlerImpl`], and calling
+//! [`hotplug_handler_class_init`] from its `class_init`:
+//!
+//! ```ignore
+//! impl ObjectImpl for MyBridgeState {
+//!     type ParentType = DeviceState;
+//!     const INTERFACES: &'static [InterfaceInfo] = interfaces![TYPE_HOTPLUG_HANDLER];
+//! }
+//!
+//! impl ClassInitImpl<MyBridgeClass> for MyBridgeState {
+//!     fn class_init(klass: &mut MyBridgeClass) {
+//!         <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+//!         hotplug_handler_class_init::<Self>(&mut klass.parent_class.parent_class);
+//!     }
+//! }
+//! ```
+
+use std::ptr::NonNull;
+
+pub use bindings::{HotplugHandler, HotplugHandlerClass, TYPE_HOTPLUG_HANDLER};
+
+use crate::{
+    bindings,
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    error::{bql_error_propagate, Result},
+    qdev::DeviceState,
+    qom::{ClassInitImpl, ObjectClass},
+};
+
+/// Trait providing the contents of [`HotplugHandlerClass`].  Each callback
+/// receives the handler and the device that is plugged or unplugged.
+pub trait HotplugHandlerImpl {
+    /// If not `None`, this is called at the end of the realization of a
+    /// device on a bus that is controlled by the handler.  An error makes
+    /// the realization fail.
+    const PLUG: Option<fn(&Self, &DeviceState) -> Result<()>> = None;
+
+    /// If not `None`, this is called by `device_del` to start the removal
+    /// of a device.  The removal can complete asynchronously, for example
+    /// once the guest has acknowledged it, by calling [`UNPLUG`] and
+    /// unparenting the device.  Because the callbacks only get shared
+    /// references, a device can also start its own removal with
+    /// [`DeviceMethods::unplug`](crate::qdev::DeviceMethods::unplug),
+    /// which calls back into `UNPLUG_REQUEST`.
+    ///
+    /// If `None`, `device_del` calls [`UNPLUG`] and unparents the device
+    /// right away.
+    ///
+    /// [`UNPLUG`]: HotplugHandlerImpl::UNPLUG
+    const UNPLUG_REQUEST: Option<fn(&Self, &DeviceState) -> Result<()>> = None;
+
+    /// If not `None`, this is called when a device is removed from a bus
+    /// that is controlled by the handler.
+    const UNPLUG: Option<fn(&Self, &DeviceState) -> Result<()>> = None;
+}
+
+/// Call `f` with the handler and the plugged device, passing the error if
+/// any to `errp`.
+///
+/// # Safety
+///
+/// `handler` must point to an instance of `T`, and `errp` must be valid
+/// as the destination argument of `error_propagate()`.
+unsafe fn hotplug_call<T: HotplugHandlerImpl>(
+    f: fn(&T, &DeviceState) -> Result<()>,
+    handler: *mut HotplugHandler,
+    dev: *mut DeviceState,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let handler = NonNull::new(handler).unwrap().cast::<T>();
+        let dev = NonNull::new(dev).unwrap();
+        // SAFETY: the handler implements the interface, so it is a `T`; the
+        // device is valid for the duration of the callback
+        let result = f(unsafe { handler.as_ref() }, unsafe { dev.as_ref() });
+        unsafe {
+            bql_error_propagate(result, errp);
+        }
+    })
+}
+
+unsafe extern "C" fn rust_hotplug_plug_fn<T: HotplugHandlerImpl>(
+    handler: *mut HotplugHandler,
+    dev: *mut DeviceState,
+    errp: *mut *mut bindings::Error,
+) {
+    // SAFETY: forwarded from QOM
+    unsafe { hotplug_call(T::PLUG.unwrap(), handler, dev, errp) }
+}
+
+unsafe extern "C" fn rust_hotplug_unplug_request_fn<T: HotplugHandlerImpl>(
+    handler: *mut HotplugHandler,
+    dev: *mut DeviceState,
+    errp: *mut *mut bindings::Error,
+) {
+    // SAFETY: forwarded from QOM
+    unsafe { hotplug_call(T::UNPLUG_REQUEST.unwrap(), handler, dev, errp) }
+}
+
+unsafe extern "C" fn rust_hotplug_unplug_fn<T: HotplugHandlerImpl>(
+    handler: *mut HotplugHandler,
+    dev: *mut DeviceState,
+    errp: *mut *mut bindings::Error,
+) {
+    // SAFETY: forwarded from QOM
+    unsafe { hotplug_call(T::UNPLUG.unwrap(), handler, dev, errp) }
+}
+
+impl<T> ClassInitImpl<HotplugHandlerClass> for T
+where
+    T: HotplugHandlerImpl,
+{
+    fn class_init(hc: &mut HotplugHandlerClass) {
+        if <T as HotplugHandlerImpl>::PLUG.is_some() {
+            hc.plug = Some(rust_hotplug_plug_fn::<T>);
+        }
+        if <T as HotplugHandlerImpl>::UNPLUG_REQUEST.is_some() {
+            hc.unplug_request = Some(rust_hotplug_unplug_request_fn::<T>);
+        }
+        if <T as HotplugHandlerImpl>::UNPLUG.is_some() {
+            hc.unplug = Some(rust_hotplug_unplug_fn::<T>);
+        }
+    }
+}
+
+/// Install the [`HotplugHandlerImpl`] callbacks of `T` in the
+/// `HotplugHandler` interface of `klass`, which is the class of `T`.
+///
+/// # Panics
+///
+/// Panics if `TYPE_HOTPLUG_HANDLER` is not among the
+/// [`INTERFACES`](crate::qom::ObjectImpl::INTERFACES) of `T`.
+pub fn hotplug_handler_class_init<T: HotplugHandlerImpl>(klass: &mut ObjectClass) {
+    // SAFETY: interfaces are set up before class_init is called, and the
+    // result of the cast is either NULL or the interface's class struct
+    let hc = unsafe {
+        NonNull::new(bindings::object_class_dynamic_cast(
+            klass,
+            TYPE_HOTPLUG_HANDLER.as_ptr().cast(),
+        ))
+        .expect("type does not implement TYPE_HOTPLUG_HANDLER")
+        .cast::<HotplugHandlerClass>()
+        .as_mut()
+    };
+    <T as ClassInitImpl<HotplugHandlerClass>>::class_init(hc);
+}
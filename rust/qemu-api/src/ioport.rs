@@ -0,0 +1,257 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for port I/O regions.
+//!
+//! Devices modeled after x86 hardware are accessed through the I/O address
+//! space, with the `in` and `out` instructions, rather than through memory.
+//! Such a device implements [`PortIoOps`] and embeds a [`PortIoRegion`],
+//! which the board or the device itself maps at a port in the system I/O
+//! space:
+//!
+//! ```ignore
+//! let owner = addr_of_mut!(*self);
+//! // SAFETY: the region is a field of `owner`
+//! unsafe { self.io.init(owner, c_str!("my-uart-io"), 8) };
+//! self.io.map(0x3f8);
+//! ```
+//!
+//! Port I/O accesses are little-endian and at most 4 bytes wide.
+
+use std::{
+    ffi::CStr,
+    ops::Deref,
+    os::raw::{c_uint, c_void},
+    ptr::NonNull,
+};
+
+use crate::{
+    bindings::{self, device_endian},
+    bql::assert_bql_locked,
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    memory::{hwaddr, MemoryRegion, MmioConfig},
+    prelude::*,
+    zeroable::Zeroable,
+};
+
+/// The size of the I/O address space.
+const IO_SPACE_SIZE: u64 = 0x10000;
+
+/// Trait for devices that handle port I/O accesses.  `offset` is relative
+/// to the first port of the region, `size` is 1, 2 or 4 bytes as for
+/// `inb`/`outb`, `inw`/`outw` and `inl`/`outl`, and only the low `size`
+/// bytes of `value` are significant.
+///
+/// As with [`MmioOps`](crate::memory::MmioOps), both callbacks receive a
+/// shared reference, and the state that `port_out` modifies must use
+/// interior mutability.
+pub trait PortIoOps: Sized {
+    /// The constraints on the accesses to the region.  The endianness
+    /// should not be changed; the valid and implemented sizes cannot be
+    /// larger than 4.
+    const CONFIG: MmioConfig = MmioConfig::DEFAULT
+        .with_endianness(device_endian::DEVICE_LITTLE_ENDIAN)
+        .with_valid_sizes(1, 4);
+
+    fn port_in(&self, offset: u16, size: u32) -> u32;
+    fn port_out(&self, offset: u16, size: u32, value: u32);
+}
+
+unsafe extern "C" fn portio_ops_read_cb<T: PortIoOps>(
+    opaque: *mut c_void,
+    addr: hwaddr,
+    size: c_uint,
+) -> u64 {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        T::port_in(unsafe { state.as_ref() }, addr as u16, size).into()
+    })
+}
+
+unsafe extern "C" fn portio_ops_write_cb<T: PortIoOps>(
+    opaque: *mut c_void,
+    addr: hwaddr,
+    data: u64,
+    size: c_uint,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        T::port_out(unsafe { state.as_ref() }, addr as u16, size, data as u32);
+    })
+}
+
+struct PortIoOpsHolder<T>(T);
+
+impl<T: PortIoOps> PortIoOpsHolder<T> {
+    const OPS: bindings::MemoryRegionOps = {
+        assert!(T::CONFIG.valid_max_access_size <= 4);
+        assert!(T::CONFIG.impl_max_access_size <= 4);
+        bindings::MemoryRegionOps {
+            read: Some(portio_ops_read_cb::<T>),
+            write: Some(portio_ops_write_cb::<T>),
+            endianness: T::CONFIG.endianness,
+            valid: bindings::MemoryRegionOps__bindgen_ty_1 {
+                min_access_size: T::CONFIG.valid_min_access_size,
+                max_access_size: T::CONFIG.valid_max_access_size,
+                unaligned: T::CONFIG.valid_unaligned,
+                ..Zeroable::ZERO
+            },
+            impl_: bindings::MemoryRegionOps__bindgen_ty_2 {
+                min_access_size: T::CONFIG.impl_min_access_size,
+                max_access_size: T::CONFIG.impl_max_access_size,
+                unaligned: T::CONFIG.impl_unaligned,
+            },
+            ..Zeroable::ZERO
+        }
+    };
+}
+
+/// Return the `MemoryRegionOps` that forward accesses to `T`'s
+/// implementation of [`PortIoOps`].
+pub const fn portio_ops<T: PortIoOps>() -> &'static bindings::MemoryRegionOps {
+    &PortIoOpsHolder::<T>::OPS
+}
+
+/// Return the system I/O address space, i.e. the ports that are seen by
+/// the `in` and `out` instructions of the guest.
+pub fn system_io() -> &'static MemoryRegion {
+    // SAFETY: the region is created at startup and never freed;
+    // MemoryRegion is a transparent wrapper
+    unsafe { &*bindings::get_system_io().cast::<MemoryRegion>() }
+}
+
+/// A memory region whose accesses are handled by a [`PortIoOps`]
+/// implementation, to be embedded in the state of a device.  It can be
+/// used wherever a [`MemoryRegion`] is expected, for example to map it in
+/// a container other than [`system_io`].
+#[repr(transparent)]
+pub struct PortIoRegion(MemoryRegion);
+
+impl PortIoRegion {
+    /// Initialize the region as `size` ports, whose accesses are handled
+    /// by `owner`'s implementation of [`PortIoOps`].
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    /// The accesses to the region are dispatched to `owner`, so its
+    /// fields must be initialized before the region is mapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is larger than the I/O address space.
+    pub unsafe fn init<T: PortIoOps + IsA<Object>>(
+        &mut self,
+        owner: *mut T,
+        name: &CStr,
+        size: u32,
+    ) {
+        assert!(u64::from(size) <= IO_SPACE_SIZE);
+        // SAFETY: the ops are static, and the caller guarantees that the
+        // opaque is a `T` that outlives the region
+        unsafe {
+            bindings::memory_region_init_io(
+                self.0.as_mut_ptr(),
+                owner.cast::<Object>(),
+                portio_ops::<T>(),
+                owner.cast::<c_void>(),
+                name.as_ptr(),
+                size.into(),
+            );
+        }
+    }
+
+    /// Make the region visible to the guest starting at `port` of the
+    /// system I/O address space.  This is the counterpart of
+    /// `isa_register_ioport()` for devices that are not on an ISA bus.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region extends past the end of the I/O address
+    /// space, or if it has no owner.
+    pub fn map(&self, port: u16) {
+        assert!(bql_locked());
+        // SAFETY: reading the size has no side effects
+        let size = unsafe { bindings::memory_region_size(self.0.as_mut_ptr()) };
+        assert!(u64::from(port) + size <= IO_SPACE_SIZE);
+        system_io().add_subregion(port.into(), &self.0);
+    }
+
+    /// Remove the region, which was mapped with
+    /// [`map`](PortIoRegion::map), from the system I/O address space.
+    pub fn unmap(&self) {
+        system_io().del_subregion(&self.0);
+    }
+}
+
+impl Deref for PortIoRegion {
+    type Target = MemoryRegion;
+
+    fn deref(&self) -> &MemoryRegion {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::addr_of_mut;
+
+    use super::*;
+    use crate::cell::{bql_start_test, BqlCell};
+
+    /// Eight byte-wide registers, accessed through port I/O.
+    #[derive(Default)]
+    struct ByteRegs {
+        regs: BqlCell<[u8; 8]>,
+    }
+
+    impl PortIoOps for ByteRegs {
+        fn port_in(&self, offset: u16, size: u32) -> u32 {
+            let start = usize::from(offset);
+            let mut bytes = [0; 4];
+            bytes[..size as usize].copy_from_slice(&self.regs.get()[start..start + size as usize]);
+            u32::from_le_bytes(bytes)
+        }
+
+        fn port_out(&self, offset: u16, size: u32, value: u32) {
+            let start = usize::from(offset);
+            let mut regs = self.regs.get();
+            regs[start..start + size as usize]
+                .copy_from_slice(&value.to_le_bytes()[..size as usize]);
+            self.regs.set(regs);
+        }
+    }
+
+    /// Check the `MemoryRegionOps` generated from a `PortIoOps`
+    /// implementation, and perform `outb`/`inb` and `outl`/`inl` accesses.
+    /// As in the tests of the memory module, the accesses go straight to
+    /// the callbacks.
+    #[test]
+    fn test_portio_ops() {
+        bql_start_test();
+        let ops = portio_ops::<ByteRegs>();
+        assert_eq!(ops.endianness, device_endian::DEVICE_LITTLE_ENDIAN);
+        assert_eq!(ops.valid.min_access_size, 1);
+        assert_eq!(ops.valid.max_access_size, 4);
+
+        let mut dev = ByteRegs::default();
+        let opaque = addr_of_mut!(dev).cast::<c_void>();
+        // SAFETY: the opaque points to a ByteRegs
+        unsafe {
+            // outb 0x5a to port 1, then inb
+            ops.write.unwrap()(opaque, 1, 0x5a, 1);
+            assert_eq!(ops.read.unwrap()(opaque, 1, 1), 0x5a);
+            // outl to port 4; the value is split across the registers
+            ops.write.unwrap()(opaque, 4, 0x1234_5678, 4);
+            assert_eq!(ops.read.unwrap()(opaque, 4, 1), 0x78);
+            assert_eq!(ops.read.unwrap()(opaque, 6, 2), 0x1234);
+            assert_eq!(ops.read.unwrap()(opaque, 4, 4), 0x1234_5678);
+        }
+        assert_eq!(dev.regs.get(), [0, 0x5a, 0, 0, 0x78, 0x56, 0x34, 0x12]);
+    }
+}
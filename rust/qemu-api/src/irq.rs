@@ -33,6 +33,14 @@
 ///
 /// Interrupt sources can only be triggered under the Big QEMU Lock; `BqlCell`
 /// allows access from whatever thread has it.
+///
+/// An `InterruptSource` can also refer directly to the input of a device,
+/// as returned by [`DeviceMethods::get_gpio_in`]; a board passes it to
+/// [`DeviceMethods::connect_gpio_out`] to wire another device's output to
+/// that input.
+///
+/// [`DeviceMethods::get_gpio_in`]: crate::qdev::DeviceMethods::get_gpio_in
+/// [`DeviceMethods::connect_gpio_out`]: crate::qdev::DeviceMethods::connect_gpio_out
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct InterruptSource<T = bool>
@@ -75,9 +83,28 @@ pub fn set(&self, level: T) {
         }
     }
 
+    /// Return whether the board has connected the source to a sink.
+    pub fn is_connected(&self) -> bool {
+        !self.cell.get().is_null()
+    }
+
     pub(crate) const fn as_ptr(&self) -> *mut *mut IRQState {
         self.cell.as_ptr()
     }
+
+    /// Return the sink that the source is connected to, or NULL.
+    pub(crate) fn sink(&self) -> *mut IRQState {
+        self.cell.get()
+    }
+}
+
+impl InterruptSource {
+    pub(crate) const fn from_raw(irq: *mut IRQState) -> Self {
+        InterruptSource {
+            cell: BqlCell::new(irq),
+            _marker: PhantomData,
+        }
+    }
 }
 
 impl Default for InterruptSource {
@@ -7,28 +7,63 @@
 #[rustfmt::skip]
 pub mod bindings;
 
+// Code generated for other crates, such as the trace events that the unit
+// tests include, refers to this crate as `::qemu_api`.
+#[cfg(test)]
+extern crate self as qemu_api;
+
 // preserve one-item-per-"use" syntax, it is clearer
 // for prelude-like modules
 #[rustfmt::skip]
 pub mod prelude;
 
 pub mod assertions;
+pub mod bh;
 pub mod bitops;
+pub mod block;
+pub mod bql;
 pub mod c_str;
 pub mod callbacks;
 pub mod cell;
+pub mod chardev;
+pub mod dma;
+pub mod error;
+pub mod fifo;
+pub mod fw_cfg;
+pub mod hotplug;
+pub mod ioport;
 pub mod irq;
+pub mod log;
+pub mod machine;
+pub mod memory;
 pub mod module;
+pub mod nic;
 pub mod offset_of;
+pub mod pci;
+pub mod prop;
+pub mod ptimer;
 pub mod qdev;
+pub mod qmp;
 pub mod qom;
+pub mod regaccess;
+pub mod replay;
+pub mod reset;
+pub mod runstate;
+pub mod savevm;
 pub mod sysbus;
+#[cfg(feature = "test-utils")]
+pub mod test_util;
+pub mod timer;
+pub mod trace;
+pub mod user_creatable;
 pub mod vmstate;
 pub mod zeroable;
 
+pub use error::{Error, Result};
+
 use std::{
     alloc::{GlobalAlloc, Layout},
-    os::raw::c_void,
+    os::raw::{c_char, c_void},
 };
 
 #[cfg(HAVE_GLIB_WITH_ALIGNED_ALLOC)]
@@ -47,9 +82,16 @@ fn g_aligned_alloc0(
     fn qemu_vfree(ptr: *mut c_void);
 }
 
+// The bindings only cover QEMU's own headers; the glib functions that
+// the crate calls are declared here.
 extern "C" {
-    fn g_malloc0(n_bytes: bindings::gsize) -> bindings::gpointer;
-    fn g_free(mem: bindings::gpointer);
+    pub(crate) fn g_malloc0(n_bytes: bindings::gsize) -> bindings::gpointer;
+    pub(crate) fn g_free(mem: bindings::gpointer);
+    pub(crate) fn g_strndup(str: *const c_char, n: bindings::gsize) -> *mut c_char;
+    pub(crate) fn g_string_free(
+        string: *mut bindings::GString,
+        free_segment: bindings::gboolean,
+    ) -> *mut c_char;
 }
 
 /// An allocator that uses the same allocator as QEMU in C.
@@ -0,0 +1,156 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Logging of guest errors and unimplemented features.
+//!
+//! These macros are the Rust counterpart of `qemu_log_mask()`.  They take
+//! the same arguments as [`format!`], but the message is only formatted and
+//! printed if the corresponding `-d` option is enabled:
+//!
+//! ```ignore
+//! log_guest_error!("pl011_read: Bad offset {:#x}", offset);
+//! log_unimp!("pl011: DMA not implemented");
+//! ```
+//!
+//! Unlike in C, the message does not end with a newline; the newline is
+//! added by the macros, as with `println!`.
+//!
+//! Accesses to an offset that a device does not decode are common enough
+//! that they have their own macro, which prefixes the message with the QOM
+//! type name of the device that was accessed:
+//!
+//! ```ignore
+//! impl MmioOps for PL011State {
+//!     fn read(&self, offset: hwaddr, size: u32) -> u64 {
+//!         match offset {
+//!             // ...
+//!             _ => {
+//!                 bad_offset!(self, offset, "read");
+//!                 0
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::{ffi::CString, ptr::addr_of};
+
+pub use bindings::{LOG_GUEST_ERROR, LOG_UNIMP};
+
+use crate::{bindings, bindings::hwaddr, c_str};
+
+/// Return whether messages in any of the categories of `mask` are
+/// printed to the log.
+pub fn log_enabled(mask: u32) -> bool {
+    // SAFETY: the log level is an int that is only written while the
+    // command line is parsed, or by the monitor
+    let level = unsafe { addr_of!(bindings::qemu_loglevel).read() };
+    level as u32 & mask != 0
+}
+
+/// Build the C string that is passed to `qemu_log()`: NUL characters,
+/// which C would take as the end of the message, are printed as `\0`,
+/// and a newline is added at the end.
+fn log_message(msg: &str) -> CString {
+    let mut bytes = Vec::with_capacity(msg.len() + 1);
+    for &b in msg.as_bytes() {
+        if b == 0 {
+            bytes.extend_from_slice(b"\\0");
+        } else {
+            bytes.push(b);
+        }
+    }
+    bytes.push(b'\n');
+    // SAFETY: all the NUL bytes were replaced above
+    unsafe { CString::from_vec_unchecked(bytes) }
+}
+
+/// Print `msg` to the log, followed by a newline, regardless of the
+/// categories that are enabled.  This is used by [`log_mask!`], which
+/// should usually be preferred.
+pub fn log_write(msg: &str) {
+    let msg = log_message(msg);
+    let fmt = c_str!("%s");
+    // SAFETY: the format string consumes exactly the C string that follows
+    unsafe {
+        bindings::qemu_log(fmt.as_ptr(), msg.as_ptr());
+    }
+}
+
+/// Format a guest physical address or an offset in a memory region, as
+/// `"0x%" HWADDR_PRIx` does in C.
+pub fn fmt_hwaddr(addr: hwaddr) -> String {
+    format!("{:#x}", addr)
+}
+
+/// Print a message to the log if any of the categories in the mask, for
+/// example [`LOG_GUEST_ERROR`], are enabled.  The arguments after the
+/// mask are the same as for [`format!`].
+#[macro_export]
+macro_rules! log_mask {
+    ($mask:expr, $($arg:tt)+) => {
+        if $crate::log::log_enabled($mask) {
+            $crate::log::log_write(&::std::format!($($arg)+));
+        }
+    };
+}
+
+/// Report that the guest did something that is invalid, for example
+/// accessing a register that does not exist.  The message is printed if
+/// `-d guest_errors` is enabled.
+#[macro_export]
+macro_rules! log_guest_error {
+    ($($arg:tt)+) => {
+        $crate::log_mask!($crate::log::LOG_GUEST_ERROR, $($arg)+)
+    };
+}
+
+/// Report that the guest used a feature of the device that is not
+/// implemented.  The message is printed if `-d unimp` is enabled.
+#[macro_export]
+macro_rules! log_unimp {
+    ($($arg:tt)+) => {
+        $crate::log_mask!($crate::log::LOG_UNIMP, $($arg)+)
+    };
+}
+
+/// Report a guest access to an offset that the device `dev` does not
+/// decode.  The message includes the QOM type name of `dev`, the offset,
+/// and a short description of the access, for example `"read"`, and is
+/// printed if `-d guest_errors` is enabled.
+///
+/// The type name is that of the object at run time, so a subclass that
+/// inherits the MMIO callbacks of its parent logs its own name.
+#[macro_export]
+macro_rules! bad_offset {
+    ($dev:expr, $offset:expr, $what:expr) => {
+        $crate::log_guest_error!(
+            "{}: {}: bad offset {}",
+            $crate::qom::ObjectMethods::typename(&$dev),
+            $what,
+            $crate::log::fmt_hwaddr($offset)
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_message() {
+        assert_eq!(
+            log_message("bad offset 0x40").as_bytes(),
+            b"bad offset 0x40\n"
+        );
+        assert_eq!(log_message("").as_bytes(), b"\n");
+        assert_eq!(log_message("a\0b").as_bytes(), b"a\\0b\n");
+    }
+
+    #[test]
+    fn test_fmt_hwaddr() {
+        assert_eq!(fmt_hwaddr(0), "0x0");
+        assert_eq!(fmt_hwaddr(0x40), "0x40");
+        assert_eq!(fmt_hwaddr(u64::MAX), "0xffffffffffffffff");
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Access to the machine that devices are part of.
+//!
+//! The board creates the machine object at `/machine` in the QOM tree,
+//! before any device is realized.  Devices can read its properties while
+//! they are realized, for example to adapt to the configuration of the
+//! board:
+//!
+//! ```ignore
+//! if machine_get_bool(c_str!("graphics")).unwrap_or(true) {
+//!     ...
+//! }
+//! ```
+
+use std::{ffi::CStr, ptr};
+
+use crate::{
+    bindings, c_str,
+    cell::bql_locked,
+    error::{Error, Result},
+    qom::{Object, ObjectType},
+};
+
+/// Return the machine, or `None` if it has not been created yet.  Unlike
+/// `qdev_get_machine()` in C, this does not abort if there is no machine.
+pub fn current_machine() -> Option<&'static Object> {
+    assert!(bql_locked());
+    let name = c_str!("machine");
+    // SAFETY: the root object always exists, and the machine is never
+    // removed from it once it has been created
+    unsafe {
+        bindings::object_resolve_path_component(bindings::object_get_root(), name.as_ptr()).as_ref()
+    }
+}
+
+fn machine_or_err() -> Result<&'static Object> {
+    current_machine().ok_or_else(|| Error::new("the machine has not been created yet"))
+}
+
+/// Return the value of the boolean property `name` of the machine.  This
+/// fails if there is no machine, or if it has no such boolean property.
+pub fn machine_get_bool(name: &CStr) -> Result<bool> {
+    let machine = machine_or_err()?;
+    let mut err = ptr::null_mut();
+    // SAFETY: the error, if any, is owned by the result
+    unsafe {
+        let value = bindings::object_property_get_bool(
+            machine.as_object_mut_ptr(),
+            name.as_ptr(),
+            ptr::addr_of_mut!(err),
+        );
+        Error::err_or_else(err, value)
+    }
+}
+
+/// Return the value of the unsigned integer property `name` of the
+/// machine.  This fails if there is no machine, or if it has no such
+/// integer property.
+pub fn machine_get_uint(name: &CStr) -> Result<u64> {
+    let machine = machine_or_err()?;
+    let mut err = ptr::null_mut();
+    // SAFETY: the error, if any, is owned by the result
+    unsafe {
+        let value = bindings::object_property_get_uint(
+            machine.as_object_mut_ptr(),
+            name.as_ptr(),
+            ptr::addr_of_mut!(err),
+        );
+        Error::err_or_else(err, value)
+    }
+}
@@ -0,0 +1,1383 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for `MemoryRegion` and `MemoryRegionOps`.
+//!
+//! A device that exposes MMIO registers implements [`MmioOps`]; the
+//! `MemoryRegionOps` struct that QEMU needs is derived from the trait
+//! implementation, including the callbacks that forward accesses to
+//! [`MmioOps::read`] and [`MmioOps::write`].
+//!
+//! ROMs are initialized with [`MemoryRegion::init_rom`].  Devices such as
+//! flash memories, where the guest reads the contents directly but writes
+//! are commands to the device, implement [`RomDeviceOps`] and use
+//! [`MemoryRegion::init_rom_device`] instead.
+//!
+//! Devices with several blocks of registers can map each of them, or
+//! aliases of them, at an offset within a container region with
+//! [`MemoryRegion::add_subregion`].
+//!
+//! Devices that need to know the memory map of an address space, such as
+//! IOMMUs or vhost backends, implement [`MemoryListenerImpl`] and register
+//! a [`MemoryListener`], which they usually embed in their state:
+//!
+//! ```ignore
+//! // SAFETY: the listener is a field of `self`
+//! unsafe { self.listener.register(self, c_str!("my-iommu"), address_space_memory()) };
+//! ```
+//!
+//! IOMMUs translate the addresses of the DMA accesses that go through an
+//! [`IommuMemoryRegion`], with their implementation of
+//! [`IommuMemoryRegionImpl`], and tell the users of the region when a
+//! translation becomes invalid:
+//!
+//! ```ignore
+//! // SAFETY: the region is a field of `owner`
+//! unsafe { self.iommu.init(owner, c_str!("my-iommu"), u64::MAX) };
+//! ...
+//! self.iommu.invalidate(iova, 0xfff);
+//! ```
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    ffi::CStr,
+    fmt, mem,
+    os::raw::{c_char, c_int, c_uint, c_void},
+    ptr::{self, addr_of, addr_of_mut, NonNull},
+};
+
+pub use bindings::{hwaddr, IOMMUAccessFlags, IOMMUTLBEntry, IOMMUTLBEvent, MemoryRegionSection};
+
+use crate::{
+    bindings::{self, device_endian, AddressSpace, IOMMUMemoryRegionClass, TypeInfo},
+    bql::assert_bql_locked,
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    dma::address_space_memory,
+    error::{Error, Result},
+    prelude::*,
+    regaccess::Endian,
+    zeroable::Zeroable,
+};
+
+/// Endianness and access size constraints for the accesses to a memory
+/// region.  Sizes are in bytes; zero means that QEMU's default is used.
+///
+/// The `valid_*` fields are the constraints that the guest sees: QEMU
+/// rejects the accesses that do not satisfy them, without calling into
+/// the device.  The `impl_*` fields describe what the device's
+/// [`MmioOps`] implementation supports: valid accesses are widened, split
+/// or aligned to satisfy them before they reach the callbacks.
+///
+/// ```
+/// # use qemu_api::memory::MmioConfig;
+/// // the guest can access any byte of a 32-bit register, while the
+/// // callbacks always see the whole register
+/// const CONFIG: MmioConfig = MmioConfig::DEFAULT
+///     .with_valid_sizes(1, 4)
+///     .with_impl_sizes(4, 4);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct MmioConfig {
+    /// The endianness of the device's registers.
+    pub endianness: device_endian,
+    /// The smallest access that the guest is allowed to perform.
+    pub valid_min_access_size: u32,
+    /// The largest access that the guest is allowed to perform.
+    pub valid_max_access_size: u32,
+    /// Whether the guest can perform unaligned accesses.
+    pub valid_unaligned: bool,
+    /// The smallest access that is passed to the callbacks; smaller
+    /// accesses are emulated with a larger one.
+    pub impl_min_access_size: u32,
+    /// The largest access that is passed to the callbacks; larger
+    /// accesses are split into several ones.
+    pub impl_max_access_size: u32,
+    /// Whether the callbacks can handle unaligned accesses; if not, they
+    /// are converted to one or more aligned ones.
+    pub impl_unaligned: bool,
+}
+
+/// Check that `min` and `max` can be used as bounds on the size of MMIO
+/// accesses.
+const fn check_access_sizes(min: u32, max: u32) {
+    assert!(min == 0 || (min.is_power_of_two() && min <= 8));
+    assert!(max == 0 || (max.is_power_of_two() && max <= 8));
+    assert!(max == 0 || min <= max);
+}
+
+impl MmioConfig {
+    /// Native endianness and QEMU's default access sizes.
+    pub const DEFAULT: Self = MmioConfig {
+        endianness: device_endian::DEVICE_NATIVE_ENDIAN,
+        valid_min_access_size: 0,
+        valid_max_access_size: 0,
+        valid_unaligned: false,
+        impl_min_access_size: 0,
+        impl_max_access_size: 0,
+        impl_unaligned: false,
+    };
+
+    /// Set the endianness of the device's registers.
+    #[must_use]
+    pub const fn with_endianness(mut self, endianness: device_endian) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    /// Set the sizes of the accesses that the guest can perform.  Both
+    /// must be powers of two between 1 and 8, or zero.
+    #[must_use]
+    pub const fn with_valid_sizes(mut self, min: u32, max: u32) -> Self {
+        check_access_sizes(min, max);
+        self.valid_min_access_size = min;
+        self.valid_max_access_size = max;
+        self
+    }
+
+    /// Allow the guest to perform unaligned accesses.
+    #[must_use]
+    pub const fn with_valid_unaligned(mut self) -> Self {
+        self.valid_unaligned = true;
+        self
+    }
+
+    /// Set the sizes of the accesses that are passed to the callbacks.
+    /// Both must be powers of two between 1 and 8, or zero.
+    #[must_use]
+    pub const fn with_impl_sizes(mut self, min: u32, max: u32) -> Self {
+        check_access_sizes(min, max);
+        self.impl_min_access_size = min;
+        self.impl_max_access_size = max;
+        self
+    }
+
+    /// Pass unaligned accesses to the callbacks as they are.
+    #[must_use]
+    pub const fn with_impl_unaligned(mut self) -> Self {
+        self.impl_unaligned = true;
+        self
+    }
+}
+
+/// The byte order of a device whose endianness is chosen when the device
+/// is realized, usually from a property, rather than fixed in its
+/// [`MmioConfig`].
+///
+/// The same value selects the `MemoryRegionOps` of the device, with
+/// [`MemoryRegion::set_endianness`], and the byte order of the
+/// [`regaccess`](crate::regaccess) helpers, so that both stay consistent:
+///
+/// ```ignore
+/// fn realize(&self) -> Result<()> {
+///     self.endian.set(DeviceEndian::from_big_endian(self.big_endian));
+///     self.iomem.set_endianness(self, self.endian.get());
+///     Ok(())
+/// }
+/// ...
+/// write_reg(self.ctrl, offset & 3, size, value, self.endian.get().into())
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DeviceEndian {
+    /// The least significant byte of a register is at the lowest address.
+    #[default]
+    Little,
+    /// The most significant byte of a register is at the lowest address.
+    Big,
+}
+
+impl DeviceEndian {
+    /// Return `Big` if `big_endian` is true, and `Little` otherwise.  This
+    /// converts the value of a boolean property such as `big-endian`.
+    pub const fn from_big_endian(big_endian: bool) -> Self {
+        if big_endian {
+            DeviceEndian::Big
+        } else {
+            DeviceEndian::Little
+        }
+    }
+}
+
+impl From<DeviceEndian> for device_endian {
+    fn from(endian: DeviceEndian) -> Self {
+        match endian {
+            DeviceEndian::Little => device_endian::DEVICE_LITTLE_ENDIAN,
+            DeviceEndian::Big => device_endian::DEVICE_BIG_ENDIAN,
+        }
+    }
+}
+
+impl From<DeviceEndian> for Endian {
+    fn from(endian: DeviceEndian) -> Self {
+        match endian {
+            DeviceEndian::Little => Endian::Little,
+            DeviceEndian::Big => Endian::Big,
+        }
+    }
+}
+
+/// Trait for devices that handle MMIO accesses.  `offset` is relative
+/// to the start of the memory region and `size` is in bytes.
+///
+/// Both callbacks receive a shared reference: while the device handles an
+/// access it can call into chardevs, interrupt lines or timers, which in
+/// turn can invoke other callbacks of the same device.  The device's
+/// `mem_reentrancy_guard` only blocks accesses to its own MMIO regions, so
+/// state that `write` modifies must use interior mutability, as for every
+/// other device callback.
+pub trait MmioOps: Sized {
+    /// The constraints on the accesses to the region.
+    const CONFIG: MmioConfig = MmioConfig::DEFAULT;
+
+    fn read(&self, offset: hwaddr, size: u32) -> u64;
+    fn write(&self, offset: hwaddr, size: u32, value: u64);
+}
+
+unsafe extern "C" fn memory_region_ops_read_cb<T: MmioOps>(
+    opaque: *mut c_void,
+    addr: hwaddr,
+    size: c_uint,
+) -> u64 {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        T::read(unsafe { state.as_ref() }, addr, size)
+    })
+}
+
+unsafe extern "C" fn memory_region_ops_write_cb<T: MmioOps>(
+    opaque: *mut c_void,
+    addr: hwaddr,
+    data: u64,
+    size: c_uint,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        T::write(unsafe { state.as_ref() }, addr, size, data);
+    })
+}
+
+struct MmioOpsHolder<T>(T);
+
+impl<T: MmioOps> MmioOpsHolder<T> {
+    const OPS: bindings::MemoryRegionOps = bindings::MemoryRegionOps {
+        read: Some(memory_region_ops_read_cb::<T>),
+        write: Some(memory_region_ops_write_cb::<T>),
+        endianness: T::CONFIG.endianness,
+        valid: bindings::MemoryRegionOps__bindgen_ty_1 {
+            min_access_size: T::CONFIG.valid_min_access_size,
+            max_access_size: T::CONFIG.valid_max_access_size,
+            unaligned: T::CONFIG.valid_unaligned,
+            ..Zeroable::ZERO
+        },
+        impl_: bindings::MemoryRegionOps__bindgen_ty_2 {
+            min_access_size: T::CONFIG.impl_min_access_size,
+            max_access_size: T::CONFIG.impl_max_access_size,
+            unaligned: T::CONFIG.impl_unaligned,
+        },
+        ..Zeroable::ZERO
+    };
+
+    const OPS_LITTLE: bindings::MemoryRegionOps = bindings::MemoryRegionOps {
+        endianness: device_endian::DEVICE_LITTLE_ENDIAN,
+        ..Self::OPS
+    };
+
+    const OPS_BIG: bindings::MemoryRegionOps = bindings::MemoryRegionOps {
+        endianness: device_endian::DEVICE_BIG_ENDIAN,
+        ..Self::OPS
+    };
+}
+
+/// Return the `MemoryRegionOps` that forward accesses to `T`'s
+/// implementation of [`MmioOps`].
+pub const fn mmio_ops<T: MmioOps>() -> &'static bindings::MemoryRegionOps {
+    &MmioOpsHolder::<T>::OPS
+}
+
+/// Same as [`mmio_ops`], but the endianness in `T`'s [`MmioConfig`] is
+/// replaced with `endian`.
+pub const fn mmio_ops_with_endianness<T: MmioOps>(
+    endian: DeviceEndian,
+) -> &'static bindings::MemoryRegionOps {
+    match endian {
+        DeviceEndian::Little => &MmioOpsHolder::<T>::OPS_LITTLE,
+        DeviceEndian::Big => &MmioOpsHolder::<T>::OPS_BIG,
+    }
+}
+
+/// Trait for ROM devices, whose contents the guest reads directly while
+/// the device is in ROMD mode, which is the default.  Writes always go to
+/// the device.  Reads only go to the device while ROMD mode is off, for
+/// example while a flash memory reports its status instead of its
+/// contents; see [`MemoryRegion::set_romd`].
+pub trait RomDeviceOps: Sized {
+    /// The constraints on the accesses that reach the device.
+    const CONFIG: MmioConfig = MmioConfig::DEFAULT;
+
+    /// Handle a read while the region is not in ROMD mode.  The default
+    /// returns zero, for devices that never leave ROMD mode.
+    fn read(&self, _offset: hwaddr, _size: u32) -> u64 {
+        0
+    }
+
+    /// Handle a write.  As with [`MmioOps`], the device can be shared with
+    /// its other callbacks, so the state that writes modify must use
+    /// interior mutability.
+    fn write(&self, offset: hwaddr, size: u32, value: u64);
+}
+
+unsafe extern "C" fn rom_device_ops_read_cb<T: RomDeviceOps>(
+    opaque: *mut c_void,
+    addr: hwaddr,
+    size: c_uint,
+) -> u64 {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        T::read(unsafe { state.as_ref() }, addr, size)
+    })
+}
+
+unsafe extern "C" fn rom_device_ops_write_cb<T: RomDeviceOps>(
+    opaque: *mut c_void,
+    addr: hwaddr,
+    data: u64,
+    size: c_uint,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        T::write(unsafe { state.as_ref() }, addr, size, data);
+    })
+}
+
+struct RomDeviceOpsHolder<T>(T);
+
+impl<T: RomDeviceOps> RomDeviceOpsHolder<T> {
+    const OPS: bindings::MemoryRegionOps = bindings::MemoryRegionOps {
+        read: Some(rom_device_ops_read_cb::<T>),
+        write: Some(rom_device_ops_write_cb::<T>),
+        endianness: T::CONFIG.endianness,
+        valid: bindings::MemoryRegionOps__bindgen_ty_1 {
+            min_access_size: T::CONFIG.valid_min_access_size,
+            max_access_size: T::CONFIG.valid_max_access_size,
+            unaligned: T::CONFIG.valid_unaligned,
+            ..Zeroable::ZERO
+        },
+        impl_: bindings::MemoryRegionOps__bindgen_ty_2 {
+            min_access_size: T::CONFIG.impl_min_access_size,
+            max_access_size: T::CONFIG.impl_max_access_size,
+            unaligned: T::CONFIG.impl_unaligned,
+        },
+        ..Zeroable::ZERO
+    };
+}
+
+/// A wrapper around the C `MemoryRegion` struct, to be embedded in
+/// the state of a device.
+#[repr(transparent)]
+pub struct MemoryRegion(bindings::MemoryRegion);
+
+impl MemoryRegion {
+    /// Initialize the region as an MMIO region of `size` bytes, whose
+    /// accesses are handled by `owner`'s implementation of [`MmioOps`].
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    /// The accesses to the region are dispatched to `owner`, so its
+    /// fields must be initialized before the region is mapped.
+    pub unsafe fn init_io<T: MmioOps + IsA<Object>>(
+        &mut self,
+        owner: *mut T,
+        name: &CStr,
+        size: u64,
+    ) {
+        // SAFETY: the ops are static, and the caller guarantees that the
+        // opaque is a `T` that outlives the region
+        unsafe {
+            bindings::memory_region_init_io(
+                addr_of_mut!(self.0),
+                owner.cast::<Object>(),
+                mmio_ops::<T>(),
+                owner.cast::<c_void>(),
+                name.as_ptr(),
+                size,
+            );
+        }
+    }
+
+    /// Replace the endianness in `T`'s [`MmioConfig`] with `endian`, for
+    /// a region that [`init_io`](MemoryRegion::init_io) initialized with
+    /// `owner`.  Properties are not set yet when `INSTANCE_INIT` runs, so
+    /// devices that select their endianness with a property call this
+    /// when they are realized, before the region is mapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region is not an MMIO region of `owner`.
+    pub fn set_endianness<T: MmioOps>(&self, owner: &T, endian: DeviceEndian) {
+        assert!(!self.0.ops.is_null());
+        assert_eq!(self.0.opaque, ptr::addr_of!(*owner) as *mut c_void);
+        // SAFETY: the callbacks are the same as those of the current ops,
+        // because the opaque is a T; the region is not mapped yet, so
+        // nothing is accessing it
+        unsafe {
+            (*self.as_mut_ptr()).ops = mmio_ops_with_endianness::<T>(endian);
+        }
+    }
+
+    /// Initialize the region as `size` bytes of RAM, allocated by QEMU
+    /// and migrated together with the rest of guest memory.  The contents
+    /// can be accessed with [`read_ram`](MemoryRegion::read_ram) and
+    /// [`write_ram`](MemoryRegion::write_ram).
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    pub unsafe fn init_ram<T: IsA<Object>>(
+        &mut self,
+        owner: *mut T,
+        name: &CStr,
+        size: u64,
+    ) -> Result<()> {
+        let mut err = ptr::null_mut();
+        // SAFETY: the caller guarantees that the owner outlives the
+        // region, and the error, if any, is owned by the result
+        unsafe {
+            bindings::memory_region_init_ram(
+                addr_of_mut!(self.0),
+                owner.cast::<Object>(),
+                name.as_ptr(),
+                size,
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, ())
+        }
+    }
+
+    /// Initialize the region as `size` bytes of ROM, allocated by QEMU
+    /// and migrated together with the rest of guest memory.  The guest
+    /// can only read the region; its writes are dropped.  The initial
+    /// contents are filled with [`write_ram`](MemoryRegion::write_ram).
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    pub unsafe fn init_rom<T: IsA<Object>>(
+        &mut self,
+        owner: *mut T,
+        name: &CStr,
+        size: u64,
+    ) -> Result<()> {
+        let mut err = ptr::null_mut();
+        // SAFETY: the caller guarantees that the owner outlives the
+        // region, and the error, if any, is owned by the result
+        unsafe {
+            bindings::memory_region_init_rom(
+                addr_of_mut!(self.0),
+                owner.cast::<Object>(),
+                name.as_ptr(),
+                size,
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, ())
+        }
+    }
+
+    /// Initialize the region as a ROM device of `size` bytes: the guest
+    /// reads the contents of the region, which are filled with
+    /// [`write_ram`](MemoryRegion::write_ram), while its writes are
+    /// handled by `owner`'s implementation of [`RomDeviceOps`].
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    /// The accesses to the region are dispatched to `owner`, so its
+    /// fields must be initialized before the region is mapped.
+    pub unsafe fn init_rom_device<T: RomDeviceOps + IsA<Object>>(
+        &mut self,
+        owner: *mut T,
+        name: &CStr,
+        size: u64,
+    ) -> Result<()> {
+        let mut err = ptr::null_mut();
+        // SAFETY: the ops are static, the caller guarantees that the
+        // opaque is a `T` that outlives the region, and the error, if any,
+        // is owned by the result
+        unsafe {
+            bindings::memory_region_init_rom_device(
+                addr_of_mut!(self.0),
+                owner.cast::<Object>(),
+                &RomDeviceOpsHolder::<T>::OPS,
+                owner.cast::<c_void>(),
+                name.as_ptr(),
+                size,
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, ())
+        }
+    }
+
+    /// Switch a ROM device between ROMD mode, where the guest reads the
+    /// contents of the region, and MMIO mode, where reads go to
+    /// [`RomDeviceOps::read`], as in `memory_region_rom_device_set_romd()`.
+    pub fn set_romd(&self, romd: bool) {
+        assert!(bql_locked());
+        // SAFETY: changes to the memory map are serialized by the BQL
+        unsafe {
+            bindings::memory_region_rom_device_set_romd(self.as_mut_ptr(), romd);
+        }
+    }
+
+    /// Return the host memory of a RAM, ROM or ROM device region and its
+    /// length, or `None` if the region is not backed by host memory (for
+    /// example because it is an MMIO region or a container).
+    fn host_memory(&self) -> Option<(NonNull<u8>, usize)> {
+        // SAFETY: RAM regions and ROM devices have a host pointer that is
+        // valid for the size of the region
+        unsafe {
+            if !self.0.rom_device && !bindings::memory_region_is_ram(self.as_mut_ptr()) {
+                return None;
+            }
+            let len = usize::try_from(bindings::memory_region_size(self.as_mut_ptr())).ok()?;
+            let data = NonNull::new(bindings::memory_region_get_ram_ptr(self.as_mut_ptr()))?;
+            Some((data.cast::<u8>(), len))
+        }
+    }
+
+    /// Return the host memory for the `len` bytes at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region is not backed by host memory, or if the range
+    /// does not fit in it.
+    fn host_range(&self, offset: usize, len: usize) -> *mut u8 {
+        let (data, size) = self
+            .host_memory()
+            .expect("region is not backed by host memory");
+        assert!(
+            offset <= size && len <= size - offset,
+            "access past the end of the region"
+        );
+        // SAFETY: the range was checked above
+        unsafe { data.as_ptr().add(offset) }
+    }
+
+    /// Copy the `buf.len()` bytes at `offset` in a RAM, ROM or ROM device
+    /// region to `buf`.
+    ///
+    /// The guest and other devices can access the memory at any time, so
+    /// it is only ever copied, and never exposed as a slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region is not backed by host memory (for example
+    /// because it is an MMIO region or a container), or if the range does
+    /// not fit in the region.
+    pub fn read_ram(&self, offset: usize, buf: &mut [u8]) {
+        let src = self.host_range(offset, buf.len());
+        // SAFETY: the range is within the host memory of the region, which
+        // `buf` cannot be part of
+        unsafe {
+            ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len());
+        }
+    }
+
+    /// Copy `data` to a RAM, ROM or ROM device region, starting at `offset`.
+    /// Unlike the guest, this can write to a ROM, for example to load its
+    /// initial contents.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`read_ram`](MemoryRegion::read_ram).
+    pub fn write_ram(&self, offset: usize, data: &[u8]) {
+        let dst = self.host_range(offset, data.len());
+        // SAFETY: as above
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), dst, data.len());
+        }
+    }
+
+    /// Initialize the region as a container of `size` bytes.  A container
+    /// does not handle accesses itself; they are routed to the subregions
+    /// added with [`add_subregion`](MemoryRegion::add_subregion) and
+    /// [`add_subregion_overlap`](MemoryRegion::add_subregion_overlap).
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    pub unsafe fn init_container<T: IsA<Object>>(&mut self, owner: *mut T, name: &CStr, size: u64) {
+        // SAFETY: the caller guarantees that the owner outlives the region
+        unsafe {
+            bindings::memory_region_init(
+                addr_of_mut!(self.0),
+                owner.cast::<Object>(),
+                name.as_ptr(),
+                size,
+            );
+        }
+    }
+
+    /// Initialize the region as an alias of the `size` bytes of `orig`
+    /// that start at `offset`.  Accesses to the alias behave exactly as
+    /// accesses to the corresponding part of `orig`.
+    ///
+    /// `orig` is kept alive by the alias through a reference to its owner.
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    pub unsafe fn init_alias<T: IsA<Object>>(
+        &mut self,
+        owner: *mut T,
+        name: &CStr,
+        orig: &MemoryRegion,
+        offset: hwaddr,
+        size: u64,
+    ) {
+        assert!(orig.has_owner());
+        // SAFETY: the caller guarantees that the owner outlives the alias,
+        // and `orig` is kept alive through its owner
+        unsafe {
+            bindings::memory_region_init_alias(
+                addr_of_mut!(self.0),
+                owner.cast::<Object>(),
+                name.as_ptr(),
+                orig.as_mut_ptr(),
+                offset,
+                size,
+            );
+        }
+    }
+
+    /// Map `child` at `offset` within this region, which must be a
+    /// container.  `child` must not overlap any other subregion of the
+    /// container; use [`add_subregion_overlap`] if it may.
+    ///
+    /// The container takes a reference to the owner of `child`, which
+    /// keeps `child` alive until it is removed with
+    /// [`del_subregion`](MemoryRegion::del_subregion) or the container is
+    /// destroyed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `child` has no owner, since nothing would then keep it
+    /// alive.
+    ///
+    /// [`add_subregion_overlap`]: MemoryRegion::add_subregion_overlap
+    pub fn add_subregion(&self, offset: hwaddr, child: &MemoryRegion) {
+        assert!(bql_locked());
+        assert!(child.has_owner());
+        // SAFETY: the owner of child is kept alive by the container
+        unsafe {
+            bindings::memory_region_add_subregion(self.as_mut_ptr(), offset, child.as_mut_ptr());
+        }
+    }
+
+    /// Same as [`add_subregion`](MemoryRegion::add_subregion), but
+    /// `child` may overlap other subregions of the container.  Where
+    /// subregions overlap, accesses go to the one with the highest
+    /// `priority`.
+    pub fn add_subregion_overlap(&self, offset: hwaddr, priority: i32, child: &MemoryRegion) {
+        assert!(bql_locked());
+        assert!(child.has_owner());
+        // SAFETY: the owner of child is kept alive by the container
+        unsafe {
+            bindings::memory_region_add_subregion_overlap(
+                self.as_mut_ptr(),
+                offset,
+                child.as_mut_ptr(),
+                priority,
+            );
+        }
+    }
+
+    /// Remove `child`, which was added with
+    /// [`add_subregion`](MemoryRegion::add_subregion) or
+    /// [`add_subregion_overlap`](MemoryRegion::add_subregion_overlap),
+    /// from this container, and drop the reference to its owner.
+    pub fn del_subregion(&self, child: &MemoryRegion) {
+        assert!(bql_locked());
+        unsafe {
+            bindings::memory_region_del_subregion(self.as_mut_ptr(), child.as_mut_ptr());
+        }
+    }
+
+    /// Enable or disable the region, as in `memory_region_set_enabled()`.
+    /// While a region is disabled, accesses go to whatever it was hiding,
+    /// as if it was not mapped; it is enabled when it is created.
+    pub fn set_enabled(&self, enabled: bool) {
+        assert!(bql_locked());
+        // SAFETY: changes to the memory map are serialized by the BQL
+        unsafe {
+            bindings::memory_region_set_enabled(self.as_mut_ptr(), enabled);
+        }
+    }
+
+    /// Move the region to `addr` within its container, as in
+    /// `memory_region_set_address()`, for example when the guest
+    /// reprograms a BAR.
+    pub fn set_address(&self, addr: hwaddr) {
+        assert!(bql_locked());
+        // SAFETY: changes to the memory map are serialized by the BQL
+        unsafe {
+            bindings::memory_region_set_address(self.as_mut_ptr(), addr);
+        }
+    }
+
+    pub(crate) fn has_owner(&self) -> bool {
+        // SAFETY: reading the owner has no side effects
+        unsafe { !bindings::memory_region_owner(self.as_mut_ptr()).is_null() }
+    }
+
+    /// Return a raw pointer to the C `MemoryRegion`, for use with C
+    /// functions.
+    pub const fn as_mut_ptr(&self) -> *mut bindings::MemoryRegion {
+        addr_of!(self.0) as *mut _
+    }
+}
+
+impl MemoryRegionSection {
+    /// Return the address at which the section starts in the address
+    /// space.
+    pub const fn offset_within_address_space(&self) -> hwaddr {
+        self.offset_within_address_space
+    }
+
+    /// Return the offset of the start of the section within its
+    /// [`region`](MemoryRegionSection::region).
+    pub const fn offset_within_region(&self) -> hwaddr {
+        self.offset_within_region
+    }
+
+    /// Return the size of the section in bytes.  A section that covers the
+    /// whole 64-bit address space has a size of 2^64, which is returned as
+    /// `u64::MAX`.
+    pub fn size(&self) -> u64 {
+        u64::try_from(self.size).unwrap_or(u64::MAX)
+    }
+
+    /// Return whether the guest can only read the section.
+    pub const fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Return the region that is mapped by the section.
+    pub fn region(&self) -> &MemoryRegion {
+        // SAFETY: MemoryRegion is a transparent wrapper, and the region
+        // is alive as long as the section that maps it
+        unsafe { &*self.mr.cast::<MemoryRegion>() }
+    }
+}
+
+/// Trait for objects that observe the memory map of an address space
+/// through a [`MemoryListener`].  Changes to the memory map are delivered
+/// inside a transaction: `BEGIN` is called first, then `REGION_ADD` and
+/// `REGION_DEL` for each section that appears or disappears, and finally
+/// `COMMIT`.
+///
+/// When the listener is registered, `REGION_ADD` is called for each
+/// section that is already mapped; when it is unregistered, `REGION_DEL`
+/// is called for each of them.
+pub trait MemoryListenerImpl: Sized {
+    /// If not `None`, this is called at the start of each transaction.
+    const BEGIN: Option<fn(&Self)> = None;
+
+    /// If not `None`, this is called at the end of each transaction, once
+    /// the new memory map is in place.
+    const COMMIT: Option<fn(&Self)> = None;
+
+    /// If not `None`, this is called for each section that is added to
+    /// the memory map.
+    const REGION_ADD: Option<fn(&Self, &MemoryRegionSection)> = None;
+
+    /// If not `None`, this is called for each section that is removed
+    /// from the memory map.
+    const REGION_DEL: Option<fn(&Self, &MemoryRegionSection)> = None;
+}
+
+/// Call `f` with the owner of the [`MemoryListener`] that contains
+/// `listener`.
+///
+/// # Safety
+///
+/// `listener` must be part of a `MemoryListener` that was registered with
+/// an owner of type `T`.
+unsafe fn listener_call<T>(listener: *mut bindings::MemoryListener, f: impl FnOnce(&T)) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let listener = NonNull::new(listener).unwrap().cast::<MemoryListener>();
+        // SAFETY: the C struct is the first field of MemoryListener, and
+        // the owner outlives the registration
+        let owner = unsafe { &*listener.as_ref().owner.get().cast::<T>() };
+        f(owner);
+    })
+}
+
+unsafe extern "C" fn rust_listener_begin_fn<T: MemoryListenerImpl>(
+    listener: *mut bindings::MemoryListener,
+) {
+    // SAFETY: the callback is only installed by MemoryListener::register
+    unsafe { listener_call(listener, T::BEGIN.unwrap()) }
+}
+
+unsafe extern "C" fn rust_listener_commit_fn<T: MemoryListenerImpl>(
+    listener: *mut bindings::MemoryListener,
+) {
+    // SAFETY: the callback is only installed by MemoryListener::register
+    unsafe { listener_call(listener, T::COMMIT.unwrap()) }
+}
+
+unsafe extern "C" fn rust_listener_region_add_fn<T: MemoryListenerImpl>(
+    listener: *mut bindings::MemoryListener,
+    section: *mut MemoryRegionSection,
+) {
+    // SAFETY: the callback is only installed by MemoryListener::register,
+    // and the section is valid for the duration of the call
+    unsafe {
+        listener_call(listener, |owner: &T| {
+            T::REGION_ADD.unwrap()(owner, NonNull::new(section).unwrap().as_ref());
+        });
+    }
+}
+
+unsafe extern "C" fn rust_listener_region_del_fn<T: MemoryListenerImpl>(
+    listener: *mut bindings::MemoryListener,
+    section: *mut MemoryRegionSection,
+) {
+    // SAFETY: the callback is only installed by MemoryListener::register,
+    // and the section is valid for the duration of the call
+    unsafe {
+        listener_call(listener, |owner: &T| {
+            T::REGION_DEL.unwrap()(owner, NonNull::new(section).unwrap().as_ref());
+        });
+    }
+}
+
+/// A wrapper around the C `MemoryListener` struct, to be embedded in the
+/// state of the object that implements [`MemoryListenerImpl`].  The
+/// listener is unregistered when it is dropped, which for QOM objects
+/// happens when the object is finalized.
+#[repr(C)]
+pub struct MemoryListener {
+    // first, so that a pointer to it is also a pointer to the wrapper
+    listener: UnsafeCell<bindings::MemoryListener>,
+    owner: Cell<*const c_void>,
+}
+
+// SAFETY: the listener is only registered and unregistered under the BQL,
+// and the memory API serializes the callbacks
+unsafe impl Send for MemoryListener {}
+unsafe impl Sync for MemoryListener {}
+
+impl MemoryListener {
+    /// Create a listener that is not registered yet.
+    pub const fn new() -> Self {
+        MemoryListener {
+            listener: UnsafeCell::new(Zeroable::ZERO),
+            owner: Cell::new(ptr::null()),
+        }
+    }
+
+    /// Start delivering the changes to the memory map of `address_space`
+    /// to `owner`'s implementation of [`MemoryListenerImpl`].  The name is
+    /// only used for debugging.  If the listener was already registered,
+    /// it is unregistered first.
+    ///
+    /// # Safety
+    ///
+    /// `owner` must remain valid until the listener is unregistered, either
+    /// with [`unregister`](MemoryListener::unregister) or by dropping
+    /// `self`, and the listener must not move in the meanwhile.  This is
+    /// the case if `self` is a field of `owner`.
+    pub unsafe fn register<T: MemoryListenerImpl>(
+        &self,
+        owner: &T,
+        name: &'static CStr,
+        address_space: &AddressSpace,
+    ) {
+        assert!(bql_locked());
+        self.unregister();
+        self.install(owner, name);
+        // SAFETY: the caller guarantees that the owner outlives the
+        // registration
+        unsafe {
+            bindings::memory_listener_register(self.as_mut_ptr(), address_space.as_mut_ptr());
+        }
+    }
+
+    /// Point the callbacks of the listener, which is not registered, to
+    /// `owner`'s implementation of [`MemoryListenerImpl`].
+    fn install<T: MemoryListenerImpl>(&self, owner: &T, name: &'static CStr) {
+        self.owner.set(ptr::addr_of!(*owner).cast::<c_void>());
+        let listener = self.as_mut_ptr();
+        // SAFETY: the listener is not registered, so nothing else accesses
+        // it; the name lives forever
+        unsafe {
+            *listener = Zeroable::ZERO;
+            (*listener).name = name.as_ptr();
+            if T::BEGIN.is_some() {
+                (*listener).begin = Some(rust_listener_begin_fn::<T>);
+            }
+            if T::COMMIT.is_some() {
+                (*listener).commit = Some(rust_listener_commit_fn::<T>);
+            }
+            if T::REGION_ADD.is_some() {
+                (*listener).region_add = Some(rust_listener_region_add_fn::<T>);
+            }
+            if T::REGION_DEL.is_some() {
+                (*listener).region_del = Some(rust_listener_region_del_fn::<T>);
+            }
+        }
+    }
+
+    /// Stop delivering changes to the memory map.  This does nothing if
+    /// the listener is not registered.
+    pub fn unregister(&self) {
+        assert!(bql_locked());
+        // SAFETY: memory_listener_unregister() does nothing for listeners
+        // that are not registered
+        unsafe {
+            bindings::memory_listener_unregister(self.as_mut_ptr());
+        }
+    }
+
+    /// Return a raw pointer to the C `MemoryListener`, for use with C
+    /// functions.
+    pub const fn as_mut_ptr(&self) -> *mut bindings::MemoryListener {
+        self.listener.get()
+    }
+}
+
+impl Default for MemoryListener {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MemoryListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryListener")
+            .field("owner", &self.owner.get())
+            .finish()
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// Trait for IOMMUs, which translate the addresses of the accesses that
+/// go through an [`IommuMemoryRegion`] that they own.
+pub trait IommuMemoryRegionImpl: Sized {
+    /// The name of the QOM type of the region, a subclass of
+    /// `TYPE_IOMMU_MEMORY_REGION` that is registered the first time
+    /// [`IommuMemoryRegion::init`] is called.  Each implementation of the
+    /// trait needs a different name.
+    const IOMMU_TYPE_NAME: &'static CStr;
+
+    /// Translate `addr`, an offset within the region, for an access with
+    /// the permissions in `flag`; `IOMMU_NONE` asks for the translation
+    /// regardless of the permissions.
+    ///
+    /// The result describes the naturally aligned block of
+    /// `addr_mask + 1` bytes that contains `addr`, and the address space
+    /// in which the translated address lives, usually
+    /// [`address_space_memory`](crate::dma::address_space_memory).  An
+    /// entry whose `perm` is `IOMMU_NONE` makes the access fail.
+    fn translate(&self, addr: hwaddr, flag: IOMMUAccessFlags) -> IOMMUTLBEntry;
+}
+
+unsafe extern "C" fn rust_iommu_translate_fn<T: IommuMemoryRegionImpl>(
+    iommu: *mut bindings::IOMMUMemoryRegion,
+    addr: hwaddr,
+    flag: IOMMUAccessFlags,
+    _iommu_idx: c_int,
+) -> IOMMUTLBEntry {
+    abort_on_panic(|| {
+        // SAFETY: the region was initialized by IommuMemoryRegion::init
+        // with an owner of type T, which contains the region
+        let owner = unsafe {
+            let mr = iommu.cast::<bindings::MemoryRegion>();
+            NonNull::new(bindings::memory_region_owner(mr))
+                .unwrap()
+                .cast::<T>()
+                .as_ref()
+        };
+        owner.translate(addr, flag)
+    })
+}
+
+unsafe extern "C" fn rust_iommu_class_init_fn<T: IommuMemoryRegionImpl>(
+    klass: *mut bindings::ObjectClass,
+    _data: *mut c_void,
+) {
+    // SAFETY: the class is an IOMMUMemoryRegionClass, because the type is
+    // a subclass of TYPE_IOMMU_MEMORY_REGION
+    let imrc = unsafe {
+        NonNull::new(klass)
+            .unwrap()
+            .cast::<IOMMUMemoryRegionClass>()
+            .as_mut()
+    };
+    imrc.translate = Some(rust_iommu_translate_fn::<T>);
+}
+
+struct IommuTypeHolder<T>(T);
+
+impl<T: IommuMemoryRegionImpl> IommuTypeHolder<T> {
+    const TYPE_INFO: TypeInfo = TypeInfo {
+        name: T::IOMMU_TYPE_NAME.as_ptr(),
+        parent: bindings::TYPE_IOMMU_MEMORY_REGION.as_ptr().cast::<c_char>(),
+        instance_size: 0,
+        instance_align: 0,
+        instance_init: None,
+        instance_post_init: None,
+        instance_finalize: None,
+        abstract_: false,
+        class_size: mem::size_of::<IOMMUMemoryRegionClass>(),
+        class_init: Some(rust_iommu_class_init_fn::<T>),
+        class_base_init: None,
+        class_data: ptr::null_mut(),
+        interfaces: ptr::null_mut(),
+    };
+}
+
+/// A wrapper around the C `IOMMUMemoryRegion` struct, to be embedded in
+/// the state of an IOMMU.  Devices that are behind the IOMMU map the
+/// region, usually with an [`AddressSpace`] whose root it is, and their
+/// accesses are translated by the IOMMU's implementation of
+/// [`IommuMemoryRegionImpl`].
+///
+/// The region has a single IOMMU index.
+#[repr(transparent)]
+pub struct IommuMemoryRegion(bindings::IOMMUMemoryRegion);
+
+impl IommuMemoryRegion {
+    /// Initialize the region, which is `size` bytes long, and point its
+    /// translations to `owner`'s implementation of
+    /// [`IommuMemoryRegionImpl`].
+    ///
+    /// # Safety
+    ///
+    /// `owner` must point to the instance of `T` that contains the region,
+    /// so that it lives at least as long as the region itself.  The
+    /// instance may still be under construction, as in `INSTANCE_INIT`.
+    /// Translations are dispatched to `owner`, so its fields must be
+    /// initialized before the region is used.
+    #[doc(alias = "memory_region_init_iommu")]
+    pub unsafe fn init<T: IommuMemoryRegionImpl + IsA<Object>>(
+        &mut self,
+        owner: *mut T,
+        name: &CStr,
+        size: u64,
+    ) {
+        assert!(bql_locked());
+        // SAFETY: the TypeInfo and the strings in it are static; the
+        // region is initialized in place, with the size of the C struct;
+        // the caller guarantees that the owner outlives the region
+        unsafe {
+            if bindings::object_class_by_name(T::IOMMU_TYPE_NAME.as_ptr()).is_null() {
+                bindings::type_register_static(&IommuTypeHolder::<T>::TYPE_INFO);
+            }
+            bindings::memory_region_init_iommu(
+                addr_of_mut!(self.0).cast::<c_void>(),
+                mem::size_of::<bindings::IOMMUMemoryRegion>(),
+                T::IOMMU_TYPE_NAME.as_ptr(),
+                owner.cast::<Object>(),
+                name.as_ptr(),
+                size,
+            );
+        }
+    }
+
+    /// Tell the notifiers that are registered on the region about a change
+    /// to the translations, as in `memory_region_notify_iommu()`.
+    /// Notifiers only see the events that they asked for, and only if
+    /// `event.entry` overlaps the range that they watch.
+    ///
+    /// An `IOMMU_NOTIFIER_UNMAP` event must have `IOMMU_NONE` permissions;
+    /// [`invalidate`](IommuMemoryRegion::invalidate) takes care of that.
+    #[doc(alias = "memory_region_notify_iommu")]
+    pub fn notify(&self, event: &IOMMUTLBEvent) {
+        assert!(bql_locked());
+        // SAFETY: the notifiers are called synchronously, and the event
+        // is copied
+        unsafe {
+            bindings::memory_region_notify_iommu(self.as_mut_ptr(), 0, *event);
+        }
+    }
+
+    /// Invalidate the translations of the naturally aligned block of
+    /// `addr_mask + 1` bytes at `iova`, for example after the guest
+    /// removed a mapping from the page tables of the IOMMU.
+    pub fn invalidate(&self, iova: hwaddr, addr_mask: hwaddr) {
+        self.notify(&IOMMUTLBEvent {
+            type_: bindings::IOMMU_NOTIFIER_UNMAP,
+            entry: IOMMUTLBEntry {
+                target_as: address_space_memory().as_mut_ptr(),
+                iova,
+                translated_addr: 0,
+                addr_mask,
+                perm: bindings::IOMMU_NONE,
+            },
+        });
+    }
+
+    /// Return the region as a plain [`MemoryRegion`], for example to map
+    /// it in a container or to create an [`AddressSpace`] on top of it.
+    pub const fn as_memory_region(&self) -> &MemoryRegion {
+        // SAFETY: MemoryRegion is a transparent wrapper around the C
+        // struct, which is the first field of IOMMUMemoryRegion
+        unsafe { &*addr_of!(self.0.parent_obj).cast::<MemoryRegion>() }
+    }
+
+    /// Return a raw pointer to the C `IOMMUMemoryRegion`, for use with C
+    /// functions.
+    pub const fn as_mut_ptr(&self) -> *mut bindings::IOMMUMemoryRegion {
+        addr_of!(self.0) as *mut _
+    }
+}
+
+impl fmt::Debug for IommuMemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IommuMemoryRegion")
+            .field(&self.as_mut_ptr())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{bql_start_test, BqlCell};
+
+    #[test]
+    fn test_mmio_config() {
+        let config = MmioConfig::DEFAULT
+            .with_valid_sizes(1, 8)
+            .with_valid_unaligned()
+            .with_impl_sizes(4, 4)
+            .with_impl_unaligned();
+        assert_eq!(config.valid_min_access_size, 1);
+        assert_eq!(config.valid_max_access_size, 8);
+        assert!(config.valid_unaligned);
+        assert_eq!(config.impl_min_access_size, 4);
+        assert_eq!(config.impl_max_access_size, 4);
+        assert!(config.impl_unaligned);
+
+        // zero is QEMU's default for either bound
+        let default_max = MmioConfig::DEFAULT.with_valid_sizes(2, 0);
+        assert_eq!(default_max.valid_min_access_size, 2);
+        assert_eq!(default_max.valid_max_access_size, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mmio_config_invalid_size() {
+        let _ = MmioConfig::DEFAULT.with_valid_sizes(1, 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mmio_config_too_large() {
+        let _ = MmioConfig::DEFAULT.with_impl_sizes(4, 16);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mmio_config_inverted() {
+        let _ = MmioConfig::DEFAULT.with_impl_sizes(4, 2);
+    }
+
+    #[derive(Default)]
+    struct Tracker {
+        events: std::cell::RefCell<Vec<(&'static str, hwaddr, u64)>>,
+    }
+
+    impl Tracker {
+        fn log(&self, event: &'static str, section: &MemoryRegionSection) {
+            self.events.borrow_mut().push((
+                event,
+                section.offset_within_address_space(),
+                section.size(),
+            ));
+        }
+    }
+
+    impl MemoryListenerImpl for Tracker {
+        const BEGIN: Option<fn(&Self)> = Some(|t| t.events.borrow_mut().push(("begin", 0, 0)));
+        const REGION_ADD: Option<fn(&Self, &MemoryRegionSection)> =
+            Some(|t, section| t.log("add", section));
+        const REGION_DEL: Option<fn(&Self, &MemoryRegionSection)> =
+            Some(|t, section| t.log("del", section));
+    }
+
+    /// memory.c cannot be linked into the tests, so the callbacks are
+    /// invoked the way that the memory API would in a transaction that
+    /// maps 4 KiB at 0x1000 and unmaps the whole address space.
+    #[test]
+    fn test_memory_listener() {
+        bql_start_test();
+        let tracker = Tracker::default();
+        // never registered, and memory_listener_unregister() is not
+        // available
+        let listener = std::mem::ManuallyDrop::new(MemoryListener::new());
+        listener.install(&tracker, crate::c_str!("tracker"));
+
+        let mut add = MemoryRegionSection {
+            size: 0x1000,
+            offset_within_address_space: 0x1000,
+            ..Default::default()
+        };
+        let mut del = MemoryRegionSection {
+            size: 1 << 64,
+            ..Default::default()
+        };
+        let l = listener.as_mut_ptr();
+        // SAFETY: the listener's owner is `tracker`
+        unsafe {
+            assert!((*l).commit.is_none());
+            (*l).begin.unwrap()(l);
+            (*l).region_add.unwrap()(l, addr_of_mut!(add));
+            (*l).region_del.unwrap()(l, addr_of_mut!(del));
+        }
+        assert_eq!(
+            *tracker.events.borrow(),
+            [
+                ("begin", 0, 0),
+                ("add", 0x1000, 0x1000),
+                ("del", 0, u64::MAX)
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct BigEndianRegs {
+        regs: BqlCell<[u32; 4]>,
+        last_size: BqlCell<u32>,
+    }
+
+    impl MmioOps for BigEndianRegs {
+        const CONFIG: MmioConfig = MmioConfig::DEFAULT
+            .with_endianness(device_endian::DEVICE_BIG_ENDIAN)
+            .with_valid_sizes(1, 4)
+            .with_impl_sizes(4, 4);
+
+        fn read(&self, offset: hwaddr, size: u32) -> u64 {
+            assert_eq!(size, 4);
+            self.regs.get()[(offset >> 2) as usize].into()
+        }
+
+        fn write(&self, offset: hwaddr, size: u32, value: u64) {
+            self.last_size.set(size);
+            let mut regs = self.regs.get();
+            regs[(offset >> 2) as usize] = value as u32;
+            self.regs.set(regs);
+        }
+    }
+
+    /// Check the `MemoryRegionOps` generated from an `MmioOps`
+    /// implementation.  `memory_region_dispatch_read/write` are defined in
+    /// a per-target file that cannot be linked into the tests, so the
+    /// accesses go straight to the callbacks that the dispatch code would
+    /// call.
+    #[test]
+    fn test_mmio_ops() {
+        bql_start_test();
+        let ops = mmio_ops::<BigEndianRegs>();
+        assert_eq!(ops.endianness, device_endian::DEVICE_BIG_ENDIAN);
+        assert_eq!(ops.valid.min_access_size, 1);
+        assert_eq!(ops.valid.max_access_size, 4);
+        assert!(!ops.valid.unaligned);
+        assert_eq!(ops.impl_.min_access_size, 4);
+        assert_eq!(ops.impl_.max_access_size, 4);
+        assert!(!ops.impl_.unaligned);
+        assert!(ops.read_with_attrs.is_none());
+        assert!(ops.write_with_attrs.is_none());
+
+        let mut dev = BigEndianRegs::default();
+        let opaque = addr_of_mut!(dev).cast::<c_void>();
+        // SAFETY: the opaque points to a BigEndianRegs
+        unsafe {
+            ops.write.unwrap()(opaque, 8, 0x1234_5678, 4);
+            assert_eq!(ops.read.unwrap()(opaque, 8, 4), 0x1234_5678);
+            assert_eq!(ops.read.unwrap()(opaque, 0, 4), 0);
+        }
+        assert_eq!(dev.regs.get(), [0, 0, 0x1234_5678, 0]);
+        assert_eq!(dev.last_size.get(), 4);
+    }
+
+    struct PanicRegs(BqlCell<u64>);
+
+    impl MmioOps for PanicRegs {
+        fn read(&self, offset: hwaddr, _size: u32) -> u64 {
+            assert!(offset < 8, "read past the end of the registers");
+            self.0.get()
+        }
+
+        fn write(&self, _offset: hwaddr, _size: u32, value: u64) {
+            self.0.set(value);
+        }
+    }
+
+    /// Set in the environment of the child process that performs the
+    /// access that panics.
+    const PANIC_CHILD_VAR: &str = "QEMU_TEST_MMIO_PANIC";
+
+    #[test]
+    #[cfg(unix)]
+    fn test_mmio_panic_aborts() {
+        use std::{env, os::unix::process::ExitStatusExt, process::Command};
+
+        bql_start_test();
+        let ops = mmio_ops::<PanicRegs>();
+        let mut regs = PanicRegs(BqlCell::new(0));
+        let opaque = addr_of_mut!(regs).cast::<c_void>();
+        // SAFETY: the opaque points to a PanicRegs
+        unsafe {
+            ops.write.unwrap()(opaque, 0, 42, 8);
+            assert_eq!(ops.read.unwrap()(opaque, 0, 8), 42);
+        }
+
+        if env::var_os(PANIC_CHILD_VAR).is_some() {
+            // SAFETY: as above; the callback panics and aborts the process
+            unsafe {
+                ops.read.unwrap()(opaque, 8, 8);
+            }
+            unreachable!("the process should have been aborted");
+        }
+
+        // The panic must not unwind into the caller of the callback, so
+        // run it in a separate process and check that it was aborted.
+        let output = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "memory::tests::test_mmio_panic_aborts"])
+            .args(["--nocapture", "--test-threads=1"])
+            .env(PANIC_CHILD_VAR, "1")
+            .output()
+            .unwrap();
+        // SIGABRT is 6 on all Unix systems that QEMU supports
+        assert_eq!(output.status.signal(), Some(6));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("read past the end of the registers"));
+        assert!(stderr.contains("aborting"));
+        assert!(!stderr.contains("should have been aborted"));
+    }
+}
@@ -17,7 +17,7 @@ macro_rules! module_init {
             #[cfg_attr(target_os = "windows", link_section = ".CRT$XCU")]
             pub static LOAD_MODULE: extern "C" fn() = {
                 extern "C" fn init_fn() {
-                    $body
+                    $crate::callbacks::abort_on_panic(|| $body)
                 }
 
                 extern "C" fn ctor_fn() {
@@ -0,0 +1,367 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for network interface cards.
+//!
+//! A [`Nic`] is embedded in the state of a network device, and is
+//! connected to a network backend (`-netdev`) by a `netdev` property,
+//! defined with [`define_netdev_property!`](crate::define_netdev_property).
+//! When the device is realized, it creates the C `NICState` and registers
+//! itself as the recipient of incoming frames by implementing
+//! [`NicHandlers`]:
+//!
+//! ```ignore
+//! impl NicHandlers for MyNicState {
+//!     fn receive(&self, frame: &[u8]) -> usize { ... }
+//! }
+//!
+//! // SAFETY: the NIC is a field of `self`
+//! unsafe { self.nic.init(self) };
+//! // ... and later, when the guest sends a frame
+//! self.nic.transmit(&frame);
+//! ```
+//!
+//! The `NICState` is deleted when the `Nic` is dropped, which for QOM
+//! objects happens when the object is finalized.
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    mem,
+    os::raw::{c_int, c_void},
+    ptr::{self, addr_of_mut, NonNull},
+    slice,
+};
+
+pub use bindings::{MACAddr, NICConf, NICState, NetClientState};
+
+use crate::{
+    bindings::{self, NICPeers},
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    prelude::*,
+    qdev::DeviceState,
+    zeroable::Zeroable,
+};
+
+/// Callbacks through which a NIC receives frames and events from its
+/// network backend.  They are registered with [`Nic::init`], and they
+/// always run under the Big QEMU Lock.
+pub trait NicHandlers {
+    /// Return whether the NIC is ready to accept a frame.  When it returns
+    /// false, the backend queues the frames it receives; when the NIC can
+    /// accept them, it must call [`Nic::flush_queued_packets`].  The
+    /// default implementation always accepts frames.
+    fn can_receive(&self) -> bool {
+        true
+    }
+
+    /// Process a frame coming from the backend, and return `frame.len()`
+    /// if it was consumed, whether it was stored in guest memory or
+    /// dropped.  Returning zero asks the backend to queue the frame, as if
+    /// [`can_receive`](NicHandlers::can_receive) had returned false.
+    fn receive(&self, frame: &[u8]) -> usize;
+
+    /// Called when the link goes up or down, for example because of the
+    /// `set_link` monitor command.  The default implementation does
+    /// nothing.
+    fn link_status_changed(&self, _link_up: bool) {}
+}
+
+/// Return the owner of the NIC that `nc` belongs to.
+///
+/// # Safety
+///
+/// `nc` must be one of the queues of a NIC created by [`Nic::init`] with
+/// an owner of type `T`.
+unsafe fn nic_owner<'a, T>(nc: *mut NetClientState) -> &'a T {
+    // SAFETY: this is the same as qemu_get_nic_opaque(); the queues of a
+    // NIC are an array that follows the NICState, whose size is in the
+    // NetClientInfo
+    unsafe {
+        let nc0 = nc.sub((*nc).queue_index as usize);
+        let nic = nc0.cast::<u8>().sub((*(*nc).info).size).cast::<NICState>();
+        NonNull::new((*nic).opaque).unwrap().cast::<T>().as_ref()
+    }
+}
+
+unsafe extern "C" fn rust_nic_can_receive_fn<T: NicHandlers>(nc: *mut NetClientState) -> bool {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        // SAFETY: the callbacks are only installed by Nic::init::<T>()
+        unsafe { nic_owner::<T>(nc) }.can_receive()
+    })
+}
+
+unsafe extern "C" fn rust_nic_receive_fn<T: NicHandlers>(
+    nc: *mut NetClientState,
+    buf: *const u8,
+    size: usize,
+) -> isize {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let frame = if size > 0 {
+            // SAFETY: the backend passes a frame of `size` bytes
+            unsafe { slice::from_raw_parts(buf, size) }
+        } else {
+            &[]
+        };
+        // SAFETY: the callbacks are only installed by Nic::init::<T>()
+        let consumed = unsafe { nic_owner::<T>(nc) }.receive(frame);
+        consumed.min(size) as isize
+    })
+}
+
+unsafe extern "C" fn rust_nic_link_status_changed_fn<T: NicHandlers>(nc: *mut NetClientState) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        // SAFETY: the callbacks are only installed by Nic::init::<T>(), and
+        // the backend passes a valid queue
+        let (owner, link_up) = unsafe { (nic_owner::<T>(nc), (*nc).link_down == 0) };
+        owner.link_status_changed(link_up);
+    })
+}
+
+struct NetClientInfoHolder<T>(T);
+
+impl<T: NicHandlers> NetClientInfoHolder<T> {
+    const INFO: bindings::NetClientInfo = bindings::NetClientInfo {
+        type_: bindings::NET_CLIENT_DRIVER_NIC,
+        size: mem::size_of::<NICState>(),
+        receive: Some(rust_nic_receive_fn::<T>),
+        can_receive: Some(rust_nic_can_receive_fn::<T>),
+        link_status_changed: Some(rust_nic_link_status_changed_fn::<T>),
+        ..Zeroable::ZERO
+    };
+}
+
+/// Return the `NetClientInfo` that forwards the callbacks of a NIC to
+/// `T`'s implementation of [`NicHandlers`].
+pub const fn net_client_info<T: NicHandlers>() -> &'static bindings::NetClientInfo {
+    &NetClientInfoHolder::<T>::INFO
+}
+
+/// The network side of a NIC model, to be embedded in the state of the
+/// device.  Its configuration is set by the `netdev` property, and the
+/// C `NICState` is created by [`init`](Nic::init) when the device is
+/// realized.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Nic {
+    // must be first, for the offset computed by define_netdev_property!
+    conf: UnsafeCell<NICConf>,
+    nic: Cell<*mut NICState>,
+}
+
+// SAFETY: the configuration is only written by the qdev properties and
+// by init(), and the NICState is only used under the Big QEMU Lock
+unsafe impl Send for Nic {}
+unsafe impl Sync for Nic {}
+
+impl Nic {
+    /// The offset of the `NICPeers` that the `netdev` property fills in,
+    /// from the start of the `Nic`.  `NICConf` starts with a `MACAddr`,
+    /// which is followed by the peers.
+    #[doc(hidden)]
+    pub const PEERS_OFFSET: usize = {
+        let align = mem::align_of::<NICPeers>();
+        (mem::size_of::<MACAddr>() + align - 1) & !(align - 1)
+    };
+
+    /// Create the C `NICState`, pick a MAC address if none was given,
+    /// and register the [`NicHandlers`] of `owner`.  This is usually
+    /// called when `owner` is realized; the NIC is connected to the
+    /// backend named by the `netdev` property, if any.
+    ///
+    /// # Safety
+    ///
+    /// `owner` must remain valid until the `Nic` is dropped, which deletes
+    /// the `NICState`.  This is the case if `self` is a field of `owner`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the NIC was already initialized.
+    pub unsafe fn init<T: NicHandlers + IsA<DeviceState>>(&self, owner: &T) {
+        assert!(bql_locked());
+        assert!(self.nic.get().is_null());
+        let info = net_client_info::<T>();
+        // SAFETY: the callbacks match the type of the opaque; the
+        // configuration lives as long as the NICState, the caller
+        // guarantees the same for the owner, and the strings are copied
+        unsafe {
+            let dev = owner.as_mut_ptr::<DeviceState>();
+            bindings::qemu_macaddr_default_if_unset(addr_of_mut!((*self.conf.get()).macaddr));
+            let nic = bindings::qemu_new_nic(
+                ptr::addr_of!(*info) as *mut _,
+                self.conf.get(),
+                bindings::object_get_typename(dev.cast::<Object>()),
+                (*dev).id,
+                addr_of_mut!((*dev).mem_reentrancy_guard),
+                ptr::addr_of!(*owner).cast::<c_void>() as *mut c_void,
+            );
+            bindings::qemu_format_nic_info_str(
+                bindings::qemu_get_queue(nic),
+                (*self.conf.get()).macaddr.a.as_mut_ptr(),
+            );
+            self.nic.set(nic);
+        }
+    }
+
+    /// Return the MAC address of the NIC.  Until [`init`](Nic::init) is
+    /// called, it is all zeroes unless it was set by the user.
+    pub fn mac_address(&self) -> [u8; 6] {
+        // SAFETY: the MAC address is only written by the qdev property
+        // and by init(), under the Big QEMU Lock
+        unsafe { (*self.conf.get()).macaddr.a }
+    }
+
+    /// Send a frame to the backend.  If the backend cannot accept it at
+    /// the moment, the frame is queued; if the NIC is not connected to any
+    /// backend, it is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the NIC was not initialized, or if the frame is larger
+    /// than 2 GiB.
+    pub fn transmit(&self, frame: &[u8]) {
+        let len = c_int::try_from(frame.len()).unwrap();
+        // SAFETY: the frame is valid for `len` bytes, and it is copied if
+        // it has to be queued
+        unsafe {
+            bindings::qemu_send_packet(self.queue(), frame.as_ptr(), len);
+        }
+    }
+
+    /// Tell the backend that the NIC can accept frames again, after
+    /// [`can_receive`](NicHandlers::can_receive) returned false or
+    /// [`receive`](NicHandlers::receive) returned zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the NIC was not initialized.
+    pub fn flush_queued_packets(&self) {
+        assert!(bql_locked());
+        // SAFETY: the queue is valid until the NICState is deleted
+        unsafe {
+            bindings::qemu_flush_queued_packets(self.queue());
+        }
+    }
+
+    fn queue(&self) -> *mut NetClientState {
+        let nic = self.nic.get();
+        assert!(!nic.is_null(), "NIC used before Nic::init()");
+        // SAFETY: the NICState is valid until self is dropped
+        unsafe { bindings::qemu_get_queue(nic) }
+    }
+}
+
+impl Default for Nic {
+    fn default() -> Self {
+        Nic {
+            conf: UnsafeCell::new(Default::default()),
+            nic: Cell::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl Drop for Nic {
+    fn drop(&mut self) {
+        let nic = self.nic.get();
+        if !nic.is_null() {
+            // SAFETY: the NICState was created by init() and has not been
+            // deleted yet
+            unsafe {
+                bindings::qemu_del_nic(nic);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::addr_of;
+
+    use super::*;
+    use crate::cell::{bql_start_test, BqlCell};
+
+    #[derive(Default)]
+    struct DummyNic {
+        ready: BqlCell<bool>,
+        frames: BqlCell<Vec<Vec<u8>>>,
+        link_up: BqlCell<Option<bool>>,
+    }
+
+    impl NicHandlers for DummyNic {
+        fn can_receive(&self) -> bool {
+            self.ready.get()
+        }
+
+        fn receive(&self, frame: &[u8]) -> usize {
+            let mut frames = self.frames.take();
+            frames.push(frame.to_vec());
+            self.frames.set(frames);
+            frame.len()
+        }
+
+        fn link_status_changed(&self, link_up: bool) {
+            self.link_up.set(Some(link_up));
+        }
+    }
+
+    /// The memory that `qemu_new_nic()` allocates for a NIC with a single
+    /// queue.
+    #[repr(C)]
+    struct DummyNicAllocation {
+        nic: NICState,
+        nc: NetClientState,
+    }
+
+    #[test]
+    fn test_peers_offset() {
+        let conf = NICConf::default();
+        let offset = addr_of!(conf.peers) as usize - addr_of!(conf) as usize;
+        assert_eq!(offset, Nic::PEERS_OFFSET);
+    }
+
+    // net/net.c is part of the system emulator rather than of a library
+    // that the tests link, so there is no qemu_new_nic() or backend to send
+    // frames to; the test calls the NetClientInfo callbacks directly, as
+    // qemu_deliver_packet_iov() and qemu_set_link() would, on a queue laid
+    // out like the one qemu_new_nic() allocates.
+    #[test]
+    fn test_nic_handlers() {
+        bql_start_test();
+        let owner = DummyNic::default();
+        let info = net_client_info::<DummyNic>();
+        assert_eq!(info.size, mem::size_of::<NICState>());
+
+        let mut alloc = DummyNicAllocation {
+            nic: NICState {
+                opaque: addr_of!(owner).cast::<c_void>() as *mut c_void,
+                ..Default::default()
+            },
+            nc: NetClientState {
+                info: addr_of!(*info) as *mut _,
+                ..Default::default()
+            },
+        };
+        let nc = addr_of_mut!(alloc.nc);
+
+        // SAFETY: the queue is laid out as if allocated by qemu_new_nic()
+        unsafe {
+            assert!(!info.can_receive.unwrap()(nc));
+            owner.ready.set(true);
+            assert!(info.can_receive.unwrap()(nc));
+
+            let frame = [0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff, 0x52, 0x54];
+            assert_eq!(info.receive.unwrap()(nc, frame.as_ptr(), frame.len()), 8);
+            assert_eq!(owner.frames.take(), vec![frame.to_vec()]);
+
+            (*nc).link_down = 1;
+            info.link_status_changed.unwrap()(nc);
+            assert_eq!(owner.link_up.get(), Some(false));
+            (*nc).link_down = 0;
+            info.link_status_changed.unwrap()(nc);
+            assert_eq!(owner.link_up.get(), Some(true));
+        }
+    }
+}
@@ -0,0 +1,283 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for PCI devices.
+//!
+//! A PCI device is a subclass of [`PCIDevice`] that implements
+//! [`PciDeviceImpl`], and lists the kind of bus it can be plugged into
+//! among its interfaces:
+//!
+//! ```ignore
+//! impl ObjectImpl for MyPciState {
+//!     type ParentType = PCIDevice;
+//!     const INTERFACES: &'static [InterfaceInfo] =
+//!         interfaces![INTERFACE_CONVENTIONAL_PCI_DEVICE];
+//! }
+//!
+//! impl PciDeviceImpl for MyPciState {
+//!     const VENDOR_ID: u16 = 0x1b36;
+//!     const DEVICE_ID: u16 = 0x0100;
+//! }
+//!
+//! impl MyPciState {
+//!     fn realize(&self) -> Result<()> {
+//!         self.register_bar(0, BarType::Memory, &self.iomem);
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! [`DeviceImpl::REALIZE`] and [`DeviceImpl::UNREALIZE`] are called by the
+//! PCI core, after it has set up the configuration space and before it
+//! tears it down, respectively.
+
+use std::{ffi::CStr, os::raw::c_int, ptr::NonNull};
+
+pub use bindings::{
+    PCIDevice, PCIDeviceClass, INTERFACE_CONVENTIONAL_PCI_DEVICE, INTERFACE_PCIE_DEVICE,
+};
+
+use crate::{
+    bindings,
+    bql::assert_bql_locked,
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    error::bql_error_propagate,
+    memory::MemoryRegion,
+    prelude::*,
+    qdev::{DeviceClass, DeviceImpl, DeviceState},
+    qom::ClassInitImpl,
+};
+
+unsafe impl ObjectType for PCIDevice {
+    type Class = PCIDeviceClass;
+    const TYPE_NAME: &'static CStr =
+        unsafe { CStr::from_bytes_with_nul_unchecked(bindings::TYPE_PCI_DEVICE) };
+}
+qom_isa!(PCIDevice: DeviceState, Object);
+
+/// Trait for devices whose QOM parent is [`PCIDevice`], providing the
+/// contents of [`PCIDeviceClass`].
+pub trait PciDeviceImpl: DeviceImpl + IsA<PCIDevice> {
+    /// The vendor ID in the configuration space.
+    const VENDOR_ID: u16;
+
+    /// The device ID in the configuration space.
+    const DEVICE_ID: u16;
+
+    /// The class code in the configuration space, for example
+    /// `PCI_CLASS_OTHERS`.
+    const CLASS_ID: u16 = bindings::PCI_CLASS_OTHERS as u16;
+
+    /// The revision ID in the configuration space.
+    const REVISION: u8 = 0;
+
+    /// The subsystem vendor ID in the configuration space.  If zero, QEMU
+    /// uses its own vendor ID.
+    const SUBSYSTEM_VENDOR_ID: u16 = 0;
+
+    /// The subsystem ID in the configuration space.  If zero, QEMU uses
+    /// its default subsystem ID.
+    const SUBSYSTEM_ID: u16 = 0;
+
+    /// If not `None`, this is called for reads of `len` bytes at `address`
+    /// in the configuration space, instead of
+    /// [`default_config_read`](PciDeviceMethods::default_config_read).
+    const CONFIG_READ: Option<fn(&Self, address: u32, len: u32) -> u32> = None;
+
+    /// If not `None`, this is called for writes of `len` bytes at `address`
+    /// in the configuration space, instead of
+    /// [`default_config_write`](PciDeviceMethods::default_config_write).
+    /// Usually the function calls `default_config_write` too, and then
+    /// reacts to the new value of the registers.
+    const CONFIG_WRITE: Option<fn(&Self, address: u32, value: u32, len: u32)> = None;
+}
+
+unsafe extern "C" fn rust_pci_realize_fn<T: PciDeviceImpl>(
+    dev: *mut PCIDevice,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(dev).unwrap().cast::<T>();
+        let result = T::REALIZE.unwrap()(unsafe { state.as_ref() });
+        unsafe {
+            bql_error_propagate(result, errp);
+        }
+    })
+}
+
+unsafe extern "C" fn rust_pci_exit_fn<T: PciDeviceImpl>(dev: *mut PCIDevice) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(dev).unwrap().cast::<T>();
+        T::UNREALIZE.unwrap()(unsafe { state.as_ref() });
+    })
+}
+
+unsafe extern "C" fn rust_pci_config_read_fn<T: PciDeviceImpl>(
+    dev: *mut PCIDevice,
+    address: u32,
+    len: c_int,
+) -> u32 {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(dev).unwrap().cast::<T>();
+        T::CONFIG_READ.unwrap()(unsafe { state.as_ref() }, address, len as u32)
+    })
+}
+
+unsafe extern "C" fn rust_pci_config_write_fn<T: PciDeviceImpl>(
+    dev: *mut PCIDevice,
+    address: u32,
+    value: u32,
+    len: c_int,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(dev).unwrap().cast::<T>();
+        T::CONFIG_WRITE.unwrap()(unsafe { state.as_ref() }, address, value, len as u32);
+    })
+}
+
+impl<T> ClassInitImpl<PCIDeviceClass> for T
+where
+    T: PciDeviceImpl + ClassInitImpl<DeviceClass>,
+{
+    fn class_init(pc: &mut PCIDeviceClass) {
+        // The realize and unrealize methods of DeviceClass belong to the
+        // PCI core, which calls the ones in PCIDeviceClass.
+        let realize = pc.parent_class.realize;
+        let unrealize = pc.parent_class.unrealize;
+        <T as ClassInitImpl<DeviceClass>>::class_init(&mut pc.parent_class);
+        pc.parent_class.realize = realize;
+        pc.parent_class.unrealize = unrealize;
+
+        if <T as DeviceImpl>::REALIZE.is_some() {
+            pc.realize = Some(rust_pci_realize_fn::<T>);
+        }
+        if <T as DeviceImpl>::UNREALIZE.is_some() {
+            pc.exit = Some(rust_pci_exit_fn::<T>);
+        }
+        if <T as PciDeviceImpl>::CONFIG_READ.is_some() {
+            pc.config_read = Some(rust_pci_config_read_fn::<T>);
+        }
+        if <T as PciDeviceImpl>::CONFIG_WRITE.is_some() {
+            pc.config_write = Some(rust_pci_config_write_fn::<T>);
+        }
+        pc.vendor_id = T::VENDOR_ID;
+        pc.device_id = T::DEVICE_ID;
+        pc.class_id = T::CLASS_ID;
+        pc.revision = T::REVISION;
+        pc.subsystem_vendor_id = T::SUBSYSTEM_VENDOR_ID;
+        pc.subsystem_id = T::SUBSYSTEM_ID;
+    }
+}
+
+/// The kind of address space that a BAR is mapped into.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BarType {
+    /// I/O ports.
+    Io,
+    /// Memory below 4 GiB.
+    Memory,
+    /// Prefetchable memory below 4 GiB.
+    MemoryPrefetch,
+    /// Memory anywhere in the 64-bit address space.  The BAR also uses
+    /// the next slot, which must not be registered.
+    Memory64,
+    /// Prefetchable memory anywhere in the 64-bit address space.
+    Memory64Prefetch,
+}
+
+impl BarType {
+    /// Return the low bits of the BAR, as passed to `pci_register_bar()`.
+    const fn attr(self) -> u8 {
+        (match self {
+            BarType::Io => bindings::PCI_BASE_ADDRESS_SPACE_IO,
+            BarType::Memory => bindings::PCI_BASE_ADDRESS_SPACE_MEMORY,
+            BarType::MemoryPrefetch => {
+                bindings::PCI_BASE_ADDRESS_SPACE_MEMORY | bindings::PCI_BASE_ADDRESS_MEM_PREFETCH
+            }
+            BarType::Memory64 => {
+                bindings::PCI_BASE_ADDRESS_SPACE_MEMORY | bindings::PCI_BASE_ADDRESS_MEM_TYPE_64
+            }
+            BarType::Memory64Prefetch => {
+                bindings::PCI_BASE_ADDRESS_SPACE_MEMORY
+                    | bindings::PCI_BASE_ADDRESS_MEM_TYPE_64
+                    | bindings::PCI_BASE_ADDRESS_MEM_PREFETCH
+            }
+        }) as u8
+    }
+}
+
+/// Trait for methods of [`PCIDevice`] and its subclasses.
+pub trait PciDeviceMethods: ObjectDeref
+where
+    Self::Target: IsA<PCIDevice>,
+{
+    /// Make `region` available to the guest through the BAR number `index`,
+    /// as in `pci_register_bar()`.  The guest maps the BAR by writing its
+    /// address to the configuration space.  This is usually called from
+    /// the device's `realize` method, for regions that are part of the
+    /// device.
+    ///
+    /// The size of `region` must be a power of two.
+    fn register_bar(&self, index: u32, bar_type: BarType, region: &MemoryRegion) {
+        assert!(bql_locked());
+        assert!(index < bindings::PCI_NUM_REGIONS);
+        // SAFETY: the region lives as long as the device that contains it
+        unsafe {
+            bindings::pci_register_bar(
+                self.as_mut_ptr(),
+                index as c_int,
+                bar_type.attr(),
+                region.as_mut_ptr(),
+            );
+        }
+    }
+
+    /// Set the level of the device's `INTx` interrupt pin, as in
+    /// `pci_set_irq()`.
+    fn set_irq(&self, level: bool) {
+        assert!(bql_locked());
+        unsafe {
+            bindings::pci_set_irq(self.as_mut_ptr(), level.into());
+        }
+    }
+
+    /// Read `len` bytes at `address` in the configuration space, with the
+    /// behavior that QEMU uses for devices that do not override
+    /// [`CONFIG_READ`](PciDeviceImpl::CONFIG_READ).
+    fn default_config_read(&self, address: u32, len: u32) -> u32 {
+        assert!(bql_locked());
+        unsafe { bindings::pci_default_read_config(self.as_mut_ptr(), address, len as c_int) }
+    }
+
+    /// Write `len` bytes of `value` at `address` in the configuration
+    /// space, with the behavior that QEMU uses for devices that do not
+    /// override [`CONFIG_WRITE`](PciDeviceImpl::CONFIG_WRITE).  This
+    /// includes remapping the BARs and updating the interrupt state.
+    fn default_config_write(&self, address: u32, value: u32, len: u32) {
+        assert!(bql_locked());
+        unsafe {
+            bindings::pci_default_write_config(self.as_mut_ptr(), address, value, len as c_int);
+        }
+    }
+}
+
+impl<R: ObjectDeref> PciDeviceMethods for R where R::Target: IsA<PCIDevice> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bar_type() {
+        assert_eq!(BarType::Io.attr(), 0x01);
+        assert_eq!(BarType::Memory.attr(), 0x00);
+        assert_eq!(BarType::MemoryPrefetch.attr(), 0x08);
+        assert_eq!(BarType::Memory64.attr(), 0x04);
+        assert_eq!(BarType::Memory64Prefetch.attr(), 0x0c);
+    }
+}
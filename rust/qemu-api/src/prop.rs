@@ -0,0 +1,440 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Safe selection of the `PropertyInfo` for qdev properties.
+//!
+//! The `info` member of a [`Property`](crate::bindings::Property) points to
+//! one of the `qdev_prop_*` globals that are defined in C.  Referring to them
+//! requires an `unsafe` block because they are foreign statics; [`PropKind`]
+//! lets [`define_property!`](crate::define_property) pick the right one
+//! without exposing the unsafety to device code:
+//!
+//! ```ignore
+//! define_property!(c_str!("freq"), MyState, freq, PropKind::U32, u32, default = 100)
+//! ```
+//!
+//! Unsigned integer properties can also be limited to a range of values,
+//! in which case the `PropertyInfo` is provided by [`PropRange`]:
+//!
+//! ```ignore
+//! define_property!(c_str!("level"), MyState, level, u32,
+//!                  min = 1, max = 15, default = 8)
+//! ```
+//!
+//! or to the values whose bits are all in a mask, with
+//! [`define_masked_property!`](crate::define_masked_property) and
+//! [`PropMask`].
+//!
+//! Enum properties are set by name, using the `QEnumLookup` that QAPI
+//! generates for the enum, and store the value of the enum in an `i32`
+//! field; [`define_enum_property!`](crate::define_enum_property) builds
+//! their `PropertyInfo` with [`enum_info`].
+
+use std::{
+    ffi::CStr,
+    marker::PhantomData,
+    mem,
+    os::raw::{c_char, c_int, c_void},
+    ptr::{self, addr_of_mut},
+};
+
+use crate::{
+    bindings::{self, Object, ObjectProperty, Property, PropertyInfo, QEnumLookup, Visitor},
+    c_str,
+    callbacks::abort_on_panic,
+    error::Error,
+    zeroable::Zeroable,
+};
+
+/// The kinds of property that can be passed to
+/// [`define_property!`](crate::define_property) instead of a raw pointer
+/// to a `PropertyInfo`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PropKind {
+    /// `qdev_prop_bool`, for `bool` fields
+    Bool,
+    /// `qdev_prop_uint8`, for `u8` fields
+    U8,
+    /// `qdev_prop_uint16`, for `u16` fields
+    U16,
+    /// `qdev_prop_uint32`, for `u32` fields
+    U32,
+    /// `qdev_prop_uint64`, for `u64` fields
+    U64,
+    /// `qdev_prop_int32`, for `i32` fields
+    I32,
+    /// `qdev_prop_size`, for `u64` fields that accept suffixes such as `4k`
+    Size,
+    /// `qdev_prop_string`, for `*mut c_char` fields
+    String,
+}
+
+/// Workaround for lack of `const_refs_static`.  Converts a [`PropKind`]
+/// to a `*const PropertyInfo`, for inclusion in a `Property`.
+#[macro_export]
+macro_rules! prop_kind_to_ref {
+    ($e:expr) => {{
+        let kind: $crate::prop::PropKind = $e;
+        // SAFETY: the qdev_prop_* statics are immutable once defined in C
+        #[allow(unused_unsafe)]
+        unsafe {
+            match kind {
+                $crate::prop::PropKind::Bool => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_bool)
+                }
+                $crate::prop::PropKind::U8 => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_uint8)
+                }
+                $crate::prop::PropKind::U16 => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_uint16)
+                }
+                $crate::prop::PropKind::U32 => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_uint32)
+                }
+                $crate::prop::PropKind::U64 => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_uint64)
+                }
+                $crate::prop::PropKind::I32 => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_int32)
+                }
+                $crate::prop::PropKind::Size => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_size)
+                }
+                $crate::prop::PropKind::String => {
+                    ::core::ptr::addr_of!($crate::bindings::qdev_prop_string)
+                }
+            }
+        }
+    }};
+}
+
+impl PropKind {
+    /// Return the `PropertyInfo` that QEMU uses for properties of this kind.
+    pub fn info(self) -> *const PropertyInfo {
+        crate::prop_kind_to_ref!(self)
+    }
+
+    /// Return the size of the fields that the `PropertyInfo` for this kind
+    /// reads and writes.
+    pub const fn field_size(self) -> usize {
+        match self {
+            PropKind::Bool => mem::size_of::<bool>(),
+            PropKind::U8 => mem::size_of::<u8>(),
+            PropKind::U16 => mem::size_of::<u16>(),
+            PropKind::U32 => mem::size_of::<u32>(),
+            PropKind::I32 => mem::size_of::<i32>(),
+            PropKind::U64 | PropKind::Size => mem::size_of::<u64>(),
+            PropKind::String => mem::size_of::<*mut c_char>(),
+        }
+    }
+}
+
+/// Internal utility function used by [`define_property!`](crate::define_property)
+/// to retrieve the size of a field.
+#[doc(hidden)]
+pub const fn size_of_field<T>(_: PhantomData<T>) -> usize {
+    mem::size_of::<T>()
+}
+
+/// Internal utility function used by
+/// [`define_array_property!`](crate::define_array_property) to retrieve the
+/// size of the elements that a pointer field points to.
+#[doc(hidden)]
+pub const fn size_of_pointee<T>(_: PhantomData<*mut T>) -> usize {
+    mem::size_of::<T>()
+}
+
+/// Types that can be the default value of an integer or boolean
+/// property.  Signed values are stored in `defval.i` and unsigned ones in
+/// `defval.u`, which is where the `PropertyInfo` for the type reads them
+/// from.
+///
+/// # Safety
+///
+/// The type must be `bool` or a primitive integer type, and `SIGNED`
+/// must say whether it is signed.  [`prop_default`] reads the value as
+/// the integer type with the same size and signedness.
+pub unsafe trait PropDefault: Copy {
+    const SIGNED: bool;
+}
+
+macro_rules! impl_prop_default {
+    ($signed:expr, $($type:ty),*) => {
+        $(unsafe impl PropDefault for $type {
+            const SIGNED: bool = $signed;
+        })*
+    };
+}
+
+impl_prop_default!(false, bool, u8, u16, u32, u64);
+impl_prop_default!(true, i8, i16, i32, i64);
+
+/// Encode `value` as the default of a property of type `T`.  This is what
+/// [`define_property!`](crate::define_property) uses for `default = ...`,
+/// so the default can be any `const` expression of the field's type:
+///
+/// ```
+/// # use qemu_api::prop::prop_default;
+/// const WIDTH: u32 = 10;
+/// const MASK: u16 = ((1 << WIDTH) - 1) << 4;
+/// assert_eq!(unsafe { prop_default::<u16>(MASK).u }, 0x3ff0);
+/// assert_eq!(unsafe { prop_default::<i16>(-2).i }, -2);
+/// assert_eq!(unsafe { prop_default::<u16>(0xfffe).u }, 0xfffe);
+/// ```
+pub const fn prop_default<T: PropDefault>(value: T) -> bindings::Property__bindgen_ty_1 {
+    use bindings::Property__bindgen_ty_1 as Defval;
+
+    let p = ptr::addr_of!(value);
+    // SAFETY: `T` is `bool` or an integer type, so it has the same size
+    // and representation as the integer type it is read as
+    unsafe {
+        match (T::SIGNED, mem::size_of::<T>()) {
+            (false, 1) => Defval {
+                u: *p.cast::<u8>() as u64,
+            },
+            (false, 2) => Defval {
+                u: *p.cast::<u16>() as u64,
+            },
+            (false, 4) => Defval {
+                u: *p.cast::<u32>() as u64,
+            },
+            (false, 8) => Defval {
+                u: *p.cast::<u64>(),
+            },
+            (true, 1) => Defval {
+                i: *p.cast::<i8>() as i64,
+            },
+            (true, 2) => Defval {
+                i: *p.cast::<i16>() as i64,
+            },
+            (true, 4) => Defval {
+                i: *p.cast::<i32>() as i64,
+            },
+            (true, 8) => Defval {
+                i: *p.cast::<i64>(),
+            },
+            _ => panic!("property defaults are 1, 2, 4 or 8 bytes wide"),
+        }
+    }
+}
+
+/// Unsigned integer types that can be used with [`PropRange`].
+pub trait PropRangeType: Copy + Default + Into<u64> {
+    /// The name of the property type, as shown in the help for a device.
+    const NAME: &'static CStr;
+
+    /// The largest value of the type.
+    const MAX: u64;
+
+    /// Visit a value of this type with the corresponding `visit_type_*`
+    /// function.
+    ///
+    /// # Safety
+    ///
+    /// The arguments must be valid for the `visit_type_*` function.
+    unsafe fn visit(
+        v: *mut Visitor,
+        name: *const c_char,
+        obj: *mut Self,
+        errp: *mut *mut bindings::Error,
+    ) -> bool;
+}
+
+macro_rules! impl_prop_range_type {
+    ($type:ty, $name:expr, $visit:ident) => {
+        impl PropRangeType for $type {
+            const NAME: &'static CStr = $name;
+            const MAX: u64 = <$type>::MAX as u64;
+
+            unsafe fn visit(
+                v: *mut Visitor,
+                name: *const c_char,
+                obj: *mut Self,
+                errp: *mut *mut bindings::Error,
+            ) -> bool {
+                unsafe { bindings::$visit(v, name, obj, errp) }
+            }
+        }
+    };
+}
+
+impl_prop_range_type!(u8, c_str!("uint8"), visit_type_uint8);
+impl_prop_range_type!(u16, c_str!("uint16"), visit_type_uint16);
+impl_prop_range_type!(u32, c_str!("uint32"), visit_type_uint32);
+impl_prop_range_type!(u64, c_str!("uint64"), visit_type_uint64);
+
+/// Provides the `PropertyInfo` for a property of type `T` that only
+/// accepts values between `MIN` and `MAX` (inclusive).  Setting the
+/// property to a value out of the range fails with an error.
+///
+/// This is used by [`define_property!`](crate::define_property) when
+/// `min` and `max` are specified.
+pub struct PropRange<T, const MIN: u64, const MAX: u64>(PhantomData<T>);
+
+impl<T: PropRangeType, const MIN: u64, const MAX: u64> PropRange<T, MIN, MAX> {
+    pub const INFO: PropertyInfo = {
+        assert!(MIN <= MAX);
+        assert!(MAX <= T::MAX);
+        PropertyInfo {
+            name: T::NAME.as_ptr(),
+            set_default_value: Some(prop_set_default_value_uint),
+            get: Some(prop_get_uint::<T>),
+            set: Some(prop_set_uint_range::<T, MIN, MAX>),
+            ..Zeroable::ZERO
+        }
+    };
+}
+
+unsafe extern "C" fn prop_set_default_value_uint(op: *mut ObjectProperty, prop: *const Property) {
+    // SAFETY: called by QEMU with a valid property whose default is
+    // stored in defval.u
+    unsafe {
+        bindings::object_property_set_default_uint(op, (*prop).defval.u);
+    }
+}
+
+unsafe extern "C" fn prop_get_uint<T: PropRangeType>(
+    obj: *mut Object,
+    v: *mut Visitor,
+    name: *const c_char,
+    opaque: *mut c_void,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        // SAFETY: opaque is the Property, whose offset points to a field of type T
+        unsafe {
+            let ptr = bindings::object_field_prop_ptr(obj, opaque.cast::<Property>()).cast::<T>();
+            T::visit(v, name, ptr, errp);
+        }
+    })
+}
+
+unsafe extern "C" fn prop_set_uint_range<T: PropRangeType, const MIN: u64, const MAX: u64>(
+    obj: *mut Object,
+    v: *mut Visitor,
+    name: *const c_char,
+    opaque: *mut c_void,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        let mut value = T::default();
+        // SAFETY: opaque is the Property, whose offset points to a field of type T
+        unsafe {
+            if !T::visit(v, name, addr_of_mut!(value), errp) {
+                return;
+            }
+            let n: u64 = value.into();
+            if !(MIN..=MAX).contains(&n) {
+                let msg = format!(
+                    "Property {}.{} doesn't take value {} (minimum: {}, maximum: {})",
+                    CStr::from_ptr(bindings::object_get_typename(obj)).to_string_lossy(),
+                    CStr::from_ptr(name).to_string_lossy(),
+                    n,
+                    MIN,
+                    MAX
+                );
+                Error::setg(errp, Error::new(&msg));
+                return;
+            }
+            let ptr = bindings::object_field_prop_ptr(obj, opaque.cast::<Property>()).cast::<T>();
+            *ptr = value;
+        }
+    })
+}
+
+/// Provides the `PropertyInfo` for a property of type `T` that only
+/// accepts values whose bits are all set in `MASK`.  Setting the property
+/// to a value with other bits fails with an error and leaves the field
+/// unchanged.
+///
+/// This is used by [`define_masked_property!`](crate::define_masked_property).
+pub struct PropMask<T, const MASK: u64>(PhantomData<T>);
+
+impl<T: PropRangeType, const MASK: u64> PropMask<T, MASK> {
+    pub const INFO: PropertyInfo = {
+        assert!(MASK <= T::MAX);
+        PropertyInfo {
+            name: T::NAME.as_ptr(),
+            set_default_value: Some(prop_set_default_value_uint),
+            get: Some(prop_get_uint::<T>),
+            set: Some(prop_set_uint_mask::<T, MASK>),
+            ..Zeroable::ZERO
+        }
+    };
+}
+
+unsafe extern "C" fn prop_set_uint_mask<T: PropRangeType, const MASK: u64>(
+    obj: *mut Object,
+    v: *mut Visitor,
+    name: *const c_char,
+    opaque: *mut c_void,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        let mut value = T::default();
+        // SAFETY: opaque is the Property, whose offset points to a field of type T
+        unsafe {
+            if !T::visit(v, name, addr_of_mut!(value), errp) {
+                return;
+            }
+            let n: u64 = value.into();
+            if n & !MASK != 0 {
+                let msg = format!(
+                    "Property value for '{}' has bits outside mask '{:#x}'",
+                    CStr::from_ptr(name).to_string_lossy(),
+                    MASK
+                );
+                Error::setg(errp, Error::new(&msg));
+                return;
+            }
+            let ptr = bindings::object_field_prop_ptr(obj, opaque.cast::<Property>()).cast::<T>();
+            *ptr = value;
+        }
+    })
+}
+
+/// Return a `PropertyInfo` for an enum property whose names are listed in
+/// `lookup`, like `qdev_prop_enum` in C but with its own `enum_table`.
+/// The field behind the property must be an `i32`.
+///
+/// This is used by [`define_enum_property!`](crate::define_enum_property).
+pub const fn enum_info(lookup: *const QEnumLookup) -> PropertyInfo {
+    PropertyInfo {
+        name: c_str!("enum").as_ptr(),
+        enum_table: lookup,
+        set_default_value: Some(prop_set_default_value_enum),
+        get: Some(prop_visit_enum),
+        set: Some(prop_visit_enum),
+        ..Zeroable::ZERO
+    }
+}
+
+unsafe extern "C" fn prop_set_default_value_enum(op: *mut ObjectProperty, prop: *const Property) {
+    // SAFETY: called by QEMU with a valid property whose default is
+    // stored in defval.i, and whose info has an enum_table
+    unsafe {
+        let name =
+            bindings::qapi_enum_lookup((*(*prop).info).enum_table, (*prop).defval.i as c_int);
+        bindings::object_property_set_default_str(op, name);
+    }
+}
+
+/// Both the getter and the setter; `visit_type_enum` converts between
+/// names and values in either direction, depending on the visitor.
+unsafe extern "C" fn prop_visit_enum(
+    obj: *mut Object,
+    v: *mut Visitor,
+    name: *const c_char,
+    opaque: *mut c_void,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        // SAFETY: opaque is the Property, whose offset points to an i32 field
+        // and whose info has an enum_table
+        unsafe {
+            let prop = opaque.cast::<Property>();
+            let ptr = bindings::object_field_prop_ptr(obj, prop).cast::<c_int>();
+            bindings::visit_type_enum(v, name, ptr, (*(*prop).info).enum_table, errp);
+        }
+    })
+}
@@ -0,0 +1,302 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for `ptimer`, the down-counter that models most periodic and
+//! one-shot hardware timers.
+//!
+//! A [`PTimer`] is embedded in the state of a device and initialized with
+//! the function that runs when the counter reaches zero.  A ptimer is only
+//! reconfigured within a transaction, so that the effect of several
+//! changes is evaluated only once:
+//!
+//! ```ignore
+//! self.timer.init(&Self::timer_expired, self, PTIMER_POLICY_LEGACY);
+//! self.timer.modify(|t| {
+//!     t.set_freq(1_000_000);
+//!     t.set_limit(1000, true);
+//!     t.run(false);
+//! });
+//! ```
+//!
+//! The counter is migrated with [`vmstate_ptimer!`](crate::vmstate_ptimer),
+//! and the ptimer is freed when the `PTimer` is dropped, which for QOM
+//! objects happens when the object is finalized.
+
+use std::{
+    cell::Cell,
+    os::raw::{c_int, c_void},
+    ptr::{self, NonNull},
+};
+
+pub use bindings::ptimer_state;
+
+use crate::{
+    bindings,
+    callbacks::{abort_on_panic, FnCall},
+    cell::bql_locked,
+};
+
+/// A wrapper around a C `ptimer_state`.
+///
+/// The expiry callback always runs under the Big QEMU Lock.
+#[derive(Debug)]
+#[repr(C)]
+pub struct PTimer {
+    // must be first, so that vmstate_ptimer! can migrate the ptimer
+    state: Cell<*mut ptimer_state>,
+    owner: Cell<*const c_void>,
+    in_transaction: Cell<bool>,
+}
+
+// SAFETY: the ptimer is only used under the Big QEMU Lock
+unsafe impl Send for PTimer {}
+unsafe impl Sync for PTimer {}
+
+unsafe extern "C" fn rust_ptimer_cb<T, F: for<'a> FnCall<(&'a T,)>>(opaque: *mut c_void) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        // SAFETY: the opaque was passed as a reference to the PTimer in
+        // PTimer::init, and the owner lives as long as the PTimer
+        let timer = unsafe { NonNull::new(opaque).unwrap().cast::<PTimer>().as_ref() };
+        let owner = unsafe { NonNull::new(timer.owner.get() as *mut T).unwrap().as_ref() };
+
+        // ptimer.c always calls the callback within a transaction
+        let was_in_transaction = timer.in_transaction.replace(true);
+        F::call((owner,));
+        timer.in_transaction.set(was_in_transaction);
+    })
+}
+
+impl PTimer {
+    /// Create a `PTimer` that is not initialized yet.  It must be
+    /// initialized with [`init`](PTimer::init) before it is used.
+    pub const fn new() -> Self {
+        PTimer {
+            state: Cell::new(ptr::null_mut()),
+            owner: Cell::new(ptr::null()),
+            in_transaction: Cell::new(false),
+        }
+    }
+
+    /// Create the ptimer.  When the counter reaches zero, `cb` is called
+    /// with `opaque` as the argument.  Any changes that `cb` makes to the
+    /// ptimer with [`modify`](PTimer::modify) are part of the transaction
+    /// that triggered it.
+    ///
+    /// `policy` is a combination of the `PTIMER_POLICY_*` constants in
+    /// [`bindings`](crate::bindings).
+    ///
+    /// The `_cb` parameter is unused; it only selects the function to call,
+    /// which must be a function item or a closure that captures nothing.
+    ///
+    /// `opaque` must live as long as the `PTimer`, and the `PTimer` must
+    /// not move once initialized; usually the `PTimer` is a field of
+    /// `opaque` itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `PTimer` was already initialized, or if `policy` has
+    /// unknown bits.
+    pub fn init<T, F: for<'a> FnCall<(&'a T,)>>(&self, _cb: &F, opaque: &T, policy: u32) {
+        assert!(bql_locked());
+        assert!(self.state.get().is_null());
+        let policy = u8::try_from(policy).unwrap();
+        let cb: unsafe extern "C" fn(*mut c_void) = rust_ptimer_cb::<T, F>;
+        self.owner.set(ptr::addr_of!(*opaque).cast::<c_void>());
+        // SAFETY: the callback matches the type of the owner, and the
+        // PTimer frees the ptimer before it goes away
+        let state = unsafe {
+            bindings::ptimer_init(
+                Some(cb),
+                ptr::addr_of!(*self).cast::<c_void>() as *mut c_void,
+                policy,
+            )
+        };
+        self.state.set(state);
+    }
+
+    /// Reconfigure the ptimer within a transaction, and return the result
+    /// of `f`.  The expiry callback, if needed, is called after `f`
+    /// returns.
+    ///
+    /// If this is called from the expiry callback, or from `f` itself, the
+    /// changes become part of the transaction that is already in progress.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `PTimer` was not initialized.
+    pub fn modify<R>(&self, f: impl FnOnce(&PTimerTransaction<'_>) -> R) -> R {
+        assert!(bql_locked());
+        let state = self.as_mut_ptr();
+        if self.in_transaction.get() {
+            return f(&PTimerTransaction(self));
+        }
+
+        self.in_transaction.set(true);
+        // SAFETY: the ptimer is valid until the PTimer is dropped
+        unsafe {
+            bindings::ptimer_transaction_begin(state);
+        }
+        let result = f(&PTimerTransaction(self));
+        // SAFETY: as above; this may call the expiry callback, which sees
+        // that a transaction is in progress
+        unsafe {
+            bindings::ptimer_transaction_commit(state);
+        }
+        self.in_transaction.set(false);
+        result
+    }
+
+    /// Return the current value of the counter.
+    pub fn count(&self) -> u64 {
+        // SAFETY: the ptimer is valid until the PTimer is dropped
+        unsafe { bindings::ptimer_get_count(self.as_mut_ptr()) }
+    }
+
+    /// Return the value that the counter is reloaded with.
+    pub fn limit(&self) -> u64 {
+        // SAFETY: the ptimer is valid until the PTimer is dropped
+        unsafe { bindings::ptimer_get_limit(self.as_mut_ptr()) }
+    }
+
+    /// Return a raw pointer to the C `ptimer_state`, for use with C
+    /// functions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `PTimer` was not initialized.
+    pub fn as_mut_ptr(&self) -> *mut ptimer_state {
+        let state = self.state.get();
+        assert!(!state.is_null(), "PTimer used before PTimer::init()");
+        state
+    }
+}
+
+impl Default for PTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for PTimer {
+    fn drop(&mut self) {
+        let state = self.state.get();
+        if !state.is_null() {
+            // SAFETY: the ptimer was created by init() and not freed yet
+            unsafe {
+                bindings::ptimer_free(state);
+            }
+        }
+    }
+}
+
+/// The operations that change the state of a [`PTimer`], which are only
+/// available within [`PTimer::modify`].
+#[derive(Debug)]
+pub struct PTimerTransaction<'a>(&'a PTimer);
+
+impl PTimerTransaction<'_> {
+    /// Set the interval between two decrements of the counter, in
+    /// nanoseconds.
+    pub fn set_period(&self, period: i64) {
+        // SAFETY: a transaction is in progress
+        unsafe {
+            bindings::ptimer_set_period(self.0.as_mut_ptr(), period);
+        }
+    }
+
+    /// Set the frequency at which the counter is decremented, in hertz.
+    pub fn set_freq(&self, freq: u32) {
+        // SAFETY: a transaction is in progress
+        unsafe {
+            bindings::ptimer_set_freq(self.0.as_mut_ptr(), freq);
+        }
+    }
+
+    /// Set the value that the counter is reloaded with when it reaches
+    /// zero.  If `reload` is true, the counter is also set to `limit`.
+    pub fn set_limit(&self, limit: u64, reload: bool) {
+        // SAFETY: a transaction is in progress
+        unsafe {
+            bindings::ptimer_set_limit(self.0.as_mut_ptr(), limit, c_int::from(reload));
+        }
+    }
+
+    /// Set the current value of the counter.
+    pub fn set_count(&self, count: u64) {
+        // SAFETY: a transaction is in progress
+        unsafe {
+            bindings::ptimer_set_count(self.0.as_mut_ptr(), count);
+        }
+    }
+
+    /// Start counting down.  A one-shot timer stops when the counter
+    /// reaches zero; otherwise, the counter is reloaded with the limit
+    /// and the timer expires periodically.
+    pub fn run(&self, oneshot: bool) {
+        // SAFETY: a transaction is in progress
+        unsafe {
+            bindings::ptimer_run(self.0.as_mut_ptr(), c_int::from(oneshot));
+        }
+    }
+
+    /// Stop counting down; the counter keeps its current value.
+    pub fn stop(&self) {
+        // SAFETY: a transaction is in progress
+        unsafe {
+            bindings::ptimer_stop(self.0.as_mut_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::ManuallyDrop;
+
+    use super::*;
+    use crate::cell::{bql_start_test, BqlCell};
+
+    struct DummyTimer {
+        timer: *const PTimer,
+        expired: BqlCell<u32>,
+        in_transaction: BqlCell<bool>,
+    }
+
+    impl DummyTimer {
+        fn expired(&self) {
+            self.expired.set(self.expired.get() + 1);
+            // SAFETY: the PTimer outlives the callback
+            self.in_transaction
+                .set(unsafe { &*self.timer }.in_transaction.get());
+        }
+    }
+
+    fn callback_for<T, F: for<'a> FnCall<(&'a T,)>>(_cb: &F) -> unsafe extern "C" fn(*mut c_void) {
+        rust_ptimer_cb::<T, F>
+    }
+
+    /// `ptimer.c` is not linked into the tests, so the `PTimer` is never
+    /// initialized or freed, and the expiry callback is invoked directly,
+    /// as `ptimer_tick()` would do once per period.
+    #[test]
+    fn test_ptimer_callback() {
+        bql_start_test();
+        let timer = ManuallyDrop::new(PTimer::new());
+        let dev = DummyTimer {
+            timer: ptr::addr_of!(*timer),
+            expired: BqlCell::new(0),
+            in_transaction: BqlCell::new(false),
+        };
+        timer.owner.set(ptr::addr_of!(dev).cast::<c_void>());
+        let cb = callback_for(&DummyTimer::expired);
+        for _ in 0..3 {
+            // SAFETY: the opaque is the PTimer, whose owner is `dev`
+            unsafe {
+                cb(ptr::addr_of!(*timer).cast::<c_void>() as *mut c_void);
+            }
+        }
+        assert_eq!(dev.expired.get(), 3);
+        assert!(dev.in_transaction.get());
+        assert!(!timer.in_transaction.get());
+    }
+}
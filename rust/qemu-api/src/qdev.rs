@@ -4,32 +4,224 @@
 
 //! Bindings to create devices and access device functionality from Rust.
 
-use std::{ffi::CStr, ptr::NonNull};
+use std::{
+    ffi::CStr,
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, Index},
+    os::raw::{c_char, c_int, c_void},
+    ptr::{self, addr_of_mut, NonNull},
+};
 
-pub use bindings::{DeviceClass, DeviceState, Property};
+pub use bindings::{
+    BusClass, BusState, Clock, ClockEvent, DeviceClass, DeviceState, Property, ResetType,
+    ResettableClass,
+};
 
 use crate::{
-    bindings::{self, Error},
+    bindings,
+    bql::assert_bql_locked,
+    callbacks::{abort_on_panic, FnCall},
+    cell::bql_locked,
+    error::{bql_error_propagate, Error, Result},
+    irq::InterruptSource,
     prelude::*,
-    qom::{ClassInitImpl, ObjectClass},
+    qom::{ClassInitImpl, Object, ObjectClass, Owned},
     vmstate::VMStateDescription,
 };
 
+/// Trait providing the contents of the `ResettablePhases` struct,
+/// which is part of the QOM `Resettable` interface.
+pub trait ResettablePhasesImpl {
+    /// If not None, this is called when the object enters reset. It
+    /// can reset local state of the object, but it must not do anything that
+    /// has a side-effect on other objects, such as raising or lowering an
+    /// interrupt line or reading or writing guest memory.
+    const ENTER: Option<fn(&Self, ResetType)> = None;
+
+    /// If not None, this is called once every object in the system which
+    /// is being reset has had its `ENTER` method called.  At this point
+    /// devices can do actions that affect other objects.
+    const HOLD: Option<fn(&Self, ResetType)> = None;
+
+    /// If not None, this phase is called when the object leaves the reset
+    /// state.  Actions affecting other objects are permitted.
+    const EXIT: Option<fn(&Self, ResetType)> = None;
+
+    /// If not None, this is called to list the objects that are reset
+    /// together with this one, by passing each of them to
+    /// [`ResetChildren::register`].  The children could be for example
+    /// devices that are embedded in a container device; they must
+    /// implement the `Resettable` interface.
+    ///
+    /// The children of the parent class, such as the buses of a device,
+    /// are reset as well.  Each phase runs on all the children before it
+    /// runs on `self`, so the `HOLD` method of a container can assume that
+    /// the registers of the children are already reset.
+    const CHILDREN: Option<fn(&Self, &mut ResetChildren)> = None;
+}
+
+/// The argument of [`ResettablePhasesImpl::CHILDREN`], which collects
+/// the children of an object for a reset.
+pub struct ResetChildren<'a>(&'a mut dyn FnMut(&Object));
+
+impl ResetChildren<'_> {
+    /// Reset `child` together with the object whose children are listed.
+    pub fn register<T: IsA<Object>>(&mut self, child: &T) {
+        (self.0)(child.upcast());
+    }
+}
+
+/// # Safety
+///
+/// We expect the FFI user of this function to pass a valid pointer that
+/// can be downcasted to type `T`. We also expect the device is
+/// readable/writeable from one thread at any time.
+unsafe extern "C" fn rust_resettable_enter_fn<T: ResettablePhasesImpl>(
+    obj: *mut Object,
+    typ: ResetType,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(obj).unwrap().cast::<T>();
+        T::ENTER.unwrap()(unsafe { state.as_ref() }, typ);
+    })
+}
+
+/// # Safety
+///
+/// We expect the FFI user of this function to pass a valid pointer that
+/// can be downcasted to type `T`. We also expect the device is
+/// readable/writeable from one thread at any time.
+unsafe extern "C" fn rust_resettable_hold_fn<T: ResettablePhasesImpl>(
+    obj: *mut Object,
+    typ: ResetType,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(obj).unwrap().cast::<T>();
+        T::HOLD.unwrap()(unsafe { state.as_ref() }, typ);
+    })
+}
+
+/// # Safety
+///
+/// We expect the FFI user of this function to pass a valid pointer that
+/// can be downcasted to type `T`. We also expect the device is
+/// readable/writeable from one thread at any time.
+unsafe extern "C" fn rust_resettable_exit_fn<T: ResettablePhasesImpl>(
+    obj: *mut Object,
+    typ: ResetType,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(obj).unwrap().cast::<T>();
+        T::EXIT.unwrap()(unsafe { state.as_ref() }, typ);
+    })
+}
+
+/// # Safety
+///
+/// We expect the FFI user of this function to pass a valid pointer that
+/// can be downcasted to type `T`, and a valid callback.
+unsafe extern "C" fn rust_resettable_child_foreach_fn<T: ResettablePhasesImpl + ObjectType>(
+    obj: *mut Object,
+    cb: bindings::ResettableChildCallback,
+    opaque: *mut c_void,
+    typ: ResetType,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: T's class and its parent implement the Resettable
+        // interface, because T does
+        unsafe {
+            let klass = bindings::object_class_by_name(T::TYPE_NAME.as_ptr());
+            let parent_rc = bindings::object_class_dynamic_cast(
+                bindings::object_class_get_parent(klass),
+                bindings::TYPE_RESETTABLE_INTERFACE.as_ptr().cast(),
+            )
+            .cast::<ResettableClass>();
+            if let Some(parent_foreach) = parent_rc.as_ref().and_then(|rc| rc.child_foreach) {
+                parent_foreach(obj, cb, opaque, typ);
+            }
+        }
+        let cb = cb.unwrap();
+        let state = NonNull::new(obj).unwrap().cast::<T>();
+        let mut children = ResetChildren(&mut |child| {
+            // SAFETY: cb and opaque come from the Resettable code, which
+            // only needs the child to implement the interface
+            unsafe {
+                cb(child as *const Object as *mut Object, opaque, typ);
+            }
+        });
+        T::CHILDREN.unwrap()(unsafe { state.as_ref() }, &mut children);
+    })
+}
+
+impl<T> ClassInitImpl<ResettableClass> for T
+where
+    T: ResettablePhasesImpl + ObjectType,
+{
+    fn class_init(rc: &mut ResettableClass) {
+        if <T as ResettablePhasesImpl>::ENTER.is_some() {
+            rc.phases.enter = Some(rust_resettable_enter_fn::<T>);
+        }
+        if <T as ResettablePhasesImpl>::HOLD.is_some() {
+            rc.phases.hold = Some(rust_resettable_hold_fn::<T>);
+        }
+        if <T as ResettablePhasesImpl>::EXIT.is_some() {
+            rc.phases.exit = Some(rust_resettable_exit_fn::<T>);
+        }
+        if <T as ResettablePhasesImpl>::CHILDREN.is_some() {
+            rc.child_foreach = Some(rust_resettable_child_foreach_fn::<T>);
+        }
+    }
+}
+
 /// Trait providing the contents of [`DeviceClass`].
-pub trait DeviceImpl {
+///
+/// Every item has a default, so a device only provides the ones that it
+/// needs; the others are inherited from the parent class.  In particular,
+/// a device that does not provide [`vmsd`](DeviceImpl::vmsd) has no
+/// state to migrate; if it does have state but cannot migrate it, it should
+/// provide a `VMStateDescription` with `unmigratable` set to block
+/// migration.
+pub trait DeviceImpl: ResettablePhasesImpl {
     /// _Realization_ is the second stage of device creation. It contains
-    /// all operations that depend on device properties and can fail (note:
-    /// this is not yet supported for Rust devices).
+    /// all operations that depend on device properties and can fail.
+    /// An error returned by the function is passed back to the caller
+    /// of `realize` through its `errp` argument.
+    ///
+    /// All the properties have been set when the function runs, so this
+    /// is where a device rejects invalid combinations of them, for
+    /// example with [`ensure!`](crate::ensure).
     ///
     /// If not `None`, the parent class's `realize` method is overridden
     /// with the function pointed to by `REALIZE`.
-    const REALIZE: Option<fn(&Self)> = None;
+    const REALIZE: Option<fn(&Self) -> Result<()>> = None;
+
+    /// _Unrealization_ undoes realization when the device is removed,
+    /// for example by `device_del` or because its parent is unparented.
+    /// It releases the resources that were acquired by `REALIZE` and
+    /// that cannot wait until the device is finalized, such as timers
+    /// that are running or backends that are connected.
+    ///
+    /// The function is only called if realization succeeded.  However,
+    /// this includes the case in which a later step failed, for example
+    /// because the hotplug handler of the bus refused the device, so the
+    /// function should not assume that the device was ever visible to
+    /// the guest.  If `REALIZE` itself fails, it must clean up after
+    /// itself, or leave it to the device's `Drop` implementation.
+    ///
+    /// If not `None`, the parent class's `unrealize` method is overridden
+    /// with the function pointed to by `UNREALIZE`.
+    const UNREALIZE: Option<fn(&Self)> = None;
 
     /// If not `None`, the parent class's `reset` method is overridden
     /// with the function pointed to by `RESET`.
     ///
-    /// Rust does not yet support the three-phase reset protocol; this is
-    /// usually okay for leaf classes.
+    /// This is the legacy single-phase reset; new devices should implement
+    /// [`ResettablePhasesImpl`] instead, and the two should not be mixed.
     const RESET: Option<fn(&Self)> = None;
 
     /// An array providing the properties that the user can set on the
@@ -54,9 +246,31 @@ fn vmsd() -> Option<&'static VMStateDescription> {
 /// We expect the FFI user of this function to pass a valid pointer that
 /// can be downcasted to type `T`. We also expect the device is
 /// readable/writeable from one thread at any time.
-unsafe extern "C" fn rust_realize_fn<T: DeviceImpl>(dev: *mut DeviceState, _errp: *mut *mut Error) {
-    let state = NonNull::new(dev).unwrap().cast::<T>();
-    T::REALIZE.unwrap()(unsafe { state.as_ref() });
+unsafe extern "C" fn rust_realize_fn<T: DeviceImpl>(
+    dev: *mut DeviceState,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(dev).unwrap().cast::<T>();
+        let result = T::REALIZE.unwrap()(unsafe { state.as_ref() });
+        unsafe {
+            bql_error_propagate(result, errp);
+        }
+    })
+}
+
+/// # Safety
+///
+/// We expect the FFI user of this function to pass a valid pointer that
+/// can be downcasted to type `T`. We also expect the device is
+/// readable/writeable from one thread at any time.
+unsafe extern "C" fn rust_unrealize_fn<T: DeviceImpl>(dev: *mut DeviceState) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(dev).unwrap().cast::<T>();
+        T::UNREALIZE.unwrap()(unsafe { state.as_ref() });
+    })
 }
 
 /// # Safety
@@ -65,23 +279,41 @@ fn vmsd() -> Option<&'static VMStateDescription> {
 /// can be downcasted to type `T`. We also expect the device is
 /// readable/writeable from one thread at any time.
 unsafe extern "C" fn rust_reset_fn<T: DeviceImpl>(dev: *mut DeviceState) {
-    let mut state = NonNull::new(dev).unwrap().cast::<T>();
-    T::RESET.unwrap()(unsafe { state.as_mut() });
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let mut state = NonNull::new(dev).unwrap().cast::<T>();
+        T::RESET.unwrap()(unsafe { state.as_mut() });
+    })
 }
 
 impl<T> ClassInitImpl<DeviceClass> for T
 where
-    T: ClassInitImpl<ObjectClass> + DeviceImpl,
+    T: ClassInitImpl<ObjectClass> + DeviceImpl + ObjectType,
 {
     fn class_init(dc: &mut DeviceClass) {
         if <T as DeviceImpl>::REALIZE.is_some() {
             dc.realize = Some(rust_realize_fn::<T>);
         }
+        if <T as DeviceImpl>::UNREALIZE.is_some() {
+            dc.unrealize = Some(rust_unrealize_fn::<T>);
+        }
         if <T as DeviceImpl>::RESET.is_some() {
             unsafe {
                 bindings::device_class_set_legacy_reset(dc, Some(rust_reset_fn::<T>));
             }
         }
+        // SAFETY: all devices implement the Resettable interface, and
+        // interfaces are set up before class_init is called.
+        let rc = unsafe {
+            NonNull::new(bindings::object_class_dynamic_cast(
+                addr_of_mut!(dc.parent_class),
+                bindings::TYPE_RESETTABLE_INTERFACE.as_ptr().cast(),
+            ))
+            .unwrap()
+            .cast::<ResettableClass>()
+            .as_mut()
+        };
+        <T as ClassInitImpl<ResettableClass>>::class_init(rc);
         if let Some(vmsd) = <T as DeviceImpl>::vmsd() {
             dc.vmsd = vmsd;
         }
@@ -96,26 +328,501 @@ fn class_init(dc: &mut DeviceClass) {
     }
 }
 
+/// Define a qdev property for the field `$field` of `$state`.
+///
+/// The type argument must match the field, and it must also match the
+/// `PropertyInfo` if a [`PropKind`](crate::prop::PropKind) is used; the
+/// setter of the property would otherwise write past the end of the field.
+/// Both checks are done at compile time:
+///
+/// ```
+/// # use qemu_api::{c_str, define_property, prop::PropKind};
+/// pub struct MyState {
+///     level: u8,
+/// }
+/// let _prop = define_property!(c_str!("level"), MyState, level, PropKind::U8, u8);
+/// ```
+///
+/// Any of the forms can end with `desc = "..."`, the help text that is
+/// shown for example by `-device foo,help`:
+///
+/// ```
+/// # use qemu_api::{c_str, define_property};
+/// # pub struct MyState { level: u8 }
+/// let _prop = define_property!(c_str!("level"), MyState, level, u8,
+///                              min = 1, max = 15, default = 8,
+///                              desc = "interrupt priority level (1-15)");
+/// ```
+///
+/// ```compile_fail
+/// # use qemu_api::{c_str, define_property, prop::PropKind};
+/// # pub struct MyState { level: u8 }
+/// // the field is smaller than the property type
+/// let _prop = define_property!(c_str!("level"), MyState, level, PropKind::U32, u32);
+/// ```
+///
+/// ```compile_fail
+/// # use qemu_api::{c_str, define_property, prop::PropKind};
+/// # pub struct MyState { level: u8 }
+/// // the property type is smaller than what the PropKind writes
+/// let _prop = define_property!(c_str!("level"), MyState, level, PropKind::U32, u8);
+/// ```
 #[macro_export]
 macro_rules! define_property {
-    ($name:expr, $state:ty, $field:ident, $prop:expr, $type:ty, default = $defval:expr$(,)*) => {
+    // Check the size of the field before anything is written to it.
+    (@check_field_size $state:ty, $field:ident, $type:ty) => {
+        const _: () = assert!(
+            $crate::call_func_with_field!($crate::prop::size_of_field, $state, $field)
+                == ::core::mem::size_of::<$type>(),
+            "the size of the field does not match the property type"
+        );
+    };
+    ($name:expr, $state:ty, $field:ident, PropKind::String, $($rest:tt)*) => {
+        $crate::define_property!(
+            $name,
+            $state,
+            $field,
+            $crate::prop_kind_to_ref!($crate::prop::PropKind::String),
+            $($rest)*
+        )
+    };
+    // The `PropertyInfo` for a `PropKind` writes a fixed number of bytes,
+    // so check it against `$type` as well as the field.
+    ($name:expr, $state:ty, $field:ident, PropKind::$kind:ident, $type:ty $(, $($rest:tt)*)?) => {{
+        const _: () = assert!(
+            $crate::prop::PropKind::$kind.field_size() == ::core::mem::size_of::<$type>(),
+            "the size of the property type does not match the PropKind"
+        );
+        $crate::define_property!(
+            $name,
+            $state,
+            $field,
+            $crate::prop_kind_to_ref!($crate::prop::PropKind::$kind),
+            $type
+            $(, $($rest)*)?
+        )
+    }};
+    // C strings (`char *` in C) are passed as `String`; the default, if any,
+    // is a `&CStr` and an empty default is the same as no default at all.
+    ($name:expr, $state:ty, $field:ident, $prop:expr, String, default = $defval:expr$(, desc = $desc:literal)?$(,)*) => {
         $crate::bindings::Property {
             // use associated function syntax for type checking
             name: ::std::ffi::CStr::as_ptr($name),
             info: $prop,
-            offset: $crate::offset_of!($state, $field) as isize,
+            offset: {
+                $crate::assert_field_type!($state, $field, *mut ::std::os::raw::c_char);
+                $crate::offset_of!($state, $field) as isize
+            },
+            // SAFETY: a CStr always has at least the NUL terminator
+            set_default: unsafe { *::std::ffi::CStr::as_ptr($defval) != 0 },
+            defval: $crate::bindings::Property__bindgen_ty_1 {
+                str_: ::std::ffi::CStr::as_ptr($defval),
+            },
+            $(description: $crate::c_str!($desc).as_ptr(),)?
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+    ($name:expr, $state:ty, $field:ident, $prop:expr, String$(, desc = $desc:literal)?$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: $prop,
+            offset: {
+                $crate::assert_field_type!($state, $field, *mut ::std::os::raw::c_char);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: false,
+            $(description: $crate::c_str!($desc).as_ptr(),)?
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+    ($name:expr, $state:ty, $field:ident, $prop:expr, $type:ty, default = $defval:expr$(, desc = $desc:literal)?$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: $prop,
+            offset: {
+                $crate::define_property!(@check_field_size $state, $field, $type);
+                $crate::offset_of!($state, $field) as isize
+            },
             set_default: true,
-            defval: $crate::bindings::Property__bindgen_ty_1 { u: $defval as u64 },
+            // give the default the property's type, so that literals such as
+            // `-1` or `u64::MAX` are encoded according to its width and sign
+            defval: {
+                const DEFVAL: $type = $defval;
+                $crate::prop::prop_default::<$type>(DEFVAL)
+            },
+            $(description: $crate::c_str!($desc).as_ptr(),)?
             ..$crate::zeroable::Zeroable::ZERO
         }
     };
-    ($name:expr, $state:ty, $field:ident, $prop:expr, $type:ty$(,)*) => {
+    ($name:expr, $state:ty, $field:ident, $prop:expr, $type:ty$(, desc = $desc:literal)?$(,)*) => {
         $crate::bindings::Property {
             // use associated function syntax for type checking
             name: ::std::ffi::CStr::as_ptr($name),
             info: $prop,
-            offset: $crate::offset_of!($state, $field) as isize,
+            offset: {
+                $crate::define_property!(@check_field_size $state, $field, $type);
+                $crate::offset_of!($state, $field) as isize
+            },
             set_default: false,
+            $(description: $crate::c_str!($desc).as_ptr(),)?
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+    // Unsigned integers with a range; the `PropertyInfo` is provided by
+    // `PropRange`, so there is no `PropKind` or `PropertyInfo` argument.
+    // These come last so that the arms above do not take `$type` for one.
+    ($name:expr, $state:ty, $field:ident, $type:ty, min = $min:expr, max = $max:expr, default = $defval:expr$(, desc = $desc:literal)?$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: &$crate::prop::PropRange::<$type, { $min as u64 }, { $max as u64 }>::INFO,
+            offset: {
+                $crate::assert_field_type!($state, $field, $type);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: true,
+            defval: {
+                const DEFVAL: $type = $defval;
+                const _: () = assert!(
+                    (DEFVAL as u64) >= ($min as u64) && (DEFVAL as u64) <= ($max as u64)
+                );
+                $crate::prop::prop_default::<$type>(DEFVAL)
+            },
+            $(description: $crate::c_str!($desc).as_ptr(),)?
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+    ($name:expr, $state:ty, $field:ident, $type:ty, min = $min:expr, max = $max:expr$(, desc = $desc:literal)?$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: &$crate::prop::PropRange::<$type, { $min as u64 }, { $max as u64 }>::INFO,
+            offset: {
+                $crate::assert_field_type!($state, $field, $type);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: false,
+            $(description: $crate::c_str!($desc).as_ptr(),)?
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a property for a C enum, stored as its value in an `i32` field.
+/// The property is set and read by the names in `$lookup`, a
+/// `*const QEnumLookup` that is usually generated by QAPI.  The default
+/// is any value that can be cast to `i32`, for example one of the C
+/// constants or a variant of a Rust enum with the same discriminants:
+///
+/// ```ignore
+/// define_enum_property!(c_str!("mode"), MyState, mode,
+///                       unsafe { addr_of!(bindings::OnOffAuto_lookup) },
+///                       default = ON_OFF_AUTO_AUTO)
+/// ```
+#[doc(alias = "DEFINE_PROP_SIGNED")]
+#[macro_export]
+macro_rules! define_enum_property {
+    ($name:expr, $state:ty, $field:ident, $lookup:expr, default = $defval:expr$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: {
+                // taking the address of a foreign static may need `unsafe`
+                #[allow(unused_unsafe)]
+                static INFO: $crate::bindings::PropertyInfo = $crate::prop::enum_info($lookup);
+                &INFO
+            },
+            offset: {
+                $crate::assert_field_type!($state, $field, i32);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: true,
+            defval: $crate::bindings::Property__bindgen_ty_1 {
+                i: {
+                    const DEFVAL: i32 = $defval as i32;
+                    DEFVAL as i64
+                },
+            },
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a boolean property that controls bit `$bitnr` of a `u32` field.
+/// Several bit properties can share the same field; setting one of them
+/// only changes its own bit.
+#[doc(alias = "DEFINE_PROP_BIT")]
+#[macro_export]
+macro_rules! define_bit_property {
+    ($name:expr, $state:ty, $field:ident, $bitnr:expr, default = $defval:expr$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            // SAFETY: qdev_prop_bit is immutable once defined in C
+            #[allow(unused_unsafe)]
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::qdev_prop_bit) },
+            offset: {
+                $crate::assert_field_type!($state, $field, u32);
+                $crate::offset_of!($state, $field) as isize
+            },
+            bitnr: {
+                const _: () = assert!($bitnr < 32);
+                $bitnr as u8
+            },
+            set_default: true,
+            defval: $crate::bindings::Property__bindgen_ty_1 {
+                u: {
+                    const DEFVAL: bool = $defval;
+                    DEFVAL as u64
+                },
+            },
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define an unsigned integer property that only accepts values whose
+/// bits are all set in `$mask`, like `DEFINE_PROP_UINT64_CHECKMASK` in C
+/// but for fields of any unsigned integer type.  Setting the property to
+/// a value with other bits fails with an error and leaves the field
+/// unchanged; the default, if any, is checked at compile time:
+///
+/// ```
+/// # use qemu_api::{c_str, define_masked_property};
+/// pub struct MyState {
+///     caps: u32,
+/// }
+/// let _prop = define_masked_property!(c_str!("caps"), MyState, caps, u32,
+///                                     mask = 0xf0f, default = 0x101);
+/// ```
+///
+/// ```compile_fail
+/// # use qemu_api::{c_str, define_masked_property};
+/// # pub struct MyState { caps: u32 }
+/// // the default has bits outside the mask
+/// let _prop = define_masked_property!(c_str!("caps"), MyState, caps, u32,
+///                                     mask = 0xf0f, default = 0x10);
+/// ```
+#[doc(alias = "DEFINE_PROP_UINT64_CHECKMASK")]
+#[macro_export]
+macro_rules! define_masked_property {
+    ($name:expr, $state:ty, $field:ident, $type:ty, mask = $mask:expr, default = $defval:expr$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: &$crate::prop::PropMask::<$type, { $mask as u64 }>::INFO,
+            offset: {
+                $crate::assert_field_type!($state, $field, $type);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: true,
+            defval: {
+                const DEFVAL: $type = $defval;
+                const _: () = assert!((DEFVAL as u64) & !($mask as u64) == 0);
+                $crate::bindings::Property__bindgen_ty_1 { u: DEFVAL as u64 }
+            },
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+    ($name:expr, $state:ty, $field:ident, $type:ty, mask = $mask:expr$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: &$crate::prop::PropMask::<$type, { $mask as u64 }>::INFO,
+            offset: {
+                $crate::assert_field_type!($state, $field, $type);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: false,
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a property for a size in bytes, stored in a `u64` field.  The
+/// property accepts suffixes such as `64K` or `2M`, which are powers of
+/// 1024, so that `2M` sets the field to `2 * 1024 * 1024`; the default is
+/// a plain byte count.
+///
+/// ```ignore
+/// define_size_property!(c_str!("ram-size"), MyState, ram_size, default = 64 * KiB)
+/// ```
+#[doc(alias = "DEFINE_PROP_SIZE")]
+#[macro_export]
+macro_rules! define_size_property {
+    ($name:expr, $state:ty, $field:ident, default = $defval:expr$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: $crate::prop_kind_to_ref!($crate::prop::PropKind::Size),
+            offset: {
+                $crate::assert_field_type!($state, $field, u64);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: true,
+            defval: {
+                const DEFVAL: u64 = $defval;
+                $crate::bindings::Property__bindgen_ty_1 { u: DEFVAL }
+            },
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+    ($name:expr, $state:ty, $field:ident$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            info: $crate::prop_kind_to_ref!($crate::prop::PropKind::Size),
+            offset: {
+                $crate::assert_field_type!($state, $field, u64);
+                $crate::offset_of!($state, $field) as isize
+            },
+            set_default: false,
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a variable-length array property, which is set as a list such as
+/// `-device foo,bar=1,2,3` (or `bar[0]`, `bar[1]`, ... from the command
+/// line).  Setting the property allocates an array of elements of kind
+/// `$elem_prop`, and stores its length in the `u32` field `$len_field` and
+/// a pointer to it in the `*mut` field `$elem_field`.
+///
+/// The length starts at zero, with a null pointer; it is an error to set
+/// the property again after it was set to a non-empty list.  The device
+/// must free the array with `g_free()` when it is finalized, for example
+/// in its `Drop` implementation.
+#[doc(alias = "DEFINE_PROP_ARRAY")]
+#[macro_export]
+macro_rules! define_array_property {
+    ($name:expr, $state:ty, $len_field:ident, $elem_field:ident, PropKind::$kind:ident$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            // SAFETY: qdev_prop_array is immutable once defined in C
+            #[allow(unused_unsafe)]
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::qdev_prop_array) },
+            offset: {
+                $crate::assert_field_type!($state, $len_field, u32);
+                $crate::offset_of!($state, $len_field) as isize
+            },
+            set_default: true,
+            defval: $crate::bindings::Property__bindgen_ty_1 { u: 0 },
+            arrayinfo: $crate::prop_kind_to_ref!($crate::prop::PropKind::$kind),
+            arrayoffset: $crate::offset_of!($state, $elem_field) as ::std::os::raw::c_int,
+            arrayfieldsize: {
+                const _: () = assert!(
+                    $crate::call_func_with_field!(
+                        $crate::prop::size_of_pointee,
+                        $state,
+                        $elem_field
+                    ) == $crate::prop::PropKind::$kind.field_size(),
+                    "the elements of the array do not match the PropKind"
+                );
+                $crate::prop::PropKind::$kind.field_size() as ::std::os::raw::c_int
+            },
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a link property, that is a reference to another QOM object.
+/// The field must have type `*mut Object`; setting the property fails
+/// unless the new target can be cast to `$target_type`.
+#[doc(alias = "DEFINE_PROP_LINK")]
+#[macro_export]
+macro_rules! define_link_property {
+    ($name:expr, $state:ty, $field:ident, $target_type:ty$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            // SAFETY: qdev_prop_link is immutable once defined in C
+            #[allow(unused_unsafe)]
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::qdev_prop_link) },
+            offset: {
+                $crate::assert_field_type!($state, $field, *mut $crate::bindings::Object);
+                $crate::offset_of!($state, $field) as isize
+            },
+            link_type: ::std::ffi::CStr::as_ptr(
+                <$target_type as $crate::qom::ObjectType>::TYPE_NAME,
+            ),
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a property that connects the [`CharBackend`] field `$field`
+/// to a chardev.  The value of the property is the id of the chardev, as
+/// in `-device foo,chardev=serial0`; the chardev is looked up and attached
+/// to the frontend when the property is set, so it is already connected
+/// when the device is realized.
+///
+/// [`CharBackend`]: crate::chardev::CharBackend
+#[doc(alias = "DEFINE_PROP_CHR")]
+#[macro_export]
+macro_rules! define_chardev_property {
+    ($name:expr, $state:ty, $field:ident$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            // SAFETY: qdev_prop_chr is immutable once defined in C
+            #[allow(unused_unsafe)]
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::qdev_prop_chr) },
+            offset: {
+                $crate::assert_field_type!($state, $field, $crate::chardev::CharBackend);
+                $crate::offset_of!($state, $field) as isize
+            },
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a property that attaches a block backend to the [`Drive`]
+/// field `$field`.  The value of the property is the name of a drive, as
+/// in `-device foo,drive=disk0`; if the property is not set, the device
+/// has no backend.
+///
+/// [`Drive`]: crate::block::Drive
+#[doc(alias = "DEFINE_PROP_DRIVE")]
+#[macro_export]
+macro_rules! define_drive_property {
+    ($name:expr, $state:ty, $field:ident$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            // SAFETY: qdev_prop_drive is immutable once defined in C
+            #[allow(unused_unsafe)]
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::qdev_prop_drive) },
+            offset: {
+                $crate::assert_field_type!($state, $field, $crate::block::Drive);
+                $crate::offset_of!($state, $field) as isize
+            },
+            ..$crate::zeroable::Zeroable::ZERO
+        }
+    };
+}
+
+/// Define a property that connects the [`Nic`] field `$field` to a
+/// network backend.  The value of the property is the id of a backend,
+/// as in `-device foo,netdev=net0`.
+///
+/// [`Nic`]: crate::nic::Nic
+#[doc(alias = "DEFINE_PROP_NETDEV")]
+#[macro_export]
+macro_rules! define_netdev_property {
+    ($name:expr, $state:ty, $field:ident$(,)*) => {
+        $crate::bindings::Property {
+            // use associated function syntax for type checking
+            name: ::std::ffi::CStr::as_ptr($name),
+            // SAFETY: qdev_prop_netdev is immutable once defined in C
+            #[allow(unused_unsafe)]
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::qdev_prop_netdev) },
+            offset: {
+                $crate::assert_field_type!($state, $field, $crate::nic::Nic);
+                ($crate::offset_of!($state, $field) + $crate::nic::Nic::PEERS_OFFSET) as isize
+            },
             ..$crate::zeroable::Zeroable::ZERO
         }
     };
@@ -123,6 +830,9 @@ macro_rules! define_property {
 
 #[macro_export]
 macro_rules! declare_properties {
+    ($ident:ident) => {
+        $crate::declare_properties!($ident,);
+    };
     ($ident:ident, $($prop:expr),*$(,)*) => {
         pub static $ident: [$crate::bindings::Property; {
             let mut len = 0;
@@ -132,14 +842,684 @@ macro_rules! declare_properties {
             })*
             len
         }] = [
-            $($prop),*,
+            $($prop),*
         ];
     };
 }
 
+/// A property array that is built at run time, for devices whose
+/// properties are not known when the code is written and therefore
+/// cannot be listed in [`declare_properties!`].
+///
+/// QOM keeps a pointer to the properties for as long as the class
+/// exists, i.e. until QEMU exits, so the array is leaked.  This is fine
+/// because [`DeviceImpl::properties`] is only called once, when the class
+/// is initialized:
+///
+/// ```ignore
+/// fn properties() -> &'static [Property] {
+///     let props = (0..NUM_PORTS).map(|i| port_property(i)).collect();
+///     PropertyList::from_vec(props).as_slice()
+/// }
+/// ```
+///
+/// The array is passed to `device_class_set_props_n()` together with its
+/// length, so unlike in C it has no terminator.
+#[derive(Clone, Copy)]
+pub struct PropertyList(&'static [Property]);
+
+impl PropertyList {
+    /// Take ownership of the properties in `props`, which stay alive
+    /// until QEMU exits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the properties has no name.
+    pub fn from_vec(props: Vec<Property>) -> Self {
+        assert!(props.iter().all(|prop| !prop.name.is_null()));
+        PropertyList(Box::leak(props.into_boxed_slice()))
+    }
+
+    /// Return the properties as a slice, for example to return them from
+    /// [`DeviceImpl::properties`].
+    pub const fn as_slice(&self) -> &'static [Property] {
+        self.0
+    }
+
+    /// Return a pointer to the first property, for use with C functions.
+    pub const fn as_ptr(&self) -> *const Property {
+        self.0.as_ptr()
+    }
+
+    /// Return the number of properties in the list.
+    pub const fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Return whether the list has no properties.
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 unsafe impl ObjectType for DeviceState {
     type Class = DeviceClass;
     const TYPE_NAME: &'static CStr =
         unsafe { CStr::from_bytes_with_nul_unchecked(bindings::TYPE_DEVICE) };
 }
 qom_isa!(DeviceState: Object);
+
+unsafe impl ObjectType for BusState {
+    type Class = BusClass;
+    const TYPE_NAME: &'static CStr =
+        unsafe { CStr::from_bytes_with_nul_unchecked(bindings::TYPE_BUS) };
+}
+qom_isa!(BusState: Object);
+
+unsafe impl ObjectType for Clock {
+    type Class = ObjectClass;
+    const TYPE_NAME: &'static CStr =
+        unsafe { CStr::from_bytes_with_nul_unchecked(bindings::TYPE_CLOCK) };
+}
+qom_isa!(Clock: Object);
+
+impl Clock {
+    /// The period of a 1 Hz clock.  Periods are expressed in units of
+    /// 2^-32 nanoseconds.
+    pub const PERIOD_1SEC: u64 = 1_000_000_000 << 32;
+
+    /// Convert a period in nanoseconds to the units used by [`Clock`].
+    pub const fn period_from_ns(ns: u32) -> u64 {
+        ns as u64 * (Self::PERIOD_1SEC / 1_000_000_000)
+    }
+
+    /// Convert a frequency in hertz to a period; zero means unclocked.
+    pub const fn period_from_hz(hz: u32) -> u64 {
+        match Self::PERIOD_1SEC.checked_div(hz as u64) {
+            Some(period) => period,
+            None => 0,
+        }
+    }
+
+    /// Return the current period of the clock, or zero if the clock is
+    /// not running.
+    pub const fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Return the current frequency of the clock in hertz, or zero if the
+    /// clock is not running.
+    pub const fn hz(&self) -> u32 {
+        match Self::PERIOD_1SEC.checked_div(self.period) {
+            Some(hz) => hz as u32,
+            None => 0,
+        }
+    }
+
+    /// Set the period of the clock and propagate it to all the clocks
+    /// that are connected to it.  This must not be called on clocks that
+    /// have a source, such as input clocks.
+    pub fn update(&self, period: u64) {
+        assert!(bql_locked());
+        // SAFETY: the clock is valid and all accesses happen under the BQL
+        unsafe {
+            let clk = self.as_mut_ptr();
+            if bindings::clock_set(clk, period) {
+                bindings::clock_propagate(clk);
+            }
+        }
+    }
+
+    /// Same as [`update`](Clock::update), with a period in nanoseconds.
+    pub fn update_ns(&self, ns: u32) {
+        self.update(Self::period_from_ns(ns));
+    }
+
+    /// Same as [`update`](Clock::update), with a frequency in hertz.
+    pub fn update_hz(&self, hz: u32) {
+        self.update(Self::period_from_hz(hz));
+    }
+
+    const fn as_mut_ptr(&self) -> *mut Clock {
+        self as *const Clock as *mut Clock
+    }
+}
+
+unsafe extern "C" fn rust_clock_cb<T, F: for<'a> FnCall<(&'a T,)>>(
+    opaque: *mut c_void,
+    _event: ClockEvent,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque was passed as a pointer to the device in
+        // DeviceMethods::init_clock_in, and the device owns the clock
+        F::call((unsafe { state.as_ref() },));
+    })
+}
+
+unsafe extern "C" fn rust_gpio_in_fn<T, F: for<'a> FnCall<(&'a T, u32, bool)>>(
+    opaque: *mut c_void,
+    line: c_int,
+    level: c_int,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque was passed as a pointer to the device in
+        // DeviceMethods::init_gpio_in_named; the device can be shared with
+        // other callbacks, so only a shared reference is created
+        F::call((unsafe { state.as_ref() }, line as u32, level != 0));
+    })
+}
+
+/// The input lines created by a call to
+/// [`init_gpio_in_named`](DeviceMethods::init_gpio_in_named).
+///
+/// The bank is indexed with the same line numbers that are passed to the
+/// callback, so that setting the level of `bank[n]` calls the callback with
+/// line `n`.
+#[derive(Debug)]
+pub struct GpioInBank {
+    first: u32,
+    lines: Box<[InterruptSource]>,
+}
+
+impl GpioInBank {
+    /// Return the input line number `line`, or `None` if it was not created
+    /// together with this bank.
+    pub fn get(&self, line: u32) -> Option<&InterruptSource> {
+        let index = line.checked_sub(self.first)?;
+        self.lines.get(index as usize)
+    }
+
+    /// Return the number of lines in the bank.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Return whether the bank has no lines.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
+
+impl Index<u32> for GpioInBank {
+    type Output = InterruptSource;
+
+    fn index(&self, line: u32) -> &InterruptSource {
+        self.get(line)
+            .unwrap_or_else(|| panic!("GPIO input line {line} out of range"))
+    }
+}
+
+/// Trait for methods exposed by the [`DeviceState`] class.  The methods can
+/// be called on all objects that have the trait `IsA<DeviceState>`.
+pub trait DeviceMethods: ObjectDeref
+where
+    Self::Target: IsA<DeviceState>,
+{
+    /// Expose `pins` outside the device as anonymous qdev GPIO outputs.
+    /// The lines are numbered in order, starting after those that were
+    /// created by previous calls to `init_gpio_out`; the board connects
+    /// them with `qdev_connect_gpio_out`.
+    fn init_gpio_out(&self, pins: &[InterruptSource]) {
+        assert!(bql_locked());
+        if pins.is_empty() {
+            return;
+        }
+        // SAFETY: InterruptSource is a transparent wrapper around qemu_irq,
+        // so the slice has the layout of the array that qdev_init_gpio_out expects
+        unsafe {
+            bindings::qdev_init_gpio_out(self.as_mut_ptr(), pins[0].as_ptr(), pins.len() as c_int);
+        }
+    }
+
+    /// Create `lines` anonymous qdev GPIO inputs.  When a line changes
+    /// level, `cb` is called with the device, the number of the line and
+    /// its new level.
+    ///
+    /// The `_cb` parameter is unused; it only selects the function to call,
+    /// which must be a function item or a closure that captures nothing:
+    ///
+    /// ```ignore
+    /// self.init_gpio_in(2, &MyDevice::gpio_in);
+    /// ```
+    fn init_gpio_in<F: for<'a> FnCall<(&'a Self::Target, u32, bool)>>(&self, lines: u32, _cb: &F) {
+        // SAFETY: a null name selects the anonymous GPIO inputs
+        unsafe {
+            init_gpio_in_bank::<Self::Target, F>(
+                self.as_mut_ptr::<Self::Target>(),
+                ptr::null(),
+                lines,
+            );
+        }
+    }
+
+    /// Same as [`init_gpio_in`](DeviceMethods::init_gpio_in), but the
+    /// lines are part of the bank called `name`.  Each bank is numbered
+    /// separately starting from zero, and can have its own callback.
+    ///
+    /// Return the new lines, indexed by the line number that the callback
+    /// receives.  If the bank already had lines, the new ones are numbered
+    /// after them.
+    fn init_gpio_in_named<F: for<'a> FnCall<(&'a Self::Target, u32, bool)>>(
+        &self,
+        name: &CStr,
+        lines: u32,
+        _cb: &F,
+    ) -> GpioInBank {
+        // SAFETY: the name is a valid C string; the new lines live as long
+        // as the device
+        unsafe {
+            let dev = self.as_mut_ptr::<DeviceState>();
+            let first = gpio_bank_len(dev, name);
+            init_gpio_in_bank::<Self::Target, F>(
+                self.as_mut_ptr::<Self::Target>(),
+                name.as_ptr(),
+                lines,
+            );
+            let lines = (first..first + lines)
+                .map(|n| {
+                    InterruptSource::from_raw(bindings::qdev_get_gpio_in_named(
+                        dev,
+                        name.as_ptr(),
+                        n as c_int,
+                    ))
+                })
+                .collect();
+            GpioInBank { first, lines }
+        }
+    }
+
+    /// Create `n` qdev GPIO outputs in the bank called `name`, and return
+    /// them.  The board connects them with `qdev_connect_gpio_out_named`,
+    /// or with [`connect_gpio_out`](DeviceMethods::connect_gpio_out).
+    ///
+    /// # Safety
+    ///
+    /// QEMU keeps a pointer to the heap buffer of the returned `Vec`, and
+    /// writes to it whenever an output is connected.  The `Vec` must be
+    /// stored in the device state and must not be dropped or resized for
+    /// as long as the device exists.
+    #[must_use]
+    unsafe fn init_gpio_out_named(&self, name: &CStr, n: u32) -> Vec<InterruptSource> {
+        assert!(bql_locked());
+        let pins: Vec<InterruptSource> = (0..n).map(|_| InterruptSource::default()).collect();
+        if n > 0 {
+            // SAFETY: InterruptSource is a transparent wrapper around qemu_irq,
+            // so the buffer has the layout of the array that
+            // qdev_init_gpio_out_named expects; moving the Vec does not
+            // move the buffer, and the caller keeps it alive
+            unsafe {
+                bindings::qdev_init_gpio_out_named(
+                    self.as_mut_ptr(),
+                    pins[0].as_ptr(),
+                    name.as_ptr(),
+                    n as c_int,
+                );
+            }
+        }
+        pins
+    }
+
+    /// Connect the output line number `pin` in the GPIO bank `name`, or
+    /// in the anonymous bank if `name` is `None`, to `target`.  `target`
+    /// is usually the input of another device, as returned by
+    /// [`get_gpio_in`](DeviceMethods::get_gpio_in); connecting to an
+    /// unconnected `InterruptSource` disconnects the pin.
+    ///
+    /// QEMU aborts if the bank does not have a line number `pin`.
+    fn connect_gpio_out(&self, name: Option<&CStr>, pin: u32, target: &InterruptSource) {
+        assert!(bql_locked());
+        let name = name.map_or(ptr::null(), CStr::as_ptr);
+        // SAFETY: the name is either NULL or a valid C string; the sink is
+        // either NULL or an IRQState that was created by qdev and is owned
+        // by the device whose input it is
+        unsafe {
+            bindings::qdev_connect_gpio_out_named(
+                self.as_mut_ptr(),
+                name,
+                pin as c_int,
+                target.sink(),
+            );
+        }
+    }
+
+    /// Return the input line number `line` in the GPIO bank `name`, or in
+    /// the anonymous bank if `name` is `None`.  Setting the level of the
+    /// returned line calls the callback that was passed to
+    /// [`init_gpio_in`](DeviceMethods::init_gpio_in) or
+    /// [`init_gpio_in_named`](DeviceMethods::init_gpio_in_named).
+    ///
+    /// QEMU aborts if the bank does not have a line number `line`.
+    fn get_gpio_in(&self, name: Option<&CStr>, line: u32) -> InterruptSource {
+        assert!(bql_locked());
+        let name = name.map_or(ptr::null(), CStr::as_ptr);
+        // SAFETY: the name is either NULL or a valid C string; the input
+        // lines live as long as the device
+        let irq =
+            unsafe { bindings::qdev_get_gpio_in_named(self.as_mut_ptr(), name, line as c_int) };
+        InterruptSource::from_raw(irq)
+    }
+
+    /// Create an input clock called `name`.  Whenever the period of the
+    /// clock changes, `cb` is called with the device, which reads the new
+    /// period from the returned clock with [`Clock::period`].
+    ///
+    /// As with [`init_gpio_in`](DeviceMethods::init_gpio_in), the `_cb`
+    /// parameter is only used to select the function to call.
+    fn init_clock_in<F: for<'a> FnCall<(&'a Self::Target,)>>(
+        &self,
+        name: &CStr,
+        _cb: &F,
+    ) -> NonNull<Clock> {
+        assert!(bql_locked());
+        let cb: unsafe extern "C" fn(*mut c_void, ClockEvent) = rust_clock_cb::<Self::Target, F>;
+        // SAFETY: the device is passed as the opaque and converted back to
+        // a `Self::Target` by rust_clock_cb; the clock is released when
+        // the device is finalized, so the callback cannot outlive it
+        unsafe {
+            let clk = bindings::qdev_init_clock_in(
+                self.as_mut_ptr(),
+                name.as_ptr(),
+                Some(cb),
+                self.as_mut_ptr::<Self::Target>().cast::<c_void>(),
+                ClockEvent::ClockUpdate.0,
+            );
+            NonNull::new(clk).unwrap()
+        }
+    }
+
+    /// Create an output clock called `name`.  The device sets its period
+    /// with [`Clock::update`], which also updates any connected clocks.
+    fn init_clock_out(&self, name: &CStr) -> NonNull<Clock> {
+        assert!(bql_locked());
+        // SAFETY: the device is valid and not realized yet
+        unsafe {
+            let clk = bindings::qdev_init_clock_out(self.as_mut_ptr(), name.as_ptr());
+            NonNull::new(clk).unwrap()
+        }
+    }
+
+    /// Return the value of the boolean property `name`.
+    fn get_bool(&self, name: &CStr) -> Result<bool> {
+        assert!(bql_locked());
+        let mut err = ptr::null_mut();
+        // SAFETY: the error, if any, is owned by the result
+        unsafe {
+            let value = bindings::object_property_get_bool(
+                self.as_mut_ptr::<DeviceState>().cast::<Object>(),
+                name.as_ptr(),
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, value)
+        }
+    }
+
+    /// Set the boolean property `name` to `value`.
+    fn set_bool(&self, name: &CStr, value: bool) -> Result<()> {
+        assert!(bql_locked());
+        let mut err = ptr::null_mut();
+        // SAFETY: the error, if any, is owned by the result
+        unsafe {
+            bindings::object_property_set_bool(
+                self.as_mut_ptr::<DeviceState>().cast::<Object>(),
+                name.as_ptr(),
+                value,
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, ())
+        }
+    }
+
+    /// Return the value of the unsigned integer property `name`.
+    fn get_uint(&self, name: &CStr) -> Result<u64> {
+        assert!(bql_locked());
+        let mut err = ptr::null_mut();
+        // SAFETY: the error, if any, is owned by the result
+        unsafe {
+            let value = bindings::object_property_get_uint(
+                self.as_mut_ptr::<DeviceState>().cast::<Object>(),
+                name.as_ptr(),
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, value)
+        }
+    }
+
+    /// Set the unsigned integer property `name` to `value`.  Setting a
+    /// property fails if the value is out of range for the property's
+    /// type, or if the device is already realized and the property cannot
+    /// be changed anymore.
+    fn set_uint(&self, name: &CStr, value: u64) -> Result<()> {
+        assert!(bql_locked());
+        let mut err = ptr::null_mut();
+        // SAFETY: the error, if any, is owned by the result
+        unsafe {
+            bindings::object_property_set_uint(
+                self.as_mut_ptr::<DeviceState>().cast::<Object>(),
+                name.as_ptr(),
+                value,
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, ())
+        }
+    }
+
+    /// Create a bus of type `typename` whose parent is the device, as in
+    /// `qbus_new()`.  Controllers usually do this in their `instance_init`
+    /// function and store the [`Bus`] in a field, so that child devices
+    /// can be plugged into it.  If `name` is `None`, the name is built from
+    /// the id of the device or from the type of the bus.
+    ///
+    /// The bus is a QOM child of the device, and the returned [`Bus`] holds
+    /// one more reference.  The bus is therefore not freed before the
+    /// device, even though the device's children are removed before the
+    /// device's own fields are dropped.
+    ///
+    /// # Panics
+    ///
+    /// Aborts if `typename` is not a subclass of `TYPE_BUS`.
+    fn new_bus(&self, typename: &CStr, name: Option<&CStr>) -> Bus {
+        assert!(bql_locked());
+        // SAFETY: qbus_new() copies the name; the reference that it leaves
+        // to the parent is the one that the QOM tree owns
+        unsafe {
+            let bus = bindings::qbus_new(
+                typename.as_ptr(),
+                self.as_mut_ptr::<DeviceState>(),
+                name.map_or(ptr::null(), CStr::as_ptr),
+            );
+            bindings::object_ref(bus.cast::<c_void>());
+            Bus(NonNull::new(bus).unwrap())
+        }
+    }
+
+    /// Request the removal of the device from its bus, as `device_del`
+    /// does.  If the hotplug handler of the bus implements
+    /// [`UNPLUG_REQUEST`](crate::hotplug::HotplugHandlerImpl::UNPLUG_REQUEST),
+    /// this only starts the removal, which may complete later; otherwise the
+    /// device is unplugged and unparented before the function returns.
+    fn unplug(&self) -> Result<()> {
+        assert!(bql_locked());
+        let mut err = ptr::null_mut();
+        // SAFETY: the error, if any, is owned by the result
+        unsafe {
+            bindings::qdev_unplug(self.as_mut_ptr::<DeviceState>(), addr_of_mut!(err));
+            Error::err_or_else(err, ())
+        }
+    }
+}
+
+impl<R: ObjectDeref> DeviceMethods for R where R::Target: IsA<DeviceState> {}
+
+/// A device that was created with [`Device::new`] but not realized yet.
+/// Its properties can be set through [`DeviceMethods`] before it is
+/// realized; dropping it without realizing the device frees the device.
+pub struct Device<T: IsA<DeviceState>>(NonNull<T>);
+
+impl<T: IsA<DeviceState>> Device<T> {
+    /// Create a device of type `typename`, as in `qdev_new()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `typename` is not `T` or a subclass of `T`.
+    pub fn new(typename: &CStr) -> Self {
+        assert!(bql_locked());
+        // SAFETY: the object is checked to be a `T` before it is cast
+        unsafe {
+            let dev = bindings::qdev_new(typename.as_ptr());
+            let obj = dev.cast::<Object>();
+            if bindings::object_dynamic_cast(obj, T::TYPE_NAME.as_ptr()).is_null() {
+                bindings::object_unref(obj.cast::<c_void>());
+                panic!("{:?} is not a subclass of {:?}", typename, T::TYPE_NAME);
+            }
+            Device(NonNull::new(dev).unwrap().cast::<T>())
+        }
+    }
+
+    /// Realize the device and plug it into `bus`, which is `None` for
+    /// devices that do not sit on a bus.  As in `qdev_realize_and_unref()`,
+    /// the reference that was returned by [`Device::new`] is dropped; on
+    /// success the device stays alive as long as its parent in the QOM tree
+    /// and the returned [`Owned`] do.
+    pub fn realize_and_unref(self, bus: Option<&BusState>) -> Result<Owned<T>> {
+        assert!(bql_locked());
+        let dev = ManuallyDrop::new(self);
+        let bus = bus.map_or(ptr::null_mut(), |bus| {
+            bus as *const BusState as *mut BusState
+        });
+        let mut err = ptr::null_mut();
+        // SAFETY: the reference owned by `dev` is passed to
+        // qdev_realize_and_unref(), after taking another one for the result
+        unsafe {
+            bindings::object_ref(dev.0.as_ptr().cast::<c_void>());
+            let owned = Owned::from_raw(dev.0.as_ptr());
+            bindings::qdev_realize_and_unref(
+                dev.0.as_ptr().cast::<DeviceState>(),
+                bus,
+                addr_of_mut!(err),
+            );
+            Error::err_or_else(err, owned)
+        }
+    }
+}
+
+impl<T: IsA<DeviceState>> Deref for Device<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the device is alive as long as the reference is held
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl<T: IsA<DeviceState>> ObjectDeref for Device<T> {}
+
+impl<T: IsA<DeviceState>> fmt::Debug for Device<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Device").field(&self.0).finish()
+    }
+}
+
+impl<T: IsA<DeviceState>> Drop for Device<T> {
+    fn drop(&mut self) {
+        assert!(bql_locked());
+        // SAFETY: the reference was taken by qdev_new()
+        unsafe {
+            bindings::object_unref(self.0.as_ptr().cast::<c_void>());
+        }
+    }
+}
+
+/// A reference to a realized device, as returned by
+/// [`Device::realize_and_unref`].  This is the same as [`Owned`]; the
+/// name is kept for code that only deals with devices.
+pub type OwnedDevice<T> = Owned<T>;
+
+/// A reference to a bus that was created with [`DeviceMethods::new_bus`].
+/// The reference is released when the `Bus` is dropped, usually when the
+/// device that created the bus is finalized.
+pub struct Bus(NonNull<BusState>);
+
+impl Bus {
+    /// Make `handler` the hotplug handler of the bus, as in
+    /// `qbus_set_hotplug_handler()`.  The handler is notified when devices
+    /// are plugged into the bus or unplugged from it; usually it is the
+    /// device that owns the bus.
+    ///
+    /// # Panics
+    ///
+    /// Aborts if `handler` does not implement `TYPE_HOTPLUG_HANDLER`.
+    pub fn set_hotplug_handler<H: IsA<Object>>(&self, handler: &H) {
+        assert!(bql_locked());
+        // SAFETY: the bus and the handler are valid QOM objects; the bus
+        // property holds a reference to the handler
+        unsafe {
+            bindings::qbus_set_hotplug_handler(self.0.as_ptr(), handler.as_mut_ptr::<Object>());
+        }
+    }
+}
+
+impl Deref for Bus {
+    type Target = BusState;
+
+    fn deref(&self) -> &BusState {
+        // SAFETY: the bus is alive as long as the reference is held
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl ObjectDeref for Bus {}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Bus").field(&self.0).finish()
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        assert!(bql_locked());
+        // SAFETY: the reference was taken by DeviceMethods::new_bus()
+        unsafe {
+            bindings::object_unref(self.0.as_ptr().cast::<c_void>());
+        }
+    }
+}
+
+/// Return the number of input lines in the GPIO bank `name`.
+///
+/// # Safety
+///
+/// `dev` must point to a valid device.
+unsafe fn gpio_bank_len(dev: *mut DeviceState, name: &CStr) -> u32 {
+    // SAFETY: the name is a valid C string; the list belongs to the
+    // device, which creates it if the bank does not exist yet
+    unsafe { (*bindings::qdev_get_named_gpio_list(dev, name.as_ptr())).num_in as u32 }
+}
+
+/// # Safety
+///
+/// `dev` must point to a `T`, and `name` must be null or point to a
+/// NUL-terminated string.
+unsafe fn init_gpio_in_bank<T: IsA<DeviceState>, F: for<'a> FnCall<(&'a T, u32, bool)>>(
+    dev: *mut T,
+    name: *const c_char,
+    lines: u32,
+) {
+    assert!(bql_locked());
+    let cb: unsafe extern "C" fn(*mut c_void, c_int, c_int) = rust_gpio_in_fn::<T, F>;
+    // SAFETY: the device is passed as the opaque and converted back to
+    // a `T` by rust_gpio_in_fn
+    unsafe {
+        bindings::qdev_init_gpio_in_named_with_opaque(
+            dev.cast::<DeviceState>(),
+            Some(cb),
+            dev.cast::<c_void>(),
+            name,
+            lines as c_int,
+        );
+    }
+}
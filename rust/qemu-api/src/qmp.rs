@@ -0,0 +1,379 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! QMP commands implemented in Rust.
+//!
+//! A command is a function that receives its arguments as a type that
+//! implements [`FromQmpArgs`], and returns a [`Result`] whose value
+//! implements [`QmpReturn`].  The arguments and the return value are
+//! scalars, which covers simple commands that do not need a QAPI schema:
+//!
+//! ```ignore
+//! struct AddArgs {
+//!     a: i64,
+//!     b: i64,
+//! }
+//!
+//! impl FromQmpArgs for AddArgs {
+//!     fn from_qmp_args(args: &QmpArgs) -> Result<Self> {
+//!         Ok(AddArgs {
+//!             a: args.get_int(c_str!("a"))?,
+//!             b: args.get_int(c_str!("b"))?,
+//!         })
+//!     }
+//! }
+//!
+//! fn qmp_x_add(args: AddArgs) -> Result<i64> {
+//!     Ok(args.a + args.b)
+//! }
+//!
+//! qmp_register_command(c_str!("x-add"), &qmp_x_add);
+//! ```
+//!
+//! [`qmp_register_command`] adds the command to the QMP monitor.  Tests
+//! can instead add commands to their own [`QmpCommands`], and run them
+//! with [`QmpCommands::dispatch`].
+
+use std::{
+    ffi::CStr,
+    fmt,
+    os::raw::c_void,
+    ptr::{self, addr_of, addr_of_mut},
+};
+
+pub use bindings::{QDict, QObject};
+
+use crate::{
+    bindings::{self, QmpCommandList},
+    bql::assert_bql_locked,
+    callbacks::{abort_on_panic, FnCall},
+    cell::bql_locked,
+    error::{Error, Result},
+    g_free, g_string_free,
+    zeroable::Zeroable,
+};
+
+/// Drop a reference to `obj`, as `qobject_unref()` does.
+///
+/// # Safety
+///
+/// `obj` must be null or point to a valid `QObject`.
+unsafe fn qobject_unref(obj: *mut QObject) {
+    // SAFETY: forwarded to the caller; QObjects are only used under the BQL
+    unsafe {
+        if let Some(obj) = obj.as_mut() {
+            assert!(obj.base.refcnt > 0);
+            obj.base.refcnt -= 1;
+            if obj.base.refcnt == 0 {
+                bindings::qobject_destroy(obj);
+            }
+        }
+    }
+}
+
+/// The arguments of a QMP command, i.e. the `arguments` member of the
+/// request.
+pub struct QmpArgs<'a>(&'a QDict);
+
+impl QmpArgs<'_> {
+    /// Return whether the argument `key` was passed to the command.
+    pub fn has(&self, key: &CStr) -> bool {
+        // SAFETY: the dictionary is valid for the lifetime of self
+        unsafe { bindings::qdict_haskey(self.0, key.as_ptr()) != 0 }
+    }
+
+    fn get(&self, key: &CStr, qtype: bindings::QType, expected: &str) -> Result<*mut QObject> {
+        // SAFETY: the dictionary is valid for the lifetime of self, and
+        // so are its values
+        let obj = unsafe { bindings::qdict_get(self.0, key.as_ptr()) };
+        if obj.is_null() {
+            return Err(Error::new(&format!(
+                "Parameter '{}' is missing",
+                key.to_string_lossy()
+            )));
+        }
+        // SAFETY: as above
+        if unsafe { (*obj).base.type_ } != qtype {
+            return Err(invalid_type(key, expected));
+        }
+        Ok(obj)
+    }
+
+    /// Return the value of the integer argument `key`.
+    pub fn get_int(&self, key: &CStr) -> Result<i64> {
+        let obj = self.get(key, bindings::QTYPE_QNUM, "integer")?;
+        let mut value = 0;
+        // SAFETY: the object was checked to be a QNum
+        if unsafe { bindings::qnum_get_try_int(obj.cast::<bindings::QNum>(), addr_of_mut!(value)) }
+        {
+            Ok(value)
+        } else {
+            Err(invalid_type(key, "integer"))
+        }
+    }
+
+    /// Return the value of the unsigned integer argument `key`.
+    pub fn get_uint(&self, key: &CStr) -> Result<u64> {
+        let obj = self.get(key, bindings::QTYPE_QNUM, "uint64")?;
+        let mut value = 0;
+        // SAFETY: the object was checked to be a QNum
+        if unsafe { bindings::qnum_get_try_uint(obj.cast::<bindings::QNum>(), addr_of_mut!(value)) }
+        {
+            Ok(value)
+        } else {
+            Err(invalid_type(key, "uint64"))
+        }
+    }
+
+    /// Return the value of the boolean argument `key`.
+    pub fn get_bool(&self, key: &CStr) -> Result<bool> {
+        let obj = self.get(key, bindings::QTYPE_QBOOL, "boolean")?;
+        // SAFETY: the object was checked to be a QBool
+        Ok(unsafe { bindings::qbool_get_bool(obj.cast::<bindings::QBool>()) })
+    }
+
+    /// Return the value of the string argument `key`.
+    pub fn get_str(&self, key: &CStr) -> Result<String> {
+        let obj = self.get(key, bindings::QTYPE_QSTRING, "string")?;
+        // SAFETY: the object was checked to be a QString
+        let s =
+            unsafe { CStr::from_ptr(bindings::qstring_get_str(obj.cast::<bindings::QString>())) };
+        Ok(s.to_string_lossy().into_owned())
+    }
+}
+
+impl fmt::Debug for QmpArgs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("QmpArgs").field(&addr_of!(*self.0)).finish()
+    }
+}
+
+fn invalid_type(key: &CStr, expected: &str) -> Error {
+    Error::new(&format!(
+        "Invalid parameter type for '{}', expected: {}",
+        key.to_string_lossy(),
+        expected
+    ))
+}
+
+/// Types that hold the arguments of a QMP command.
+pub trait FromQmpArgs: Sized {
+    /// Extract the arguments from `args`, failing if one is missing or
+    /// has the wrong type.
+    fn from_qmp_args(args: &QmpArgs) -> Result<Self>;
+}
+
+/// For commands without arguments.
+impl FromQmpArgs for () {
+    fn from_qmp_args(_args: &QmpArgs) -> Result<Self> {
+        Ok(())
+    }
+}
+
+/// Types that can be returned by a QMP command.
+pub trait QmpReturn {
+    /// Convert the value to a new `QObject`, or return null for an empty
+    /// reply.  The conversion fails if the value cannot be represented
+    /// in QMP; the client then receives the error instead of the value.
+    fn into_qobject(self) -> Result<*mut QObject>;
+}
+
+/// For commands that do not return anything; the reply is `{}`.
+impl QmpReturn for () {
+    fn into_qobject(self) -> Result<*mut QObject> {
+        Ok(ptr::null_mut())
+    }
+}
+
+impl QmpReturn for bool {
+    fn into_qobject(self) -> Result<*mut QObject> {
+        // SAFETY: the new object is owned by the caller
+        Ok(unsafe { bindings::qbool_from_bool(self).cast::<QObject>() })
+    }
+}
+
+impl QmpReturn for i64 {
+    fn into_qobject(self) -> Result<*mut QObject> {
+        // SAFETY: the new object is owned by the caller
+        Ok(unsafe { bindings::qnum_from_int(self).cast::<QObject>() })
+    }
+}
+
+impl QmpReturn for u64 {
+    fn into_qobject(self) -> Result<*mut QObject> {
+        // SAFETY: the new object is owned by the caller
+        Ok(unsafe { bindings::qnum_from_uint(self).cast::<QObject>() })
+    }
+}
+
+impl QmpReturn for &CStr {
+    fn into_qobject(self) -> Result<*mut QObject> {
+        // SAFETY: the string is copied into the new object, which is owned
+        // by the caller
+        Ok(unsafe { bindings::qstring_from_str(self.as_ptr()).cast::<QObject>() })
+    }
+}
+
+impl QmpReturn for String {
+    fn into_qobject(self) -> Result<*mut QObject> {
+        // a string with a NUL in the middle cannot be represented in QMP
+        let s = std::ffi::CString::new(self)
+            .map_err(|_| Error::new("the returned string contains a NUL character"))?;
+        s.as_c_str().into_qobject()
+    }
+}
+
+unsafe extern "C" fn rust_qmp_command_cb<A, R, F>(
+    args: *mut QDict,
+    ret: *mut *mut QObject,
+    errp: *mut *mut bindings::Error,
+) where
+    A: FromQmpArgs,
+    R: QmpReturn,
+    F: FnCall<(A,), Result<R>>,
+{
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: qmp_dispatch() passes a dictionary that is valid until
+        // the command returns
+        let args = QmpArgs(unsafe { &*args });
+        match A::from_qmp_args(&args)
+            .and_then(|args| F::call((args,)))
+            .and_then(R::into_qobject)
+        {
+            // SAFETY: qmp_dispatch() takes ownership of the value
+            Ok(value) => unsafe { *ret = value },
+            // SAFETY: errp is valid for error_propagate()
+            Err(err) => unsafe { Error::setg(errp, err) },
+        }
+    })
+}
+
+/// Add `cb` to `cmds` as the command `name`.
+///
+/// # Safety
+///
+/// `cmds` must be a valid list of commands.
+unsafe fn register_command<A, R, F>(cmds: *mut QmpCommandList, name: &'static CStr, _cb: &F)
+where
+    A: FromQmpArgs,
+    R: QmpReturn,
+    F: FnCall<(A,), Result<R>>,
+{
+    assert!(bql_locked());
+    let cb: unsafe extern "C" fn(*mut QDict, *mut *mut QObject, *mut *mut bindings::Error) =
+        rust_qmp_command_cb::<A, R, F>;
+    // SAFETY: the name is static, because the command refers to it
+    unsafe {
+        bindings::qmp_register_command(cmds, name.as_ptr(), Some(cb), 0, 0);
+    }
+}
+
+extern "C" {
+    // defined in monitor/qmp.c
+    static mut qmp_commands: QmpCommandList;
+}
+
+/// Add `cb` to the commands of the QMP monitor, as the command `name`.
+/// The name of a command that is not part of the QAPI schema should start
+/// with `x-` or with a vendor prefix.
+///
+/// The monitor initializes its list of commands in a constructor, so this
+/// must not be called from [`module_init!`](crate::module_init), which
+/// may run first; class initialization or realize are fine.
+///
+/// As with other callbacks, `cb` is only used to select the function to
+/// call, which must be a function item or a closure that captures
+/// nothing.
+pub fn qmp_register_command<A, R, F>(name: &'static CStr, cb: &F)
+where
+    A: FromQmpArgs,
+    R: QmpReturn,
+    F: FnCall<(A,), Result<R>>,
+{
+    // SAFETY: the list of commands is initialized before QEMU starts
+    unsafe {
+        register_command(addr_of_mut!(qmp_commands), name, cb);
+    }
+}
+
+/// A list of QMP commands that is not connected to the monitor, for
+/// example to test QMP commands.
+pub struct QmpCommands(Box<QmpCommandList>);
+
+impl QmpCommands {
+    /// Create an empty list of commands.
+    pub fn new() -> Self {
+        let mut cmds = Box::new(QmpCommandList::ZERO);
+        // QTAILQ_INIT(); the list is not moved once it is boxed
+        cmds.tqh_circ.tql_prev = addr_of_mut!(cmds.tqh_circ);
+        QmpCommands(cmds)
+    }
+
+    /// Add `cb` as the command `name`; see [`qmp_register_command`].
+    pub fn register<A, R, F>(&mut self, name: &'static CStr, cb: &F)
+    where
+        A: FromQmpArgs,
+        R: QmpReturn,
+        F: FnCall<(A,), Result<R>>,
+    {
+        // SAFETY: the list was initialized by new()
+        unsafe {
+            register_command(addr_of_mut!(*self.0), name, cb);
+        }
+    }
+
+    /// Run the QMP `request`, a JSON object such as
+    /// `{"execute": "x-add", "arguments": {"a": 1, "b": 2}}`, and return
+    /// the JSON response.  Failures of the command are reported in the
+    /// response, as the monitor would do, so an error is only returned if
+    /// `request` is not valid JSON.
+    pub fn dispatch(&self, request: &str) -> Result<String> {
+        assert!(bql_locked());
+        // a request with a NUL in the middle is not valid JSON
+        let request = std::ffi::CString::new(request).unwrap_or_default();
+        let mut err = ptr::null_mut();
+        // SAFETY: the request and the response are freed below; the
+        // string returned by g_string_free() is copied before it is freed
+        unsafe {
+            let req = bindings::qobject_from_json(request.as_ptr(), addr_of_mut!(err));
+            Error::err_or_else(err, ())?;
+            let rsp = bindings::qmp_dispatch(addr_of!(*self.0), req, false, ptr::null_mut());
+            qobject_unref(req);
+            let json = g_string_free(bindings::qobject_to_json(rsp.cast::<QObject>()), 0);
+            qobject_unref(rsp.cast::<QObject>());
+            let response = CStr::from_ptr(json).to_string_lossy().into_owned();
+            g_free(json.cast::<c_void>());
+            Ok(response)
+        }
+    }
+}
+
+impl Default for QmpCommands {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for QmpCommands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("QmpCommands")
+            .field(&addr_of!(*self.0))
+            .finish()
+    }
+}
+
+impl Drop for QmpCommands {
+    fn drop(&mut self) {
+        // SAFETY: the commands were allocated by qmp_register_command(),
+        // and nothing refers to them once the list is gone
+        unsafe {
+            let mut cmd = self.0.tqh_first;
+            while !cmd.is_null() {
+                let next = (*cmd).node.tqe_next;
+                g_free(cmd.cast::<c_void>());
+                cmd = next;
+            }
+        }
+    }
+}
@@ -52,18 +52,40 @@
 //!   This provides access to class-wide functionality that doesn't depend on
 //!   instance data. Like instance methods, these are automatically inherited by
 //!   child classes.
+//!
+//! # Interfaces
+//!
+//! A QOM interface only has a class struct, which starts with
+//! [`InterfaceClass`] and holds the interface's virtual methods.  Rust code
+//! declares one with [`qom_interface!`](crate::qom_interface), lists it
+//! among the [`INTERFACES`](ObjectImpl::INTERFACES) of each type that
+//! implements it, and fills the methods from `class_init` with
+//! [`class_override`].  An object is converted to the interface with
+//! [`dynamic_cast`](ObjectCast::dynamic_cast), and the methods are found
+//! with [`InterfaceType::get_class`].
 
 use std::{
+    cell::Cell,
     ffi::CStr,
     fmt,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop, MaybeUninit},
     ops::{Deref, DerefMut},
-    os::raw::c_void,
-    ptr::NonNull,
+    os::raw::{c_char, c_void},
+    ptr::{self, addr_of, addr_of_mut, NonNull},
 };
 
-pub use bindings::{Object, ObjectClass};
+pub use bindings::{InterfaceClass, InterfaceInfo, Object, ObjectClass};
 
-use crate::bindings::{self, object_dynamic_cast, object_get_class, object_get_typename, TypeInfo};
+use crate::{
+    bindings::{self, object_dynamic_cast, object_get_class, object_get_typename, TypeInfo},
+    c_str::glib_strdup,
+    callbacks::{abort_on_panic, FnCall},
+    cell::{bql_locked, BqlCell},
+    error::{self, bql_error_propagate, Error},
+    g_free,
+    zeroable::Zeroable,
+};
 
 /// Marker trait: `Self` can be statically upcasted to `P` (i.e. `P` is a direct
 /// or indirect parent of `Self`).
@@ -107,6 +129,78 @@ fn as_ref(&self) -> &$parent {
     };
 }
 
+/// Build the list of QOM interfaces implemented by a type, for
+/// [`ObjectImpl::INTERFACES`], from the names of the interface types.
+/// The list is terminated by an empty entry, as expected by `TypeInfo`.
+///
+/// ```ignore
+/// const INTERFACES: &'static [InterfaceInfo] = interfaces![TYPE_HOTPLUG_HANDLER];
+/// ```
+#[macro_export]
+macro_rules! interfaces {
+    ($($name:expr),* $(,)?) => {
+        &[
+            $($crate::bindings::InterfaceInfo {
+                type_: $name.as_ptr().cast::<::std::os::raw::c_char>(),
+            },)*
+            $crate::bindings::InterfaceInfo {
+                type_: ::core::ptr::null(),
+            },
+        ]
+    };
+}
+
+/// Declare a QOM interface, whose class struct is `$class`, and register
+/// it with QOM under the name `$type_name`.  This creates a struct `$name`
+/// that stands for the objects implementing the interface, and that
+/// implements [`InterfaceType`](crate::qom::InterfaceType):
+///
+/// ```ignore
+/// #[repr(C)]
+/// pub struct MyInterfaceClass {
+///     parent_class: InterfaceClass,
+///     get_id: Option<fn(&Object) -> u32>,
+/// }
+///
+/// qom_interface!(pub MyInterface: MyInterfaceClass = c_str!("my-interface"));
+/// ```
+///
+/// `$class` must be `#[repr(C)]` and start with a field of type
+/// [`InterfaceClass`](crate::qom::InterfaceClass); all its other fields
+/// must be valid when zeroed, because they are zero until `class_init`
+/// of the implementing type fills them.
+///
+/// The struct cannot be constructed, and `IsA` must not be implemented
+/// for it, because objects do not start with the interface; references
+/// to it can only be obtained with
+/// [`dynamic_cast`](crate::qom::ObjectCast::dynamic_cast).
+#[macro_export]
+macro_rules! qom_interface {
+    ($(#[$attr:meta])* $vis:vis $name:ident: $class:ty = $type_name:expr) => {
+        $(#[$attr])*
+        #[repr(C)]
+        #[derive(Debug)]
+        $vis struct $name {
+            _private: [u8; 0],
+        }
+
+        unsafe impl $crate::qom::ObjectType for $name {
+            type Class = $class;
+            const TYPE_NAME: &'static ::std::ffi::CStr = $type_name;
+        }
+
+        unsafe impl $crate::qom::InterfaceType for $name {}
+
+        $crate::module_init! {
+            MODULE_INIT_QOM => unsafe {
+                $crate::bindings::type_register_static(
+                    &<$name as $crate::qom::InterfaceType>::TYPE_INFO,
+                );
+            }
+        }
+    };
+}
+
 /// This is the same as [`ManuallyDrop<T>`](std::mem::ManuallyDrop), though
 /// it hides the standard methods of `ManuallyDrop`.
 ///
@@ -146,6 +240,15 @@ fn deref_mut(&mut self) -> &mut Self::Target {
     }
 }
 
+/// A zeroed parent field, for use in the value that is returned by
+/// [`ObjectImpl::INSTANCE_DEFAULT`].  The parent field of that value is
+/// never copied to the object, so it only needs to be a placeholder.
+impl<T: ObjectType + Zeroable> Default for ParentField<T> {
+    fn default() -> Self {
+        ParentField(std::mem::ManuallyDrop::new(T::ZERO))
+    }
+}
+
 impl<T: fmt::Display + ObjectType> fmt::Display for ParentField<T> {
     #[inline(always)]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
@@ -153,45 +256,83 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
     }
 }
 
-unsafe extern "C" fn rust_instance_init<T: ObjectImpl>(obj: *mut Object) {
-    let mut state = NonNull::new(obj).unwrap().cast::<T>();
-    // SAFETY: obj is an instance of T, since rust_instance_init<T>
-    // is called from QOM core as the instance_init function
-    // for class T
+/// Overwrite the fields of `*obj` that come after the parent field with
+/// those of `value`, without dropping the previous contents.
+///
+/// # Safety
+///
+/// `obj` must point to an instance of `T` whose parent field is
+/// initialized, and whose other fields can be overwritten; this is the
+/// case in `instance_init`, where the fields are all zero.
+unsafe fn instance_init_fields<T: ObjectImpl>(obj: *mut T, value: T) {
+    // The parent field is the first field of `T` and is left alone, so that
+    // the already-initialized parent is not replaced by the placeholder in
+    // `value`.  The placeholder is never dropped because it is a
+    // `ParentField`, and the other fields now belong to `*obj`.
+    let value = mem::ManuallyDrop::new(value);
+    let parent_size = mem::size_of::<T::ParentType>();
+    // SAFETY: both pointers are valid for `size_of::<T>()` bytes
     unsafe {
-        T::INSTANCE_INIT.unwrap()(state.as_mut());
+        std::ptr::copy_nonoverlapping(
+            std::ptr::addr_of!(*value).cast::<u8>().add(parent_size),
+            obj.cast::<u8>().add(parent_size),
+            mem::size_of::<T>() - parent_size,
+        );
     }
 }
 
+unsafe extern "C" fn rust_instance_init<T: ObjectImpl>(obj: *mut Object) {
+    abort_on_panic(|| {
+        let mut state = NonNull::new(obj).unwrap().cast::<T>();
+        // SAFETY: obj is an instance of T, since rust_instance_init<T>
+        // is called from QOM core as the instance_init function
+        // for class T
+        unsafe {
+            if let Some(default) = T::INSTANCE_DEFAULT {
+                instance_init_fields(state.as_ptr(), default());
+            }
+            if let Some(init) = T::INSTANCE_INIT {
+                init(state.as_mut());
+            }
+        }
+    })
+}
+
 unsafe extern "C" fn rust_instance_post_init<T: ObjectImpl>(obj: *mut Object) {
-    let state = NonNull::new(obj).unwrap().cast::<T>();
-    // SAFETY: obj is an instance of T, since rust_instance_post_init<T>
-    // is called from QOM core as the instance_post_init function
-    // for class T
-    T::INSTANCE_POST_INIT.unwrap()(unsafe { state.as_ref() });
+    abort_on_panic(|| {
+        let state = NonNull::new(obj).unwrap().cast::<T>();
+        // SAFETY: obj is an instance of T, since rust_instance_post_init<T>
+        // is called from QOM core as the instance_post_init function
+        // for class T
+        T::INSTANCE_POST_INIT.unwrap()(unsafe { state.as_ref() });
+    })
 }
 
 unsafe extern "C" fn rust_class_init<T: ObjectType + ClassInitImpl<T::Class>>(
     klass: *mut ObjectClass,
     _data: *mut c_void,
 ) {
-    let mut klass = NonNull::new(klass)
-        .unwrap()
-        .cast::<<T as ObjectType>::Class>();
-    // SAFETY: klass is a T::Class, since rust_class_init<T>
-    // is called from QOM core as the class_init function
-    // for class T
-    T::class_init(unsafe { klass.as_mut() })
+    abort_on_panic(|| {
+        let mut klass = NonNull::new(klass)
+            .unwrap()
+            .cast::<<T as ObjectType>::Class>();
+        // SAFETY: klass is a T::Class, since rust_class_init<T>
+        // is called from QOM core as the class_init function
+        // for class T
+        T::class_init(unsafe { klass.as_mut() })
+    })
 }
 
 unsafe extern "C" fn drop_object<T: ObjectImpl>(obj: *mut Object) {
-    // SAFETY: obj is an instance of T, since drop_object<T> is called
-    // from the QOM core function object_deinit() as the instance_finalize
-    // function for class T.  Note that while object_deinit() will drop the
-    // superclass field separately after this function returns, `T` must
-    // implement the unsafe trait ObjectType; the safety rules for the
-    // trait mandate that the parent field is manually dropped.
-    unsafe { std::ptr::drop_in_place(obj.cast::<T>()) }
+    abort_on_panic(|| {
+        // SAFETY: obj is an instance of T, since drop_object<T> is called
+        // from the QOM core function object_deinit() as the instance_finalize
+        // function for class T.  Note that while object_deinit() will drop the
+        // superclass field separately after this function returns, `T` must
+        // implement the unsafe trait ObjectType; the safety rules for the
+        // trait mandate that the parent field is manually dropped.
+        unsafe { std::ptr::drop_in_place(obj.cast::<T>()) }
+    })
 }
 
 /// Trait exposed by all structs corresponding to QOM objects.
@@ -256,6 +397,50 @@ unsafe fn as_object_mut_ptr(&self) -> *mut Object {
     }
 }
 
+/// Trait for QOM interfaces declared in Rust, usually with
+/// [`qom_interface!`](crate::qom_interface).  A reference to the
+/// implementing struct is a reference to an object that implements the
+/// interface.
+///
+/// # Safety
+///
+/// `Class` must be `#[repr(C)]` and start with [`InterfaceClass`].  The
+/// struct must not be constructed, and must not implement [`IsA`].
+pub unsafe trait InterfaceType: ObjectType {
+    /// The `TypeInfo` that registers the interface with QOM.
+    const TYPE_INFO: TypeInfo = TypeInfo {
+        name: Self::TYPE_NAME.as_ptr(),
+        parent: bindings::TYPE_INTERFACE.as_ptr().cast::<c_char>(),
+        instance_size: 0,
+        instance_align: 0,
+        instance_init: None,
+        instance_post_init: None,
+        instance_finalize: None,
+        abstract_: false,
+        class_size: core::mem::size_of::<Self::Class>(),
+        class_init: None,
+        class_base_init: None,
+        class_data: core::ptr::null_mut(),
+        interfaces: core::ptr::null_mut(),
+    };
+
+    /// Return the interface's class struct for the type of `self`, which
+    /// holds the methods that the type implements.
+    fn get_class(&self) -> &'static Self::Class {
+        // SAFETY: a reference to Self is only obtained by casting an
+        // object that implements the interface, so the result of the cast
+        // is the interface's class struct for the type of the object
+        unsafe {
+            let klass = object_get_class(self.as_object_mut_ptr());
+            let iface = bindings::object_class_dynamic_cast(klass, Self::TYPE_NAME.as_ptr());
+            NonNull::new(iface)
+                .expect("object does not implement the interface")
+                .cast::<Self::Class>()
+                .as_ref()
+        }
+    }
+}
+
 /// This trait provides safe casting operations for QOM objects to raw pointers,
 /// to be used for example for FFI. The trait can be applied to any kind of
 /// reference or smart pointers, and enforces correctness through the [`IsA`]
@@ -363,6 +548,30 @@ unsafe fn unsafe_cast<'a, U: ObjectType>(self) -> &'a U
 impl<T: ObjectType> ObjectDeref for &T {}
 impl<T: ObjectType> ObjectCast for &T {}
 
+/// Convert a raw pointer to a QOM object, for example one received from
+/// C code, to a reference to a Rust type.
+///
+/// Returns `None` if `obj` is null or if the object is not actually of
+/// type `T`. This is verified at runtime by checking the object's type
+/// information.
+///
+/// # Safety
+///
+/// `obj` must be either null or a valid pointer to a QOM object; the
+/// object must outlive `'a` and must not be accessed through other
+/// references while the returned one is alive.
+pub unsafe fn downcast<'a, T: ObjectType>(obj: *mut Object) -> Option<&'a mut T> {
+    if obj.is_null() {
+        return None;
+    }
+    unsafe {
+        // SAFETY: obj is a valid object, and the return value of
+        // object_dynamic_cast is either NULL or the argument itself
+        let result: *mut T = object_dynamic_cast(obj, T::TYPE_NAME.as_ptr()).cast();
+        result.as_mut()
+    }
+}
+
 /// Trait for mutable type casting operations in the QOM hierarchy.
 ///
 /// This trait provides the mutable counterparts to [`ObjectCast`]'s conversion
@@ -457,12 +666,27 @@ pub trait ObjectImpl: ObjectType + ClassInitImpl<Self::Class> {
     /// Whether the object can be instantiated
     const ABSTRACT: bool = false;
 
+    /// If not `None`, a function that returns the initial value of the
+    /// object, usually `Self::default`.  QOM allocates objects with all
+    /// bytes set to zero, which is not a valid value for types such as
+    /// `NonNull`, `String` or most enums; therefore, before
+    /// [`INSTANCE_INIT`](ObjectImpl::INSTANCE_INIT) runs, the fields other
+    /// than the parent field are overwritten with those of the returned
+    /// value.
+    ///
+    /// The parent field of the returned value is ignored.  When it is
+    /// built with `ParentField::default()`, the parent type must be one of
+    /// the C types that implement [`Zeroable`].
+    const INSTANCE_DEFAULT: Option<fn() -> Self> = None;
+
     /// Function that is called to initialize an object.  The parent class will
     /// have already been initialized so the type is only responsible for
     /// initializing its own members.
     ///
     /// FIXME: The argument is not really a valid reference. `&mut
-    /// MaybeUninit<Self>` would be a better description.
+    /// MaybeUninit<Self>` would be a better description, unless
+    /// [`INSTANCE_DEFAULT`](ObjectImpl::INSTANCE_DEFAULT) has already
+    /// initialized the fields.
     const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = None;
 
     /// Function that is called to finish initialization of an object, once
@@ -478,14 +702,26 @@ pub trait ObjectImpl: ObjectType + ClassInitImpl<Self::Class> {
         unsafe extern "C" fn(klass: *mut ObjectClass, data: *mut c_void),
     > = None;
 
+    /// The QOM interfaces that the type implements, built with
+    /// [`interfaces!`](crate::interfaces).  The class struct of each
+    /// interface is filled in by the type's `class_init`.
+    const INTERFACES: &'static [InterfaceInfo] = &[];
+
+    /// The `TypeInfo` that registers the type with QOM.  The default is
+    /// derived from the other items of the trait; an override must keep
+    /// the instance and class sizes large enough for `Self` and
+    /// `Self::Class`, which [`type_register`] checks.
+    // the interfaces are only read by C code
+    #[allow(clippy::as_ptr_cast_mut)]
     const TYPE_INFO: TypeInfo = TypeInfo {
         name: Self::TYPE_NAME.as_ptr(),
         parent: Self::ParentType::TYPE_NAME.as_ptr(),
         instance_size: core::mem::size_of::<Self>(),
         instance_align: core::mem::align_of::<Self>(),
-        instance_init: match Self::INSTANCE_INIT {
-            None => None,
-            Some(_) => Some(rust_instance_init::<Self>),
+        instance_init: if Self::INSTANCE_INIT.is_none() && Self::INSTANCE_DEFAULT.is_none() {
+            None
+        } else {
+            Some(rust_instance_init::<Self>)
         },
         instance_post_init: match Self::INSTANCE_POST_INIT {
             None => None,
@@ -497,13 +733,79 @@ pub trait ObjectImpl: ObjectType + ClassInitImpl<Self::Class> {
         class_init: Some(rust_class_init::<Self>),
         class_base_init: Self::CLASS_BASE_INIT,
         class_data: core::ptr::null_mut(),
-        interfaces: core::ptr::null_mut(),
+        interfaces: if Self::INTERFACES.is_empty() {
+            core::ptr::null_mut()
+        } else {
+            Self::INTERFACES.as_ptr() as *mut InterfaceInfo
+        },
     };
 
     // methods on ObjectClass
     const UNPARENT: Option<fn(&Self)> = None;
 }
 
+/// Register `T` with QOM, using [`ObjectImpl::TYPE_INFO`].  This is
+/// called at startup for types that use `#[derive(Object)]`.
+///
+/// # Panics
+///
+/// Panics, before registering the type, if the `TypeInfo` would make QOM
+/// allocate less memory than `T` or `T::Class` need, or if `T` or
+/// `T::Class` are smaller than the corresponding struct of the parent
+/// type.
+pub fn type_register<T: ObjectImpl>() {
+    let info = T::TYPE_INFO;
+    check_type_info::<T>(&info);
+    // SAFETY: the sizes in the TypeInfo were checked above; QOM copies
+    // the TypeInfo, and the strings in it are static
+    unsafe {
+        bindings::type_register_static(addr_of!(info));
+    }
+}
+
+/// Panic if `info` would make QOM allocate less memory than `T` or
+/// `T::Class` need, or if `T` or `T::Class` are smaller than the
+/// corresponding struct of the parent type.
+fn check_type_info<T: ObjectImpl>(info: &TypeInfo) {
+    let name = T::TYPE_NAME.to_string_lossy();
+    assert!(
+        info.instance_size >= mem::size_of::<T>(),
+        "{}: instance_size ({}) is smaller than the instance struct ({})",
+        name,
+        info.instance_size,
+        mem::size_of::<T>()
+    );
+    // zero means the alignment of malloc(), which is enough for u64
+    let instance_align = match info.instance_align {
+        0 => mem::align_of::<u64>(),
+        align => align,
+    };
+    assert!(
+        instance_align >= mem::align_of::<T>(),
+        "{}: instance_align ({}) is smaller than the alignment of the instance struct ({})",
+        name,
+        info.instance_align,
+        mem::align_of::<T>()
+    );
+    assert!(
+        info.class_size >= mem::size_of::<T::Class>(),
+        "{}: class_size ({}) is smaller than the class struct ({})",
+        name,
+        info.class_size,
+        mem::size_of::<T::Class>()
+    );
+    assert!(
+        mem::size_of::<T>() >= mem::size_of::<T::ParentType>(),
+        "{}: the instance struct is smaller than the parent's",
+        name
+    );
+    assert!(
+        mem::size_of::<T::Class>() >= mem::size_of::<<T::ParentType as ObjectType>::Class>(),
+        "{}: the class struct is smaller than the parent's",
+        name
+    );
+}
+
 /// Internal trait used to automatically fill in a class struct.
 ///
 /// Each QOM class that has virtual methods describes them in a
@@ -542,7 +844,11 @@ pub trait ObjectImpl: ObjectType + ClassInitImpl<Self::Class> {
 ///   own class struct `FooClass` and implement `ClassInitImpl<FooClass>`.
 ///   `ClassInitImpl<FooClass>`'s `class_init` method will then forward to
 ///   multiple other `class_init`s, for the interfaces as well as the
-///   superclass. (Note that there is no Rust example yet for using interfaces).
+///   superclass.  For example, the `class_init` of a type that lists
+///   `TYPE_HOTPLUG_HANDLER` in its [`INTERFACES`](ObjectImpl::INTERFACES)
+///   calls [`hotplug_handler_class_init`].
+///
+/// [`hotplug_handler_class_init`]: crate::hotplug::hotplug_handler_class_init
 ///
 /// * for classes implemented outside the ``qemu-api`` crate, it's not possible
 ///   to add blanket implementations like the above one, due to orphan rules. In
@@ -589,8 +895,10 @@ pub trait ClassInitImpl<T> {
 /// can be downcasted to type `T`. We also expect the device is
 /// readable/writeable from one thread at any time.
 unsafe extern "C" fn rust_unparent_fn<T: ObjectImpl>(dev: *mut Object) {
-    let state = NonNull::new(dev).unwrap().cast::<T>();
-    T::UNPARENT.unwrap()(unsafe { state.as_ref() });
+    abort_on_panic(|| {
+        let state = NonNull::new(dev).unwrap().cast::<T>();
+        T::UNPARENT.unwrap()(unsafe { state.as_ref() });
+    })
 }
 
 impl<T> ClassInitImpl<ObjectClass> for T
@@ -610,6 +918,25 @@ unsafe impl ObjectType for Object {
         unsafe { CStr::from_bytes_with_nul_unchecked(bindings::TYPE_OBJECT) };
 }
 
+/// Return the name of the type of `obj`, which is the name of the most
+/// derived class.  Types are never unregistered, so the name lives
+/// forever.
+pub fn typename(obj: &Object) -> &'static CStr {
+    // SAFETY: the object is valid, and the type name is never freed
+    unsafe { CStr::from_ptr(object_get_typename(obj)) }
+}
+
+/// Return the name of the type that `klass` is the class of.  Types are
+/// never unregistered, so the name lives forever.
+pub fn class_name(klass: &ObjectClass) -> &'static CStr {
+    // SAFETY: the class is valid, and the type name is never freed
+    unsafe {
+        CStr::from_ptr(bindings::object_class_get_name(
+            (klass as *const ObjectClass) as *mut ObjectClass,
+        ))
+    }
+}
+
 /// Trait for methods exposed by the Object class.  The methods can be
 /// called on all objects that have the trait `IsA<Object>`.
 ///
@@ -621,13 +948,7 @@ pub trait ObjectMethods: ObjectDeref
 {
     /// Return the name of the type of `self`
     fn typename(&self) -> std::borrow::Cow<'_, str> {
-        let obj = self.upcast::<Object>();
-        // SAFETY: safety of this is the requirement for implementing IsA
-        // The result of the C API has static lifetime
-        unsafe {
-            let p = object_get_typename(obj.as_mut_ptr());
-            CStr::from_ptr(p).to_string_lossy()
-        }
+        typename(self.upcast::<Object>()).to_string_lossy()
     }
 
     fn get_class(&self) -> &'static <Self::Target as ObjectType>::Class {
@@ -641,6 +962,763 @@ fn get_class(&self) -> &'static <Self::Target as ObjectType>::Class {
 
         klass
     }
+
+    /// Expose `field` as a read-only integer property called `name`.
+    /// Reading the property returns the value that `field` has at the time
+    /// of the read, while writing it fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `field` is not part of `self`, because the property could
+    /// otherwise outlive the field, or if `self` already has a property
+    /// called `name`.  Use [`try_add_uint_ptr_property`] if the name is
+    /// not known in advance.
+    ///
+    /// [`try_add_uint_ptr_property`]: ObjectMethods::try_add_uint_ptr_property
+    fn add_uint_ptr_property<F: UintPtrProperty>(&self, name: &CStr, field: &F) {
+        if let Err(err) = self.try_add_uint_ptr_property(name, field) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Same as [`add_uint_ptr_property`](ObjectMethods::add_uint_ptr_property),
+    /// but return an error instead of aborting if `self` already has a
+    /// property called `name`.  Objects that build their properties at run
+    /// time, for example from user-supplied names, should use this method.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `field` is not part of `self`.
+    fn try_add_uint_ptr_property<F: UintPtrProperty>(
+        &self,
+        name: &CStr,
+        field: &F,
+    ) -> error::Result<()> {
+        assert!(bql_locked());
+        let start = (self.deref() as *const Self::Target) as usize;
+        let field_start = (field as *const F) as usize;
+        assert!(
+            field_start >= start
+                && field_start + mem::size_of::<F>() <= start + mem::size_of::<Self::Target>(),
+            "property field must be part of the object"
+        );
+        // SAFETY: the object is valid; the field lives as long as the
+        // object, and the property only reads it
+        unsafe {
+            let obj = self.as_mut_ptr::<Object>();
+            if !bindings::object_property_find(obj, name.as_ptr()).is_null() {
+                return Err(Error::new(&format!(
+                    "property '{}' already exists",
+                    name.to_string_lossy()
+                )));
+            }
+            F::add_property(obj, name, (field as *const F).cast::<F::Value>());
+        }
+        Ok(())
+    }
+
+    /// Add a property called `name` that forwards reads and writes to the
+    /// property `target_name` of `target`, as in `object_property_add_alias()`.
+    /// This is mostly used to keep old property names working, or to expose
+    /// a property of a child object on its parent.
+    ///
+    /// An error is returned if `target` has no property called `target_name`,
+    /// or if `self` already has a property called `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `target` is neither `self` nor one of its descendants in
+    /// the QOM tree, because the alias could otherwise outlive it.
+    fn add_property_alias<T: IsA<Object>>(
+        &self,
+        name: &CStr,
+        target: &T,
+        target_name: &CStr,
+    ) -> error::Result<()> {
+        assert!(bql_locked());
+        // SAFETY: the pointers are only passed to C functions, and used to
+        // walk the QOM tree
+        let (obj, target) = unsafe { (self.as_mut_ptr::<Object>(), target.as_mut_ptr::<Object>()) };
+        let mut p = target;
+        // SAFETY: the parent of an object is either null or an object that
+        // is alive as long as its children
+        while !p.is_null() && p != obj {
+            p = unsafe { (*p).parent };
+        }
+        assert!(
+            !p.is_null(),
+            "alias target must be a descendant of the object"
+        );
+
+        // SAFETY: both objects are valid, and target lives as long as obj
+        unsafe {
+            if bindings::object_property_find(target, target_name.as_ptr()).is_null() {
+                return Err(Error::new(&format!(
+                    "property '{}' not found",
+                    target_name.to_string_lossy()
+                )));
+            }
+            if !bindings::object_property_find(obj, name.as_ptr()).is_null() {
+                return Err(Error::new(&format!(
+                    "property '{}' already exists",
+                    name.to_string_lossy()
+                )));
+            }
+            bindings::object_property_add_alias(obj, name.as_ptr(), target, target_name.as_ptr());
+        }
+        Ok(())
+    }
+
+    /// Add `child` to the QOM composition tree as a child of `self`
+    /// called `name`, as in `object_property_add_child()`.  The child then
+    /// appears at `name` below the path of `self`, and is unparented when
+    /// `self` is finalized.
+    ///
+    /// `self` takes a reference to `child`, so the caller can drop its own
+    /// reference, for example an [`Owned`], once the child is added; the
+    /// child is then finalized together with `self`.
+    ///
+    /// An error is returned if `self` already has a property called `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `child` already has a parent.
+    fn add_child<T: IsA<Object>>(&self, name: &CStr, child: &T) -> error::Result<()> {
+        assert!(bql_locked());
+        // SAFETY: the pointers are only passed to C functions
+        let (obj, child) = unsafe { (self.as_mut_ptr::<Object>(), child.as_mut_ptr::<Object>()) };
+        // SAFETY: the child is a valid object
+        assert!(
+            unsafe { (*child).parent }.is_null(),
+            "object already has a parent"
+        );
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: both objects are valid; on success, the new property
+        // holds a reference to the child
+        unsafe {
+            bindings::object_property_try_add_child(obj, name.as_ptr(), child, addr_of_mut!(err));
+            Error::err_or_else(err, ())
+        }
+    }
+
+    /// Return the path of `self` in the QOM composition tree, for example
+    /// `/machine/peripheral/serial0`, or `None` if `self` is not part of
+    /// the tree.
+    fn canonical_path(&self) -> Option<String> {
+        assert!(bql_locked());
+        // SAFETY: the result of object_get_canonical_path() is either null
+        // or a newly allocated string, which is copied and then freed
+        unsafe {
+            let path = bindings::object_get_canonical_path(self.as_ptr::<Object>());
+            if path.is_null() {
+                return None;
+            }
+            let result = CStr::from_ptr(path).to_string_lossy().into_owned();
+            g_free(path.cast::<c_void>());
+            Some(result)
+        }
+    }
+
+    /// Return an iterator over the properties of `self`, including the
+    /// class properties of its type and of its superclasses, as in
+    /// `object_property_iter_init()`.  The iterator yields the name and
+    /// the type of each property.
+    ///
+    /// Properties must not be added to `self` or removed from it until the
+    /// iterator is dropped.
+    fn property_iter(&self) -> PropertyIter<'_> {
+        assert!(bql_locked());
+        let mut iter = MaybeUninit::<bindings::ObjectPropertyIterator>::uninit();
+        // SAFETY: object_property_iter_init() initializes the iterator,
+        // which borrows the object
+        unsafe {
+            bindings::object_property_iter_init(iter.as_mut_ptr(), self.as_mut_ptr::<Object>());
+            PropertyIter {
+                iter: iter.assume_init(),
+                _obj: PhantomData,
+            }
+        }
+    }
 }
 
 impl<R: ObjectDeref> ObjectMethods for R where R::Target: IsA<Object> {}
+
+/// An iterator over the properties of an object, as returned by
+/// [`ObjectMethods::property_iter`].  Each item is the name and the type
+/// of a property, such as `("realized", "bool")`.
+pub struct PropertyIter<'a> {
+    iter: bindings::ObjectPropertyIterator,
+    _obj: PhantomData<&'a Object>,
+}
+
+impl Iterator for PropertyIter<'_> {
+    type Item = (String, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        assert!(bql_locked());
+        // SAFETY: the iterator was initialized by property_iter(), and the
+        // object it walks is borrowed for the lifetime of `self`
+        unsafe {
+            let prop = bindings::object_property_iter_next(addr_of_mut!(self.iter)).as_ref()?;
+            Some((
+                CStr::from_ptr(prop.name).to_string_lossy().into_owned(),
+                CStr::from_ptr(prop.type_).to_string_lossy().into_owned(),
+            ))
+        }
+    }
+}
+
+impl fmt::Debug for PropertyIter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PropertyIter").finish_non_exhaustive()
+    }
+}
+
+/// Types of the fields that can be exposed as properties with
+/// [`ObjectMethods::add_uint_ptr_property`]: unsigned integers, and cells
+/// that hold one.
+///
+/// # Safety
+///
+/// `Self` must have the same memory representation as
+/// [`Value`](UintPtrProperty::Value).
+pub unsafe trait UintPtrProperty {
+    /// The integer type that the property reads.
+    type Value;
+
+    /// Add a read-only property that reads `*value`.
+    ///
+    /// # Safety
+    ///
+    /// `value` must remain valid as long as `obj`.
+    unsafe fn add_property(obj: *mut Object, name: &CStr, value: *const Self::Value);
+}
+
+macro_rules! impl_uint_ptr_property {
+    ($type:ty, $func:ident) => {
+        unsafe impl UintPtrProperty for $type {
+            type Value = $type;
+
+            unsafe fn add_property(obj: *mut Object, name: &CStr, value: *const $type) {
+                // SAFETY: forwarded to the caller
+                unsafe {
+                    bindings::$func(
+                        obj,
+                        name.as_ptr(),
+                        value,
+                        bindings::ObjectPropertyFlags::OBJ_PROP_FLAG_READ,
+                    );
+                }
+            }
+        }
+    };
+}
+
+impl_uint_ptr_property!(u8, object_property_add_uint8_ptr);
+impl_uint_ptr_property!(u16, object_property_add_uint16_ptr);
+impl_uint_ptr_property!(u32, object_property_add_uint32_ptr);
+impl_uint_ptr_property!(u64, object_property_add_uint64_ptr);
+
+// SAFETY: cells have the same in-memory representation as their contents
+unsafe impl<T: UintPtrProperty<Value = T>> UintPtrProperty for Cell<T> {
+    type Value = T;
+
+    unsafe fn add_property(obj: *mut Object, name: &CStr, value: *const T) {
+        // SAFETY: forwarded to the caller
+        unsafe { T::add_property(obj, name, value) }
+    }
+}
+
+// SAFETY: cells have the same in-memory representation as their contents
+unsafe impl<T: UintPtrProperty<Value = T>> UintPtrProperty for BqlCell<T> {
+    type Value = T;
+
+    unsafe fn add_property(obj: *mut Object, name: &CStr, value: *const T) {
+        // SAFETY: forwarded to the caller
+        unsafe { T::add_property(obj, name, value) }
+    }
+}
+
+/// Convert `obj` to the type that registered a class property.
+///
+/// # Safety
+///
+/// `obj` must point to an instance of the class that the property was
+/// added to, or of one of its subclasses.
+unsafe fn class_property_owner<'a, T: ObjectType>(obj: *mut Object) -> &'a T {
+    assert!(bql_locked());
+    // SAFETY: forwarded to the caller
+    unsafe { NonNull::new(obj).unwrap().cast::<T>().as_ref() }
+}
+
+unsafe extern "C" fn rust_class_bool_get<T: ObjectType, G: for<'a> FnCall<(&'a T,), bool>>(
+    obj: *mut Object,
+    _errp: *mut *mut bindings::Error,
+) -> bool {
+    abort_on_panic(|| {
+        // SAFETY: QOM only calls the getter on instances of the class
+        G::call((unsafe { class_property_owner::<T>(obj) },))
+    })
+}
+
+unsafe extern "C" fn rust_class_bool_set<
+    T: ObjectType,
+    S: for<'a> FnCall<(&'a T, bool), error::Result<()>>,
+>(
+    obj: *mut Object,
+    value: bool,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        // SAFETY: QOM only calls the setter on instances of the class
+        let result = S::call((unsafe { class_property_owner::<T>(obj) }, value));
+        unsafe {
+            bql_error_propagate(result, errp);
+        }
+    })
+}
+
+unsafe extern "C" fn rust_class_str_get<T: ObjectType, G: for<'a> FnCall<(&'a T,), String>>(
+    obj: *mut Object,
+    _errp: *mut *mut bindings::Error,
+) -> *mut c_char {
+    abort_on_panic(|| {
+        // SAFETY: QOM only calls the getter on instances of the class
+        let value = G::call((unsafe { class_property_owner::<T>(obj) },));
+        // the caller frees the copy with g_free()
+        glib_strdup(&value)
+    })
+}
+
+unsafe extern "C" fn rust_class_str_set<
+    T: ObjectType,
+    S: for<'a, 'b> FnCall<(&'a T, &'b str), error::Result<()>>,
+>(
+    obj: *mut Object,
+    value: *const c_char,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        // SAFETY: QOM only calls the setter on instances of the class, and
+        // passes a valid C string
+        let owner = unsafe { class_property_owner::<T>(obj) };
+        let result = unsafe { CStr::from_ptr(value) }
+            .to_str()
+            .map_err(|_| Error::new("property value is not valid UTF-8"))
+            .and_then(|value| S::call((owner, value)));
+        unsafe {
+            bql_error_propagate(result, errp);
+        }
+    })
+}
+
+/// Add a boolean property called `name` to `klass`, which is the class of
+/// `T`, or one of its superclasses.  This is meant to be called from
+/// `class_init`.
+///
+/// Unlike the properties that are added to an instance, a class property
+/// is registered once per type and exists in all instances of the type
+/// and its subclasses, including those that are created later.  Reading
+/// and writing the property calls `getter` and `setter` on the instance
+/// that is accessed.
+pub fn class_add_bool<T, G, S>(
+    klass: &mut ObjectClass,
+    name: &'static CStr,
+    _getter: &G,
+    _setter: &S,
+) where
+    T: ObjectType,
+    G: for<'a> FnCall<(&'a T,), bool>,
+    S: for<'a> FnCall<(&'a T, bool), error::Result<()>>,
+{
+    // SAFETY: the callbacks expect instances of `T`, which is either the
+    // type of `klass` or a subclass of it; `name` lives forever
+    unsafe {
+        bindings::object_class_property_add_bool(
+            klass,
+            name.as_ptr(),
+            Some(rust_class_bool_get::<T, G>),
+            Some(rust_class_bool_set::<T, S>),
+        );
+    }
+}
+
+/// Add a string property called `name` to `klass`, which is the class of
+/// `T`, or one of its superclasses.  This is meant to be called from
+/// `class_init`; see [`class_add_bool`] for the differences from instance
+/// properties.
+///
+/// Values that are not valid UTF-8 are rejected before `setter` is called.
+pub fn class_add_str<T, G, S>(
+    klass: &mut ObjectClass,
+    name: &'static CStr,
+    _getter: &G,
+    _setter: &S,
+) where
+    T: ObjectType,
+    G: for<'a> FnCall<(&'a T,), String>,
+    S: for<'a, 'b> FnCall<(&'a T, &'b str), error::Result<()>>,
+{
+    // SAFETY: the callbacks expect instances of `T`, which is either the
+    // type of `klass` or a subclass of it; `name` lives forever
+    unsafe {
+        bindings::object_class_property_add_str(
+            klass,
+            name.as_ptr(),
+            Some(rust_class_str_get::<T, G>),
+            Some(rust_class_str_set::<T, S>),
+        );
+    }
+}
+
+/// Set the help text of the class property `name` of `klass`, which is
+/// shown for example by `-device foo,help`.  This is meant to be called
+/// from `class_init`, after the property is added.
+///
+/// # Panics
+///
+/// Panics if `klass` has no property called `name`.
+pub fn class_set_description(klass: &mut ObjectClass, name: &CStr, description: &CStr) {
+    // SAFETY: both strings are valid and copied by QOM
+    unsafe {
+        assert!(
+            !bindings::object_class_property_find(klass, name.as_ptr()).is_null(),
+            "class has no property {}",
+            name.to_string_lossy()
+        );
+        bindings::object_class_property_set_description(klass, name.as_ptr(), description.as_ptr());
+    }
+}
+
+/// Find the class struct of `T` within `klass`, which is the class of `T`
+/// or of one of its subclasses, and pass it to `f`.  This is meant to be
+/// called from `class_init`, to override virtual methods of an ancestor
+/// class when only the `ObjectClass` is at hand:
+///
+/// ```ignore
+/// class_override::<DeviceState>(klass, |dc| {
+///     dc.realize = Some(my_realize_fn);
+/// });
+/// ```
+///
+/// # Panics
+///
+/// Panics if `klass` is not the class of `T` or of a subclass of `T`.
+pub fn class_override<T: ObjectType>(klass: &mut ObjectClass, f: impl FnOnce(&mut T::Class)) {
+    // SAFETY: the result of the cast is either NULL or a class of type
+    // T::TYPE_NAME, whose struct starts with T::Class; the class struct is
+    // borrowed mutably for the duration of the call
+    let parent = unsafe {
+        NonNull::new(bindings::object_class_dynamic_cast(
+            klass,
+            T::TYPE_NAME.as_ptr(),
+        ))
+        .unwrap_or_else(|| {
+            panic!(
+                "class is not a subclass of {}",
+                T::TYPE_NAME.to_string_lossy()
+            )
+        })
+        .cast::<T::Class>()
+        .as_mut()
+    };
+    f(parent);
+}
+
+/// The reason why [`resolve_path`] or [`resolve_path_typed`] did not find
+/// an object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolvePathError {
+    /// No object matches the path.
+    NotFound,
+    /// The path is a partial path, and more than one object matches it.
+    Ambiguous,
+}
+
+fn resolve_path_of_type(
+    path: &CStr,
+    typename: Option<&CStr>,
+) -> Result<NonNull<Object>, ResolvePathError> {
+    assert!(bql_locked());
+    let mut ambiguous = false;
+    // SAFETY: the strings are valid for the duration of the call, and the
+    // C function only sets `ambiguous` when it finds more than one match
+    let obj = unsafe {
+        match typename {
+            Some(typename) => bindings::object_resolve_path_type(
+                path.as_ptr(),
+                typename.as_ptr(),
+                addr_of_mut!(ambiguous),
+            ),
+            None => bindings::object_resolve_path(path.as_ptr(), addr_of_mut!(ambiguous)),
+        }
+    };
+    match NonNull::new(obj) {
+        Some(obj) => Ok(obj),
+        None if ambiguous => Err(ResolvePathError::Ambiguous),
+        None => Err(ResolvePathError::NotFound),
+    }
+}
+
+/// Find an object in the QOM tree.  An absolute path such as
+/// `/machine/peripheral/foo` starts at the root; a partial path such as
+/// `peripheral/foo` matches any object whose path ends with it, and
+/// fails with [`ResolvePathError::Ambiguous`] if there is more than one.
+///
+/// The object is not referenced; it lives as long as it stays in the
+/// QOM tree.  Devices usually resolve paths while they are realized, and
+/// should add a link property if they keep the pointer for longer.
+pub fn resolve_path(path: &CStr) -> Result<NonNull<Object>, ResolvePathError> {
+    resolve_path_of_type(path, None)
+}
+
+/// Find an object of type `T`, or of a subclass of `T`, in the QOM tree.
+/// A partial path only matches objects of that type, so it can be
+/// unambiguous even when [`resolve_path`] is not.  An absolute path that
+/// names an object of a different type fails with
+/// [`ResolvePathError::NotFound`].
+pub fn resolve_path_typed<T: ObjectType>(path: &CStr) -> Result<NonNull<T>, ResolvePathError> {
+    resolve_path_of_type(path, Some(T::TYPE_NAME)).map(NonNull::cast)
+}
+
+/// A reference to a QOM object, which is released when the `Owned` is
+/// dropped.  The object is finalized when the last reference goes away.
+///
+/// Cloning an `Owned` takes another reference to the same object.  At the
+/// boundary with C code, [`from_raw`](Owned::from_raw) and
+/// [`into_raw`](Owned::into_raw) transfer a reference without changing
+/// the reference count.
+#[doc(alias = "ObjectRef")]
+pub struct Owned<T: ObjectType>(NonNull<T>);
+
+impl<T: ObjectType> Owned<T> {
+    /// Take a new reference to `obj`, as in `object_ref()`.
+    pub fn from_ref(obj: &T) -> Self {
+        assert!(bql_locked());
+        // SAFETY: the object is alive, because it is borrowed
+        unsafe {
+            bindings::object_ref(addr_of!(*obj) as *mut c_void);
+        }
+        Owned(NonNull::from(obj))
+    }
+
+    /// Take ownership of a reference that was obtained from C code, for
+    /// example the result of `object_new()`, or that was returned by
+    /// [`into_raw`](Owned::into_raw).  The reference count is not changed.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a valid, non-null pointer to an object of type `T`,
+    /// and the caller must own one reference to it, which is released when
+    /// the `Owned` is dropped.
+    pub const unsafe fn from_raw(ptr: *mut T) -> Self {
+        // SAFETY: the caller guarantees that the pointer is not null
+        Owned(unsafe { NonNull::new_unchecked(ptr) })
+    }
+
+    /// Give up the reference without releasing it, for example to pass it
+    /// to C code that takes ownership.  The reference can be turned back
+    /// into an `Owned` with [`from_raw`](Owned::from_raw).
+    #[must_use]
+    pub fn into_raw(self) -> *mut T {
+        ManuallyDrop::new(self).0.as_ptr()
+    }
+}
+
+impl<T: ObjectType> Clone for Owned<T> {
+    fn clone(&self) -> Self {
+        Self::from_ref(self)
+    }
+}
+
+impl<T: ObjectType> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the object is alive as long as the reference is held
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl<T: ObjectType> ObjectDeref for Owned<T> {}
+
+impl<T: ObjectType> fmt::Debug for Owned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Owned").field(&self.0).finish()
+    }
+}
+
+impl<T: ObjectType> Drop for Owned<T> {
+    fn drop(&mut self) {
+        assert!(bql_locked());
+        // SAFETY: the reference was taken when the Owned was created
+        unsafe {
+            bindings::object_unref(self.0.as_ptr().cast::<c_void>());
+        }
+    }
+}
+
+/// Create an object and set its properties, as in
+/// `object_new_with_props()`:
+///
+/// ```ignore
+/// let obj = ObjectBuilder::<MyBackend>::new(c_str!("my-backend"))
+///     .property(c_str!("size"), c_str!("64k"))
+///     .property(c_str!("share"), c_str!("on"))
+///     .build()?;
+/// ```
+///
+/// Values are parsed according to the type of the property, as they would
+/// be on the command line.  Objects that implement `TYPE_USER_CREATABLE`
+/// are completed once all the properties are set.
+#[derive(Debug)]
+pub struct ObjectBuilder<'a, T: ObjectType> {
+    typename: &'a CStr,
+    properties: Vec<(&'a CStr, &'a CStr)>,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T: ObjectType> ObjectBuilder<'a, T> {
+    /// Prepare to create an object of type `typename`, which must be `T`
+    /// or a subclass of `T`.
+    pub const fn new(typename: &'a CStr) -> Self {
+        ObjectBuilder {
+            typename,
+            properties: Vec::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Set the property `name` to `value` after the object is created.
+    /// Properties are set in the order in which they are added.
+    #[must_use]
+    pub fn property(mut self, name: &'a CStr, value: &'a CStr) -> Self {
+        self.properties.push((name, value));
+        self
+    }
+
+    /// Create the object and set its properties.  If a property cannot be
+    /// set, or the object fails to complete, the object is freed and the
+    /// error is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `typename` is not `T` or a subclass of `T`.
+    pub fn build(self) -> error::Result<Owned<T>> {
+        assert!(bql_locked());
+        // SAFETY: the object is checked to be a `T` before it is cast, and
+        // the reference returned by object_new() is owned by `obj`
+        let obj = unsafe {
+            let obj = bindings::object_new(self.typename.as_ptr());
+            if object_dynamic_cast(obj, T::TYPE_NAME.as_ptr()).is_null() {
+                bindings::object_unref(obj.cast::<c_void>());
+                panic!(
+                    "{:?} is not a subclass of {:?}",
+                    self.typename,
+                    T::TYPE_NAME
+                );
+            }
+            Owned(NonNull::new(obj).unwrap().cast::<T>())
+        };
+        let ptr = obj.0.as_ptr().cast::<Object>();
+
+        for (name, value) in self.properties {
+            let mut err = ptr::null_mut();
+            // SAFETY: the strings are valid for the duration of the call;
+            // the error, if any, is owned by the result
+            unsafe {
+                bindings::object_property_parse(
+                    ptr,
+                    name.as_ptr(),
+                    value.as_ptr(),
+                    addr_of_mut!(err),
+                );
+                Error::err_or_else(err, ())?;
+            }
+        }
+
+        // SAFETY: as above
+        unsafe {
+            let uc = object_dynamic_cast(ptr, bindings::TYPE_USER_CREATABLE.as_ptr().cast());
+            if !uc.is_null() {
+                let mut err = ptr::null_mut();
+                bindings::user_creatable_complete(
+                    uc.cast::<bindings::UserCreatable>(),
+                    addr_of_mut!(err),
+                );
+                Error::err_or_else(err, ())?;
+            }
+        }
+        Ok(obj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c_str;
+
+    #[repr(C)]
+    struct Regs {
+        parent: ParentField<Object>,
+        regs: [u64; 4],
+    }
+
+    unsafe impl ObjectType for Regs {
+        type Class = ObjectClass;
+        const TYPE_NAME: &'static CStr = c_str!("regs");
+    }
+
+    impl ObjectImpl for Regs {
+        type ParentType = Object;
+    }
+
+    /// A `TypeInfo` for `Regs` with the given instance size and alignment;
+    /// the default `TYPE_INFO` cannot be used because its callbacks call
+    /// into QOM.
+    fn regs_info(instance_size: usize, instance_align: usize) -> TypeInfo {
+        TypeInfo {
+            name: Regs::TYPE_NAME.as_ptr(),
+            parent: Object::TYPE_NAME.as_ptr(),
+            instance_size,
+            instance_align,
+            instance_init: None,
+            instance_post_init: None,
+            instance_finalize: None,
+            abstract_: false,
+            class_size: mem::size_of::<ObjectClass>(),
+            class_init: None,
+            class_base_init: None,
+            class_data: ptr::null_mut(),
+            interfaces: ptr::null_mut(),
+        }
+    }
+
+    #[test]
+    fn test_check_type_info() {
+        check_type_info::<Regs>(&regs_info(mem::size_of::<Regs>(), 0));
+        check_type_info::<Regs>(&regs_info(mem::size_of::<Regs>() + 8, 8));
+    }
+
+    #[test]
+    #[should_panic(expected = "regs: instance_size")]
+    fn test_check_type_info_instance_size() {
+        check_type_info::<Regs>(&regs_info(mem::size_of::<Object>(), 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "regs: instance_align")]
+    fn test_check_type_info_instance_align() {
+        check_type_info::<Regs>(&regs_info(mem::size_of::<Regs>(), 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "regs: class_size")]
+    fn test_check_type_info_class_size() {
+        let info = TypeInfo {
+            class_size: 0,
+            ..regs_info(mem::size_of::<Regs>(), 0)
+        };
+        check_type_info::<Regs>(&info);
+    }
+}
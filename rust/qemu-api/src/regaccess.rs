@@ -0,0 +1,776 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Helpers to implement MMIO accesses to device registers.
+//!
+//! Devices usually keep each register in an integer field of the width of
+//! the register, but the guest can access it with a narrower access, or
+//! with an access that is not aligned to the start of the register.  In
+//! that case, which bytes of the register are accessed depends on the
+//! endianness of the device.  [`read_reg`] and [`write_reg`] take care of
+//! the shifting:
+//!
+//! ```ignore
+//! fn read(&self, offset: hwaddr, size: u32) -> u64 {
+//!     match offset & !3 {
+//!         CTRL => read_reg(self.ctrl, offset & 3, size, Endian::Little),
+//!         ...
+//!     }
+//! }
+//! ```
+//!
+//! Bit fields within a register are accessed with
+//! [`IntegerExt::extract`] and [`IntegerExt::deposit`]; the free functions
+//! [`extract32`], [`deposit32`] and their 64-bit counterparts are the same
+//! operations, under the names used by QEMU's C code.  A [`BitField`]
+//! names the position of a field once, and
+//! [`bitfields!`](crate::bitfields) declares a register type with an
+//! accessor for each of its fields:
+//!
+//! ```ignore
+//! bitfields! {
+//!     pub struct Status(u32) {
+//!         (busy, set_busy, 0, 1),
+//!         (level, set_level, 4, 4),
+//!     }
+//! }
+//!
+//! let mut status = Status::default();
+//! status.set_level(3);
+//! ```
+//!
+//! The layout of a register block can be declared with
+//! [`registers!`](crate::registers), which checks the offset, width and
+//! direction of each access before the device sees it:
+//!
+//! ```ignore
+//! registers! {
+//!     pub enum Reg {
+//!         (CTRL, 0x00, 4, ReadWrite),
+//!         (STATUS, 0x04, 4, ReadOnly),
+//!     }
+//! }
+//!
+//! fn read(&self, offset: hwaddr, size: u32) -> u64 {
+//!     Reg::dispatch_read(offset, size, |reg| match reg {
+//!         Reg::CTRL => self.ctrl.into(),
+//!         Reg::STATUS => self.status.into(),
+//!     })
+//! }
+//! ```
+
+use std::marker::PhantomData;
+
+use crate::{bitops::IntegerExt, memory::hwaddr};
+
+/// The byte order of a device's registers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Endian {
+    /// The least significant byte of a register is at the lowest address.
+    Little,
+    /// The most significant byte of a register is at the lowest address.
+    Big,
+}
+
+/// Integer types that can hold a device register.
+pub trait Register: IntegerExt + Into<u64> {
+    /// Truncate `value` to the width of the register.
+    fn from_u64(value: u64) -> Self;
+}
+
+macro_rules! impl_register {
+    ($($type:ty),*) => {
+        $(impl Register for $type {
+            #[inline]
+            fn from_u64(value: u64) -> Self {
+                value as $type
+            }
+        })*
+    };
+}
+
+impl_register!(u8, u16, u32, u64);
+
+/// Return the position, in bits, of the `size` bytes at `offset` within a
+/// register of type `T`.
+fn reg_shift<T: Register>(offset: hwaddr, size: u32, endian: Endian) -> u32 {
+    let bytes = T::BITS / 8;
+    assert!(
+        size.is_power_of_two() && size <= bytes,
+        "invalid access size {} for a {}-byte register",
+        size,
+        bytes
+    );
+    assert!(
+        offset < u64::from(bytes) && offset as u32 + size <= bytes,
+        "access of {} bytes at offset {} is outside a {}-byte register",
+        size,
+        offset,
+        bytes
+    );
+    let offset = offset as u32;
+    match endian {
+        Endian::Little => offset * 8,
+        Endian::Big => (bytes - offset - size) * 8,
+    }
+}
+
+/// Return the result of a `size`-byte read, at byte `offset` from the
+/// start of a register whose current value is `reg`.
+///
+/// # Panics
+///
+/// Panics if `size` is not a power of two, or if the access does not fit
+/// in the register.
+pub fn read_reg<T: Register>(reg: T, offset: hwaddr, size: u32, endian: Endian) -> u64 {
+    let shift = reg_shift::<T>(offset, size, endian);
+    reg.into().extract(shift, size * 8)
+}
+
+/// Return the new value of a register whose current value is `reg`, after
+/// a `size`-byte write of `value` at byte `offset` from the start of the
+/// register.  The bytes that are not covered by the access are unchanged.
+///
+/// # Panics
+///
+/// Panics if `size` is not a power of two, or if the access does not fit
+/// in the register.
+#[must_use]
+pub fn write_reg<T: Register>(reg: T, offset: hwaddr, size: u32, value: u64, endian: Endian) -> T {
+    let shift = reg_shift::<T>(offset, size, endian);
+    T::from_u64(reg.into().deposit(shift, size * 8, value))
+}
+
+/// Extract the `length` bits starting at bit `start` of `value`.
+#[inline]
+#[must_use]
+pub fn extract32(value: u32, start: u32, length: u32) -> u32 {
+    value.extract(start, length)
+}
+
+/// Extract the `length` bits starting at bit `start` of `value`.
+#[inline]
+#[must_use]
+pub fn extract64(value: u64, start: u32, length: u32) -> u64 {
+    value.extract(start, length)
+}
+
+/// Replace the `length` bits starting at bit `start` of `value` with the
+/// low bits of `fieldval`.
+#[inline]
+#[must_use]
+pub fn deposit32(value: u32, start: u32, length: u32, fieldval: u32) -> u32 {
+    value.deposit(start, length, fieldval)
+}
+
+/// Replace the `length` bits starting at bit `start` of `value` with the
+/// low bits of `fieldval`.
+#[inline]
+#[must_use]
+pub fn deposit64(value: u64, start: u32, length: u32, fieldval: u64) -> u64 {
+    value.deposit(start, length, fieldval)
+}
+
+/// A field of `length` bits starting at bit `start` of a register of type
+/// `T`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BitField<T> {
+    start: u32,
+    length: u32,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: IntegerExt> BitField<T> {
+    /// Return a field of `length` bits starting at bit `start`.
+    ///
+    /// # Panics
+    ///
+    /// Panics, or fails to compile in a constant, if the field is empty or
+    /// does not fit in `T`.
+    pub const fn new(start: u32, length: u32) -> Self {
+        assert!(
+            length > 0 && start < T::BITS && length <= T::BITS - start,
+            "bit field does not fit in the register"
+        );
+        BitField {
+            start,
+            length,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Return the lowest bit of the field.
+    pub const fn start(self) -> u32 {
+        self.start
+    }
+
+    /// Return the number of bits in the field.
+    pub const fn length(self) -> u32 {
+        self.length
+    }
+
+    /// Return the bits of the register that belong to the field.
+    #[must_use]
+    pub fn mask(self) -> T {
+        T::mask(self.start, self.length)
+    }
+
+    /// Return the largest value that the field can hold.
+    #[must_use]
+    pub fn max(self) -> T {
+        T::MAX >> (T::BITS - self.length)
+    }
+
+    /// Return the value of the field in `reg`.
+    #[must_use]
+    pub fn get(self, reg: T) -> T {
+        reg.extract(self.start, self.length)
+    }
+
+    /// Return `reg` with the field replaced by `value`.  In debug builds,
+    /// panic if `value` is too wide for the field; otherwise, the bits that
+    /// do not fit are dropped.
+    #[must_use]
+    pub fn set(self, reg: T, value: T) -> T {
+        debug_assert!(
+            value <= self.max(),
+            "value does not fit in a {}-bit field",
+            self.length
+        );
+        reg.deposit(self.start, self.length, value)
+    }
+}
+
+/// Declare a register type as a tuple struct around an integer, with a
+/// getter and a setter for each of its fields.  Each field is a
+/// `(getter, setter, start, length)` tuple; the getter and setter work as
+/// [`BitField::get`] and [`BitField::set`], so that in debug builds the
+/// setter panics if the value is too wide for the field.  Compilation
+/// fails if a field does not fit in the register.
+///
+/// The struct can be converted to and from the integer, and it implements
+/// [`VMState`](crate::vmstate::VMState), so that it can be migrated with
+/// [`vmstate_of!`](crate::vmstate_of).
+///
+/// # Examples
+///
+/// ```
+/// # use qemu_api::bitfields;
+/// bitfields! {
+///     /// Interrupt control register
+///     pub struct IntCtrl(u32) {
+///         /// Interrupt enable
+///         (enable, set_enable, 0, 1),
+///         (priority, set_priority, 4, 3),
+///     }
+/// }
+///
+/// let mut ctrl = IntCtrl::from(0x31);
+/// assert_eq!(ctrl.enable(), 1);
+/// assert_eq!(ctrl.priority(), 3);
+/// ctrl.set_priority(5);
+/// assert_eq!(u32::from(ctrl), 0x51);
+/// ```
+#[macro_export]
+macro_rules! bitfields {
+    ($(#[$attr:meta])* $vis:vis struct $name:ident($type:ty) {
+        $($(#[$field_attr:meta])*
+          ($get:ident, $set:ident, $start:expr, $length:expr)),* $(,)?
+    }) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+        $vis struct $name(pub $type);
+
+        #[allow(dead_code)]
+        impl $name {
+            $(
+                $(#[$field_attr])*
+                $vis fn $get(&self) -> $type {
+                    const FIELD: $crate::regaccess::BitField<$type> =
+                        $crate::regaccess::BitField::new($start, $length);
+                    FIELD.get(self.0)
+                }
+
+                $(#[$field_attr])*
+                $vis fn $set(&mut self, value: $type) {
+                    const FIELD: $crate::regaccess::BitField<$type> =
+                        $crate::regaccess::BitField::new($start, $length);
+                    self.0 = FIELD.set(self.0, value);
+                }
+            )*
+        }
+
+        impl ::core::convert::From<$type> for $name {
+            fn from(value: $type) -> Self {
+                Self(value)
+            }
+        }
+
+        impl ::core::convert::From<$name> for $type {
+            fn from(reg: $name) -> Self {
+                reg.0
+            }
+        }
+
+        $crate::impl_vmstate_forward!($name);
+    };
+}
+
+/// The directions in which the guest can access a register.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    /// Writes are ignored.
+    ReadOnly,
+    /// Reads return zero.
+    WriteOnly,
+    /// The register can be both read and written.
+    ReadWrite,
+}
+
+impl Access {
+    /// Return whether the guest can read the register.
+    pub const fn can_read(self) -> bool {
+        !matches!(self, Access::WriteOnly)
+    }
+
+    /// Return whether the guest can write the register.
+    pub const fn can_write(self) -> bool {
+        !matches!(self, Access::ReadOnly)
+    }
+}
+
+/// A block of registers, usually implemented by an enum that is declared
+/// with [`registers!`](crate::registers).
+pub trait RegisterBlock: Copy {
+    /// Return the register at `offset`, if any.
+    fn decode(offset: hwaddr) -> Option<Self>;
+
+    /// Return the offset of the register from the start of the block.
+    fn offset(self) -> hwaddr;
+
+    /// Return the width of the register in bytes.
+    fn width(self) -> u32;
+
+    /// Return the directions in which the register can be accessed.
+    fn access(self) -> Access;
+
+    /// Return the value of the register after a reset.
+    fn reset_value(self) -> u64 {
+        0
+    }
+
+    /// Return the register that a read of `size` bytes at `offset`
+    /// targets, or `None` if there is no such register, if it cannot be
+    /// read, or if `size` is not its width.
+    fn decode_read(offset: hwaddr, size: u32) -> Option<Self> {
+        Self::decode(offset).filter(|reg| reg.width() == size && reg.access().can_read())
+    }
+
+    /// Return the register that a write of `size` bytes at `offset`
+    /// targets, or `None` if there is no such register, if it cannot be
+    /// written, or if `size` is not its width.
+    fn decode_write(offset: hwaddr, size: u32) -> Option<Self> {
+        Self::decode(offset).filter(|reg| reg.width() == size && reg.access().can_write())
+    }
+
+    /// Call `read` with the register that is read by the guest, or return
+    /// zero if [`decode_read`](RegisterBlock::decode_read) rejects the
+    /// access.
+    fn dispatch_read(offset: hwaddr, size: u32, read: impl FnOnce(Self) -> u64) -> u64 {
+        Self::decode_read(offset, size).map_or(0, read)
+    }
+
+    /// Call `write` with the register that is written by the guest and
+    /// the value, or do nothing if
+    /// [`decode_write`](RegisterBlock::decode_write) rejects the access.
+    fn dispatch_write(offset: hwaddr, size: u32, value: u64, write: impl FnOnce(Self, u64)) {
+        if let Some(reg) = Self::decode_write(offset, size) {
+            write(reg, value);
+        }
+    }
+}
+
+/// Declare a block of registers as `(name, offset, width, access)`
+/// tuples, where `width` is in bytes and `access` is a variant of
+/// [`Access`].  A register whose value after reset is not zero adds
+/// `reset = value` to its tuple.
+///
+/// For each register, the macro defines a constant with its offset and a
+/// variant of the enum with the same name; the enum implements
+/// [`RegisterBlock`].  Compilation fails if the width of a register is
+/// not 1, 2, 4 or 8, if its offset is not aligned to the width, or if the
+/// reset value does not fit in the register.
+///
+/// Optionally, the macro also declares a struct that holds the values of
+/// all registers.  Its `new()` and `reset()` methods set each register
+/// to its reset value, so that a device can call `reset()` from its
+/// [`ResettablePhasesImpl`](crate::qdev::ResettablePhasesImpl) handler;
+/// `get()` and `set()` access a single register.  The struct implements
+/// [`VMState`](crate::vmstate::VMState), so that it can be migrated with
+/// [`vmstate_of!`](crate::vmstate_of).
+///
+/// # Examples
+///
+/// ```
+/// # use qemu_api::{registers, regaccess::{Access, RegisterBlock}};
+/// registers! {
+///     pub enum Reg {
+///         /// Control register
+///         (CTRL, 0x00, 4, ReadWrite, reset = 0x300),
+///         (DATA, 0x04, 1, WriteOnly),
+///     }
+///     pub struct RegValues;
+/// }
+///
+/// assert_eq!(CTRL, 0x00);
+/// assert_eq!(Reg::decode(0x04), Some(Reg::DATA));
+/// assert_eq!(Reg::DATA.access(), Access::WriteOnly);
+///
+/// let mut regs = RegValues::new();
+/// regs.set(Reg::CTRL, 0x1234);
+/// regs.reset();
+/// assert_eq!(regs.get(Reg::CTRL), 0x300);
+/// ```
+#[macro_export]
+macro_rules! registers {
+    (@reset) => { 0 };
+    (@reset $reset:expr) => { $reset };
+
+    (@storage [] $name:ident { $($regs:tt)* }) => {};
+    (@storage [$(#[$attr:meta])* $vis:vis struct $storage:ident;] $name:ident {
+        $(($reg:ident $(, $reset:expr)?)),*
+    }) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        $vis struct $storage([u64; { [$($reg),*].len() }]);
+
+        #[allow(dead_code)]
+        impl $storage {
+            /// Return a register block in which all registers have their
+            /// reset value.
+            $vis const fn new() -> Self {
+                Self([$($crate::registers!(@reset $($reset)?)),*])
+            }
+
+            /// Return the value of `reg`.
+            $vis const fn get(&self, reg: $name) -> u64 {
+                self.0[reg as usize]
+            }
+
+            /// Set `reg` to `value`, truncated to the width of the register.
+            $vis fn set(&mut self, reg: $name, value: u64) {
+                let width = $crate::regaccess::RegisterBlock::width(reg);
+                self.0[reg as usize] = value & (u64::MAX >> (64 - width * 8));
+            }
+
+            /// Set all registers to their reset value.
+            $vis fn reset(&mut self) {
+                *self = Self::new();
+            }
+        }
+
+        impl ::core::default::Default for $storage {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        $crate::impl_vmstate_forward!($storage);
+    };
+
+    ($(#[$attr:meta])* $vis:vis enum $name:ident {
+        $($(#[$reg_attr:meta])*
+          ($reg:ident, $offset:expr, $width:expr, $access:ident $(, reset = $reset:expr)?)),* $(,)?
+    }
+    $($storage:tt)*) => {
+        $(
+            $(#[$reg_attr])*
+            #[allow(dead_code)]
+            $vis const $reg: $crate::memory::hwaddr = {
+                let offset: $crate::memory::hwaddr = $offset;
+                let width: u32 = $width;
+                let reset: u64 = $crate::registers!(@reset $($reset)?);
+                assert!(
+                    width == 1 || width == 2 || width == 4 || width == 8,
+                    "invalid register width"
+                );
+                assert!(offset % (width as $crate::memory::hwaddr) == 0, "misaligned register");
+                assert!(width == 8 || reset >> (width * 8) == 0, "invalid reset value");
+                offset
+            };
+        )*
+
+        $(#[$attr])*
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        $vis enum $name {
+            $($(#[$reg_attr])* $reg),*
+        }
+
+        impl $crate::regaccess::RegisterBlock for $name {
+            fn decode(offset: $crate::memory::hwaddr) -> ::core::option::Option<Self> {
+                match offset {
+                    $($reg => ::core::option::Option::Some(Self::$reg),)*
+                    _ => ::core::option::Option::None,
+                }
+            }
+
+            fn offset(self) -> $crate::memory::hwaddr {
+                match self {
+                    $(Self::$reg => $reg,)*
+                }
+            }
+
+            fn width(self) -> u32 {
+                match self {
+                    $(Self::$reg => $width,)*
+                }
+            }
+
+            fn access(self) -> $crate::regaccess::Access {
+                match self {
+                    $(Self::$reg => $crate::regaccess::Access::$access,)*
+                }
+            }
+
+            fn reset_value(self) -> u64 {
+                match self {
+                    $(Self::$reg => $crate::registers!(@reset $($reset)?),)*
+                }
+            }
+        }
+
+        $crate::registers!(@storage [$($storage)*] $name {
+            $(($reg $(, $reset)?)),*
+        });
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bindings::VMStateFlags, vmstate::VMState};
+
+    #[test]
+    fn test_read_full() {
+        assert_eq!(read_reg(0x12u8, 0, 1, Endian::Little), 0x12);
+        assert_eq!(read_reg(0x1234u16, 0, 2, Endian::Big), 0x1234);
+        assert_eq!(read_reg(0x1234_5678u32, 0, 4, Endian::Little), 0x1234_5678);
+        assert_eq!(
+            read_reg(0x0102_0304_0506_0708u64, 0, 8, Endian::Big),
+            0x0102_0304_0506_0708
+        );
+    }
+
+    #[test]
+    fn test_read_sub_register() {
+        let reg32 = 0x1234_5678u32;
+        assert_eq!(read_reg(reg32, 0, 1, Endian::Little), 0x78);
+        assert_eq!(read_reg(reg32, 3, 1, Endian::Little), 0x12);
+        assert_eq!(read_reg(reg32, 2, 2, Endian::Little), 0x1234);
+        assert_eq!(read_reg(reg32, 0, 1, Endian::Big), 0x12);
+        assert_eq!(read_reg(reg32, 3, 1, Endian::Big), 0x78);
+        assert_eq!(read_reg(reg32, 2, 2, Endian::Big), 0x5678);
+
+        let reg64 = 0x0102_0304_0506_0708u64;
+        assert_eq!(read_reg(reg64, 4, 4, Endian::Little), 0x0102_0304);
+        assert_eq!(read_reg(reg64, 4, 4, Endian::Big), 0x0506_0708);
+        assert_eq!(read_reg(reg64, 1, 1, Endian::Big), 0x02);
+    }
+
+    #[test]
+    fn test_write_full() {
+        assert_eq!(write_reg(0u8, 0, 1, 0xab, Endian::Big), 0xab);
+        assert_eq!(write_reg(0u16, 0, 2, 0xabcd, Endian::Little), 0xabcd);
+        assert_eq!(write_reg(0u32, 0, 4, 0xdead_beef, Endian::Big), 0xdead_beef);
+        assert_eq!(write_reg(1u64, 0, 8, u64::MAX, Endian::Little), u64::MAX);
+        // bits above the access size are ignored
+        assert_eq!(write_reg(0u16, 0, 2, 0x1_abcd, Endian::Little), 0xabcd);
+    }
+
+    #[test]
+    fn test_write_sub_register() {
+        let reg32 = 0x1234_5678u32;
+        assert_eq!(write_reg(reg32, 0, 1, 0xaa, Endian::Little), 0x1234_56aa);
+        assert_eq!(write_reg(reg32, 2, 2, 0xbbcc, Endian::Little), 0xbbcc_5678);
+        assert_eq!(write_reg(reg32, 0, 1, 0xaa, Endian::Big), 0xaa34_5678);
+        assert_eq!(write_reg(reg32, 2, 2, 0xbbcc, Endian::Big), 0x1234_bbcc);
+
+        let reg64 = 0x0102_0304_0506_0708u64;
+        assert_eq!(
+            write_reg(reg64, 4, 4, 0xaabb_ccdd, Endian::Little),
+            0xaabb_ccdd_0506_0708
+        );
+        assert_eq!(
+            write_reg(reg64, 4, 4, 0xaabb_ccdd, Endian::Big),
+            0x0102_0304_aabb_ccdd
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "outside a 4-byte register")]
+    fn test_access_outside_register() {
+        let _ = read_reg(0u32, 3, 2, Endian::Little);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid access size")]
+    fn test_access_too_large() {
+        let _ = read_reg(0u16, 0, 4, Endian::Big);
+    }
+
+    registers! {
+        enum TestReg {
+            (CTRL, 0x00, 4, ReadWrite, reset = 0x0000_0300),
+            (STATUS, 0x04, 2, ReadOnly, reset = 0x90),
+            (DATA, 0x08, 1, WriteOnly),
+        }
+        struct TestRegValues;
+    }
+
+    #[test]
+    fn test_registers_decode() {
+        assert_eq!([CTRL, STATUS, DATA], [0x00, 0x04, 0x08]);
+        assert_eq!(TestReg::decode(0x00), Some(TestReg::CTRL));
+        assert_eq!(TestReg::decode(0x04), Some(TestReg::STATUS));
+        assert_eq!(TestReg::decode(0x08), Some(TestReg::DATA));
+        assert_eq!(TestReg::decode(0x02), None);
+        assert_eq!(TestReg::decode(0x0c), None);
+        assert_eq!(TestReg::STATUS.offset(), 0x04);
+        assert_eq!(TestReg::STATUS.width(), 2);
+    }
+
+    #[test]
+    fn test_registers_access() {
+        assert_eq!(TestReg::decode_read(CTRL, 4), Some(TestReg::CTRL));
+        assert_eq!(TestReg::decode_write(CTRL, 4), Some(TestReg::CTRL));
+        // the access must have the width of the register
+        assert_eq!(TestReg::decode_read(CTRL, 2), None);
+        assert_eq!(TestReg::decode_write(CTRL, 8), None);
+        assert_eq!(TestReg::decode_read(STATUS, 2), Some(TestReg::STATUS));
+        assert_eq!(TestReg::decode_read(STATUS, 4), None);
+        // ... and a direction that the register supports
+        assert_eq!(TestReg::decode_write(STATUS, 2), None);
+        assert_eq!(TestReg::decode_read(DATA, 1), None);
+        assert_eq!(TestReg::decode_write(DATA, 1), Some(TestReg::DATA));
+    }
+
+    #[test]
+    fn test_registers_dispatch() {
+        let read = |reg| match reg {
+            TestReg::CTRL => 0x1234_5678,
+            TestReg::STATUS => 0xabcd,
+            TestReg::DATA => unreachable!(),
+        };
+        assert_eq!(TestReg::dispatch_read(CTRL, 4, read), 0x1234_5678);
+        assert_eq!(TestReg::dispatch_read(STATUS, 2, read), 0xabcd);
+        assert_eq!(TestReg::dispatch_read(CTRL, 1, read), 0);
+        assert_eq!(TestReg::dispatch_read(DATA, 1, read), 0);
+        assert_eq!(TestReg::dispatch_read(0x10, 4, read), 0);
+
+        let mut written = Vec::new();
+        TestReg::dispatch_write(DATA, 1, 0x5a, |reg, value| written.push((reg, value)));
+        TestReg::dispatch_write(DATA, 4, 0xa5, |reg, value| written.push((reg, value)));
+        TestReg::dispatch_write(STATUS, 2, 0, |reg, value| written.push((reg, value)));
+        assert_eq!(written, [(TestReg::DATA, 0x5a)]);
+    }
+
+    #[test]
+    fn test_registers_reset() {
+        assert_eq!(TestReg::CTRL.reset_value(), 0x300);
+        assert_eq!(TestReg::DATA.reset_value(), 0);
+
+        let mut regs = TestRegValues::new();
+        assert_eq!(regs, TestRegValues::default());
+        assert_eq!(regs.get(TestReg::CTRL), 0x300);
+        assert_eq!(regs.get(TestReg::STATUS), 0x90);
+        assert_eq!(regs.get(TestReg::DATA), 0);
+
+        regs.set(TestReg::CTRL, 0xdead_beef);
+        regs.set(TestReg::DATA, 0x5a);
+        // values are truncated to the width of the register
+        regs.set(TestReg::STATUS, 0x1_2345);
+        assert_eq!(regs.get(TestReg::CTRL), 0xdead_beef);
+        assert_eq!(regs.get(TestReg::STATUS), 0x2345);
+        assert_eq!(regs.get(TestReg::DATA), 0x5a);
+
+        regs.reset();
+        assert_eq!(regs, TestRegValues::new());
+        assert_eq!(regs.get(TestReg::CTRL), 0x300);
+        assert_eq!(regs.get(TestReg::STATUS), 0x90);
+        assert_eq!(regs.get(TestReg::DATA), 0);
+    }
+
+    #[test]
+    fn test_registers_vmstate() {
+        let base = <TestRegValues as VMState>::BASE;
+        assert_eq!(base.flags, VMStateFlags::VMS_ARRAY);
+        assert_eq!(base.num, 3);
+        assert_eq!(base.size, 8);
+    }
+
+    #[test]
+    fn test_extract_deposit() {
+        assert_eq!(extract32(0xf0, 4, 4), 0xf);
+        assert_eq!(extract64(0x8000_0000_0000_0000, 63, 1), 1);
+        assert_eq!(deposit32(0xffff_ffff, 8, 8, 0), 0xffff_00ff);
+        assert_eq!(deposit64(0, 32, 32, 0x1_2345_6789), 0x2345_6789_0000_0000);
+    }
+
+    bitfields! {
+        struct TestStatus(u16) {
+            (ready, set_ready, 0, 1),
+            (count, set_count, 1, 5),
+            (mode, set_mode, 12, 4),
+        }
+    }
+
+    #[test]
+    fn test_bit_field() {
+        let field = BitField::<u16>::new(4, 8);
+        assert_eq!(field.mask(), 0x0ff0);
+        assert_eq!(field.max(), 0xff);
+        assert_eq!(field.get(0xabcd), 0xbc);
+        assert_eq!(field.set(0xabcd, 0x12), 0xa12d);
+        assert_eq!(BitField::<u64>::new(0, 64).max(), u64::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "bit field does not fit in the register")]
+    fn test_bit_field_too_large() {
+        let _ = BitField::<u8>::new(4, 5);
+    }
+
+    #[test]
+    fn test_bitfields() {
+        let mut status = TestStatus::default();
+        status.set_ready(1);
+        status.set_count(0x1f);
+        status.set_mode(0xa);
+        assert_eq!(u16::from(status), 0xa03f);
+        assert_eq!(status.ready(), 1);
+        assert_eq!(status.count(), 0x1f);
+        assert_eq!(status.mode(), 0xa);
+
+        // setting a field leaves the others alone
+        status.set_count(2);
+        assert_eq!(status, TestStatus(0xa005));
+        let decoded = TestStatus::from(0x5ffe);
+        assert_eq!(
+            (decoded.ready(), decoded.count(), decoded.mode()),
+            (0, 0x1f, 5)
+        );
+
+        let base = <TestStatus as VMState>::BASE;
+        assert_eq!(base.size, 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "value does not fit in a 5-bit field")]
+    fn test_bitfields_too_wide() {
+        let mut status = TestStatus::default();
+        status.set_count(0x20);
+    }
+}
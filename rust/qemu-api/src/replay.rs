@@ -0,0 +1,140 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Helpers for devices that must behave the same when an execution is
+//! recorded and when it is replayed with `-icount rr=record|replay`.
+//!
+//! Anything that the guest can observe must come from the replay log
+//! while replaying.  The clocks returned by
+//! [`clock_get_ns`](crate::timer::clock_get_ns) already take care of it;
+//! a device that reads the time of the host in some other way, or that
+//! needs random numbers, goes through these helpers instead:
+//!
+//! ```ignore
+//! let now = replay_clock(ReplayClockKind::REPLAY_CLOCK_HOST, || host_time_ns());
+//! let mut nonce = [0u8; 16];
+//! guest_getrandom(&mut nonce);
+//! ```
+
+use std::os::raw::c_void;
+
+pub use bindings::{ReplayClockKind, ReplayMode};
+
+use crate::bindings;
+
+/// Return whether the execution is being recorded, replayed, or neither.
+pub fn replay_mode() -> ReplayMode {
+    // SAFETY: the mode is only written while QEMU starts
+    unsafe { bindings::replay_mode }
+}
+
+/// The parts of the replay log that the helpers use.
+trait ReplayLog {
+    fn mode(&self) -> ReplayMode;
+    fn save_clock(&self, kind: ReplayClockKind, clock: i64) -> i64;
+    fn read_clock(&self, kind: ReplayClockKind) -> i64;
+}
+
+/// The log that `-icount rr=...` opens.
+struct SystemReplayLog;
+
+impl ReplayLog for SystemReplayLog {
+    fn mode(&self) -> ReplayMode {
+        replay_mode()
+    }
+
+    fn save_clock(&self, kind: ReplayClockKind, clock: i64) -> i64 {
+        // SAFETY: only called while recording, when icount is enabled
+        unsafe { bindings::replay_save_clock(kind, clock, bindings::icount_get_raw()) }
+    }
+
+    fn read_clock(&self, kind: ReplayClockKind) -> i64 {
+        // SAFETY: only called while replaying, when icount is enabled
+        unsafe { bindings::replay_read_clock(kind, bindings::icount_get_raw()) }
+    }
+}
+
+fn replay_clock_with<L: ReplayLog, F: FnOnce() -> i64>(
+    log: &L,
+    kind: ReplayClockKind,
+    read: F,
+) -> i64 {
+    match log.mode() {
+        ReplayMode::REPLAY_MODE_PLAY => log.read_clock(kind),
+        ReplayMode::REPLAY_MODE_RECORD => log.save_clock(kind, read()),
+        _ => read(),
+    }
+}
+
+/// Return the value of a clock of kind `kind`, as in the C
+/// `REPLAY_CLOCK()` macro.  `read` returns the live value of the clock,
+/// which is also saved to the log while recording; while replaying, the
+/// value comes from the log and `read` is not called.
+pub fn replay_clock<F: FnOnce() -> i64>(kind: ReplayClockKind, read: F) -> i64 {
+    replay_clock_with(&SystemReplayLog, kind, read)
+}
+
+/// Fill `buf` with random bytes, as in `qemu_guest_getrandom_nofail()`.
+/// The bytes are not taken from the replay log; instead, they repeat
+/// across executions if QEMU is started with `-seed`, which is what
+/// record and replay need.
+pub fn guest_getrandom(buf: &mut [u8]) {
+    // SAFETY: the buffer is valid for `buf.len()` bytes
+    unsafe {
+        bindings::qemu_guest_getrandom_nofail(buf.as_mut_ptr().cast::<c_void>(), buf.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    /// A log that is kept in memory.
+    struct DummyLog {
+        mode: Cell<ReplayMode>,
+        clocks: RefCell<Vec<(ReplayClockKind, i64)>>,
+        next: Cell<usize>,
+    }
+
+    impl ReplayLog for DummyLog {
+        fn mode(&self) -> ReplayMode {
+            self.mode.get()
+        }
+
+        fn save_clock(&self, kind: ReplayClockKind, clock: i64) -> i64 {
+            self.clocks.borrow_mut().push((kind, clock));
+            clock
+        }
+
+        fn read_clock(&self, kind: ReplayClockKind) -> i64 {
+            let (saved_kind, clock) = self.clocks.borrow()[self.next.get()];
+            assert_eq!(saved_kind, kind);
+            self.next.set(self.next.get() + 1);
+            clock
+        }
+    }
+
+    #[test]
+    fn test_replay_clock() {
+        let log = DummyLog {
+            mode: Cell::new(ReplayMode::REPLAY_MODE_NONE),
+            clocks: RefCell::new(Vec::new()),
+            next: Cell::new(0),
+        };
+        let host = ReplayClockKind::REPLAY_CLOCK_HOST;
+        assert_eq!(replay_clock_with(&log, host, || 5), 5);
+        assert!(log.clocks.borrow().is_empty());
+
+        log.mode.set(ReplayMode::REPLAY_MODE_RECORD);
+        assert_eq!(replay_clock_with(&log, host, || 10), 10);
+        assert_eq!(replay_clock_with(&log, host, || 20), 20);
+
+        // the live clock is not read while replaying
+        log.mode.set(ReplayMode::REPLAY_MODE_PLAY);
+        let live = || -> i64 { panic!("live clock read while replaying") };
+        assert_eq!(replay_clock_with(&log, host, live), 10);
+        assert_eq!(replay_clock_with(&log, host, live), 20);
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for system reset handlers.
+//!
+//! Devices are reset through the [`ResettablePhasesImpl`] trait.  State
+//! that does not belong to a device, for example a singleton set up by
+//! a machine, can instead run a function whenever the whole system is
+//! reset, as with `qemu_register_reset()`:
+//!
+//! ```ignore
+//! let handler = register_reset(move || state.clear());
+//! ```
+//!
+//! The function runs until the returned [`ResetHandler`] is dropped.
+//!
+//! Boards and tests that need to reset a single device, together with
+//! the devices below it, call [`reset_all`].
+//!
+//! [`ResettablePhasesImpl`]: crate::qdev::ResettablePhasesImpl
+
+use std::{os::raw::c_void, ptr::addr_of};
+
+use crate::{bindings, callbacks::abort_on_panic, cell::bql_locked, qdev::DeviceState};
+
+/// A function that runs on system reset.  The function is unregistered
+/// and freed when the `ResetHandler` is dropped.
+#[must_use = "the reset handler is unregistered when dropped"]
+pub struct ResetHandler(Box<Box<dyn Fn()>>);
+
+impl std::fmt::Debug for ResetHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ResetHandler").finish()
+    }
+}
+
+unsafe extern "C" fn rust_reset_cb(opaque: *mut c_void) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        // SAFETY: the opaque is the closure that the ResetHandler owns,
+        // and the handler is unregistered before the closure is freed
+        let f = unsafe { &*opaque.cast::<Box<dyn Fn()>>() };
+        f();
+    })
+}
+
+impl ResetHandler {
+    fn opaque(&self) -> *mut c_void {
+        addr_of!(*self.0).cast::<c_void>() as *mut c_void
+    }
+}
+
+impl Drop for ResetHandler {
+    fn drop(&mut self) {
+        assert!(bql_locked());
+        // SAFETY: the handler was registered by register_reset() with
+        // the same opaque
+        unsafe {
+            bindings::qemu_unregister_reset(Some(rust_reset_cb), self.opaque());
+        }
+    }
+}
+
+/// Call `f` whenever the system is reset, until the returned
+/// [`ResetHandler`] is dropped.
+///
+/// Reset handlers run under the Big QEMU Lock, in the order in which they
+/// were registered.
+pub fn register_reset<F: Fn() + 'static>(f: F) -> ResetHandler {
+    assert!(bql_locked());
+    let handler = ResetHandler(Box::new(Box::new(f)));
+    // SAFETY: the closure lives until the handler is unregistered in drop()
+    unsafe {
+        bindings::qemu_register_reset(Some(rust_reset_cb), handler.opaque());
+    }
+    handler
+}
+
+/// Stop calling the function of `handler` on system reset.  This is the
+/// same as dropping `handler`.
+pub fn unregister_reset(handler: ResetHandler) {
+    drop(handler);
+}
+
+/// Perform a cold reset of `dev` and of its children, as with
+/// `device_cold_reset()`.  Each of the three phases (enter, hold and
+/// exit) runs on the whole subtree before the next one starts, and
+/// within a phase the children are reset before their parent.
+///
+/// The children are the buses of `dev` and the devices on them, plus
+/// those that `dev` registers in
+/// [`ResettablePhasesImpl::CHILDREN`](crate::qdev::ResettablePhasesImpl::CHILDREN).
+pub fn reset_all(dev: &DeviceState) {
+    assert!(bql_locked());
+    // SAFETY: the device is valid, and resetting it only goes through
+    // its Resettable interface
+    unsafe {
+        bindings::device_cold_reset((dev as *const DeviceState) as *mut DeviceState);
+    }
+}
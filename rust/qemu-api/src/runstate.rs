@@ -0,0 +1,126 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for VM run state change handlers.
+//!
+//! Devices that must act when the VM stops or resumes, for example to
+//! flush caches when it stops, run a function on each transition, as with
+//! `qemu_add_vm_change_state_handler()`:
+//!
+//! ```ignore
+//! let handler = add_vm_change_state_handler(move |running, _state| {
+//!     if !running {
+//!         cache.flush();
+//!     }
+//! });
+//! ```
+//!
+//! The function runs until the returned [`VmChangeStateHandler`] is
+//! dropped.
+
+use std::{os::raw::c_void, ptr::addr_of};
+
+pub use bindings::RunState;
+
+use crate::{bindings, callbacks::abort_on_panic, cell::bql_locked};
+
+/// A function that runs when the VM starts or stops.  The function is
+/// unregistered and freed when the `VmChangeStateHandler` is dropped.
+#[must_use = "the handler is unregistered when dropped"]
+pub struct VmChangeStateHandler {
+    f: Box<Box<dyn Fn(bool, RunState)>>,
+    entry: *mut bindings::VMChangeStateEntry,
+}
+
+impl std::fmt::Debug for VmChangeStateHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("VmChangeStateHandler")
+            .field(&self.entry)
+            .finish()
+    }
+}
+
+unsafe extern "C" fn rust_vm_change_state_cb(opaque: *mut c_void, running: bool, state: RunState) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        // SAFETY: the opaque is the closure that the VmChangeStateHandler
+        // owns, and the handler is unregistered before the closure is freed
+        let f = unsafe { &*opaque.cast::<Box<dyn Fn(bool, RunState)>>() };
+        f(running, state);
+    })
+}
+
+impl VmChangeStateHandler {
+    fn opaque(&self) -> *mut c_void {
+        addr_of!(*self.f).cast::<c_void>() as *mut c_void
+    }
+}
+
+impl Drop for VmChangeStateHandler {
+    fn drop(&mut self) {
+        assert!(bql_locked());
+        // SAFETY: the entry was returned by
+        // qemu_add_vm_change_state_handler() and not deleted yet
+        unsafe {
+            bindings::qemu_del_vm_change_state_handler(self.entry);
+        }
+    }
+}
+
+/// Call `f` whenever the VM starts or stops, until the returned
+/// [`VmChangeStateHandler`] is dropped.  `f` receives whether the VM
+/// is running and the state that it is entering.
+///
+/// The handlers run under the Big QEMU Lock, in the order in which they
+/// were added when the VM starts, and in the opposite order when it
+/// stops.
+pub fn add_vm_change_state_handler<F: Fn(bool, RunState) + 'static>(f: F) -> VmChangeStateHandler {
+    assert!(bql_locked());
+    let mut handler = VmChangeStateHandler {
+        f: Box::new(Box::new(f)),
+        entry: std::ptr::null_mut(),
+    };
+    // SAFETY: the closure lives until the handler is deleted in drop()
+    handler.entry = unsafe {
+        bindings::qemu_add_vm_change_state_handler(Some(rust_vm_change_state_cb), handler.opaque())
+    };
+    handler
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, mem::ManuallyDrop, ptr, rc::Rc};
+
+    use super::*;
+    use crate::cell::bql_start_test;
+
+    /// `runstate.c` is not linked into the tests, so the handler is not
+    /// registered, and its callback is invoked directly, as
+    /// `vm_state_notify()` would do.
+    #[test]
+    fn test_vm_change_state_handler() {
+        bql_start_test();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        let handler = ManuallyDrop::new(VmChangeStateHandler {
+            f: Box::new(Box::new(move |running, state| {
+                log.borrow_mut().push((running, state));
+            })),
+            entry: ptr::null_mut(),
+        });
+
+        let transitions = [
+            (true, RunState::RUN_STATE_RUNNING),
+            (false, RunState::RUN_STATE_PAUSED),
+            (true, RunState::RUN_STATE_RUNNING),
+            (false, RunState::RUN_STATE_SHUTDOWN),
+        ];
+        for (running, state) in transitions {
+            // SAFETY: the opaque is the closure of the handler
+            unsafe {
+                rust_vm_change_state_cb(handler.opaque(), running, state);
+            }
+        }
+        assert_eq!(*events.borrow(), transitions);
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for devices whose migration stream is not described by a
+//! [`VMStateDescription`](crate::bindings::VMStateDescription).
+//!
+//! Most devices should use [`vmstate`](crate::vmstate) instead.  The
+//! handlers in this module are for devices that must keep an existing
+//! stream format that does not map to `VMStateField`s, for example one
+//! that contains variable-length records.  Such a device implements
+//! [`SaveVmImpl`] and writes to or reads from a [`QemuFile`]:
+//!
+//! ```ignore
+//! impl SaveVmImpl for MyDevice {
+//!     fn save_state(&self, f: &QemuFile) {
+//!         let data = self.data.borrow();
+//!         f.put_be32(data.len() as u32);
+//!         f.put_buffer(&data);
+//!     }
+//!
+//!     fn load_state(&self, f: &QemuFile, _version_id: u32) -> Result<(), i32> {
+//!         let mut data = vec![0; f.get_be32() as usize];
+//!         f.get_buffer(&mut data);
+//!         f.error()?;
+//!         *self.data.borrow_mut() = data;
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! The handlers are then registered with a [`SaveVmRegistration`], usually
+//! from the device's `realize` function.
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    ffi::CStr,
+    fmt,
+    marker::PhantomData,
+    os::raw::{c_int, c_void},
+    ptr,
+};
+
+use crate::{
+    bindings::{self, SaveVMHandlers},
+    bql::assert_bql_locked,
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    zeroable::Zeroable,
+};
+
+/// A migration stream, as passed to the [`SaveVmImpl`] handlers.
+///
+/// Errors are sticky: once a read or write fails, further reads return
+/// zeroes and further writes are dropped, and [`error`](QemuFile::error)
+/// returns the error.  Therefore it is enough to check for errors once,
+/// after reading all the data.
+#[repr(transparent)]
+pub struct QemuFile(UnsafeCell<bindings::QEMUFile>);
+
+impl fmt::Debug for QemuFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("QemuFile").field(&self.as_mut_ptr()).finish()
+    }
+}
+
+impl QemuFile {
+    /// Convert a pointer to a C `QEMUFile` into a reference.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a valid `QEMUFile` that is not closed for the lifetime
+    /// `'a`.
+    pub unsafe fn from_raw<'a>(ptr: *mut bindings::QEMUFile) -> &'a Self {
+        // SAFETY: the struct is transparent, and the caller guarantees that
+        // the pointer is valid
+        unsafe { &*ptr.cast::<Self>() }
+    }
+
+    /// Return the pointer to the C `QEMUFile`.
+    pub const fn as_mut_ptr(&self) -> *mut bindings::QEMUFile {
+        self.0.get()
+    }
+
+    /// Write one byte to the stream.
+    pub fn put_byte(&self, v: u8) {
+        // SAFETY: the file is valid for the lifetime of `self`
+        unsafe { bindings::qemu_put_byte(self.as_mut_ptr(), v.into()) }
+    }
+
+    /// Read one byte from the stream.
+    pub fn get_byte(&self) -> u8 {
+        // SAFETY: the file is valid for the lifetime of `self`; the C
+        // function returns a value between 0 and 255
+        unsafe { bindings::qemu_get_byte(self.as_mut_ptr()) as u8 }
+    }
+
+    /// Write a big-endian 32-bit value to the stream.
+    pub fn put_be32(&self, v: u32) {
+        // SAFETY: the file is valid for the lifetime of `self`
+        unsafe { bindings::qemu_put_be32(self.as_mut_ptr(), v) }
+    }
+
+    /// Read a big-endian 32-bit value from the stream.
+    pub fn get_be32(&self) -> u32 {
+        // SAFETY: the file is valid for the lifetime of `self`
+        unsafe { bindings::qemu_get_be32(self.as_mut_ptr()) }
+    }
+
+    /// Write a big-endian 64-bit value to the stream.
+    pub fn put_be64(&self, v: u64) {
+        // SAFETY: the file is valid for the lifetime of `self`
+        unsafe { bindings::qemu_put_be64(self.as_mut_ptr(), v) }
+    }
+
+    /// Read a big-endian 64-bit value from the stream.
+    pub fn get_be64(&self) -> u64 {
+        // SAFETY: the file is valid for the lifetime of `self`
+        unsafe { bindings::qemu_get_be64(self.as_mut_ptr()) }
+    }
+
+    /// Write all of `buf` to the stream.
+    pub fn put_buffer(&self, buf: &[u8]) {
+        // SAFETY: the file is valid for the lifetime of `self`, and the
+        // buffer is valid for `buf.len()` bytes
+        unsafe { bindings::qemu_put_buffer(self.as_mut_ptr(), buf.as_ptr(), buf.len()) }
+    }
+
+    /// Fill `buf` from the stream, and return the number of bytes that
+    /// were read.  The number is smaller than `buf.len()` only if the
+    /// stream ended or failed.
+    pub fn get_buffer(&self, buf: &mut [u8]) -> usize {
+        // SAFETY: the file is valid for the lifetime of `self`, and the
+        // buffer is valid for `buf.len()` bytes
+        unsafe { bindings::qemu_get_buffer(self.as_mut_ptr(), buf.as_mut_ptr(), buf.len()) }
+    }
+
+    /// Return the negative errno of the first failed read or write, if any.
+    pub fn error(&self) -> Result<(), i32> {
+        // SAFETY: the file is valid for the lifetime of `self`
+        match unsafe { bindings::qemu_file_get_error(self.as_mut_ptr()) } {
+            0 => Ok(()),
+            err => Err(err),
+        }
+    }
+}
+
+/// Custom save and load handlers for a device, registered with a
+/// [`SaveVmRegistration`].
+///
+/// The handlers run with the Big QEMU Lock taken; state that they modify
+/// should be stored in a [`BqlCell`](crate::cell::BqlCell) or
+/// [`BqlRefCell`](crate::cell::BqlRefCell).
+pub trait SaveVmImpl {
+    /// Write the state of the device to `f`, using the format of the
+    /// version that was passed to [`SaveVmRegistration::register`].
+    fn save_state(&self, f: &QemuFile);
+
+    /// Read the state of the device from `f`.  `version_id` is the version
+    /// of the incoming stream, and is never greater than the one that was
+    /// passed to [`SaveVmRegistration::register`].  On failure, return
+    /// a negative errno.
+    fn load_state(&self, f: &QemuFile, version_id: u32) -> Result<(), i32>;
+}
+
+unsafe extern "C" fn rust_save_state_fn<T: SaveVmImpl>(
+    f: *mut bindings::QEMUFile,
+    opaque: *mut c_void,
+) {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: the opaque was passed as a reference to `T`, and the file
+        // is valid for the duration of the call
+        let (owner, f) = unsafe { (&*opaque.cast::<T>(), QemuFile::from_raw(f)) };
+        owner.save_state(f);
+    })
+}
+
+unsafe extern "C" fn rust_load_state_fn<T: SaveVmImpl>(
+    f: *mut bindings::QEMUFile,
+    opaque: *mut c_void,
+    version_id: c_int,
+) -> c_int {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: the opaque was passed as a reference to `T`, and the file
+        // is valid for the duration of the call
+        let (owner, f) = unsafe { (&*opaque.cast::<T>(), QemuFile::from_raw(f)) };
+        match owner.load_state(f, version_id as u32) {
+            Ok(()) => 0,
+            Err(err) => err,
+        }
+    })
+}
+
+struct SaveVmHandlersHolder<T: SaveVmImpl>(PhantomData<T>);
+
+impl<T: SaveVmImpl> SaveVmHandlersHolder<T> {
+    const HANDLERS: SaveVMHandlers = SaveVMHandlers {
+        save_state: Some(rust_save_state_fn::<T>),
+        load_state: Some(rust_load_state_fn::<T>),
+        ..Zeroable::ZERO
+    };
+}
+
+/// A set of [`SaveVmImpl`] handlers registered with the migration core.
+///
+/// Usually embedded in the device whose state it migrates, so that the
+/// handlers are unregistered when the device is finalized.
+pub struct SaveVmRegistration {
+    idstr: Cell<Option<&'static CStr>>,
+    opaque: Cell<*mut c_void>,
+}
+
+impl fmt::Debug for SaveVmRegistration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaveVmRegistration")
+            .field("idstr", &self.idstr.get())
+            .field("opaque", &self.opaque.get())
+            .finish()
+    }
+}
+
+impl Default for SaveVmRegistration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SaveVmRegistration {
+    /// Create a registration that is not registered yet.
+    pub const fn new() -> Self {
+        SaveVmRegistration {
+            idstr: Cell::new(None),
+            opaque: Cell::new(ptr::null_mut()),
+        }
+    }
+
+    /// Register `owner`'s [`SaveVmImpl`] handlers for the section `idstr`.
+    /// `instance_id` distinguishes devices that use the same `idstr`, or
+    /// is `u32::MAX` (`VMSTATE_INSTANCE_ID_ANY`) to pick the next free
+    /// one; `version_id` is the version of the format written by
+    /// [`SaveVmImpl::save_state`].
+    ///
+    /// # Safety
+    ///
+    /// `owner` must remain valid until the handlers are unregistered, either
+    /// with [`unregister`](SaveVmRegistration::unregister) or by dropping
+    /// `self`.  This is the case if `self` is a field of `owner`.
+    pub unsafe fn register<T: SaveVmImpl>(
+        &self,
+        owner: &T,
+        idstr: &'static CStr,
+        instance_id: u32,
+        version_id: u32,
+    ) {
+        assert!(bql_locked());
+        assert!(self.idstr.get().is_none(), "handlers already registered");
+        let opaque = (owner as *const T).cast::<c_void>() as *mut c_void;
+        // SAFETY: the handlers are static, and the caller guarantees that
+        // the opaque outlives the registration
+        unsafe {
+            bindings::register_savevm_live(
+                idstr.as_ptr(),
+                instance_id,
+                version_id as c_int,
+                &SaveVmHandlersHolder::<T>::HANDLERS,
+                opaque,
+            );
+        }
+        self.idstr.set(Some(idstr));
+        self.opaque.set(opaque);
+    }
+
+    /// Unregister the handlers, if they were registered.
+    pub fn unregister(&self) {
+        if let Some(idstr) = self.idstr.take() {
+            assert!(bql_locked());
+            // SAFETY: the section was registered with the same idstr and
+            // opaque in register()
+            unsafe {
+                bindings::unregister_savevm(ptr::null_mut(), idstr.as_ptr(), self.opaque.get());
+            }
+            self.opaque.set(ptr::null_mut());
+        }
+    }
+}
+
+impl Drop for SaveVmRegistration {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
@@ -2,7 +2,7 @@
 // Author(s): Paolo Bonzini <pbonzini@redhat.com>
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use std::{ffi::CStr, ptr::addr_of};
+use std::ffi::CStr;
 
 pub use bindings::{SysBusDevice, SysBusDeviceClass};
 
@@ -10,8 +10,9 @@
     bindings,
     cell::bql_locked,
     irq::InterruptSource,
+    memory::{hwaddr, MemoryRegion},
     prelude::*,
-    qdev::{DeviceClass, DeviceState},
+    qdev::{DeviceClass, DeviceImpl, DeviceState},
     qom::ClassInitImpl,
 };
 
@@ -22,10 +23,13 @@ unsafe impl ObjectType for SysBusDevice {
 }
 qom_isa!(SysBusDevice: DeviceState, Object);
 
-// TODO: add SysBusDeviceImpl
+/// Trait for devices whose QOM parent is [`SysBusDevice`], i.e. that
+/// ultimately subclass `TYPE_SYS_BUS_DEVICE`.
+pub trait SysBusDeviceImpl: DeviceImpl + IsA<SysBusDevice> {}
+
 impl<T> ClassInitImpl<SysBusDeviceClass> for T
 where
-    T: ClassInitImpl<DeviceClass>,
+    T: SysBusDeviceImpl + ClassInitImpl<DeviceClass>,
 {
     fn class_init(sdc: &mut SysBusDeviceClass) {
         <T as ClassInitImpl<DeviceClass>>::class_init(&mut sdc.parent_class);
@@ -42,10 +46,10 @@ pub trait SysBusDeviceMethods: ObjectDeref
     /// important, since whoever creates the sysbus device will refer to the
     /// region with a number that corresponds to the order of calls to
     /// `init_mmio`.
-    fn init_mmio(&self, iomem: &bindings::MemoryRegion) {
+    fn init_mmio(&self, iomem: &MemoryRegion) {
         assert!(bql_locked());
         unsafe {
-            bindings::sysbus_init_mmio(self.as_mut_ptr(), addr_of!(*iomem) as *mut _);
+            bindings::sysbus_init_mmio(self.as_mut_ptr(), iomem.as_mut_ptr());
         }
     }
 
@@ -59,6 +63,33 @@ fn init_irq(&self, irq: &InterruptSource) {
             bindings::sysbus_init_irq(self.as_mut_ptr(), irq.as_ptr());
         }
     }
+
+    /// Map the memory region number `id`, as created by `init_mmio`,
+    /// at address `addr` of the system address space.  This is called
+    /// by the board after realizing the device.
+    fn mmio_map(&self, id: u32, addr: hwaddr) {
+        assert!(bql_locked());
+        unsafe {
+            bindings::sysbus_mmio_map(self.as_mut_ptr(), id as i32, addr);
+        }
+    }
+
+    /// Connect the interrupt number `id`, as created by `init_irq`, to
+    /// the sink that `irq` refers to, usually the input of another device
+    /// as returned by
+    /// [`get_gpio_in`](crate::qdev::DeviceMethods::get_gpio_in).  This is
+    /// called by the board.  Connecting to an unconnected `InterruptSource`
+    /// disconnects the interrupt.
+    fn connect_irq(&self, id: u32, irq: &InterruptSource) {
+        assert!(bql_locked());
+        // SAFETY: the sink is either NULL or an IRQState that was created
+        // by qdev and is owned by the device whose input it is; the BQL,
+        // which is held, protects the output array that the sink is
+        // stored into.
+        unsafe {
+            bindings::sysbus_connect_irq(self.as_mut_ptr(), id as i32, irq.sink());
+        }
+    }
 }
 
 impl<R: ObjectDeref> SysBusDeviceMethods for R where R::Target: IsA<SysBusDevice> {}
@@ -0,0 +1,362 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! An in-process harness for testing devices without a virtual machine.
+//!
+//! [`DeviceHarness`] instantiates a device by type name, realizes it and
+//! then lets the test access its registers and watch its output lines,
+//! much like a qtest would do through the guest address space:
+//!
+//! ```ignore
+//! let mut h = DeviceHarness::<MyState>::new(MyState::TYPE_NAME);
+//! let irq = h.observe_gpio_out(None, 0);
+//! h.writel(REG_CTRL, 1);
+//! assert_eq!(h.readl(REG_STATUS), 1);
+//! assert!(irq.level());
+//! ```
+//!
+//! Accesses go straight to the `MemoryRegionOps` of the device, and not
+//! through `memory_region_dispatch_read()` and
+//! `memory_region_dispatch_write()`; therefore the harness works even
+//! where the memory API is not linked in, but the test must only perform
+//! accesses that the device's [`MmioConfig`](crate::memory::MmioConfig)
+//! implements.  The ops are those of the first MMIO region of a sysbus
+//! device, if its opaque is the device, and otherwise those that
+//! [`mmio_ops`] builds for `T`.
+//!
+//! Like the memory API, the harness checks the device's
+//! `mem_reentrancy_guard` and drops the accesses that a device performs on
+//! itself while it handles another one.  It also behaves like a
+//! little-endian guest: accesses to a region whose ops are big-endian are
+//! byte-swapped, as `memory.c` would do on a little-endian target.
+//!
+//! The module is only available with the `test-utils` feature.
+
+use std::{
+    ffi::CStr,
+    os::raw::{c_int, c_void},
+    ptr::{self, addr_of_mut},
+    rc::Rc,
+};
+
+use crate::{
+    bindings::{self, device_endian, qemu_irq, MemoryRegionOps},
+    c_str,
+    callbacks::abort_on_panic,
+    cell::{bql_locked, bql_start_test, BqlCell},
+    log_guest_error,
+    memory::{hwaddr, mmio_ops, MmioOps},
+    prelude::*,
+    qdev::{BusState, Device, DeviceState, OwnedDevice},
+    sysbus::SysBusDevice,
+};
+
+/// Take the Big QEMU Lock and register the QOM types, unless a previous
+/// test already did.
+fn init_qom() {
+    bql_start_test();
+    // SAFETY: module_call_init() does nothing after the first call
+    unsafe {
+        bindings::module_call_init(bindings::module_init_type::MODULE_INIT_QOM);
+    }
+}
+
+/// Records the changes to a device output line that a test is watching,
+/// as returned by [`DeviceHarness::observe_gpio_out`].
+#[derive(Debug, Default)]
+pub struct IrqProbe {
+    level: BqlCell<bool>,
+    raised: BqlCell<u32>,
+}
+
+impl IrqProbe {
+    /// Return the current level of the line.
+    pub fn level(&self) -> bool {
+        self.level.get()
+    }
+
+    /// Return how many times the line went from low to high.
+    pub fn raise_count(&self) -> u32 {
+        self.raised.get()
+    }
+}
+
+unsafe extern "C" fn harness_irq_handler(opaque: *mut c_void, _n: c_int, level: c_int) {
+    abort_on_panic(|| {
+        // SAFETY: the opaque is an IrqProbe that the harness keeps alive
+        // as long as the qemu_irq
+        let probe = unsafe { &*opaque.cast::<IrqProbe>() };
+        let level = level != 0;
+        if level && !probe.level.get() {
+            probe.raised.set(probe.raised.get() + 1);
+        }
+        probe.level.set(level);
+    })
+}
+
+/// Clears a device's `engaged_in_io` flag when an access ends, including
+/// when it panics, so that a failed access does not block the later ones.
+struct EngagedInIo(*mut bool);
+
+impl Drop for EngagedInIo {
+    fn drop(&mut self) {
+        // SAFETY: the flag belongs to the device, which outlives the access,
+        // and is only accessed under the Big QEMU Lock
+        unsafe {
+            self.0.write(false);
+        }
+    }
+}
+
+/// A realized device of type `T`, plus the output lines that the test
+/// has connected to an [`IrqProbe`].  The device is unrealized and
+/// released when the harness is dropped.
+#[derive(Debug)]
+pub struct DeviceHarness<T: IsA<DeviceState> + MmioOps> {
+    dev: Option<OwnedDevice<T>>,
+    ops: &'static MemoryRegionOps,
+    irqs: Vec<(qemu_irq, Rc<IrqProbe>)>,
+}
+
+impl<T: IsA<DeviceState> + MmioOps> DeviceHarness<T> {
+    /// Create a device of type `typename` and realize it.  Use this for
+    /// devices that do not sit on a bus.
+    ///
+    /// This can be called first thing in a test: it takes the Big QEMU
+    /// Lock and registers the QOM types if that was not done yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `typename` is not `T` or a subclass of `T`, or if the
+    /// device fails to realize.
+    pub fn new(typename: &CStr) -> Self {
+        Self::with_bus(typename, &[], None)
+    }
+
+    /// Same as [`new`](DeviceHarness::new), but set the properties in
+    /// `props`, each given as a name and a value as for `-device`, before
+    /// realizing the device.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a property does not exist or `value` is not valid for
+    /// it, and in the same cases as [`new`](DeviceHarness::new).
+    pub fn new_with_props(typename: &CStr, props: &[(&CStr, &CStr)]) -> Self {
+        Self::with_bus(typename, props, None)
+    }
+
+    fn with_bus(typename: &CStr, props: &[(&CStr, &CStr)], bus: Option<&BusState>) -> Self {
+        init_qom();
+        let dev = Device::<T>::new(typename);
+        for (name, value) in props {
+            // SAFETY: the device is not realized yet, and error_abort
+            // turns any error into a crash
+            unsafe {
+                bindings::object_property_parse(
+                    dev.as_object_mut_ptr(),
+                    name.as_ptr(),
+                    value.as_ptr(),
+                    addr_of_mut!(bindings::error_abort),
+                );
+            }
+        }
+        // SAFETY: the root object lives forever; the "[*]" suffix gives
+        // each device a different name
+        unsafe {
+            bindings::object_property_add_child(
+                bindings::object_get_root(),
+                c_str!("harness-device[*]").as_ptr(),
+                dev.as_object_mut_ptr(),
+            );
+        }
+        let dev = dev
+            .realize_and_unref(bus)
+            .unwrap_or_else(|e| panic!("could not realize {:?}: {}", typename, e));
+        let ops = Self::find_mmio_ops(&dev);
+        DeviceHarness {
+            dev: Some(dev),
+            ops,
+            irqs: Vec::new(),
+        }
+    }
+
+    /// Return the ops of the first MMIO region of `dev` if it is a sysbus
+    /// device, and the region's accesses go to `dev`; otherwise, return
+    /// the ops that [`mmio_ops`] builds for `T`.
+    fn find_mmio_ops(dev: &T) -> &'static MemoryRegionOps {
+        // SAFETY: the device is realized, so its MMIO regions, if any, are
+        // initialized; their ops are static
+        unsafe {
+            let obj = dev.as_mut_ptr::<DeviceState>().cast::<Object>();
+            let sbd =
+                bindings::object_dynamic_cast(obj, bindings::TYPE_SYS_BUS_DEVICE.as_ptr().cast())
+                    .cast::<bindings::SysBusDevice>();
+            if !sbd.is_null() && (*sbd).num_mmio > 0 {
+                let mr = (*sbd).mmio[0].memory;
+                if !mr.is_null() && (*mr).opaque == obj.cast::<c_void>() && !(*mr).ops.is_null() {
+                    return &*(*mr).ops;
+                }
+            }
+        }
+        mmio_ops::<T>()
+    }
+
+    /// Return the device under test.
+    pub fn device(&self) -> &T {
+        self.dev.as_ref().unwrap()
+    }
+
+    /// Perform a read of `size` bytes at `offset` in the device's MMIO
+    /// region.  A read that the device performs on itself while handling
+    /// another access returns zero.
+    pub fn read(&self, offset: hwaddr, size: u32) -> u64 {
+        assert!(bql_locked());
+        let read = self.ops.read.unwrap();
+        // SAFETY: the opaque of the device's MMIO region is the device
+        let value =
+            self.with_reentrancy_guard(offset, || unsafe { read(self.opaque(), offset, size) });
+        self.adjust_endianness(value, size)
+    }
+
+    /// Perform a write of `size` bytes at `offset` in the device's MMIO
+    /// region.  A write that the device performs on itself while handling
+    /// another access is dropped.
+    pub fn write(&self, offset: hwaddr, size: u32, value: u64) {
+        assert!(bql_locked());
+        let write = self.ops.write.unwrap();
+        let value = self.adjust_endianness(value, size);
+        // SAFETY: the opaque of the device's MMIO region is the device
+        self.with_reentrancy_guard(offset, || unsafe {
+            write(self.opaque(), offset, value, size)
+        })
+    }
+
+    /// Read the 32-bit register at `offset`.
+    pub fn readl(&self, offset: hwaddr) -> u32 {
+        self.read(offset, 4) as u32
+    }
+
+    /// Write `value` to the 32-bit register at `offset`.
+    pub fn writel(&self, offset: hwaddr, value: u32) {
+        self.write(offset, 4, value.into());
+    }
+
+    /// Connect the output line number `n` in the GPIO bank `name` (or the
+    /// unnamed bank if `None`) to a new [`IrqProbe`], and return it.
+    pub fn observe_gpio_out(&mut self, name: Option<&CStr>, n: u32) -> Rc<IrqProbe> {
+        assert!(bql_locked());
+        let probe = Rc::new(IrqProbe::default());
+        let opaque = Rc::as_ptr(&probe) as *mut c_void;
+        let name = name.map_or(ptr::null(), CStr::as_ptr);
+        // SAFETY: the probe is kept alive by self.irqs until the qemu_irq
+        // is freed in drop()
+        unsafe {
+            let irq = bindings::qemu_allocate_irq(Some(harness_irq_handler), opaque, n as c_int);
+            bindings::object_property_add_child(
+                bindings::object_get_root(),
+                c_str!("harness-irq[*]").as_ptr(),
+                irq.cast::<Object>(),
+            );
+            bindings::qdev_connect_gpio_out_named(
+                self.dev.as_ref().unwrap().as_mut_ptr::<DeviceState>(),
+                name,
+                n as c_int,
+                irq,
+            );
+            self.irqs.push((irq, Rc::clone(&probe)));
+        }
+        probe
+    }
+
+    /// Run `f`, which accesses the device's MMIO region, unless the device
+    /// is already handling an access; in that case log a guest error and
+    /// return the default value of `R`.  This is the check that
+    /// `access_with_adjusted_size()` performs on the device's
+    /// `mem_reentrancy_guard`, which `memory.c` would apply if the harness
+    /// went through it.
+    fn with_reentrancy_guard<R: Default>(&self, offset: hwaddr, f: impl FnOnce() -> R) -> R {
+        // SAFETY: the guard is only accessed under the Big QEMU Lock
+        let engaged_in_io = unsafe {
+            let dev = self.device().as_mut_ptr::<DeviceState>();
+            addr_of_mut!((*dev).mem_reentrancy_guard.engaged_in_io)
+        };
+        // SAFETY: as above
+        if unsafe { engaged_in_io.replace(true) } {
+            log_guest_error!(
+                "Blocked re-entrant IO on {} at offset {:#x}",
+                self.device().upcast::<DeviceState>().typename(),
+                offset
+            );
+            return R::default();
+        }
+        let _engaged = EngagedInIo(engaged_in_io);
+        f()
+    }
+
+    /// Convert `value` between the little-endian order of the harness and
+    /// the order of the device's registers.
+    fn adjust_endianness(&self, value: u64, size: u32) -> u64 {
+        if !matches!(self.ops.endianness, device_endian::DEVICE_BIG_ENDIAN) {
+            return value;
+        }
+        match size {
+            2 => u64::from((value as u16).swap_bytes()),
+            4 => u64::from((value as u32).swap_bytes()),
+            8 => value.swap_bytes(),
+            _ => value,
+        }
+    }
+
+    fn opaque(&self) -> *mut c_void {
+        self.device() as *const T as *mut c_void
+    }
+}
+
+impl<T: IsA<SysBusDevice> + IsA<DeviceState> + MmioOps> DeviceHarness<T> {
+    /// Create a device of type `typename` and plug it into the main system
+    /// bus, which is created if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `typename` is not `T` or a subclass of `T`, or if the
+    /// device fails to realize.
+    pub fn new_sysbus(typename: &CStr) -> Self {
+        Self::new_sysbus_with_props(typename, &[])
+    }
+
+    /// Same as [`new_sysbus`](DeviceHarness::new_sysbus), but set the
+    /// properties in `props` before realizing the device, like
+    /// [`new_with_props`](DeviceHarness::new_with_props).
+    pub fn new_sysbus_with_props(typename: &CStr, props: &[(&CStr, &CStr)]) -> Self {
+        init_qom();
+        // SAFETY: the main system bus lives forever once created
+        let bus = unsafe { &*bindings::sysbus_get_default() };
+        Self::with_bus(typename, props, Some(bus))
+    }
+
+    /// Connect the interrupt number `n`, as created by
+    /// [`init_irq`](crate::sysbus::SysBusDeviceMethods::init_irq), to a
+    /// new [`IrqProbe`], and return it.
+    pub fn observe_irq(&mut self, n: u32) -> Rc<IrqProbe> {
+        // SAFETY: SYSBUS_DEVICE_GPIO_IRQ is a NUL-terminated constant
+        let name = unsafe { CStr::from_bytes_with_nul_unchecked(bindings::SYSBUS_DEVICE_GPIO_IRQ) };
+        self.observe_gpio_out(Some(name), n)
+    }
+}
+
+impl<T: IsA<DeviceState> + MmioOps> Drop for DeviceHarness<T> {
+    fn drop(&mut self) {
+        assert!(bql_locked());
+        let dev = self.dev.take().unwrap();
+        // SAFETY: the device and the IRQs were added to the QOM tree by
+        // the harness, which also owns a reference to each of them
+        unsafe {
+            bindings::object_unparent(dev.as_object_mut_ptr());
+            drop(dev);
+            for (irq, _) in self.irqs.drain(..) {
+                bindings::object_unparent(irq.cast::<Object>());
+                bindings::qemu_free_irq(irq);
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for `QEMUTimer`.
+//!
+//! A [`Timer`] is embedded in the state of a device and initialized with
+//! the function that runs when the timer expires, as well as the object
+//! that is passed to it:
+//!
+//! ```ignore
+//! self.timer.init(QEMUClockType::QEMU_CLOCK_VIRTUAL, &Self::timer_cb, self);
+//! ```
+//!
+//! The timer is deleted when the `Timer` is dropped, which for QOM objects
+//! happens when the object is finalized.
+//!
+//! The current time of a clock is read with [`clock_get_ns`].
+
+use std::{
+    cell::UnsafeCell,
+    os::raw::{c_int, c_void},
+    ptr::{self, NonNull},
+};
+
+pub use bindings::QEMUClockType;
+
+use crate::{
+    bindings::{self, QEMUTimer},
+    callbacks::{abort_on_panic, FnCall},
+    cell::bql_locked,
+    zeroable::Zeroable,
+};
+
+/// A wrapper around the C `QEMUTimer` struct.
+///
+/// Timers are attached to the main loop, and therefore their callback
+/// always runs under the Big QEMU Lock.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct Timer(UnsafeCell<QEMUTimer>);
+
+// SAFETY: QEMU's timer lists protect the timer with a mutex, and the
+// other fields are only written before the timer is armed.
+unsafe impl Send for Timer {}
+unsafe impl Sync for Timer {}
+
+unsafe extern "C" fn rust_timer_cb<T, F: for<'a> FnCall<(&'a T,)>>(opaque: *mut c_void) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let state = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque was passed as a reference to `T` in Timer::init
+        F::call((unsafe { state.as_ref() },));
+    })
+}
+
+impl Timer {
+    /// Create a timer that is not attached to any clock yet.  It must be
+    /// initialized with [`init`](Timer::init) before it is armed.
+    pub const fn new() -> Self {
+        Timer(UnsafeCell::new(Zeroable::ZERO))
+    }
+
+    /// Attach the timer to `clock`, with deadlines expressed in
+    /// nanoseconds.  When the timer expires, `cb` is called with
+    /// `opaque` as the argument.
+    ///
+    /// The `_cb` parameter is unused; it only selects the function to call,
+    /// which must be a function item or a closure that captures nothing.
+    ///
+    /// `opaque` must live as long as the timer; usually the timer is a
+    /// field of `opaque` itself.  If the timer was armed, it is deleted
+    /// first.
+    pub fn init<T, F: for<'a> FnCall<(&'a T,)>>(&self, clock: QEMUClockType, _cb: &F, opaque: &T) {
+        assert!(bql_locked());
+        self.del();
+        let cb: unsafe extern "C" fn(*mut c_void) = rust_timer_cb::<T, F>;
+        // SAFETY: the timer is not in any timer list, and a null timer
+        // list group selects the main loop
+        unsafe {
+            bindings::timer_init_full(
+                self.as_mut_ptr(),
+                ptr::null_mut(),
+                clock,
+                bindings::SCALE_NS as c_int,
+                0,
+                Some(cb),
+                ptr::addr_of!(*opaque).cast::<c_void>() as *mut c_void,
+            );
+        }
+    }
+
+    /// Arm the timer so that it expires when its clock reaches `deadline`
+    /// nanoseconds.  If the timer was already armed, the previous deadline
+    /// is discarded.
+    pub fn mod_ns(&self, deadline: i64) {
+        assert!(self.is_initialized());
+        // SAFETY: the timer is attached to a timer list
+        unsafe {
+            bindings::timer_mod_ns(self.as_mut_ptr(), deadline);
+        }
+    }
+
+    /// Disarm the timer.  This does nothing if the timer is not armed.
+    pub fn del(&self) {
+        // SAFETY: timer_del() does nothing for timers that were never
+        // initialized
+        unsafe {
+            bindings::timer_del(self.as_mut_ptr());
+        }
+    }
+
+    /// Return whether the timer is armed.
+    pub fn is_pending(&self) -> bool {
+        // SAFETY: the timer is valid, even if it was never initialized
+        unsafe { bindings::timer_pending(self.as_mut_ptr()) }
+    }
+
+    fn is_initialized(&self) -> bool {
+        // SAFETY: the field is written only by timer_init_full()
+        unsafe { !(*self.0.get()).timer_list.is_null() }
+    }
+
+    /// Return a raw pointer to the C `QEMUTimer`, for use with C functions.
+    pub const fn as_mut_ptr(&self) -> *mut QEMUTimer {
+        self.0.get()
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.del();
+    }
+}
+
+impl QEMUClockType {
+    /// Return the current value of the clock in nanoseconds.
+    pub fn get_ns(self) -> i64 {
+        // SAFETY: reading a clock has no side effects
+        unsafe { bindings::qemu_clock_get_ns(self) }
+    }
+}
+
+/// The clocks that devices can read and arm timers on, as in the C
+/// `QEMUClockType` but without the `QEMU_CLOCK_MAX` sentinel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockType {
+    /// Real time, which keeps running while the VM is stopped.  Devices
+    /// should only use it for state that is not visible to the guest.
+    Realtime,
+
+    /// Virtual time, which only runs while the VM is running.  This is
+    /// the clock that devices use to model the passing of guest time.
+    Virtual,
+
+    /// The host's wall clock, which can jump when the host's time is
+    /// changed.  It is mostly used for real-time clock devices.
+    Host,
+
+    /// Same as `Virtual` outside icount mode; with icount, it follows
+    /// real time while the virtual CPUs are sleeping.
+    VirtualRt,
+}
+
+impl From<ClockType> for QEMUClockType {
+    fn from(clock: ClockType) -> Self {
+        match clock {
+            ClockType::Realtime => QEMUClockType::QEMU_CLOCK_REALTIME,
+            ClockType::Virtual => QEMUClockType::QEMU_CLOCK_VIRTUAL,
+            ClockType::Host => QEMUClockType::QEMU_CLOCK_HOST,
+            ClockType::VirtualRt => QEMUClockType::QEMU_CLOCK_VIRTUAL_RT,
+        }
+    }
+}
+
+/// Return the current value of `clock` in nanoseconds, as in
+/// `qemu_clock_get_ns()`.
+pub fn clock_get_ns(clock: ClockType) -> i64 {
+    QEMUClockType::from(clock).get_ns()
+}
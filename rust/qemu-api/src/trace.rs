@@ -0,0 +1,183 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Emitting trace events from Rust.
+//!
+//! Trace events are defined in the `trace-events` file of a directory,
+//! exactly as for C code.  Events that are emitted by Rust code must have
+//! the `rust` property:
+//!
+//! ```text
+//! rust pl011_can_receive(uint32_t lcr, int read_count, int r) "..."
+//! ```
+//!
+//! For each of them, tracetool generates a C function `rust_trace_<event>`
+//! that forwards its arguments to `trace_<event>()`, and therefore to
+//! whichever tracing backends are enabled.  It also generates a Rust file
+//! `trace/trace-<group>.rs` in the build tree, with a function for each
+//! event that calls the C wrapper.  A Rust crate includes that file in a
+//! module called `trace` at the root of the crate:
+//!
+//! ```ignore
+//! // src/trace.rs
+//! #[cfg(MESON)]
+//! include!("trace-hw_char.rs");
+//! #[cfg(not(MESON))]
+//! include!(concat!(env!("MESON_BUILD_ROOT"), "/trace/trace-hw_char.rs"));
+//! ```
+//!
+//! and then emits the events with the [`trace!`](crate::trace) macro:
+//!
+//! ```ignore
+//! trace!(pl011_can_receive(lcr, read_count, r));
+//! ```
+//!
+//! Integer and `bool` arguments are passed with the Rust type that
+//! corresponds to the C type.  Strings and pointers can be passed as any
+//! type that implements [`TraceArg`].
+
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_void},
+    ptr::NonNull,
+};
+
+/// Types that can be passed to a pointer argument of a trace event.  Each
+/// of them is converted to the C type [`CType`](TraceArg::CType):
+/// `const char *` for C strings, `void *` for everything else.
+pub trait TraceArg {
+    /// The type of the argument in the C function.
+    type CType;
+
+    /// Convert the value to the C type.
+    fn into_trace_arg(self) -> Self::CType;
+}
+
+impl<T> TraceArg for *const T {
+    type CType = *const c_void;
+    #[inline]
+    fn into_trace_arg(self) -> *const c_void {
+        self.cast()
+    }
+}
+
+impl<T> TraceArg for *mut T {
+    type CType = *const c_void;
+    #[inline]
+    fn into_trace_arg(self) -> *const c_void {
+        (self as *const T).cast()
+    }
+}
+
+impl<T> TraceArg for NonNull<T> {
+    type CType = *const c_void;
+    #[inline]
+    fn into_trace_arg(self) -> *const c_void {
+        self.as_ptr().into_trace_arg()
+    }
+}
+
+impl<T> TraceArg for &T {
+    type CType = *const c_void;
+    #[inline]
+    fn into_trace_arg(self) -> *const c_void {
+        (self as *const T).into_trace_arg()
+    }
+}
+
+impl TraceArg for &CStr {
+    type CType = *const c_char;
+    #[inline]
+    fn into_trace_arg(self) -> *const c_char {
+        self.as_ptr()
+    }
+}
+
+/// Emit a trace event that was generated by tracetool in the `trace`
+/// module of the current crate.  Whether the event is recorded depends on
+/// the tracing backends and on the events that are enabled at run time.
+#[macro_export]
+// `crate` refers to the crate that emits the event, not to qemu_api
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! trace {
+    ($event:ident($($arg:expr),*$(,)?)) => {
+        crate::trace::$event($($arg),*)
+    };
+}
+
+// The events in tests/trace-events, which the unit tests below emit.
+#[cfg(all(test, MESON))]
+include!("trace-qemu_api_test.rs");
+#[cfg(all(test, not(MESON)))]
+include!(concat!(
+    env!("MESON_BUILD_ROOT"),
+    "/rust/qemu-api/trace-qemu_api_test.rs"
+));
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        os::raw::{c_char, c_void},
+        ptr,
+        sync::Mutex,
+    };
+
+    use crate::c_str;
+
+    #[derive(Debug, PartialEq)]
+    struct Record {
+        value: u64,
+        level: i32,
+        ptr: usize,
+        name: String,
+    }
+
+    /// The events that reached the C side, or `None` if the event is
+    /// disabled.
+    static SINK: Mutex<Option<Vec<Record>>> = Mutex::new(None);
+
+    // Stand-in for the C wrapper that tracetool generates together with
+    // the Rust function; the unit tests do not link the C trace code.
+    #[no_mangle]
+    extern "C" fn rust_trace_rust_test_event(
+        value: u64,
+        level: i32,
+        ptr: *const c_void,
+        name: *const c_char,
+    ) {
+        if let Some(records) = SINK.lock().unwrap().as_mut() {
+            records.push(Record {
+                value,
+                level,
+                ptr: ptr as usize,
+                // SAFETY: the trace event is passed a C string
+                name: unsafe { CStr::from_ptr(name) }.to_str().unwrap().to_owned(),
+            });
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let data = 42u8;
+        trace!(rust_test_event(1, -1, ptr::null::<u8>(), c_str!("off")));
+        assert!(SINK.lock().unwrap().is_none());
+
+        *SINK.lock().unwrap() = Some(Vec::new());
+        trace!(rust_test_event(
+            u64::MAX,
+            -2,
+            ptr::addr_of!(data),
+            c_str!("on")
+        ));
+        assert_eq!(
+            SINK.lock().unwrap().take().unwrap(),
+            [Record {
+                value: u64::MAX,
+                level: -2,
+                ptr: ptr::addr_of!(data) as usize,
+                name: "on".to_owned(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2024 Red Hat, Inc.
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+//! Bindings for the QOM `UserCreatable` interface.
+//!
+//! Objects that can be created with `-object` or `object-add` implement the
+//! `UserCreatable` interface.  Once all the properties of such an object
+//! are set, its `complete` callback finishes its initialization, or fails
+//! if the properties are not valid.  A Rust type provides the callback by
+//! listing the interface in
+//! [`ObjectImpl::INTERFACES`](crate::qom::ObjectImpl::INTERFACES),
+//! implementing [`UserCreatableImpl`], and calling
+//! [`user_creatable_class_init`] from its `class_init`:
+//!
+//! ```ignore
+//! impl ObjectImpl for MyBackend {
+//!     type ParentType = Object;
+//!     const INTERFACES: &'static [InterfaceInfo] = interfaces![TYPE_USER_CREATABLE];
+//! }
+//!
+//! impl ClassInitImpl<MyBackendClass> for MyBackend {
+//!     fn class_init(klass: &mut MyBackendClass) {
+//!         <Self as ClassInitImpl<ObjectClass>>::class_init(&mut klass.parent_class);
+//!         user_creatable_class_init::<Self>(&mut klass.parent_class);
+//!     }
+//! }
+//! ```
+//!
+//! [`ObjectBuilder`](crate::qom::ObjectBuilder) completes the objects that
+//! it creates, so that they go through the same path as with `-object`.
+
+use std::ptr::NonNull;
+
+pub use bindings::{UserCreatable, UserCreatableClass, TYPE_USER_CREATABLE};
+
+use crate::{
+    bindings,
+    callbacks::abort_on_panic,
+    cell::bql_locked,
+    error::{bql_error_propagate, Result},
+    qom::{ClassInitImpl, ObjectClass},
+};
+
+/// Trait providing the contents of [`UserCreatableClass`].
+pub trait UserCreatableImpl {
+    /// Finish the initialization of the object, after all its properties
+    /// are set.  An error, for example because a mandatory property is
+    /// missing, makes the creation of the object fail; its message is
+    /// reported to the user of `-object` or `object-add`.
+    fn complete(&mut self) -> Result<()>;
+}
+
+unsafe extern "C" fn rust_user_creatable_complete_fn<T: UserCreatableImpl>(
+    uc: *mut UserCreatable,
+    errp: *mut *mut bindings::Error,
+) {
+    abort_on_panic(|| {
+        assert!(bql_locked());
+        let mut obj = NonNull::new(uc).unwrap().cast::<T>();
+        // SAFETY: the object implements the interface, so it is a `T`; it
+        // is only completed before anything else can refer to it
+        let result = unsafe { obj.as_mut() }.complete();
+        // SAFETY: errp is valid, as passed to user_creatable_complete()
+        unsafe {
+            bql_error_propagate(result, errp);
+        }
+    })
+}
+
+impl<T> ClassInitImpl<UserCreatableClass> for T
+where
+    T: UserCreatableImpl,
+{
+    fn class_init(ucc: &mut UserCreatableClass) {
+        ucc.complete = Some(rust_user_creatable_complete_fn::<T>);
+    }
+}
+
+/// Install the [`UserCreatableImpl`] callbacks of `T` in the
+/// `UserCreatable` interface of `klass`, which is the class of `T`.
+///
+/// # Panics
+///
+/// Panics if `TYPE_USER_CREATABLE` is not among the
+/// [`INTERFACES`](crate::qom::ObjectImpl::INTERFACES) of `T`.
+pub fn user_creatable_class_init<T: UserCreatableImpl>(klass: &mut ObjectClass) {
+    // SAFETY: interfaces are set up before class_init is called, and the
+    // result of the cast is either NULL or the interface's class struct
+    let ucc = unsafe {
+        NonNull::new(bindings::object_class_dynamic_cast(
+            klass,
+            TYPE_USER_CREATABLE.as_ptr().cast(),
+        ))
+        .expect("type does not implement TYPE_USER_CREATABLE")
+        .cast::<UserCreatableClass>()
+        .as_mut()
+    };
+    <T as ClassInitImpl<UserCreatableClass>>::class_init(ucc);
+}
@@ -9,7 +9,9 @@
 //! * [`vmstate_unused!`](crate::vmstate_unused) and
 //!   [`vmstate_of!`](crate::vmstate_of), which are used to express the
 //!   migration format for a struct.  This is based on the [`VMState`] trait,
-//!   which is defined by all migrateable types.
+//!   which is defined by all migrateable types.  Macros such as
+//!   [`vmstate_uint32!`](crate::vmstate_uint32) wrap `vmstate_of!` and
+//!   additionally check the type of the field.
 //!
 //! * [`impl_vmstate_forward`](crate::impl_vmstate_forward) and
 //!   [`impl_vmstate_bitsized`](crate::impl_vmstate_bitsized), which help with
@@ -18,17 +20,28 @@
 //!
 //! * helper macros to declare a device model state struct, in particular
 //!   [`vmstate_subsections`](crate::vmstate_subsections) and
-//!   [`vmstate_fields`](crate::vmstate_fields).
+//!   [`vmstate_fields`](crate::vmstate_fields), as well as [`vmstate_needed`],
+//!   [`vmstate_field_exists`], [`vmstate_post_load`] and [`vmstate_pre_save`]
+//!   for the callbacks.  For simple structs, the [`Migrate`] trait can
+//!   instead be derived from the struct definition.
 //!
 //! * direct equivalents to the C macros declared in
 //!   `include/migration/vmstate.h`. These are not type-safe and only provide
 //!   functionality that is missing from `vmstate_of!`.
 
-use core::{marker::PhantomData, mem, ptr::NonNull};
+use core::{
+    ffi::{c_int, c_void},
+    marker::PhantomData,
+    mem,
+    ptr::{self, NonNull},
+};
 
 pub use crate::bindings::{VMStateDescription, VMStateField};
 use crate::{
     bindings::{self, VMStateFlags},
+    bql::assert_bql_locked,
+    callbacks::{abort_on_panic, FnCall},
+    g_free, g_malloc0,
     zeroable::Zeroable,
 };
 
@@ -182,12 +195,18 @@ pub const fn vmstate_varray_flag<T: VMState>(_: PhantomData<T>) -> VMStateFlags
     T::VARRAY_FLAG
 }
 
+/// Internal utility function to retrieve the size of a field's type; used
+/// by [`vmstate_struct!`](crate::vmstate_struct).
+pub const fn vmstate_size_of<T>(_: PhantomData<T>) -> usize {
+    mem::size_of::<T>()
+}
+
 /// Return the `VMStateField` for a field of a struct.  The field must be
 /// visible in the current scope.
 ///
 /// Only a limited set of types is supported out of the box:
 /// * scalar types (integer and `bool`)
-/// * the C struct `QEMUTimer`
+/// * the C struct `QEMUTimer` and its wrapper [`Timer`](crate::timer::Timer)
 /// * a transparent wrapper for any of the above (`Cell`, `UnsafeCell`,
 ///   [`BqlCell`](crate::cell::BqlCell), [`BqlRefCell`](crate::cell::BqlRefCell)
 /// * a raw pointer to any of the above
@@ -206,7 +225,7 @@ macro_rules! vmstate_of {
                 .as_bytes()
                 .as_ptr() as *const ::std::os::raw::c_char,
             offset: $crate::offset_of!($struct_name, $field_name),
-            $(.num_offset: $crate::offset_of!($struct_name, $num),)?
+            $(num_offset: $crate::offset_of!($struct_name, $num),)?
             // The calls to `call_func_with_field!` are the magic that
             // computes most of the VMStateField from the type of the field.
             info: $crate::info_enum_to_ref!($crate::call_func_with_field!(
@@ -268,11 +287,27 @@ pub const fn with_pointer_flag(mut self) -> Self {
         self
     }
 
+    /// Turn an array, or a pointer to the first element of an array, into
+    /// a variable-sized array whose length is found at `num_offset`.
     #[must_use]
-    pub const fn with_varray_flag<T: VMState>(mut self, flag: VMStateFlags) -> VMStateField {
-        assert!((self.flags.0 & VMStateFlags::VMS_ARRAY.0) != 0);
-        self.flags = VMStateFlags(self.flags.0 & !VMStateFlags::VMS_ARRAY.0);
+    pub const fn with_varray_flag(mut self, flag: VMStateFlags) -> VMStateField {
+        assert!((self.flags.0 & (VMStateFlags::VMS_ARRAY.0 | VMStateFlags::VMS_POINTER.0)) != 0);
+        self.flags =
+            VMStateFlags(self.flags.0 & !(VMStateFlags::VMS_ARRAY.0 | VMStateFlags::VMS_SINGLE.0));
         self.flags = VMStateFlags(self.flags.0 | flag.0);
+        self.num = 0;
+        self
+    }
+
+    /// Only migrate the field if `field_exists` returns true.  Usually
+    /// called through [`vmstate_cond!`](crate::vmstate_cond).
+    #[must_use]
+    pub const fn with_exist_check(
+        mut self,
+        field_exists: Option<unsafe extern "C" fn(*mut c_void, c_int) -> bool>,
+    ) -> Self {
+        assert!(self.field_exists.is_none());
+        self.field_exists = field_exists;
         self
     }
 
@@ -377,6 +412,7 @@ unsafe impl VMState for $type {
 impl_vmstate_scalar!(vmstate_info_uint32, u32, VMS_VARRAY_UINT32);
 impl_vmstate_scalar!(vmstate_info_uint64, u64);
 impl_vmstate_scalar!(vmstate_info_timer, bindings::QEMUTimer);
+impl_vmstate_scalar!(vmstate_info_timer, crate::timer::Timer);
 
 // Pointer types using the underlying type's VMState plus VMS_POINTER
 // Note that references are not supported, though references to cells
@@ -407,13 +443,23 @@ unsafe impl<$base> VMState for $type where $base: VMState $($where)* {
     const BASE: VMStateField = <T as VMState>::BASE.with_array_flag(N);
 }
 
+/// Reserve `$size` bytes in the migration stream that are not stored in
+/// the device state: they are written as zeros and skipped on load.
+/// This keeps the stream compatible with older versions after a field
+/// is removed, by replacing the field with `vmstate_unused!` of the same
+/// size.
+///
+/// `version = N` specifies the version in which the bytes were added;
+/// streams from older versions do not include them.
 #[doc(alias = "VMSTATE_UNUSED")]
+#[doc(alias = "VMSTATE_UNUSED_V")]
 #[macro_export]
 macro_rules! vmstate_unused {
-    ($size:expr) => {{
+    ($size:expr $(, version = $version:expr)? $(,)?) => {{
         $crate::bindings::VMStateField {
             name: $crate::c_str!("unused").as_ptr(),
             size: $size,
+            $(version_id: $version,)?
             info: unsafe { ::core::ptr::addr_of!($crate::bindings::vmstate_info_unused_buffer) },
             flags: $crate::bindings::VMStateFlags::VMS_BUFFER,
             ..$crate::zeroable::Zeroable::ZERO
@@ -427,16 +473,21 @@ macro_rules! vmstate_unused {
 // `vmstate_of!`.  While VMSTATE_CLOCK can at least try to be type-safe,
 // VMSTATE_STRUCT includes $type only for documentation purposes; it
 // is checked against $field_name and $struct_name, but not against $vmsd
-// which is what really would matter.
+// which is what really would matter.  If $type is omitted, the size is
+// taken from the type of the field; this is only possible for non-array
+// fields.
+//
+// `version = N` specifies the version in which the field was introduced.
 #[doc(alias = "VMSTATE_STRUCT")]
+#[doc(alias = "VMSTATE_STRUCT_V")]
 #[macro_export]
 macro_rules! vmstate_struct {
-    ($struct_name:ty, $field_name:ident $([0 .. $num:ident $(* $factor:expr)?])?, $vmsd:expr, $type:ty $(,)?) => {
+    ($struct_name:ty, $field_name:ident $([0 .. $num:ident $(* $factor:expr)?])?, $vmsd:expr, $type:ty $(, version = $version:expr)? $(,)?) => {
         $crate::bindings::VMStateField {
             name: ::core::concat!(::core::stringify!($field_name), "\0")
                 .as_bytes()
                 .as_ptr() as *const ::std::os::raw::c_char,
-            $(.num_offset: $crate::offset_of!($struct_name, $num),)?
+            $(num_offset: $crate::offset_of!($struct_name, $num),)?
             offset: {
                 $crate::assert_field_type!($struct_name, $field_name, $type);
                 $crate::offset_of!($struct_name, $field_name)
@@ -450,14 +501,79 @@ macro_rules! vmstate_struct {
                     $struct_name,
                     $num))
                $(.with_varray_multiply($factor))?)?
-        }
+        } $(.with_version_id($version))?
     };
+    ($struct_name:ty, $field_name:ident, $vmsd:expr $(, version = $version:expr)? $(,)?) => {
+        $crate::bindings::VMStateField {
+            name: ::core::concat!(::core::stringify!($field_name), "\0")
+                .as_bytes()
+                .as_ptr() as *const ::std::os::raw::c_char,
+            offset: $crate::offset_of!($struct_name, $field_name),
+            size: $crate::call_func_with_field!(
+                $crate::vmstate::vmstate_size_of,
+                $struct_name,
+                $field_name
+            ),
+            flags: $crate::bindings::VMStateFlags::VMS_STRUCT,
+            vmsd: unsafe { $vmsd },
+            ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    };
+}
+
+/// Types of the fields that can be passed to
+/// [`vmstate_timer!`](crate::vmstate_timer): a timer embedded in the
+/// struct, as in `VMSTATE_TIMER`, or a pointer to one, as in
+/// `VMSTATE_TIMER_PTR`.
+pub trait VMStateTimer: VMState {}
+
+impl VMStateTimer for crate::timer::Timer {}
+impl VMStateTimer for bindings::QEMUTimer {}
+impl<T: VMStateTimer> VMStateTimer for *const T {}
+impl<T: VMStateTimer> VMStateTimer for *mut T {}
+impl<T: VMStateTimer> VMStateTimer for NonNull<T> {}
+impl<T: VMStateTimer> VMStateTimer for Box<T> {}
+
+/// Internal utility function used by [`vmstate_timer!`](crate::vmstate_timer)
+/// to check the type of the field.
+#[doc(hidden)]
+pub const fn assert_vmstate_timer<T: VMStateTimer>(_: PhantomData<T>) {}
+
+/// Migrate the deadline of a timer.  The field can be a
+/// [`Timer`](crate::timer::Timer) or a pointer to it; the destination must
+/// have initialized the timer before the state is loaded, which is usually
+/// done when the device is created.
+#[doc(alias = "VMSTATE_TIMER")]
+#[doc(alias = "VMSTATE_TIMER_PTR")]
+#[macro_export]
+macro_rules! vmstate_timer {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::call_func_with_field!(
+            $crate::vmstate::assert_vmstate_timer,
+            $struct_name,
+            $field_name
+        );
+        $crate::vmstate_of!($struct_name, $field_name) $(.with_version_id($version))?
+    }};
 }
 
+/// Migrate the period of a clock that was created with
+/// [`init_clock_in`] or [`init_clock_out`].  The field must be the
+/// `NonNull<Clock>` that was returned by those functions; the clock itself
+/// is created when the device is, so the destination already has it when
+/// the state is loaded.
+///
+/// Loading the state does not call the callback of an input clock, so
+/// devices that derive other state from the period should recompute it in
+/// their `post_load` function.
+///
+/// [`init_clock_in`]: crate::qdev::DeviceMethods::init_clock_in
+/// [`init_clock_out`]: crate::qdev::DeviceMethods::init_clock_out
 #[doc(alias = "VMSTATE_CLOCK")]
+#[doc(alias = "VMSTATE_CLOCK_V")]
 #[macro_export]
 macro_rules! vmstate_clock {
-    ($struct_name:ty, $field_name:ident) => {{
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
         $crate::bindings::VMStateField {
             name: ::core::concat!(::core::stringify!($field_name), "\0")
                 .as_bytes()
@@ -466,18 +582,427 @@ macro_rules! vmstate_clock {
                 $crate::assert_field_type!(
                     $struct_name,
                     $field_name,
-                    core::ptr::NonNull<$crate::bindings::Clock>
+                    ::core::ptr::NonNull<$crate::bindings::Clock>
                 );
                 $crate::offset_of!($struct_name, $field_name)
             },
             size: ::core::mem::size_of::<*const $crate::bindings::Clock>(),
-            flags: VMStateFlags(VMStateFlags::VMS_STRUCT.0 | VMStateFlags::VMS_POINTER.0),
+            flags: $crate::bindings::VMStateFlags(
+                $crate::bindings::VMStateFlags::VMS_STRUCT.0
+                    | $crate::bindings::VMStateFlags::VMS_POINTER.0,
+            ),
             vmsd: unsafe { ::core::ptr::addr_of!($crate::bindings::vmstate_clock) },
             ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    }};
+}
+
+/// Migrate the counter and configuration of a [`PTimer`] field.  The
+/// destination must have initialized the `PTimer` before the state is
+/// loaded, which is usually done when the device is created.
+///
+/// [`PTimer`]: crate::ptimer::PTimer
+#[doc(alias = "VMSTATE_PTIMER")]
+#[macro_export]
+macro_rules! vmstate_ptimer {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::bindings::VMStateField {
+            name: ::core::concat!(::core::stringify!($field_name), "\0")
+                .as_bytes()
+                .as_ptr() as *const ::std::os::raw::c_char,
+            // the ptimer_state pointer is the first field of PTimer
+            offset: {
+                $crate::assert_field_type!($struct_name, $field_name, $crate::ptimer::PTimer);
+                $crate::offset_of!($struct_name, $field_name)
+            },
+            size: ::core::mem::size_of::<*const $crate::bindings::ptimer_state>(),
+            flags: $crate::bindings::VMStateFlags(
+                $crate::bindings::VMStateFlags::VMS_STRUCT.0
+                    | $crate::bindings::VMStateFlags::VMS_POINTER.0,
+            ),
+            vmsd: unsafe { ::core::ptr::addr_of!($crate::bindings::vmstate_ptimer) },
+            version_id: 1,
+            ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    }};
+}
+
+/// Migrate the contents of a [`Fifo8`] field.  The destination must have
+/// created a FIFO with the same capacity before the state is loaded.
+///
+/// [`Fifo8`]: crate::fifo::Fifo8
+#[doc(alias = "VMSTATE_FIFO8")]
+#[macro_export]
+macro_rules! vmstate_fifo8 {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::bindings::VMStateField {
+            name: ::core::concat!(::core::stringify!($field_name), "\0")
+                .as_bytes()
+                .as_ptr() as *const ::std::os::raw::c_char,
+            // Fifo8 is a transparent wrapper around the C struct
+            offset: {
+                $crate::assert_field_type!($struct_name, $field_name, $crate::fifo::Fifo8);
+                $crate::offset_of!($struct_name, $field_name)
+            },
+            size: ::core::mem::size_of::<$crate::bindings::Fifo8>(),
+            flags: $crate::bindings::VMStateFlags::VMS_STRUCT,
+            vmsd: unsafe { ::core::ptr::addr_of!($crate::bindings::vmstate_fifo8) },
+            version_id: 1,
+            ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    }};
+}
+
+/// Returned by [`get_struct_pointer`] when the stream is corrupted, like
+/// `-EINVAL` in C.
+const EINVAL: c_int = 22;
+
+unsafe extern "C" fn get_struct_pointer(
+    f: *mut bindings::QEMUFile,
+    pv: *mut c_void,
+    size: usize,
+    field: *const VMStateField,
+) -> c_int {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: the field was declared with vmstate_struct_pointer!, so
+        // `pv` points to a pointer to a zeroable struct of `size` bytes,
+        // described by the field's vmsd
+        unsafe {
+            let ptr = pv.cast::<*mut c_void>();
+            match bindings::qemu_get_byte(f) {
+                0 => {
+                    g_free(*ptr);
+                    *ptr = ptr::null_mut();
+                    0
+                }
+                1 => {
+                    if (*ptr).is_null() {
+                        *ptr = g_malloc0(size.try_into().unwrap());
+                    }
+                    let vmsd = (*field).vmsd;
+                    bindings::vmstate_load_state(f, vmsd, *ptr, (*vmsd).version_id)
+                }
+                _ => -EINVAL,
+            }
+        }
+    })
+}
+
+unsafe extern "C" fn put_struct_pointer(
+    f: *mut bindings::QEMUFile,
+    pv: *mut c_void,
+    _size: usize,
+    field: *const VMStateField,
+    _vmdesc: *mut bindings::JSONWriter,
+) -> c_int {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: see get_struct_pointer
+        unsafe {
+            let ptr = *pv.cast::<*mut c_void>();
+            if ptr.is_null() {
+                bindings::qemu_put_byte(f, 0);
+                0
+            } else {
+                bindings::qemu_put_byte(f, 1);
+                bindings::vmstate_save_state(f, (*field).vmsd, ptr, ptr::null_mut())
+            }
+        }
+    })
+}
+
+/// The `VMStateInfo` used by
+/// [`vmstate_struct_pointer!`](crate::vmstate_struct_pointer).
+#[doc(hidden)]
+pub static VMSTATE_INFO_STRUCT_POINTER: bindings::VMStateInfo = bindings::VMStateInfo {
+    name: crate::c_str!("struct_pointer").as_ptr(),
+    get: Some(get_struct_pointer),
+    put: Some(put_struct_pointer),
+};
+
+/// Internal utility function used by
+/// [`vmstate_struct_pointer!`](crate::vmstate_struct_pointer) to check
+/// that the pointee can be allocated with `g_malloc0`.
+#[doc(hidden)]
+pub const fn vmstate_struct_pointer_size<T: Zeroable>() -> usize {
+    mem::size_of::<T>()
+}
+
+/// Migrate a field of type `*mut $type`, which may be null, as the struct
+/// described by `$vmsd`.
+///
+/// The stream records whether the pointer was null.  If it was not, the
+/// destination allocates the struct with `g_malloc0` if needed, and then
+/// loads it; if it was, the destination frees its struct, if any, with
+/// `g_free` and sets the field to null.  The device should likewise use
+/// `g_free` to free the struct, which must be [`Zeroable`].
+///
+/// `VMS_POINTER | VMS_ALLOC` in C always allocates on load, and cannot
+/// migrate a null pointer; therefore, the stream is not compatible with
+/// C devices that used `VMSTATE_STRUCT_POINTER` with `VMS_ALLOC`.
+#[doc(alias = "VMSTATE_STRUCT_POINTER")]
+#[macro_export]
+macro_rules! vmstate_struct_pointer {
+    ($struct_name:ty, $field_name:ident, $vmsd:expr, $type:ty $(, version = $version:expr)? $(,)?) => {
+        $crate::bindings::VMStateField {
+            name: ::core::concat!(::core::stringify!($field_name), "\0")
+                .as_bytes()
+                .as_ptr() as *const ::std::os::raw::c_char,
+            offset: {
+                $crate::assert_field_type!($struct_name, $field_name, *mut $type);
+                $crate::offset_of!($struct_name, $field_name)
+            },
+            size: $crate::vmstate::vmstate_struct_pointer_size::<$type>(),
+            flags: $crate::bindings::VMStateFlags::VMS_SINGLE,
+            info: ::core::ptr::addr_of!($crate::vmstate::VMSTATE_INFO_STRUCT_POINTER),
+            vmsd: unsafe { $vmsd },
+            ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    };
+}
+
+// Unlike `vmstate_of!`, the following macros check that the field has
+// exactly the type named by the macro, so that changing the type of a
+// field does not silently change the migration stream.  An optional
+// third argument specifies the version in which the field was introduced.
+
+/// Migrate a `bool` field as a single byte, like `VMSTATE_BOOL` in C.
+#[doc(alias = "VMSTATE_BOOL")]
+#[macro_export]
+macro_rules! vmstate_bool {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $field_name, bool);
+        $crate::vmstate_of!($struct_name, $field_name) $(.with_version_id($version))?
+    }};
+}
+
+unsafe extern "C" fn get_bool_as_int(
+    f: *mut bindings::QEMUFile,
+    pv: *mut c_void,
+    _size: usize,
+    _field: *const VMStateField,
+) -> c_int {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: the field was declared with vmstate_bool_as_int!, which
+        // checks that it is a bool
+        unsafe {
+            *pv.cast::<bool>() = bindings::qemu_get_be32(f) != 0;
         }
+        0
+    })
+}
+
+unsafe extern "C" fn put_bool_as_int(
+    f: *mut bindings::QEMUFile,
+    pv: *mut c_void,
+    _size: usize,
+    _field: *const VMStateField,
+    _vmdesc: *mut bindings::JSONWriter,
+) -> c_int {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        // SAFETY: the field was declared with vmstate_bool_as_int!, which
+        // checks that it is a bool
+        unsafe {
+            bindings::qemu_put_be32(f, (*pv.cast::<bool>()).into());
+        }
+        0
+    })
+}
+
+/// The `VMStateInfo` used by [`vmstate_bool_as_int!`](crate::vmstate_bool_as_int).
+/// Its name is the same as for `vmstate_info_uint32`, so that the
+/// description of the stream is also unchanged.
+#[doc(hidden)]
+pub static VMSTATE_INFO_BOOL_AS_INT: bindings::VMStateInfo = bindings::VMStateInfo {
+    name: crate::c_str!("uint32").as_ptr(),
+    get: Some(get_bool_as_int),
+    put: Some(put_bool_as_int),
+};
+
+/// Migrate a `bool` field as a 32-bit big-endian integer.  This keeps the
+/// migration stream compatible with C devices that stored the same state
+/// in an `int` or `uint32_t` and migrated it with `VMSTATE_INT32` or
+/// `VMSTATE_UINT32`.  Any nonzero value is loaded as `true`.
+#[macro_export]
+macro_rules! vmstate_bool_as_int {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $field_name, bool);
+        $crate::bindings::VMStateField {
+            name: ::core::concat!(::core::stringify!($field_name), "\0")
+                .as_bytes()
+                .as_ptr() as *const ::std::os::raw::c_char,
+            offset: $crate::offset_of!($struct_name, $field_name),
+            size: ::core::mem::size_of::<bool>(),
+            flags: $crate::bindings::VMStateFlags::VMS_SINGLE,
+            info: ::core::ptr::addr_of!($crate::vmstate::VMSTATE_INFO_BOOL_AS_INT),
+            ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    }};
+}
+
+#[doc(alias = "VMSTATE_UINT8")]
+#[macro_export]
+macro_rules! vmstate_uint8 {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $field_name, u8);
+        $crate::vmstate_of!($struct_name, $field_name) $(.with_version_id($version))?
+    }};
+}
+
+#[doc(alias = "VMSTATE_UINT16")]
+#[macro_export]
+macro_rules! vmstate_uint16 {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $field_name, u16);
+        $crate::vmstate_of!($struct_name, $field_name) $(.with_version_id($version))?
+    }};
+}
+
+#[doc(alias = "VMSTATE_UINT32")]
+#[macro_export]
+macro_rules! vmstate_uint32 {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $field_name, u32);
+        $crate::vmstate_of!($struct_name, $field_name) $(.with_version_id($version))?
+    }};
+}
+
+#[doc(alias = "VMSTATE_UINT64")]
+#[macro_export]
+macro_rules! vmstate_uint64 {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $field_name, u64);
+        $crate::vmstate_of!($struct_name, $field_name) $(.with_version_id($version))?
+    }};
+}
+
+// The type argument is the type of a single element; the field must be
+// an array of exactly `$num` elements of that type, otherwise compilation
+// fails.
+#[doc(alias = "VMSTATE_ARRAY")]
+#[macro_export]
+macro_rules! vmstate_array {
+    ($struct_name:ty, $field_name:ident, $num:expr, $type:ty $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $field_name, [$type; $num]);
+        $crate::vmstate_of!($struct_name, $field_name) $(.with_version_id($version))?
     }};
 }
 
+/// Internal utility function used by [`vmstate_buffer!`](crate::vmstate_buffer)
+/// to check that the field is a byte array, and retrieve its length.
+#[doc(hidden)]
+pub const fn vmstate_buffer_len<const N: usize>(_: PhantomData<[u8; N]>) -> usize {
+    N
+}
+
+/// Migrate a `[u8; N]` field as a single buffer of `N` bytes, like
+/// `VMSTATE_BUFFER` in C.  This is the same stream as
+/// [`vmstate_array!`](crate::vmstate_array) with `u8` elements, but it is
+/// transferred in one piece, which is faster for large buffers such as
+/// the contents of an SRAM.
+#[doc(alias = "VMSTATE_BUFFER")]
+#[macro_export]
+macro_rules! vmstate_buffer {
+    ($struct_name:ty, $field_name:ident $(, $version:expr)? $(,)?) => {{
+        $crate::bindings::VMStateField {
+            name: ::core::concat!(::core::stringify!($field_name), "\0")
+                .as_bytes()
+                .as_ptr() as *const ::std::os::raw::c_char,
+            offset: $crate::offset_of!($struct_name, $field_name),
+            size: $crate::call_func_with_field!(
+                $crate::vmstate::vmstate_buffer_len,
+                $struct_name,
+                $field_name
+            ),
+            flags: $crate::bindings::VMStateFlags::VMS_BUFFER,
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::vmstate_info_buffer) },
+            ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    }};
+}
+
+/// Migrate the first `$size` bytes of a field of any type as raw bytes,
+/// like `VMSTATE_BUFFER_UNSAFE` in C.  The memory representation of the
+/// field becomes part of the migration stream, so this should only be
+/// used for plain data whose layout will not change, such as a
+/// `#[repr(C)]` struct of integers.
+///
+/// Compilation fails if `$size` is larger than the field.
+#[doc(alias = "VMSTATE_BUFFER_UNSAFE")]
+#[macro_export]
+macro_rules! vmstate_buffer_unsafe {
+    ($struct_name:ty, $field_name:ident, $size:expr $(, $version:expr)? $(,)?) => {{
+        $crate::bindings::VMStateField {
+            name: ::core::concat!(::core::stringify!($field_name), "\0")
+                .as_bytes()
+                .as_ptr() as *const ::std::os::raw::c_char,
+            offset: $crate::offset_of!($struct_name, $field_name),
+            size: {
+                let size: usize = $size;
+                assert!(
+                    size <= $crate::call_func_with_field!(
+                        $crate::vmstate::vmstate_size_of,
+                        $struct_name,
+                        $field_name
+                    ),
+                    "buffer is larger than the field"
+                );
+                size
+            },
+            flags: $crate::bindings::VMStateFlags::VMS_BUFFER,
+            info: unsafe { ::core::ptr::addr_of!($crate::bindings::vmstate_info_buffer) },
+            ..$crate::zeroable::Zeroable::ZERO
+        } $(.with_version_id($version))?
+    }};
+}
+
+/// Migrate a heap-allocated buffer, whose number of elements is stored in
+/// the `u32` field `$num_field` of the same struct.  The field must be a
+/// pointer to the first element, and the destination must have allocated
+/// enough room for the incoming elements before the state is loaded; the
+/// length should be migrated before the buffer, so that it is already
+/// known when the buffer is loaded.
+#[doc(alias = "VMSTATE_VARRAY_UINT32")]
+#[macro_export]
+macro_rules! vmstate_varray_uint32 {
+    ($struct_name:ty, $field_name:ident, $num_field:ident $(, $version:expr)? $(,)?) => {{
+        $crate::assert_field_type!($struct_name, $num_field, u32);
+        let field = $crate::vmstate_of!($struct_name, $field_name[0 .. $num_field]);
+        assert!(
+            (field.flags.0 & $crate::bindings::VMStateFlags::VMS_POINTER.0) != 0,
+            "the buffer of a variable-sized array must be a pointer"
+        );
+        field $(.with_version_id($version))?
+    }};
+}
+
+/// Migrate the field described by `$field` only if `$exists` returns true.
+/// `$exists` is a function that takes the migrated struct and the version
+/// of the stream, and that must capture nothing:
+///
+/// ```ignore
+/// fn has_fifo(&self, _version_id: i32) -> bool {
+///     self.mode == Mode::Fifo
+/// }
+///
+/// vmstate_cond!(vmstate_array!(FooState, fifo), FooState::has_fifo),
+/// ```
+///
+/// The function is called both when saving and when loading, so on the
+/// destination it can only look at configuration (such as properties) or
+/// at fields that were loaded earlier.  Unlike with a subsection, the
+/// stream does not record whether the field was sent.
+#[doc(alias = "VMSTATE_SINGLE_TEST")]
+#[macro_export]
+macro_rules! vmstate_cond {
+    ($field:expr, $exists:expr $(,)?) => {
+        $field.with_exist_check($crate::vmstate::vmstate_field_exists(&$exists))
+    };
+}
+
 /// Helper macro to declare a list of
 /// ([`VMStateField`](`crate::bindings::VMStateField`)) into a static and return
 /// a pointer to the array of values it created.
@@ -495,6 +1020,45 @@ macro_rules! vmstate_fields {
     }}
 }
 
+/// Trait for structs whose migration format is derived from the
+/// struct definition with `#[derive(Migrate)]`.
+///
+/// All fields are migrated in declaration order, unless they are marked
+/// with `#[migrate(skip)]`; a field that was added in a later version of
+/// the migration stream is marked with `#[migrate(version = N)]`.  The
+/// name of the description and its minimum version can be set on the
+/// struct with `#[migrate(name = "...", minimum_version = N)]`:
+///
+/// ```ignore
+/// #[derive(Migrate)]
+/// #[migrate(name = "my-device")]
+/// struct MyDeviceRegs {
+///     ctrl: u32,
+///     fifo: [u8; 16],
+///     #[migrate(version = 2)]
+///     status: bool,
+///     #[migrate(skip)]
+///     scratch: u64,
+/// }
+/// ```
+///
+/// Devices that must be migrated before others, such as an IOMMU before
+/// the PCI devices behind it, set the priority of the description with
+/// `#[migrate(priority = MIG_PRI_IOMMU)]`, naming one of the variants of
+/// `MigrationPriority`.  Descriptions with a higher priority are saved
+/// and loaded first.
+///
+/// State that the destination needs before the bulk of memory is migrated
+/// is marked with `#[migrate(early_setup)]`.  Such a description is sent
+/// during the setup phase of migration instead of when the VM stops.
+///
+/// The derive only supports integers, `bool` and arrays of them; use
+/// [`vmstate_fields`](crate::vmstate_fields) for anything else.
+pub trait Migrate {
+    /// Return the description of the migration format of `Self`.
+    fn vmsd() -> &'static VMStateDescription;
+}
+
 /// A transparent wrapper type for the `subsections` field of
 /// [`VMStateDescription`].
 ///
@@ -525,3 +1089,120 @@ macro_rules! vmstate_subsections {
         _SUBSECTIONS.0.as_ptr()
     }}
 }
+
+unsafe extern "C" fn rust_vms_needed_cb<T, F: for<'a> FnCall<(&'a T,), bool>>(
+    opaque: *mut c_void,
+) -> bool {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let owner = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque is the struct that the description applies to
+        F::call((unsafe { owner.as_ref() },))
+    })
+}
+
+/// Build the `needed` callback of a subsection from a function that takes
+/// the migrated struct and returns whether the subsection has to be sent:
+///
+/// ```ignore
+/// static VMSTATE_FOO_EXTRA: VMStateDescription = VMStateDescription {
+///     name: c_str!("foo/extra").as_ptr(),
+///     needed: vmstate_needed(&FooState::extra_needed),
+///     ...
+/// };
+/// ```
+///
+/// A subsection that is not needed is not included in the migration
+/// stream, and a stream that lacks it can be loaded by a destination that
+/// knows about it.  This is what allows migrating to and from older
+/// versions of QEMU, as long as the subsection is only needed when the
+/// guest uses a feature that the older version did not have.
+///
+/// The `_cb` parameter is unused; it only selects the function to call,
+/// which must be a function item or a closure that captures nothing.
+pub const fn vmstate_needed<T, F: for<'a> FnCall<(&'a T,), bool>>(
+    _cb: &F,
+) -> Option<unsafe extern "C" fn(*mut c_void) -> bool> {
+    Some(rust_vms_needed_cb::<T, F>)
+}
+
+unsafe extern "C" fn rust_vms_field_exists_cb<T, F: for<'a> FnCall<(&'a T, i32), bool>>(
+    opaque: *mut c_void,
+    version_id: c_int,
+) -> bool {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let owner = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque is the struct that the description applies to
+        F::call((unsafe { owner.as_ref() }, version_id))
+    })
+}
+
+/// Build the `field_exists` callback of a [`VMStateField`] from a function
+/// that takes the migrated struct and the version of the stream, and
+/// returns whether the field is migrated.  See
+/// [`vmstate_cond!`](crate::vmstate_cond).
+///
+/// The `_cb` parameter is unused; it only selects the function to call,
+/// which must be a function item or a closure that captures nothing.
+pub const fn vmstate_field_exists<T, F: for<'a> FnCall<(&'a T, i32), bool>>(
+    _cb: &F,
+) -> Option<unsafe extern "C" fn(*mut c_void, c_int) -> bool> {
+    Some(rust_vms_field_exists_cb::<T, F>)
+}
+
+const fn vms_result_to_c(result: Result<(), i32>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => e,
+    }
+}
+
+unsafe extern "C" fn rust_vms_post_load_cb<T, F: for<'a> FnCall<(&'a T, u32), Result<(), i32>>>(
+    opaque: *mut c_void,
+    version_id: c_int,
+) -> c_int {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let owner = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque is the struct that the description applies to
+        vms_result_to_c(F::call((unsafe { owner.as_ref() }, version_id as u32)))
+    })
+}
+
+unsafe extern "C" fn rust_vms_pre_save_cb<T, F: for<'a> FnCall<(&'a T,), Result<(), i32>>>(
+    opaque: *mut c_void,
+) -> c_int {
+    abort_on_panic(|| {
+        assert_bql_locked();
+        let owner = NonNull::new(opaque).unwrap().cast::<T>();
+        // SAFETY: the opaque is the struct that the description applies to
+        vms_result_to_c(F::call((unsafe { owner.as_ref() },)))
+    })
+}
+
+/// Build the `post_load` callback of a [`VMStateDescription`].  The
+/// function is called with the struct and the version of the incoming
+/// stream after all fields were loaded, and can recompute any state that is
+/// not migrated.  Returning `Err` with a negative errno value makes the
+/// load fail.
+///
+/// As for [`vmstate_needed`], `_cb` must be a function item or a closure
+/// that captures nothing.
+pub const fn vmstate_post_load<T, F: for<'a> FnCall<(&'a T, u32), Result<(), i32>>>(
+    _cb: &F,
+) -> Option<unsafe extern "C" fn(*mut c_void, c_int) -> c_int> {
+    Some(rust_vms_post_load_cb::<T, F>)
+}
+
+/// Build the `pre_save` callback of a [`VMStateDescription`].  The
+/// function is called with the struct before its fields are saved;
+/// returning `Err` with a negative errno value makes the migration fail.
+///
+/// As for [`vmstate_needed`], `_cb` must be a function item or a closure
+/// that captures nothing.
+pub const fn vmstate_pre_save<T, F: for<'a> FnCall<(&'a T,), Result<(), i32>>>(
+    _cb: &F,
+) -> Option<unsafe extern "C" fn(*mut c_void) -> c_int> {
+    Some(rust_vms_pre_save_cb::<T, F>)
+}
@@ -95,8 +95,21 @@ fn default() -> Self {
 
 impl_zeroable!(crate::bindings::Property__bindgen_ty_1);
 impl_zeroable!(crate::bindings::Property);
+impl_zeroable!(crate::bindings::PropertyInfo);
 impl_zeroable!(crate::bindings::VMStateFlags);
 impl_zeroable!(crate::bindings::VMStateField);
 impl_zeroable!(crate::bindings::VMStateDescription);
 impl_zeroable!(crate::bindings::MemoryRegionOps__bindgen_ty_1);
 impl_zeroable!(crate::bindings::MemoryRegionOps__bindgen_ty_2);
+impl_zeroable!(crate::bindings::MemoryRegionOps);
+impl_zeroable!(crate::bindings::MemoryListener);
+impl_zeroable!(crate::bindings::NetClientInfo);
+impl_zeroable!(crate::bindings::QEMUTimer);
+impl_zeroable!(crate::bindings::CharBackend);
+impl_zeroable!(crate::bindings::Object);
+impl_zeroable!(crate::bindings::DeviceState);
+impl_zeroable!(crate::bindings::SysBusDevice);
+impl_zeroable!(crate::bindings::AddressSpace);
+impl_zeroable!(crate::bindings::rcu_head);
+impl_zeroable!(crate::bindings::QmpCommandList);
+impl_zeroable!(crate::bindings::SaveVMHandlers);
@@ -3,23 +3,74 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use std::{
-    ffi::CStr,
-    os::raw::c_void,
-    ptr::{addr_of, addr_of_mut},
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int, c_void},
+    panic,
+    ptr::{self, addr_of, addr_of_mut, NonNull},
 };
 
 use qemu_api::{
+    assert_device_layout, bad_offset,
+    bh::BottomHalf,
     bindings::*,
+    block::Drive,
+    bql::{assert_bql_locked, BqlGuard},
     c_str,
-    cell::{self, BqlCell},
-    declare_properties, define_property,
+    c_str::glib_strdup,
+    cell::{self, BqlCell, BqlRefCell},
+    chardev::{CharBackend, CharBackendHandlers, QEMUChrEvent},
+    declare_properties, define_array_property, define_bit_property, define_chardev_property,
+    define_drive_property, define_enum_property, define_link_property, define_masked_property,
+    define_netdev_property, define_property, define_size_property,
+    dma::{address_space_memory, phys_mem_read, phys_mem_write, AddressSpace},
+    ensure,
+    error::bql_error_propagate,
+    error_append_hint, error_setg,
+    fifo::Fifo8,
+    hotplug::{hotplug_handler_class_init, HotplugHandlerImpl},
+    interfaces,
+    irq::InterruptSource,
+    log_guest_error, log_unimp,
+    machine::{current_machine, machine_get_bool, machine_get_uint},
+    memory::{
+        mmio_ops, IOMMUAccessFlags, IOMMUTLBEntry, IOMMUTLBEvent, IommuMemoryRegion,
+        IommuMemoryRegionImpl, MemoryRegion, MmioConfig, MmioOps, RomDeviceOps,
+    },
+    nic::{Nic, NicHandlers},
     prelude::*,
-    qdev::{DeviceClass, DeviceImpl, DeviceState, Property},
-    qom::{ClassInitImpl, ObjectImpl, ParentField},
-    vmstate::VMStateDescription,
+    qdev::{
+        Bus, BusClass, BusState, Clock, Device, DeviceClass, DeviceImpl, DeviceState, Property,
+        PropertyList, ResetChildren, ResetType, ResettablePhasesImpl,
+    },
+    qmp::{FromQmpArgs, QmpArgs, QmpCommands},
+    qom::{
+        self, ClassInitImpl, InterfaceClass, InterfaceType, ObjectBuilder, ObjectClass, ObjectImpl,
+        ParentField,
+    },
+    qom_interface,
+    reset::{register_reset, reset_all, unregister_reset},
+    savevm::{QemuFile, SaveVmImpl, SaveVmRegistration},
+    sysbus::{SysBusDevice, SysBusDeviceImpl},
+    timer::{clock_get_ns, ClockType, QEMUClockType, Timer},
+    user_creatable::{user_creatable_class_init, UserCreatableImpl},
+    vmstate::{vmstate_needed, vmstate_post_load, vmstate_pre_save, Migrate, VMStateDescription},
+    vmstate_array, vmstate_bool, vmstate_bool_as_int, vmstate_buffer, vmstate_buffer_unsafe,
+    vmstate_clock, vmstate_cond, vmstate_fields, vmstate_fifo8, vmstate_struct,
+    vmstate_struct_pointer, vmstate_subsections, vmstate_timer, vmstate_uint32, vmstate_uint64,
+    vmstate_uint8, vmstate_unused, vmstate_varray_uint32,
     zeroable::Zeroable,
 };
 
+// Opening a QEMUFile on a channel is internal to the migration subsystem
+// and is not part of the bindings; the tests need it to save and load
+// vmstate into a buffer.  See migration/qemu-file.h.
+extern "C" {
+    fn qemu_file_new_input(ioc: *mut QIOChannel) -> *mut QEMUFile;
+    fn qemu_file_new_output(ioc: *mut QIOChannel) -> *mut QEMUFile;
+    fn qemu_fclose(f: *mut QEMUFile) -> c_int;
+    fn qemu_fflush(f: *mut QEMUFile) -> c_int;
+}
+
 // Test that macros can compile.
 pub static VMSTATE: VMStateDescription = VMStateDescription {
     name: c_str!("name").as_ptr(),
@@ -27,126 +78,5103 @@
     ..Zeroable::ZERO
 };
 
-#[derive(qemu_api_macros::offsets)]
-#[repr(C)]
-#[derive(qemu_api_macros::Object)]
-pub struct DummyState {
-    parent: ParentField<DeviceState>,
-    migrate_clock: bool,
-}
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyRegs {
+    ctrl: u32,
+    count: u64,
+    flags: u8,
+}
+
+pub static VMSTATE_REGS: VMStateDescription = VMStateDescription {
+    name: c_str!("regs").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyRegs, ctrl),
+        vmstate_uint64!(DummyRegs, count),
+        vmstate_uint8!(DummyRegs, flags),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyVersioned {
+    ctrl: u32,
+    extra: u32,
+}
+
+/// The first version of the migration format only had `ctrl`.
+pub static VMSTATE_VERSIONED_V1: VMStateDescription = VMStateDescription {
+    name: c_str!("versioned").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyVersioned, ctrl),
+    },
+    ..Zeroable::ZERO
+};
+
+pub static VMSTATE_VERSIONED_V2: VMStateDescription = VMStateDescription {
+    name: c_str!("versioned").as_ptr(),
+    version_id: 2,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyVersioned, ctrl),
+        vmstate_uint32!(DummyVersioned, extra, 2),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyFifo {
+    head: u32,
+    tail: u32,
+}
+
+pub static VMSTATE_FIFO: VMStateDescription = VMStateDescription {
+    name: c_str!("fifo").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyFifo, head),
+        vmstate_uint32!(DummyFifo, tail),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyParent {
+    ctrl: u32,
+    fifo: DummyFifo,
+}
+
+pub static VMSTATE_PARENT: VMStateDescription = VMStateDescription {
+    name: c_str!("parent").as_ptr(),
+    version_id: 2,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyParent, ctrl),
+        vmstate_struct!(DummyParent, fifo, addr_of!(VMSTATE_FIFO), version = 2),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyBank {
+    regs: [u32; 8],
+}
+
+pub static VMSTATE_BANK: VMStateDescription = VMStateDescription {
+    name: c_str!("bank").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_array!(DummyBank, regs, 8, u32),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+pub struct DummyVarray {
+    len: u32,
+    buf: *mut u32,
+}
+
+pub static VMSTATE_VARRAY: VMStateDescription = VMStateDescription {
+    name: c_str!("varray").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyVarray, len),
+        vmstate_varray_uint32!(DummyVarray, buf, len),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyOptional {
+    ctrl: u32,
+    has_extra: bool,
+    extra: u32,
+}
+
+#[allow(clippy::missing_const_for_fn)]
+fn dummy_extra_needed(s: &DummyOptional) -> bool {
+    s.has_extra
+}
+
+// The description as it was before the "extra" subsection was added.
+pub static VMSTATE_OPTIONAL_OLD: VMStateDescription = VMStateDescription {
+    name: c_str!("optional").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyOptional, ctrl),
+        vmstate_bool!(DummyOptional, has_extra),
+    },
+    ..Zeroable::ZERO
+};
+
+pub static VMSTATE_OPTIONAL: VMStateDescription = VMStateDescription {
+    name: c_str!("optional").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyOptional, ctrl),
+        vmstate_bool!(DummyOptional, has_extra),
+    },
+    subsections: vmstate_subsections! {
+        VMStateDescription {
+            name: c_str!("optional/extra").as_ptr(),
+            version_id: 1,
+            minimum_version_id: 1,
+            needed: vmstate_needed(&dummy_extra_needed),
+            fields: vmstate_fields! {
+                vmstate_uint32!(DummyOptional, extra),
+            },
+            ..Zeroable::ZERO
+        }
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyConditional {
+    // configuration, not migrated
+    fifo_mode: bool,
+    ctrl: u32,
+    fifo: u32,
+}
+
+impl DummyConditional {
+    const fn has_fifo(&self, version_id: i32) -> bool {
+        assert!(version_id == 1);
+        self.fifo_mode
+    }
+}
+
+pub static VMSTATE_CONDITIONAL: VMStateDescription = VMStateDescription {
+    name: c_str!("conditional").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyConditional, ctrl),
+        vmstate_cond!(vmstate_uint32!(DummyConditional, fifo), DummyConditional::has_fifo),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct DummyHooks {
+    value: u32,
+    // not migrated, rebuilt by post_load
+    doubled: std::cell::Cell<u32>,
+}
+
+impl DummyHooks {
+    fn post_load(&self, version_id: u32) -> Result<(), i32> {
+        assert_eq!(version_id, 1);
+        if self.value == u32::MAX {
+            return Err(-22);
+        }
+        self.doubled.set(self.value.wrapping_mul(2));
+        Ok(())
+    }
+
+    const fn pre_save(&self) -> Result<(), i32> {
+        if self.value == 0 {
+            Err(-22)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub static VMSTATE_HOOKS: VMStateDescription = VMStateDescription {
+    name: c_str!("hooks").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    post_load: vmstate_post_load(&DummyHooks::post_load),
+    pre_save: vmstate_pre_save(&DummyHooks::pre_save),
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyHooks, value),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(qemu_api_macros::Migrate)]
+#[migrate(name = "migrate", minimum_version = 1)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyMigrate {
+    ctrl: u32,
+    fifo: [u16; 4],
+    #[migrate(skip)]
+    scratch: u64,
+    #[migrate(version = 2)]
+    enabled: bool,
+}
+
+// What #[derive(Migrate)] should produce for DummyMigrate.
+pub static VMSTATE_MIGRATE: VMStateDescription = VMStateDescription {
+    name: c_str!("migrate").as_ptr(),
+    version_id: 2,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyMigrate, ctrl),
+        vmstate_array!(DummyMigrate, fifo, 4, u16),
+        vmstate_bool!(DummyMigrate, enabled, 2),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyFlags {
+    narrow: bool,
+    wide: bool,
+}
+
+pub static VMSTATE_FLAGS: VMStateDescription = VMStateDescription {
+    name: c_str!("flags").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_bool!(DummyFlags, narrow),
+        vmstate_bool_as_int!(DummyFlags, wide),
+    },
+    ..Zeroable::ZERO
+};
+
+/// An older version of `DummyTrimmed`, which had a `legacy` field.
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyUntrimmed {
+    ctrl: u32,
+    legacy: u32,
+    flags: u8,
+}
+
+pub static VMSTATE_UNTRIMMED: VMStateDescription = VMStateDescription {
+    name: c_str!("trimmed").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyUntrimmed, ctrl),
+        vmstate_uint32!(DummyUntrimmed, legacy),
+        vmstate_uint8!(DummyUntrimmed, flags),
+    },
+    ..Zeroable::ZERO
+};
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyTrimmed {
+    ctrl: u32,
+    flags: u8,
+}
+
+pub static VMSTATE_TRIMMED: VMStateDescription = VMStateDescription {
+    name: c_str!("trimmed").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyTrimmed, ctrl),
+        vmstate_unused!(std::mem::size_of::<u32>()),
+        vmstate_uint8!(DummyTrimmed, flags),
+    },
+    ..Zeroable::ZERO
+};
+
+/// The same state as `DummyFlags`, as a C device would have stored it.
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DummyLegacyFlags {
+    narrow: bool,
+    wide: u32,
+}
+
+pub static VMSTATE_LEGACY_FLAGS: VMStateDescription = VMStateDescription {
+    name: c_str!("flags").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_bool!(DummyLegacyFlags, narrow),
+        vmstate_uint32!(DummyLegacyFlags, wide),
+    },
+    ..Zeroable::ZERO
+};
+
+/// Define a QOM type for the tests: a struct whose first field, `parent`,
+/// is of the first type after the colon, its `IsA` implementations for
+/// that type and for the other ancestors listed after it, and its
+/// `ObjectType` and `ObjectImpl` implementations.  The class struct is the
+/// parent's unless `type Class` is given, and the items in `impl
+/// ObjectImpl`, if any, are added after `ParentType`:
+///
+/// ```ignore
+/// dummy_type! {
+///     pub struct DummyFooState: DeviceState, Object = "dummy_foo" {
+///         regs: BqlCell<u32>,
+///     }
+///     impl ObjectImpl {
+///         const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+///     }
+/// }
+/// ```
+macro_rules! dummy_type {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident: $parent:ident $(, $ancestor:ident)* = $type_name:literal {
+            $($field:tt)*
+        }
+        $(type Class = $class:ty;)?
+        $(impl ObjectImpl { $($item:tt)* })?
+    ) => {
+        $(#[$attr])*
+        #[derive(qemu_api_macros::offsets)]
+        #[repr(C)]
+        #[derive(qemu_api_macros::Object)]
+        pub struct $name {
+            parent: ::qemu_api::qom::ParentField<$parent>,
+            $($field)*
+        }
+
+        ::qemu_api::qom_isa!($name: $parent $(, $ancestor)*);
+
+        unsafe impl ::qemu_api::qom::ObjectType for $name {
+            type Class = dummy_type!(@class $parent $(, $class)?);
+            const TYPE_NAME: &'static ::std::ffi::CStr = ::qemu_api::c_str!($type_name);
+        }
+
+        impl ::qemu_api::qom::ObjectImpl for $name {
+            type ParentType = $parent;
+            $($($item)*)?
+        }
+    };
+    (@class $parent:ident) => {
+        <$parent as ::qemu_api::qom::ObjectType>::Class
+    };
+    (@class $parent:ident, $class:ty) => {
+        $class
+    };
+}
+
+dummy_type! {
+    pub struct DummyState: DeviceState, Object = "dummy" {
+        migrate_clock: bool,
+        path: *mut c_char,
+        level: u32,
+        bias: i32,
+        limit: u64,
+        features: u32,
+        caps: u32,
+        mode: i32,
+        counter: BqlCell<u32>,
+        child: *mut Object,
+        chr: CharBackend,
+    }
+    type Class = DummyClass;
+}
+
+assert_device_layout!(DummyState, parent);
+
+/// Same discriminants as the C `OnOffAuto`, whose names are in
+/// `OnOffAuto_lookup`.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DummyMode {
+    Auto,
+    On,
+    Off,
+}
+
+pub struct DummyClass {
+    parent_class: <DeviceState as ObjectType>::Class,
+}
+
+declare_properties! {
+    DUMMY_PROPERTIES,
+        define_property!(
+            c_str!("migrate-clk"),
+            DummyState,
+            migrate_clock,
+            PropKind::Bool,
+            bool
+        ),
+        define_property!(
+            c_str!("path"),
+            DummyState,
+            path,
+            PropKind::String,
+            String,
+            default = c_str!("/dev/null")
+        ),
+        define_property!(
+            c_str!("level"),
+            DummyState,
+            level,
+            u32,
+            min = 1,
+            max = 15,
+            default = 8,
+            desc = "interrupt priority level (1-15)"
+        ),
+        define_property!(
+            c_str!("bias"),
+            DummyState,
+            bias,
+            PropKind::I32,
+            i32,
+            default = -1
+        ),
+        define_property!(
+            c_str!("limit"),
+            DummyState,
+            limit,
+            PropKind::U64,
+            u64,
+            default = u64::MAX
+        ),
+        define_bit_property!(c_str!("feature-a"), DummyState, features, 0, default = true),
+        define_bit_property!(c_str!("feature-b"), DummyState, features, 5, default = false),
+        define_masked_property!(
+            c_str!("caps"),
+            DummyState,
+            caps,
+            u32,
+            mask = 0xff00_00ff,
+            default = 0x0100_0001
+        ),
+        define_enum_property!(
+            c_str!("mode"),
+            DummyState,
+            mode,
+            unsafe { addr_of!(OnOffAuto_lookup) },
+            default = DummyMode::On
+        ),
+        define_link_property!(c_str!("child"), DummyState, child, DummyChildState),
+        define_chardev_property!(c_str!("chardev"), DummyState, chr),
+}
+
+declare_properties!(EMPTY_PROPERTIES);
+declare_properties!(EMPTY_PROPERTIES_COMMA,);
+
+impl DeviceImpl for DummyState {
+    fn properties() -> &'static [Property] {
+        &DUMMY_PROPERTIES
+    }
+    fn vmsd() -> Option<&'static VMStateDescription> {
+        Some(&VMSTATE)
+    }
+}
+
+impl ResettablePhasesImpl for DummyState {}
+
+// `impl<T> ClassInitImpl<DummyClass> for T` doesn't work since it violates
+// orphan rule.
+impl ClassInitImpl<DummyClass> for DummyState {
+    fn class_init(klass: &mut DummyClass) {
+        <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+    }
+}
+
+dummy_type! {
+    pub struct DummyChildState: DummyState, DeviceState, Object = "dummy_child" {}
+    type Class = DummyChildClass;
+}
+
+pub struct DummyChildClass {
+    parent_class: <DummyState as ObjectType>::Class,
+}
+
+impl DeviceImpl for DummyChildState {}
+
+impl ResettablePhasesImpl for DummyChildState {}
+
+impl ClassInitImpl<DummyClass> for DummyChildState {
+    fn class_init(klass: &mut DummyClass) {
+        <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+    }
+}
+
+impl ClassInitImpl<DummyChildClass> for DummyChildState {
+    fn class_init(klass: &mut DummyChildClass) {
+        <Self as ClassInitImpl<DummyClass>>::class_init(&mut klass.parent_class);
+    }
+}
+
+dummy_type! {
+    pub struct DummyHotplugState: DeviceState, Object = "dummy-hotplug" {
+        plugged: BqlCell<*const DeviceState>,
+        unplug_requested: BqlCell<*const DeviceState>,
+    }
+    type Class = DummyHotplugClass;
+    impl ObjectImpl {
+        const INTERFACES: &'static [InterfaceInfo] = interfaces![TYPE_HOTPLUG_HANDLER];
+    }
+}
+
+pub struct DummyHotplugClass {
+    parent_class: <DeviceState as ObjectType>::Class,
+}
+
+impl DeviceImpl for DummyHotplugState {}
+
+impl ResettablePhasesImpl for DummyHotplugState {}
+
+impl DummyHotplugState {
+    fn plug(&self, dev: &DeviceState) -> qemu_api::Result<()> {
+        self.plugged.set(dev);
+        Ok(())
+    }
+
+    fn unplug_request(&self, dev: &DeviceState) -> qemu_api::Result<()> {
+        self.unplug_requested.set(dev);
+        Ok(())
+    }
+}
+
+impl HotplugHandlerImpl for DummyHotplugState {
+    const PLUG: Option<fn(&Self, &DeviceState) -> qemu_api::Result<()>> = Some(Self::plug);
+    const UNPLUG_REQUEST: Option<fn(&Self, &DeviceState) -> qemu_api::Result<()>> =
+        Some(Self::unplug_request);
+}
+
+impl ClassInitImpl<DummyHotplugClass> for DummyHotplugState {
+    fn class_init(klass: &mut DummyHotplugClass) {
+        <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+        hotplug_handler_class_init::<Self>(&mut klass.parent_class.parent_class);
+    }
+}
+
+dummy_type! {
+    pub struct DummyBusState: BusState, Object = "dummy_bus" {}
+    type Class = DummyBusClass;
+}
+
+pub struct DummyBusClass {
+    parent_class: BusClass,
+}
+
+impl ClassInitImpl<DummyBusClass> for DummyBusState {
+    fn class_init(klass: &mut DummyBusClass) {
+        <Self as ClassInitImpl<ObjectClass>>::class_init(&mut klass.parent_class.parent_class);
+    }
+}
+
+dummy_type! {
+    pub struct DummyControllerState: DeviceState, Object = "dummy_controller" {
+        bus: Option<Bus>,
+    }
+    impl ObjectImpl {
+        const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+    }
+}
+
+impl DeviceImpl for DummyControllerState {}
+
+impl ResettablePhasesImpl for DummyControllerState {}
+
+impl DummyControllerState {
+    unsafe fn init(&mut self) {
+        let bus = self.new_bus(DummyBusState::TYPE_NAME, Some(c_str!("dummy-bus")));
+        self.bus = Some(bus);
+    }
+}
+
+dummy_type! {
+    pub struct DummyArrayState: DeviceState, Object = "dummy_array" {
+        num_irqs: u32,
+        irqs: *mut u32,
+    }
+}
+
+declare_properties! {
+    DUMMY_ARRAY_PROPERTIES,
+        define_array_property!(
+            c_str!("irqs"),
+            DummyArrayState,
+            num_irqs,
+            irqs,
+            PropKind::U32
+        ),
+}
+
+impl DeviceImpl for DummyArrayState {
+    fn properties() -> &'static [Property] {
+        &DUMMY_ARRAY_PROPERTIES
+    }
+}
+
+impl ResettablePhasesImpl for DummyArrayState {}
+
+impl DummyArrayState {
+    fn irqs(&self) -> Vec<u32> {
+        if self.irqs.is_null() {
+            Vec::new()
+        } else {
+            // SAFETY: qdev_prop_array allocated num_irqs elements
+            unsafe { std::slice::from_raw_parts(self.irqs, self.num_irqs as usize) }.to_vec()
+        }
+    }
+}
+
+impl Drop for DummyArrayState {
+    fn drop(&mut self) {
+        unsafe {
+            g_free(self.irqs.cast::<c_void>());
+        }
+    }
+}
+
+dummy_type! {
+    pub struct DummyPortsState: DeviceState, Object = "dummy_ports" {
+        ports: [u32; 4],
+    }
+}
+
+impl DeviceImpl for DummyPortsState {
+    /// One "portN" property for each element of `ports`, defaulting to N.
+    fn properties() -> &'static [Property] {
+        let props = (0..4)
+            .map(|i| {
+                let name = CString::new(format!("port{i}")).unwrap();
+                Property {
+                    name: Box::leak(name.into_boxed_c_str()).as_ptr(),
+                    info: PropKind::U32.info(),
+                    offset: (qemu_api::offset_of!(DummyPortsState, ports)
+                        + i * std::mem::size_of::<u32>()) as isize,
+                    set_default: true,
+                    defval: Property__bindgen_ty_1 { u: i as u64 },
+                    ..Zeroable::ZERO
+                }
+            })
+            .collect();
+        PropertyList::from_vec(props).as_slice()
+    }
+}
+
+impl ResettablePhasesImpl for DummyPortsState {}
+
+pub const TYPE_RUST_DUMMY: &CStr = c_str!("rust-dummy");
+
+#[repr(C)]
+#[derive(qemu_api_macros::Object)]
+pub struct RustDummyObject {
+    parent: ParentField<Object>,
+}
+
+qom_isa!(RustDummyObject: Object);
+
+unsafe impl ObjectType for RustDummyObject {
+    type Class = ObjectClass;
+    const TYPE_NAME: &'static CStr = TYPE_RUST_DUMMY;
+}
+
+impl ObjectImpl for RustDummyObject {
+    type ParentType = Object;
+}
+
+dummy_type! {
+    pub struct DummyClassPropObject: Object = "dummy_class_prop" {
+        enabled: BqlCell<bool>,
+        label: BqlRefCell<String>,
+    }
+    type Class = DummyClassPropClass;
+}
+
+pub struct DummyClassPropClass {
+    parent_class: ObjectClass,
+}
+
+/// The number of `DummyClassPropObject`s that were finalized.
+static CLASS_PROP_FINALIZED: BqlCell<u32> = BqlCell::new(0);
+
+impl Drop for DummyClassPropObject {
+    fn drop(&mut self) {
+        CLASS_PROP_FINALIZED.set(CLASS_PROP_FINALIZED.get() + 1);
+    }
+}
+
+impl DummyClassPropObject {
+    fn enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn set_enabled(&self, value: bool) -> qemu_api::Result<()> {
+        self.enabled.set(value);
+        Ok(())
+    }
+
+    fn label(&self) -> String {
+        self.label.borrow().clone()
+    }
+
+    fn set_label(&self, value: &str) -> qemu_api::Result<()> {
+        if value.is_empty() {
+            return Err(qemu_api::Error::new("empty label"));
+        }
+        *self.label.borrow_mut() = value.to_owned();
+        Ok(())
+    }
+}
+
+impl ClassInitImpl<DummyClassPropClass> for DummyClassPropObject {
+    fn class_init(klass: &mut DummyClassPropClass) {
+        <Self as ClassInitImpl<ObjectClass>>::class_init(&mut klass.parent_class);
+        qom::class_add_bool(
+            &mut klass.parent_class,
+            c_str!("enabled"),
+            &Self::enabled,
+            &Self::set_enabled,
+        );
+        qom::class_add_str(
+            &mut klass.parent_class,
+            c_str!("label"),
+            &Self::label,
+            &Self::set_label,
+        );
+    }
+}
+
+dummy_type! {
+    /// An object that can be created with `-object`, and that needs its `path`
+    /// property to be set.
+    pub struct DummyUserCreatableObject: Object = "dummy-user-creatable" {
+        path: BqlRefCell<String>,
+        completed: bool,
+    }
+    type Class = DummyUserCreatableClass;
+    impl ObjectImpl {
+        const INTERFACES: &'static [InterfaceInfo] = interfaces![TYPE_USER_CREATABLE];
+        const INSTANCE_DEFAULT: Option<fn() -> Self> = Some(|| DummyUserCreatableObject {
+            parent: ParentField::default(),
+            path: BqlRefCell::new(String::new()),
+            completed: false,
+        });
+    }
+}
+
+pub struct DummyUserCreatableClass {
+    parent_class: ObjectClass,
+}
+
+impl DummyUserCreatableObject {
+    fn path(&self) -> String {
+        self.path.borrow().clone()
+    }
+
+    fn set_path(&self, value: &str) -> qemu_api::Result<()> {
+        *self.path.borrow_mut() = value.to_owned();
+        Ok(())
+    }
+}
+
+impl UserCreatableImpl for DummyUserCreatableObject {
+    fn complete(&mut self) -> qemu_api::Result<()> {
+        ensure!(!self.path.borrow().is_empty(), "'path' property not set");
+        self.completed = true;
+        Ok(())
+    }
+}
+
+impl ClassInitImpl<DummyUserCreatableClass> for DummyUserCreatableObject {
+    fn class_init(klass: &mut DummyUserCreatableClass) {
+        <Self as ClassInitImpl<ObjectClass>>::class_init(&mut klass.parent_class);
+        qom::class_add_str(
+            &mut klass.parent_class,
+            c_str!("path"),
+            &Self::path,
+            &Self::set_path,
+        );
+        user_creatable_class_init::<Self>(&mut klass.parent_class);
+    }
+}
+
+dummy_type! {
+    pub struct DummyResetState: DeviceState, Object = "dummy_reset" {
+        enter_count: BqlCell<u32>,
+        hold_count: BqlCell<u32>,
+        exit_count: BqlCell<u32>,
+    }
+}
+
+impl DeviceImpl for DummyResetState {}
+
+impl ResettablePhasesImpl for DummyResetState {
+    const ENTER: Option<fn(&Self, ResetType)> = Some(Self::reset_enter);
+    const HOLD: Option<fn(&Self, ResetType)> = Some(Self::reset_hold);
+    const EXIT: Option<fn(&Self, ResetType)> = Some(Self::reset_exit);
+}
+
+impl DummyResetState {
+    fn reset_enter(&self, _type: ResetType) {
+        self.enter_count.set(self.enter_count.get() + 1);
+    }
+
+    fn reset_hold(&self, _type: ResetType) {
+        self.hold_count.set(self.hold_count.get() + 1);
+    }
+
+    fn reset_exit(&self, _type: ResetType) {
+        self.exit_count.set(self.exit_count.get() + 1);
+    }
+}
+
+/// The reset phases that ran, and the `id` of the device they ran on.
+static RESET_LOG: BqlRefCell<Vec<(&str, u32)>> = BqlRefCell::new(Vec::new());
+
+fn log_reset(phase: &'static str, id: u32) {
+    RESET_LOG.borrow_mut().push((phase, id));
+}
+
+dummy_type! {
+    pub struct DummyResetChildState: DeviceState, Object = "dummy_reset_child" {
+        id: BqlCell<u32>,
+    }
+}
+
+impl DeviceImpl for DummyResetChildState {}
+
+impl ResettablePhasesImpl for DummyResetChildState {
+    const ENTER: Option<fn(&Self, ResetType)> = Some(|s, _| log_reset("enter", s.id.get()));
+    const HOLD: Option<fn(&Self, ResetType)> = Some(|s, _| log_reset("hold", s.id.get()));
+    const EXIT: Option<fn(&Self, ResetType)> = Some(|s, _| log_reset("exit", s.id.get()));
+}
+
+dummy_type! {
+    /// A container whose two children are reset together with it.
+    pub struct DummyResetParentState: DeviceState, Object = "dummy_reset_parent" {
+        children: BqlRefCell<Vec<Device<DummyResetChildState>>>,
+    }
+}
+
+impl DeviceImpl for DummyResetParentState {}
+
+impl ResettablePhasesImpl for DummyResetParentState {
+    const ENTER: Option<fn(&Self, ResetType)> = Some(|_, _| log_reset("enter", 0));
+    const HOLD: Option<fn(&Self, ResetType)> = Some(|_, _| log_reset("hold", 0));
+    const EXIT: Option<fn(&Self, ResetType)> = Some(|_, _| log_reset("exit", 0));
+    const CHILDREN: Option<fn(&Self, &mut ResetChildren)> = Some(Self::reset_children);
+}
+
+impl DummyResetParentState {
+    fn reset_children(&self, children: &mut ResetChildren) {
+        for child in self.children.borrow().iter() {
+            children.register(&**child);
+        }
+    }
+}
+
+dummy_type! {
+    /// A node of a tree of devices with one register each, which goes back to
+    /// `RESET_VALUE` on reset.
+    pub struct DummyResetTreeState: DeviceState, Object = "dummy_reset_tree" {
+        reg: BqlCell<u32>,
+        children: BqlRefCell<Vec<Device<DummyResetTreeState>>>,
+    }
+}
+
+impl DeviceImpl for DummyResetTreeState {}
+
+impl ResettablePhasesImpl for DummyResetTreeState {
+    const HOLD: Option<fn(&Self, ResetType)> = Some(Self::reset_hold);
+    const CHILDREN: Option<fn(&Self, &mut ResetChildren)> = Some(Self::reset_children);
+}
+
+impl DummyResetTreeState {
+    const RESET_VALUE: u32 = 0x5a5a;
+
+    fn reset_hold(&self, _type: ResetType) {
+        self.reg.set(Self::RESET_VALUE);
+    }
+
+    fn reset_children(&self, children: &mut ResetChildren) {
+        for child in self.children.borrow().iter() {
+            children.register(&**child);
+        }
+    }
+
+    /// Call `f` on `self` and on all the devices below it.
+    fn for_each(&self, f: &mut impl FnMut(&Self)) {
+        f(self);
+        for child in self.children.borrow().iter() {
+            child.for_each(f);
+        }
+    }
+}
+
+dummy_type! {
+    pub struct DummyFailState: DeviceState, Object = "dummy_fail" {}
+}
+
+impl DeviceImpl for DummyFailState {
+    const REALIZE: Option<fn(&Self) -> qemu_api::Result<()>> = Some(Self::realize);
+}
+
+impl ResettablePhasesImpl for DummyFailState {}
+
+impl DummyFailState {
+    fn realize(&self) -> qemu_api::Result<()> {
+        Err(qemu_api::Error::new("dummy realize failed"))
+    }
+}
+
+dummy_type! {
+    /// A device that only provides properties and a `realize` method, and
+    /// leaves everything else in `DeviceImpl` to the defaults.
+    pub struct DummyRealizeOnlyState: DeviceState, Object = "dummy_realize_only" {
+        value: u32,
+    }
+}
+
+declare_properties! {
+    DUMMY_REALIZE_ONLY_PROPERTIES,
+        define_property!(
+            c_str!("value"),
+            DummyRealizeOnlyState,
+            value,
+            PropKind::U32,
+            u32,
+            default = 7
+        ),
+}
+
+impl DeviceImpl for DummyRealizeOnlyState {
+    fn properties() -> &'static [Property] {
+        &DUMMY_REALIZE_ONLY_PROPERTIES
+    }
+    const REALIZE: Option<fn(&Self) -> qemu_api::Result<()>> = Some(Self::realize);
+}
+
+impl ResettablePhasesImpl for DummyRealizeOnlyState {}
+
+impl DummyRealizeOnlyState {
+    fn realize(&self) -> qemu_api::Result<()> {
+        if self.value == 0 {
+            return Err(qemu_api::Error::new("value must not be zero"));
+        }
+        Ok(())
+    }
+}
+
+pub static VMSTATE_VMSD_ONLY: VMStateDescription = VMStateDescription {
+    name: c_str!("dummy_vmsd_only").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyVmsdOnlyState, value),
+    },
+    ..Zeroable::ZERO
+};
+
+dummy_type! {
+    /// A device that only provides a migration format.
+    pub struct DummyVmsdOnlyState: DeviceState, Object = "dummy_vmsd_only" {
+        value: u32,
+    }
+}
+
+impl DeviceImpl for DummyVmsdOnlyState {
+    fn vmsd() -> Option<&'static VMStateDescription> {
+        Some(&VMSTATE_VMSD_ONLY)
+    }
+}
+
+impl ResettablePhasesImpl for DummyVmsdOnlyState {}
+
+dummy_type! {
+    /// A device whose properties are checked against each other when it is
+    /// realized.
+    pub struct DummyBlockSizeState: DeviceState, Object = "dummy_block_size" {
+        size: u32,
+        block_size: u32,
+    }
+}
+
+declare_properties! {
+    DUMMY_BLOCK_SIZE_PROPERTIES,
+        define_property!(
+            c_str!("size"),
+            DummyBlockSizeState,
+            size,
+            PropKind::U32,
+            u32,
+            default = 4096
+        ),
+        define_property!(
+            c_str!("block-size"),
+            DummyBlockSizeState,
+            block_size,
+            PropKind::U32,
+            u32,
+            default = 512
+        ),
+}
+
+impl DeviceImpl for DummyBlockSizeState {
+    fn properties() -> &'static [Property] {
+        &DUMMY_BLOCK_SIZE_PROPERTIES
+    }
+    const REALIZE: Option<fn(&Self) -> qemu_api::Result<()>> = Some(Self::realize);
+}
+
+impl ResettablePhasesImpl for DummyBlockSizeState {}
+
+impl DummyBlockSizeState {
+    fn realize(&self) -> qemu_api::Result<()> {
+        ensure!(
+            self.size.is_power_of_two(),
+            "size {} is not a power of two",
+            self.size
+        );
+        ensure!(
+            self.size.checked_rem(self.block_size) == Some(0),
+            "size {} is not a multiple of block-size {}",
+            self.size,
+            self.block_size
+        );
+        Ok(())
+    }
+}
+
+dummy_type! {
+    /// A device with a memory size property.
+    pub struct DummyMemSizeState: DeviceState, Object = "dummy_mem_size" {
+        mem_size: u64,
+        cache_size: u64,
+    }
+}
+
+declare_properties! {
+    DUMMY_MEM_SIZE_PROPERTIES,
+        define_size_property!(
+            c_str!("mem-size"),
+            DummyMemSizeState,
+            mem_size,
+            default = 64 * 1024
+        ),
+        define_size_property!(c_str!("cache-size"), DummyMemSizeState, cache_size),
+}
+
+impl DeviceImpl for DummyMemSizeState {
+    fn properties() -> &'static [Property] {
+        &DUMMY_MEM_SIZE_PROPERTIES
+    }
+}
+
+impl ResettablePhasesImpl for DummyMemSizeState {}
+
+/// Number of buffers allocated by `DummyUnrealizeState` that are alive.
+static DUMMY_BUFFERS: BqlCell<u32> = BqlCell::new(0);
+
+dummy_type! {
+    /// A device that allocates a buffer when it is realized, and frees it
+    /// when it is unrealized.
+    pub struct DummyUnrealizeState: DeviceState, Object = "dummy_unrealize" {
+        buf: BqlRefCell<Option<Vec<u8>>>,
+    }
+}
+
+impl DeviceImpl for DummyUnrealizeState {
+    const REALIZE: Option<fn(&Self) -> qemu_api::Result<()>> = Some(Self::realize);
+    const UNREALIZE: Option<fn(&Self)> = Some(Self::unrealize);
+}
+
+impl ResettablePhasesImpl for DummyUnrealizeState {}
+
+impl DummyUnrealizeState {
+    fn realize(&self) -> qemu_api::Result<()> {
+        *self.buf.borrow_mut() = Some(vec![0; 16]);
+        DUMMY_BUFFERS.set(DUMMY_BUFFERS.get() + 1);
+        Ok(())
+    }
+
+    fn unrealize(&self) {
+        if self.buf.borrow_mut().take().is_some() {
+            DUMMY_BUFFERS.set(DUMMY_BUFFERS.get() - 1);
+        }
+    }
+}
+
+dummy_type! {
+    /// A subclass of `DummyFailState` whose realize succeeds, because it
+    /// overrides the parent's `realize` method.
+    pub struct DummyOverrideState: DummyFailState, DeviceState, Object = "dummy_override" {
+        realized: BqlCell<bool>,
+    }
+    type Class = DummyOverrideClass;
+}
+
+pub struct DummyOverrideClass {
+    parent_class: <DummyFailState as ObjectType>::Class,
+}
+
+impl DeviceImpl for DummyOverrideState {}
+
+impl ResettablePhasesImpl for DummyOverrideState {}
+
+unsafe extern "C" fn dummy_override_realize(dev: *mut DeviceState, _errp: *mut *mut Error) {
+    let state = unsafe { &*dev.cast::<DummyOverrideState>() };
+    state.realized.set(true);
+}
+
+impl ClassInitImpl<DummyOverrideClass> for DummyOverrideState {
+    fn class_init(klass: &mut DummyOverrideClass) {
+        <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+        qom::class_override::<DeviceState>(&mut klass.parent_class.parent_class, |dc| {
+            dc.realize = Some(dummy_override_realize);
+        });
+    }
+}
+
+dummy_type! {
+    pub struct DummyDiskState: DeviceState, Object = "dummy_disk" {
+        drive: Drive,
+    }
+}
+
+declare_properties! {
+    DUMMY_DISK_PROPERTIES,
+        define_drive_property!(c_str!("drive"), DummyDiskState, drive),
+}
+
+impl DeviceImpl for DummyDiskState {
+    fn properties() -> &'static [Property] {
+        &DUMMY_DISK_PROPERTIES
+    }
+}
+
+impl ResettablePhasesImpl for DummyDiskState {}
+
+dummy_type! {
+    pub struct DummyNicState: DeviceState, Object = "dummy_nic" {
+        nic: Nic,
+        received: BqlCell<usize>,
+    }
+}
+
+declare_properties! {
+    DUMMY_NIC_PROPERTIES,
+        define_netdev_property!(c_str!("netdev"), DummyNicState, nic),
+}
+
+impl DeviceImpl for DummyNicState {
+    fn properties() -> &'static [Property] {
+        &DUMMY_NIC_PROPERTIES
+    }
+}
+
+impl ResettablePhasesImpl for DummyNicState {}
+
+impl NicHandlers for DummyNicState {
+    fn receive(&self, frame: &[u8]) -> usize {
+        self.received.set(self.received.get() + 1);
+        frame.len()
+    }
+}
+
+/// A type whose hand-written `TypeInfo` does not account for its fields.
+/// It is not derived with `#[derive(Object)]`, so that it is only
+/// registered by `test_type_register_size`.
+#[repr(C)]
+pub struct DummyBadSizeState {
+    parent: ParentField<DeviceState>,
+    regs: [u64; 4],
+}
+
+qom_isa!(DummyBadSizeState: Object, DeviceState);
+
+unsafe impl ObjectType for DummyBadSizeState {
+    type Class = DeviceClass;
+    const TYPE_NAME: &'static CStr = c_str!("dummy_bad_size");
+}
+
+impl ObjectImpl for DummyBadSizeState {
+    type ParentType = DeviceState;
+    const TYPE_INFO: TypeInfo = TypeInfo {
+        name: Self::TYPE_NAME.as_ptr(),
+        parent: DeviceState::TYPE_NAME.as_ptr(),
+        instance_size: core::mem::size_of::<DeviceState>(),
+        instance_align: core::mem::align_of::<Self>(),
+        instance_init: None,
+        instance_post_init: None,
+        instance_finalize: None,
+        abstract_: false,
+        class_size: core::mem::size_of::<DeviceClass>(),
+        class_init: None,
+        class_base_init: None,
+        class_data: ptr::null_mut(),
+        interfaces: ptr::null_mut(),
+    };
+}
+
+impl DeviceImpl for DummyBadSizeState {}
+
+impl ResettablePhasesImpl for DummyBadSizeState {}
+
+#[repr(C)]
+pub struct DummyInterfaceClass {
+    parent_class: InterfaceClass,
+    get_id: Option<fn(&Object) -> u32>,
+}
+
+qom_interface!(pub DummyInterface: DummyInterfaceClass = c_str!("dummy-interface"));
+
+dummy_type! {
+    /// A device that implements `DummyInterface`.
+    pub struct DummyIfaceState: DeviceState, Object = "dummy_iface" {}
+    type Class = DummyIfaceClass;
+    impl ObjectImpl {
+        const INTERFACES: &'static [InterfaceInfo] = interfaces![DummyInterface::TYPE_NAME];
+    }
+}
+
+pub struct DummyIfaceClass {
+    parent_class: DeviceClass,
+}
+
+impl DeviceImpl for DummyIfaceState {}
+
+impl ResettablePhasesImpl for DummyIfaceState {}
+
+impl ClassInitImpl<DummyIfaceClass> for DummyIfaceState {
+    fn class_init(klass: &mut DummyIfaceClass) {
+        <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+        qom::class_override::<DummyInterface>(&mut klass.parent_class.parent_class, |ic| {
+            ic.get_id = Some(|_| 42);
+        });
+    }
+}
+
+dummy_type! {
+    pub struct DummyIrqState: DeviceState, Object = "dummy_irq" {
+        out: [InterruptSource; 2],
+        levels: [BqlCell<bool>; 2],
+    }
+    impl ObjectImpl {
+        const INSTANCE_POST_INIT: Option<fn(&Self)> = Some(Self::post_init);
+    }
+}
+
+impl DeviceImpl for DummyIrqState {}
+
+impl ResettablePhasesImpl for DummyIrqState {}
+
+impl DummyIrqState {
+    fn post_init(&self) {
+        self.init_gpio_out(&self.out);
+    }
+
+    fn toggle(&self, n: usize) {
+        let level = !self.levels[n].get();
+        self.levels[n].set(level);
+        self.out[n].set(level);
+    }
+}
+
+dummy_type! {
+    pub struct DummyGpioInState: DeviceState, Object = "dummy_gpio_in" {
+        /// (bank, line, level) of the last changes to the inputs
+        events: BqlCell<[(u32, u32, bool); 4]>,
+        n_events: BqlCell<usize>,
+    }
+    impl ObjectImpl {
+        const INSTANCE_POST_INIT: Option<fn(&Self)> = Some(Self::post_init);
+    }
+}
+
+impl DeviceImpl for DummyGpioInState {}
+
+impl ResettablePhasesImpl for DummyGpioInState {}
+
+impl DummyGpioInState {
+    fn post_init(&self) {
+        self.init_gpio_in(3, &Self::gpio_in);
+        self.init_gpio_in_named(c_str!("wake"), 2, &|s: &Self, line, level| {
+            s.record(1, line, level)
+        });
+    }
+
+    fn gpio_in(&self, line: u32, level: bool) {
+        self.record(0, line, level);
+    }
+
+    fn record(&self, bank: u32, line: u32, level: bool) {
+        let n = self.n_events.get();
+        let mut events = self.events.get();
+        events[n] = (bank, line, level);
+        self.events.set(events);
+        self.n_events.set(n + 1);
+    }
+}
+
+dummy_type! {
+    pub struct DummyGpioBankState: DeviceState, Object = "dummy_gpio_bank" {
+        /// (line, level) of the last changes to the inputs
+        events: BqlCell<[(u32, bool); 8]>,
+        n_events: BqlCell<usize>,
+    }
+}
+
+impl DeviceImpl for DummyGpioBankState {}
+
+impl ResettablePhasesImpl for DummyGpioBankState {}
+
+impl DummyGpioBankState {
+    fn gpio_in(&self, line: u32, level: bool) {
+        let n = self.n_events.get();
+        let mut events = self.events.get();
+        events[n] = (line, level);
+        self.events.set(events);
+        self.n_events.set(n + 1);
+    }
+}
+
+dummy_type! {
+    /// A sysbus device with a data register at offset 0 and an interrupt
+    /// register at offset 4 that drives its only interrupt.
+    pub struct DummySysBusState: SysBusDevice, DeviceState, Object = "dummy_sysbus" {
+        iomem: MemoryRegion,
+        irq: InterruptSource,
+        data: BqlCell<u32>,
+    }
+    impl ObjectImpl {
+        const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+    }
+}
+
+impl DeviceImpl for DummySysBusState {}
+
+impl ResettablePhasesImpl for DummySysBusState {}
+
+impl SysBusDeviceImpl for DummySysBusState {}
+
+impl DummySysBusState {
+    unsafe fn init(&mut self) {
+        let owner = addr_of_mut!(*self);
+        // SAFETY: the region is a field of `owner`
+        unsafe {
+            self.iomem.init_io(owner, Self::TYPE_NAME, 8);
+        }
+        self.init_mmio(&self.iomem);
+        self.init_irq(&self.irq);
+    }
+}
+
+impl MmioOps for DummySysBusState {
+    const CONFIG: MmioConfig = MmioConfig::DEFAULT.with_impl_sizes(4, 4);
+
+    fn read(&self, offset: hwaddr, _size: u32) -> u64 {
+        match offset {
+            0 => self.data.get().into(),
+            _ => {
+                bad_offset!(self, offset, "read");
+                0
+            }
+        }
+    }
+
+    fn write(&self, offset: hwaddr, _size: u32, value: u64) {
+        match offset {
+            0 => self.data.set(value as u32),
+            4 => self.irq.set(value != 0),
+            _ => {}
+        }
+    }
+}
+
+dummy_type! {
+    /// A subclass of `DummySysBusState` that only changes the type name.
+    pub struct DummySysBusChildState:
+        DummySysBusState, SysBusDevice, DeviceState, Object = "dummy_sysbus_child" {}
+}
+
+impl DeviceImpl for DummySysBusChildState {}
+
+impl ResettablePhasesImpl for DummySysBusChildState {}
+
+impl SysBusDeviceImpl for DummySysBusChildState {}
+
+dummy_type! {
+    /// An IOMMU that maps each 4 KiB page to itself.
+    pub struct DummyIommuState: DeviceState, Object = "dummy_iommu" {
+        iommu: IommuMemoryRegion,
+        translations: BqlCell<u32>,
+    }
+    impl ObjectImpl {
+        const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+    }
+}
+
+impl DeviceImpl for DummyIommuState {}
+
+impl ResettablePhasesImpl for DummyIommuState {}
+
+impl IommuMemoryRegionImpl for DummyIommuState {
+    const IOMMU_TYPE_NAME: &'static CStr = c_str!("dummy-iommu-memory-region");
+
+    fn translate(&self, addr: hwaddr, _flag: IOMMUAccessFlags) -> IOMMUTLBEntry {
+        self.translations.set(self.translations.get() + 1);
+        IOMMUTLBEntry {
+            target_as: address_space_memory().as_mut_ptr(),
+            iova: addr & !0xfff,
+            translated_addr: addr & !0xfff,
+            addr_mask: 0xfff,
+            perm: IOMMU_RW,
+        }
+    }
+}
+
+impl DummyIommuState {
+    unsafe fn init(&mut self) {
+        let owner = addr_of_mut!(*self);
+        // SAFETY: the region is a field of `owner`
+        unsafe {
+            self.iommu.init(owner, c_str!("dummy-iommu"), u64::MAX);
+        }
+    }
+}
+
+dummy_type! {
+    /// A device that owns 4 KiB of RAM, to be the root of an address space.
+    pub struct DummyRamState: DeviceState, Object = "dummy_ram" {
+        ram: MemoryRegion,
+    }
+    impl ObjectImpl {
+        const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+    }
+}
+
+impl DeviceImpl for DummyRamState {}
+
+impl ResettablePhasesImpl for DummyRamState {}
+
+impl DummyRamState {
+    unsafe fn init(&mut self) {
+        let owner = addr_of_mut!(*self);
+        // SAFETY: the region is a field of `owner`
+        unsafe {
+            self.ram.init_ram(owner, Self::TYPE_NAME, 0x1000).unwrap();
+        }
+    }
+}
+
+dummy_type! {
+    /// A device with a 256-byte ROM and a 256-byte flash memory, whose writes
+    /// are recorded.
+    pub struct DummyRomState: DeviceState, Object = "dummy_rom" {
+        rom: MemoryRegion,
+        flash: MemoryRegion,
+        flash_writes: BqlCell<u32>,
+        last_flash_write: BqlCell<(hwaddr, u64)>,
+    }
+    impl ObjectImpl {
+        const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+    }
+}
+
+impl DeviceImpl for DummyRomState {}
+
+impl ResettablePhasesImpl for DummyRomState {}
+
+impl DummyRomState {
+    unsafe fn init(&mut self) {
+        let owner = addr_of_mut!(*self);
+        // SAFETY: both regions are fields of `owner`
+        unsafe {
+            self.rom
+                .init_rom(owner, c_str!("dummy-rom"), 0x100)
+                .unwrap();
+            self.flash
+                .init_rom_device(owner, c_str!("dummy-flash"), 0x100)
+                .unwrap();
+        }
+    }
+}
+
+impl RomDeviceOps for DummyRomState {
+    const CONFIG: MmioConfig = MmioConfig::DEFAULT.with_impl_sizes(4, 4);
+
+    fn read(&self, _offset: hwaddr, _size: u32) -> u64 {
+        0x80
+    }
+
+    fn write(&self, offset: hwaddr, _size: u32, value: u64) {
+        self.flash_writes.set(self.flash_writes.get() + 1);
+        self.last_flash_write.set((offset, value));
+    }
+}
+
+dummy_type! {
+    pub struct DummyClockState: DeviceState, Object = "dummy_clock" {
+        clk_in: NonNull<Clock>,
+        clk_out: NonNull<Clock>,
+        updates: BqlCell<u32>,
+        last_period: BqlCell<u64>,
+    }
+    impl ObjectImpl {
+        const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+    }
+}
+
+impl DeviceImpl for DummyClockState {}
+
+impl ResettablePhasesImpl for DummyClockState {}
+
+impl DummyClockState {
+    unsafe fn init(&mut self) {
+        self.clk_in = self.init_clock_in(c_str!("clk_in"), &Self::clock_update);
+        self.clk_out = self.init_clock_out(c_str!("clk_out"));
+    }
+
+    fn clock_update(&self) {
+        self.updates.set(self.updates.get() + 1);
+        // SAFETY: the clock was created in INSTANCE_INIT and lives as long
+        // as the device
+        self.last_period
+            .set(unsafe { self.clk_in.as_ref() }.period());
+    }
+}
+
+dummy_type! {
+    pub struct DummyDefaultState: DeviceState, Object = "dummy_default" {
+        buf: Option<NonNull<u8>>,
+        label: String,
+        count: BqlCell<u32>,
+    }
+    impl ObjectImpl {
+        const INSTANCE_DEFAULT: Option<fn() -> Self> = Some(Self::default);
+        const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+    }
+}
+
+impl DeviceImpl for DummyDefaultState {}
+
+impl ResettablePhasesImpl for DummyDefaultState {}
+
+impl Default for DummyDefaultState {
+    fn default() -> Self {
+        DummyDefaultState {
+            parent: ParentField::default(),
+            buf: None,
+            label: "unnamed".to_owned(),
+            count: BqlCell::new(1),
+        }
+    }
+}
+
+impl DummyDefaultState {
+    unsafe fn init(&mut self) {
+        // the fields already hold the values from Default
+        self.label.push_str("-init");
+        self.count.set(self.count.get() + 1);
+    }
+}
+
+extern "C" {
+    fn g_malloc0(n_bytes: usize) -> *mut c_void;
+    fn g_free(mem: *mut c_void);
+}
+
+fn init_qom() {
+    static ONCE: BqlCell<bool> = BqlCell::new(false);
+
+    cell::bql_start_test();
+    if !ONCE.get() {
+        unsafe {
+            module_call_init(module_init_type::MODULE_INIT_QOM);
+        }
+        ONCE.set(true);
+    }
+}
+
+/// Add `dev` to the QOM tree as `/name`, as boards do before realizing
+/// their devices, and return it as an `Object` for `object_unparent()`.
+fn add_to_root<R: ObjectDeref>(dev: &R, name: &CStr) -> *mut Object
+where
+    R::Target: IsA<Object>,
+{
+    // SAFETY: the root container takes its own reference to the object
+    unsafe {
+        let obj = dev.as_mut_ptr::<Object>();
+        object_property_add_child(object_get_root(), name.as_ptr(), obj);
+        obj
+    }
+}
+
+#[test]
+/// Create and immediately drop an instance.
+fn test_object_new() {
+    init_qom();
+    unsafe {
+        object_unref(object_new(DummyState::TYPE_NAME.as_ptr()).cast());
+        object_unref(object_new(DummyChildState::TYPE_NAME.as_ptr()).cast());
+    }
+}
+
+/// Register `TYPE_HOTPLUG_HANDLER`, which comes from `hw/core/hotplug.c`.
+/// That file is only part of the emulators, and the tests link stubs of
+/// the hotplug functions instead.
+fn init_hotplug_handler_type() {
+    unsafe {
+        if object_class_by_name(TYPE_HOTPLUG_HANDLER.as_ptr().cast()).is_null() {
+            let info = TypeInfo {
+                name: TYPE_HOTPLUG_HANDLER.as_ptr().cast(),
+                parent: TYPE_INTERFACE.as_ptr().cast(),
+                class_size: std::mem::size_of::<HotplugHandlerClass>(),
+                ..Default::default()
+            };
+            type_register_static(addr_of!(info));
+        }
+    }
+}
+
+#[test]
+/// Plug a device into a Rust hotplug handler and request its removal,
+/// calling the handler through its `HotplugHandlerClass` as
+/// `device_realize()` and `qdev_unplug()` do.
+fn test_hotplug_handler() {
+    init_qom();
+    init_hotplug_handler_type();
+
+    let handler = Device::<DummyHotplugState>::new(DummyHotplugState::TYPE_NAME);
+    let dev = Device::<DummyState>::new(DummyState::TYPE_NAME);
+    let dev_ptr = dev.as_ptr::<DeviceState>();
+    unsafe {
+        let hc = object_class_dynamic_cast(
+            object_get_class(handler.as_object_mut_ptr()),
+            TYPE_HOTPLUG_HANDLER.as_ptr().cast(),
+        )
+        .cast::<HotplugHandlerClass>();
+        assert!(!hc.is_null());
+        assert!((*hc).unplug.is_none());
+        let hotplug_ctrl = handler.as_object_mut_ptr().cast::<HotplugHandler>();
+
+        (*hc).plug.unwrap()(
+            hotplug_ctrl,
+            dev.as_mut_ptr::<DeviceState>(),
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(handler.plugged.get(), dev_ptr);
+        assert!(handler.unplug_requested.get().is_null());
+
+        (*hc).unplug_request.unwrap()(
+            hotplug_ctrl,
+            dev.as_mut_ptr::<DeviceState>(),
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(handler.unplug_requested.get(), dev_ptr);
+    }
+}
+
+#[test]
+/// Create a controller that owns a bus, and plug a device into the bus.
+fn test_child_bus() {
+    init_qom();
+    init_hotplug_handler_type();
+    let controller = Device::<DummyControllerState>::new(DummyControllerState::TYPE_NAME);
+    let ctrl_obj = add_to_root(&controller, c_str!("dummy-controller"));
+    let controller = controller.realize_and_unref(None).unwrap();
+
+    let bus = controller.bus.as_ref().unwrap();
+    unsafe {
+        assert_eq!(bus.parent, ctrl_obj.cast::<DeviceState>());
+        assert_eq!(CStr::from_ptr(bus.name), c_str!("dummy-bus"));
+        // one reference is owned by the QOM tree, one by the `Bus`
+        assert_eq!(bus.obj.ref_, 2);
+    }
+
+    let handler = Device::<DummyHotplugState>::new(DummyHotplugState::TYPE_NAME);
+    bus.set_hotplug_handler(&*handler);
+    unsafe {
+        assert_eq!(
+            object_property_get_link(
+                bus.as_object_mut_ptr(),
+                c_str!("hotplug-handler").as_ptr(),
+                addr_of_mut!(error_abort),
+            ),
+            handler.as_object_mut_ptr()
+        );
+    }
+
+    let child = Device::<DummyState>::new(DummyState::TYPE_NAME)
+        .realize_and_unref(controller.bus.as_deref())
+        .unwrap();
+    unsafe {
+        assert_eq!(
+            (*child.as_ptr::<DeviceState>()).parent_bus,
+            bus.as_mut_ptr::<BusState>()
+        );
+    }
+
+    drop(child);
+    drop(controller);
+    unsafe {
+        object_unparent(ctrl_obj);
+    }
+}
+
+#[test]
+/// Read the properties of a minimal machine, which is a device whose
+/// properties are known.
+fn test_machine_properties() {
+    init_qom();
+    assert!(current_machine().is_none());
+    machine_get_bool(c_str!("migrate-clk")).unwrap_err();
+
+    let machine = Device::<DummyState>::new(DummyState::TYPE_NAME);
+    machine.set_bool(c_str!("migrate-clk"), true).unwrap();
+    let obj = add_to_root(&machine, c_str!("machine"));
+    assert_eq!(
+        current_machine().unwrap().as_object_ptr(),
+        obj as *const Object
+    );
+    assert!(machine_get_bool(c_str!("migrate-clk")).unwrap());
+    assert_eq!(machine_get_uint(c_str!("level")).unwrap(), 8);
+
+    // missing properties and properties of the wrong type are errors
+    machine_get_bool(c_str!("no-such-property")).unwrap_err();
+    machine_get_uint(c_str!("no-such-property")).unwrap_err();
+    machine_get_uint(c_str!("path")).unwrap_err();
+
+    drop(machine);
+    unsafe {
+        object_unparent(obj);
+    }
+    assert!(current_machine().is_none());
+}
+
+#[test]
+/// Check that properties built at run time with `PropertyList` are
+/// registered, and that their defaults and setters reach the fields.
+fn test_property_list() {
+    init_qom();
+    let props = PropertyList::from_vec(vec![]);
+    assert!(props.is_empty());
+
+    let klass = unsafe { object_class_by_name(DummyPortsState::TYPE_NAME.as_ptr()) };
+    for name in [c_str!("port0"), c_str!("port3")] {
+        assert!(unsafe { !object_class_property_find(klass, name.as_ptr()).is_null() });
+    }
+    assert!(unsafe { object_class_property_find(klass, c_str!("port4").as_ptr()).is_null() });
+
+    let dev = Device::<DummyPortsState>::new(DummyPortsState::TYPE_NAME);
+    assert_eq!(dev.ports, [0, 1, 2, 3]);
+    unsafe {
+        object_property_set_uint(
+            dev.as_object_mut_ptr(),
+            c_str!("port2").as_ptr(),
+            42,
+            addr_of_mut!(error_abort),
+        );
+    }
+    assert_eq!(dev.ports, [0, 1, 42, 3]);
+}
+
+#[test]
+/// Set size properties with and without suffixes.
+fn test_size_property() {
+    init_qom();
+    let dev = Device::<DummyMemSizeState>::new(DummyMemSizeState::TYPE_NAME);
+    assert_eq!(dev.mem_size, 64 * 1024);
+    assert_eq!(dev.cache_size, 0);
+    assert_eq!(dev.get_uint(c_str!("mem-size")).unwrap(), 64 * 1024);
+    unsafe {
+        let obj = dev.as_object_mut_ptr();
+        object_property_parse(
+            obj,
+            c_str!("mem-size").as_ptr(),
+            c_str!("2M").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        object_property_parse(
+            obj,
+            c_str!("cache-size").as_ptr(),
+            c_str!("4096").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(dev.mem_size, 2 * 1024 * 1024);
+        assert_eq!(dev.cache_size, 4096);
+
+        // invalid sizes are rejected and leave the field alone
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_parse(
+            obj,
+            c_str!("mem-size").as_ptr(),
+            c_str!("lots").as_ptr(),
+            addr_of_mut!(err)
+        ));
+        error_free(err);
+        assert_eq!(dev.mem_size, 2 * 1024 * 1024);
+    }
+}
+
+#[test]
+/// Set an array property from a list, and check that it can only be set
+/// once unless the list was empty.
+fn test_array_property() {
+    init_qom();
+    let dev = Device::<DummyArrayState>::new(DummyArrayState::TYPE_NAME);
+    assert!(dev.irqs().is_empty());
+    unsafe {
+        let obj = dev.as_object_mut_ptr();
+        let name = c_str!("irqs").as_ptr();
+
+        object_property_parse(obj, name, c_str!("").as_ptr(), addr_of_mut!(error_abort));
+        assert_eq!(dev.num_irqs, 0);
+        assert!(dev.irqs.is_null());
+
+        object_property_parse(
+            obj,
+            name,
+            c_str!("5,7,9").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(dev.num_irqs, 3);
+        assert_eq!(dev.irqs(), [5, 7, 9]);
+
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_parse(
+            obj,
+            name,
+            c_str!("1").as_ptr(),
+            addr_of_mut!(err)
+        ));
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(err)),
+            c_str!("array size property irqs may not be set more than once")
+        );
+        error_free(err);
+        assert_eq!(dev.irqs(), [5, 7, 9]);
+    }
+}
+
+#[test]
+/// Check that `INSTANCE_DEFAULT` constructs the fields before
+/// `INSTANCE_INIT` runs, and leaves the parent alone.
+fn test_instance_default() {
+    init_qom();
+    let dev = Device::<DummyDefaultState>::new(DummyDefaultState::TYPE_NAME);
+    assert!(dev.buf.is_none());
+    assert_eq!(dev.label, "unnamed-init");
+    assert_eq!(dev.count.get(), 2);
+    assert_eq!(dev.typename(), "dummy_default");
+}
+
+#[test]
+/// Check that `#[derive(Object)]` registers the type with QOM.
+fn test_object_registration() {
+    init_qom();
+    unsafe {
+        assert!(!object_class_by_name(TYPE_RUST_DUMMY.as_ptr()).is_null());
+
+        let obj = object_new(TYPE_RUST_DUMMY.as_ptr());
+        assert_eq!(object_dynamic_cast(obj, TYPE_RUST_DUMMY.as_ptr()), obj);
+        assert_eq!(object_dynamic_cast(obj, Object::TYPE_NAME.as_ptr()), obj);
+        assert!(object_dynamic_cast(obj, DeviceState::TYPE_NAME.as_ptr()).is_null());
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Look up the class properties of a type and access them through
+/// instances that were created after the class was initialized.
+fn test_class_properties() {
+    init_qom();
+    let name = DummyClassPropObject::TYPE_NAME;
+    unsafe {
+        let klass = object_class_by_name(name.as_ptr());
+        assert!(!object_class_property_find(klass, c_str!("enabled").as_ptr()).is_null());
+        assert!(!object_class_property_find(klass, c_str!("label").as_ptr()).is_null());
+        assert!(object_class_property_find(klass, c_str!("no-such-property").as_ptr()).is_null());
+
+        let first = object_new(name.as_ptr());
+        let second = object_new(name.as_ptr());
+        // each instance has its own value
+        object_property_set_bool(
+            first,
+            c_str!("enabled").as_ptr(),
+            true,
+            addr_of_mut!(error_abort),
+        );
+        assert!(object_property_get_bool(
+            first,
+            c_str!("enabled").as_ptr(),
+            addr_of_mut!(error_abort)
+        ));
+        assert!(!object_property_get_bool(
+            second,
+            c_str!("enabled").as_ptr(),
+            addr_of_mut!(error_abort)
+        ));
+        assert!((*first.cast::<DummyClassPropObject>()).enabled.get());
+
+        object_property_set_str(
+            first,
+            c_str!("label").as_ptr(),
+            c_str!("hello").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        let label =
+            object_property_get_str(first, c_str!("label").as_ptr(), addr_of_mut!(error_abort));
+        assert_eq!(CStr::from_ptr(label), c_str!("hello"));
+        g_free(label.cast::<c_void>());
+
+        // errors from the setter are passed to the caller
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_set_str(
+            first,
+            c_str!("label").as_ptr(),
+            c_str!("").as_ptr(),
+            addr_of_mut!(err)
+        ));
+        assert_eq!(CStr::from_ptr(error_get_pretty(err)), c_str!("empty label"));
+        error_free(err);
+        assert_eq!(
+            *(*first.cast::<DummyClassPropObject>()).label.borrow(),
+            "hello"
+        );
+
+        object_unref(first.cast::<c_void>());
+        object_unref(second.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that `glib_strdup` returns strings that can be freed with `g_free`,
+/// like those returned by the getters of string properties.
+fn test_glib_strdup() {
+    // the copy stops at the first NUL character
+    for (value, expected) in [
+        ("/machine/unattached", c_str!("/machine/unattached")),
+        ("", c_str!("")),
+        ("foo\0bar", c_str!("foo")),
+    ] {
+        let copy = glib_strdup(value);
+        unsafe {
+            assert_eq!(CStr::from_ptr(copy), expected);
+            g_free(copy.cast::<c_void>());
+        }
+    }
+}
+
+#[test]
+/// Check that `ObjectBuilder` sets the properties of the object, and
+/// frees the object if one of them cannot be set.
+fn test_object_builder() {
+    init_qom();
+    let name = DummyClassPropObject::TYPE_NAME;
+    let obj = ObjectBuilder::<DummyClassPropObject>::new(name)
+        .property(c_str!("enabled"), c_str!("on"))
+        .property(c_str!("label"), c_str!("built"))
+        .build()
+        .unwrap();
+    assert!(obj.enabled.get());
+    assert_eq!(*obj.label.borrow(), "built");
+    let finalized = CLASS_PROP_FINALIZED.get();
+    drop(obj);
+    assert_eq!(CLASS_PROP_FINALIZED.get(), finalized + 1);
+
+    // errors from the setter are returned, and the object is freed
+    let err = ObjectBuilder::<DummyClassPropObject>::new(name)
+        .property(c_str!("enabled"), c_str!("on"))
+        .property(c_str!("label"), c_str!(""))
+        .build()
+        .unwrap_err();
+    assert_eq!(err.message(), c_str!("empty label"));
+    assert_eq!(CLASS_PROP_FINALIZED.get(), finalized + 2);
+
+    let not_found = ObjectBuilder::<DummyClassPropObject>::new(name)
+        .property(c_str!("no-such-property"), c_str!("1"))
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        not_found.message(),
+        c_str!("Property 'dummy_class_prop.no-such-property' not found")
+    );
+    assert_eq!(CLASS_PROP_FINALIZED.get(), finalized + 3);
+}
+
+#[test]
+/// Create a user-creatable object, and check that its `complete` callback
+/// runs after the properties are set and can make the creation fail.
+fn test_user_creatable() {
+    init_qom();
+    let name = DummyUserCreatableObject::TYPE_NAME;
+    let obj = ObjectBuilder::<DummyUserCreatableObject>::new(name)
+        .property(c_str!("path"), c_str!("/tmp/dummy"))
+        .build()
+        .unwrap();
+    assert!(obj.completed);
+    assert_eq!(*obj.path.borrow(), "/tmp/dummy");
+
+    let err = ObjectBuilder::<DummyUserCreatableObject>::new(name)
+        .build()
+        .unwrap_err();
+    assert_eq!(err.message(), c_str!("'path' property not set"));
+
+    // the callback is also reached through user_creatable_complete()
+    unsafe {
+        let raw = object_new(name.as_ptr());
+        let mut c_err: *mut Error = ptr::null_mut();
+        assert!(!user_creatable_complete(
+            raw.cast::<UserCreatable>(),
+            addr_of_mut!(c_err)
+        ));
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(c_err)),
+            c_str!("'path' property not set")
+        );
+        error_free(c_err);
+        object_unref(raw.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that cloning an `Owned` takes a reference, and that the object
+/// is finalized only after all the references are dropped.
+fn test_owned_refcount() {
+    init_qom();
+    let obj = ObjectBuilder::<DummyClassPropObject>::new(DummyClassPropObject::TYPE_NAME)
+        .build()
+        .unwrap();
+    let refcount = |o: &DummyClassPropObject| unsafe { (*o.as_object_ptr()).ref_ };
+    let finalized = CLASS_PROP_FINALIZED.get();
+    assert_eq!(refcount(&obj), 1);
+
+    let copy = obj.clone();
+    assert_eq!(refcount(&obj), 2);
+    let borrowed = qom::Owned::from_ref(&*copy);
+    assert_eq!(refcount(&obj), 3);
+
+    // the raw pointer keeps the reference until it is turned back
+    let raw = borrowed.into_raw();
+    assert_eq!(refcount(&obj), 3);
+    let restored = unsafe { qom::Owned::from_raw(raw) };
+    drop(restored);
+    drop(copy);
+    assert_eq!(refcount(&obj), 1);
+    assert_eq!(CLASS_PROP_FINALIZED.get(), finalized);
+
+    drop(obj);
+    assert_eq!(CLASS_PROP_FINALIZED.get(), finalized + 1);
+}
+
+#[test]
+/// Add a child to an object, find it below the parent in the QOM tree,
+/// and check that it is finalized together with the parent.
+fn test_add_child() {
+    init_qom();
+    let new_object = || {
+        ObjectBuilder::<DummyClassPropObject>::new(DummyClassPropObject::TYPE_NAME)
+            .build()
+            .unwrap()
+    };
+    let parent = new_object();
+    let child = new_object();
+    let other = new_object();
+
+    parent.add_child(c_str!("sub"), &*child).unwrap();
+    let err = parent.add_child(c_str!("sub"), &*other).unwrap_err();
+    assert_eq!(
+        err.message(),
+        c_str!("attempt to add duplicate property 'sub' to object (type 'dummy_class_prop')")
+    );
+
+    unsafe {
+        object_property_add_child(
+            object_get_root(),
+            c_str!("add-child-parent").as_ptr(),
+            parent.as_mut_ptr::<Object>(),
+        );
+    }
+    assert_eq!(
+        qom::resolve_path(c_str!("/add-child-parent/sub"))
+            .unwrap()
+            .as_ptr() as *const Object,
+        child.as_ptr::<Object>()
+    );
+    assert_eq!(
+        child.canonical_path().as_deref(),
+        Some("/add-child-parent/sub")
+    );
+
+    // The parent keeps the child alive after the caller's reference is
+    // dropped.
+    let finalized = CLASS_PROP_FINALIZED.get();
+    drop(child);
+    drop(other);
+    assert_eq!(CLASS_PROP_FINALIZED.get(), finalized + 1);
+
+    unsafe {
+        object_unparent(parent.as_mut_ptr::<Object>());
+    }
+    drop(parent);
+    assert_eq!(CLASS_PROP_FINALIZED.get(), finalized + 3);
+}
+
+#[test]
+/// Check that objects are found by absolute and partial paths, and that
+/// an ambiguous partial path is reported as such.
+fn test_resolve_path() {
+    init_qom();
+    unsafe {
+        let root = object_get_root();
+        let mut parents = vec![];
+        let mut leaves = vec![];
+        for name in [c_str!("resolve-a"), c_str!("resolve-b")] {
+            let parent = object_new(TYPE_RUST_DUMMY.as_ptr());
+            let leaf = object_new(DummyState::TYPE_NAME.as_ptr());
+            object_property_add_child(root, name.as_ptr(), parent);
+            object_property_add_child(parent, c_str!("leaf").as_ptr(), leaf);
+            object_unref(parent.cast::<c_void>());
+            object_unref(leaf.cast::<c_void>());
+            parents.push(parent);
+            leaves.push(leaf);
+        }
+
+        assert_eq!(
+            qom::resolve_path(c_str!("/resolve-a/leaf"))
+                .unwrap()
+                .as_ptr(),
+            leaves[0]
+        );
+        assert_eq!(
+            qom::resolve_path(c_str!("resolve-b/leaf"))
+                .unwrap()
+                .as_ptr(),
+            leaves[1]
+        );
+        assert_eq!(
+            qom::resolve_path(c_str!("leaf")),
+            Err(qom::ResolvePathError::Ambiguous)
+        );
+        assert_eq!(
+            qom::resolve_path(c_str!("/resolve-c/leaf")),
+            Err(qom::ResolvePathError::NotFound)
+        );
+
+        let leaf = qom::resolve_path_typed::<DummyState>(c_str!("/resolve-a/leaf")).unwrap();
+        assert_eq!(leaf.as_ref().level, 8);
+        assert_eq!(
+            qom::resolve_path_typed::<DummyState>(c_str!("leaf")),
+            Err(qom::ResolvePathError::Ambiguous)
+        );
+        assert_eq!(
+            qom::resolve_path_typed::<DummyChildState>(c_str!("/resolve-a/leaf")),
+            Err(qom::ResolvePathError::NotFound)
+        );
+
+        for parent in parents {
+            object_unparent(parent);
+        }
+    }
+    assert_eq!(
+        qom::resolve_path(c_str!("/resolve-a/leaf")),
+        Err(qom::ResolvePathError::NotFound)
+    );
+}
+
+#[test]
+/// Check that an alias forwards reads and writes to a property of a
+/// child object.
+fn test_property_alias() {
+    init_qom();
+    unsafe {
+        let parent = object_new(TYPE_RUST_DUMMY.as_ptr());
+        let child = object_new(DummyState::TYPE_NAME.as_ptr());
+        object_property_add_child(parent, c_str!("dev").as_ptr(), child);
+        object_unref(child.cast::<c_void>());
+
+        let parent_ref = &*parent.cast::<RustDummyObject>();
+        let child_ref = &*child.cast::<DummyState>();
+        parent_ref
+            .add_property_alias(c_str!("dev-level"), child_ref, c_str!("level"))
+            .unwrap();
+
+        object_property_set_uint(
+            parent,
+            c_str!("dev-level").as_ptr(),
+            5,
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(child_ref.level, 5);
+        child_ref.set_uint(c_str!("level"), 9).unwrap();
+        assert_eq!(
+            object_property_get_uint(
+                parent,
+                c_str!("dev-level").as_ptr(),
+                addr_of_mut!(error_abort)
+            ),
+            9
+        );
+
+        // the target property must exist, and the alias must not
+        let err = parent_ref
+            .add_property_alias(c_str!("dev-speed"), child_ref, c_str!("speed"))
+            .unwrap_err();
+        assert_eq!(err.message(), c_str!("property 'speed' not found"));
+        parent_ref
+            .add_property_alias(c_str!("dev-level"), child_ref, c_str!("bias"))
+            .unwrap_err();
+
+        object_unref(parent.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Try invoking a method on an object.
+fn test_typename() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    assert_eq!(p_ref.typename(), "dummy");
+    unsafe {
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check the type name of an object and the name of its class and of
+/// the parent class.
+fn test_typename_and_class_name() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    let name: &'static CStr = qom::typename(p_ref.upcast::<Object>());
+    assert_eq!(name, DummyState::TYPE_NAME);
+
+    unsafe {
+        let klass = object_get_class(p_ref.as_object_mut_ptr());
+        assert_eq!(qom::class_name(&*klass), DummyState::TYPE_NAME);
+        let parent = object_class_get_parent(klass);
+        assert_eq!(qom::class_name(&*parent), DeviceState::TYPE_NAME);
+    }
+    unsafe {
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that an error returned by `REALIZE` reaches the caller.
+fn test_realize_error() {
+    init_qom();
+    let p: *mut DummyFailState = unsafe { object_new(DummyFailState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyFailState = unsafe { &*p };
+    unsafe {
+        let obj = p_ref.as_object_mut_ptr();
+        object_property_add_child(object_get_root(), c_str!("dummy-fail").as_ptr(), obj);
+
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!qdev_realize(
+            p.cast::<DeviceState>(),
+            ptr::null_mut(),
+            addr_of_mut!(err)
+        ));
+        assert!(!err.is_null());
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(err)),
+            c_str!("dummy realize failed")
+        );
+        error_free(err);
+
+        object_unparent(obj);
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that `REALIZE` sees the values of all the properties, and that
+/// it can reject combinations of them with `ensure!`.
+fn test_realize_validate_properties() {
+    init_qom();
+    let realize = |size: u64, block_size: u64| {
+        let dev = Device::<DummyBlockSizeState>::new(DummyBlockSizeState::TYPE_NAME);
+        dev.set_uint(c_str!("size"), size).unwrap();
+        dev.set_uint(c_str!("block-size"), block_size).unwrap();
+        let obj = add_to_root(&dev, c_str!("dummy-block-size"));
+        let result = dev.realize_and_unref(None).map(drop);
+        unsafe {
+            object_unparent(obj);
+        }
+        result
+    };
+
+    realize(4096, 512).unwrap();
+    realize(8192, 8192).unwrap();
+    let not_pow2 = realize(3000, 512).unwrap_err();
+    assert_eq!(
+        not_pow2.message(),
+        c_str!("size 3000 is not a power of two")
+    );
+    let not_multiple = realize(4096, 3000).unwrap_err();
+    assert_eq!(
+        not_multiple.message(),
+        c_str!("size 4096 is not a multiple of block-size 3000")
+    );
+    realize(4096, 0).unwrap_err();
+}
+
+#[test]
+/// Check the reference counting of `Device` and `OwnedDevice`.
+fn test_device_realize_and_unref() {
+    init_qom();
+    let dev = Device::<DummyState>::new(DummyState::TYPE_NAME);
+    dev.set_uint(c_str!("level"), 3).unwrap();
+    let obj = unsafe { dev.as_object_mut_ptr() };
+    unsafe {
+        object_property_add_child(object_get_root(), c_str!("dummy-owned").as_ptr(), obj);
+        assert_eq!((*obj).ref_, 2);
+    }
+
+    let owned = dev.realize_and_unref(None).unwrap();
+    assert_eq!(owned.level, 3);
+    unsafe {
+        assert_eq!((*obj).ref_, 2);
+        assert!((*owned.as_ptr::<DeviceState>()).realized);
+    }
+
+    // the QOM tree keeps the device alive after the handle is dropped
+    drop(owned);
+    unsafe {
+        assert_eq!((*obj).ref_, 1);
+        object_unparent(obj);
+    }
+
+    // a failed realize drops the handle's reference too
+    let fail_dev = Device::<DummyFailState>::new(DummyFailState::TYPE_NAME);
+    let fail_obj = add_to_root(&fail_dev, c_str!("dummy-owned-fail"));
+    let err = fail_dev.realize_and_unref(None).unwrap_err();
+    assert_eq!(err.message(), c_str!("dummy realize failed"));
+    unsafe {
+        assert_eq!((*fail_obj).ref_, 1);
+        object_unparent(fail_obj);
+    }
+}
+
+#[test]
+/// Check that `UNREALIZE` runs, and frees what `REALIZE` allocated, when
+/// a realized device is removed.
+fn test_unrealize() {
+    init_qom();
+    let dev = Device::<DummyUnrealizeState>::new(DummyUnrealizeState::TYPE_NAME);
+    let obj = add_to_root(&dev, c_str!("dummy-unrealize"));
+    let owned = dev.realize_and_unref(None).unwrap();
+    assert!(owned.buf.borrow().is_some());
+    assert_eq!(DUMMY_BUFFERS.get(), 1);
+    drop(owned);
+
+    unsafe {
+        object_unparent(obj);
+    }
+    assert_eq!(DUMMY_BUFFERS.get(), 0);
+}
+
+#[test]
+/// Check that `class_override` replaces the `realize` method inherited
+/// from the parent class, and that qdev calls the override.
+fn test_class_override() {
+    init_qom();
+    let dev = Device::<DummyOverrideState>::new(DummyOverrideState::TYPE_NAME);
+    let obj = add_to_root(&dev, c_str!("dummy-override"));
+    assert!(!dev.realized.get());
+
+    let owned = dev.realize_and_unref(None).unwrap();
+    assert!(owned.realized.get());
+    drop(owned);
+    unsafe {
+        object_unparent(obj);
+    }
+}
+
+#[test]
+/// Check that the methods that a `DeviceImpl` leaves out keep the
+/// defaults of `TYPE_DEVICE`.
+fn test_device_impl_defaults() {
+    init_qom();
+    let class_of =
+        |name: &CStr| unsafe { &*object_class_by_name(name.as_ptr()).cast::<DeviceClass>() };
+
+    let realize_dc = class_of(DummyRealizeOnlyState::TYPE_NAME);
+    assert!(realize_dc.realize.is_some());
+    assert!(realize_dc.unrealize.is_none());
+    assert!(realize_dc.legacy_reset.is_none());
+    assert!(realize_dc.vmsd.is_null());
+    assert_eq!(realize_dc.props_count_, 1);
+
+    let realize_dev = Device::<DummyRealizeOnlyState>::new(DummyRealizeOnlyState::TYPE_NAME);
+    assert_eq!(realize_dev.value, 7);
+    let realize_obj = add_to_root(&realize_dev, c_str!("dummy-realize-only"));
+    let realize_owned = realize_dev.realize_and_unref(None).unwrap();
+    assert!(unsafe { (*realize_owned.as_ptr::<DeviceState>()).realized });
+    drop(realize_owned);
+    unsafe {
+        object_unparent(realize_obj);
+    }
+
+    let vmsd_dc = class_of(DummyVmsdOnlyState::TYPE_NAME);
+    assert!(vmsd_dc.realize.is_none());
+    assert!(vmsd_dc.legacy_reset.is_none());
+    assert!(ptr::eq(vmsd_dc.vmsd, addr_of!(VMSTATE_VMSD_ONLY)));
+    assert_eq!(vmsd_dc.props_count_, 0);
+
+    let vmsd_dev = Device::<DummyVmsdOnlyState>::new(DummyVmsdOnlyState::TYPE_NAME);
+    let vmsd_obj = add_to_root(&vmsd_dev, c_str!("dummy-vmsd-only"));
+    let vmsd_owned = vmsd_dev.realize_and_unref(None).unwrap();
+    assert!(unsafe { (*vmsd_owned.as_ptr::<DeviceState>()).realized });
+    drop(vmsd_owned);
+    unsafe {
+        object_unparent(vmsd_obj);
+    }
+}
+
+#[test]
+#[should_panic(expected = "is not a subclass of")]
+/// Check that `Device::new` refuses a type that is not a `T`.
+fn test_device_new_wrong_type() {
+    init_qom();
+    let _dev = Device::<DummyFailState>::new(DummyState::TYPE_NAME);
+}
+
+#[test]
+/// Create an error and drop it without passing it to C.
+fn test_error_new() {
+    let err = qemu_api::Error::new("first error");
+    assert_eq!(err.message(), c_str!("first error"));
+    assert_eq!(err.to_string(), "first error");
+}
+
+#[test]
+/// Check that `Error::setg` passes ownership of the error to `errp`.
+fn test_error_setg() {
+    let mut errp: *mut Error = ptr::null_mut();
+    unsafe {
+        qemu_api::Error::setg(addr_of_mut!(errp), qemu_api::Error::new("first error"));
+        assert!(!errp.is_null());
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(errp)),
+            c_str!("first error")
+        );
+
+        // The first error is kept, the second is freed.
+        qemu_api::Error::setg(addr_of_mut!(errp), qemu_api::Error::new("second error"));
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(errp)),
+            c_str!("first error")
+        );
+        error_free(errp);
+
+        // A null errp ignores the error.
+        qemu_api::Error::setg(ptr::null_mut(), qemu_api::Error::new("ignored error"));
+    }
+}
+
+#[test]
+/// Format an error with a runtime value into `errp`, and add hints to it.
+fn test_error_setg_macro() {
+    let mut errp: *mut Error = ptr::null_mut();
+    let value = 300;
+    unsafe {
+        error_setg!(addr_of_mut!(errp), "value {} out of range", value);
+        assert!(!errp.is_null());
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(errp)),
+            c_str!("value 300 out of range")
+        );
+        error_append_hint!(addr_of_mut!(errp), "valid values are 0 to {}\n", 255);
+        error_free(errp);
+
+        // As in C, a null errp ignores both the error and the hint.
+        error_setg!(ptr::null_mut(), "ignored error {}", value);
+        error_append_hint!(ptr::null_mut(), "ignored hint\n");
+    }
+
+    let mut err = qemu_api::Error::new("bad value");
+    err.append_hint("try again\n");
+    assert_eq!(err.message(), c_str!("bad value"));
+}
+
+/// The arguments of `x-dummy-add`.
+struct DummyAddArgs {
+    a: i64,
+    b: i64,
+}
+
+impl FromQmpArgs for DummyAddArgs {
+    fn from_qmp_args(args: &QmpArgs) -> qemu_api::Result<Self> {
+        Ok(DummyAddArgs {
+            a: args.get_int(c_str!("a"))?,
+            b: args.get_int(c_str!("b"))?,
+        })
+    }
+}
+
+fn qmp_dummy_add(args: DummyAddArgs) -> qemu_api::Result<i64> {
+    args.a
+        .checked_add(args.b)
+        .ok_or_else(|| qemu_api::Error::new("integer overflow"))
+}
+
+fn qmp_dummy_nul(_args: ()) -> qemu_api::Result<String> {
+    Ok("before\0after".to_owned())
+}
+
+#[test]
+/// Run a QMP command implemented in Rust through `qmp_dispatch()`, and
+/// check its replies for success, invalid arguments and failure, and for
+/// a return value that cannot be converted.
+fn test_qmp_command() {
+    init_qom();
+    let mut cmds = QmpCommands::new();
+    cmds.register(c_str!("x-dummy-add"), &qmp_dummy_add);
+    cmds.register(c_str!("x-dummy-nul"), &qmp_dummy_nul);
+    let run = |request| cmds.dispatch(request).unwrap();
+
+    assert_eq!(
+        run(r#"{"execute": "x-dummy-add", "arguments": {"a": 40, "b": 2}}"#),
+        r#"{"return": 42}"#
+    );
+
+    assert_eq!(
+        run(r#"{"execute": "x-dummy-add", "arguments": {"a": 1}}"#),
+        r#"{"error": {"class": "GenericError", "desc": "Parameter 'b' is missing"}}"#
+    );
+
+    assert_eq!(
+        run(r#"{"execute": "x-dummy-add", "arguments": {"a": 1, "b": "2"}}"#),
+        r#"{"error": {"class": "GenericError", "desc": "Invalid parameter type for 'b', expected: integer"}}"#
+    );
+
+    assert_eq!(
+        run(r#"{"execute": "x-dummy-add", "arguments": {"a": 9223372036854775807, "b": 1}}"#),
+        r#"{"error": {"class": "GenericError", "desc": "integer overflow"}}"#
+    );
+
+    // a string that C cannot represent is an error, not an abort
+    assert_eq!(
+        run(r#"{"execute": "x-dummy-nul"}"#),
+        r#"{"error": {"class": "GenericError", "desc": "the returned string contains a NUL character"}}"#
+    );
+
+    cmds.dispatch(r#"{"execute": "#).unwrap_err();
+}
+
+#[test]
+/// Check the conversion of a `Result` to the C convention.
+fn test_bql_error_propagate() {
+    let mut errp: *mut Error = ptr::null_mut();
+    unsafe {
+        assert_eq!(bql_error_propagate(Ok(42), addr_of_mut!(errp)), Some(42));
+        assert!(errp.is_null());
+
+        let result: qemu_api::Result<u32> = Err(qemu_api::Error::new("failed"));
+        assert_eq!(bql_error_propagate(result, addr_of_mut!(errp)), None);
+        assert!(!errp.is_null());
+        assert_eq!(CStr::from_ptr(error_get_pretty(errp)), c_str!("failed"));
+        error_free(errp);
+    }
+}
+
+#[test]
+/// Send the log to a file, and check that only the enabled categories
+/// are printed.
+fn test_log_guest_error() {
+    let path = std::env::temp_dir().join(format!("qemu-rust-log-{}.txt", std::process::id()));
+    let name = CString::new(path.to_str().unwrap()).unwrap();
+    unsafe {
+        qemu_set_log_filename_flags(
+            name.as_ptr(),
+            LOG_GUEST_ERROR as c_int,
+            addr_of_mut!(error_abort),
+        );
+    }
+    log_guest_error!("dummy_read: Bad offset {:#x}", 0x40);
+    log_unimp!("dummy: DMA not implemented");
+    log_guest_error!("embedded\0nul");
+    unsafe {
+        qemu_set_log_filename_flags(ptr::null(), 0, addr_of_mut!(error_abort));
+    }
+
+    // the log is flushed after each message
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(contents, "dummy_read: Bad offset 0x40\nembedded\\0nul\n");
+}
+
+/// Create a device of type `T` at `base`, read an offset that it does not
+/// decode, and return what was logged.
+fn bad_offset_log<T: IsA<DeviceState> + IsA<SysBusDevice>>(base: hwaddr) -> String {
+    init_qom();
+    let dev = Device::<T>::new(T::TYPE_NAME);
+    let bus = unsafe { &*sysbus_get_default() };
+    let dev = dev.realize_and_unref(Some(bus)).unwrap();
+    dev.mmio_map(0, base);
+
+    let path = std::env::temp_dir().join(format!(
+        "qemu-rust-bad-offset-{}-{:x}.txt",
+        std::process::id(),
+        base
+    ));
+    let name = CString::new(path.to_str().unwrap()).unwrap();
+    unsafe {
+        qemu_set_log_filename_flags(
+            name.as_ptr(),
+            LOG_GUEST_ERROR as c_int,
+            addr_of_mut!(error_abort),
+        );
+    }
+    let mut buf = [0u8; 4];
+    phys_mem_read(base + 4, &mut buf).unwrap();
+    assert_eq!(buf, [0; 4]);
+    unsafe {
+        qemu_set_log_filename_flags(ptr::null(), 0, addr_of_mut!(error_abort));
+    }
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    unsafe {
+        object_unparent(dev.as_object_mut_ptr());
+    }
+    contents
+}
+
+#[test]
+/// Read an offset that the device does not decode, and check that the
+/// message in the log names the device and the offset.
+fn test_bad_offset() {
+    assert_eq!(
+        bad_offset_log::<DummySysBusState>(0x1400_0000),
+        "dummy_sysbus: read: bad offset 0x4\n"
+    );
+}
+
+#[test]
+/// The message names the runtime type of the device, not the type whose
+/// `MmioOps` implementation logged it.
+fn test_bad_offset_subclass() {
+    assert_eq!(
+        bad_offset_log::<DummySysBusChildState>(0x1400_1000),
+        "dummy_sysbus_child: read: bad offset 0x4\n"
+    );
+}
+
+#[test]
+/// Check that reset handlers run on system reset until they are
+/// unregistered.
+fn test_register_reset() {
+    init_qom();
+    let count = std::rc::Rc::new(BqlCell::new(0));
+    let first = {
+        let count = count.clone();
+        register_reset(move || count.set(count.get() + 1))
+    };
+    let second = {
+        let count = count.clone();
+        register_reset(move || count.set(count.get() + 10))
+    };
+
+    unsafe {
+        qemu_devices_reset(ResetType::RESET_TYPE_COLD);
+    }
+    assert_eq!(count.get(), 11);
+
+    unregister_reset(second);
+    unsafe {
+        qemu_devices_reset(ResetType::RESET_TYPE_COLD);
+    }
+    assert_eq!(count.get(), 12);
+
+    // dropping the handler also unregisters it, and frees the closure
+    drop(first);
+    unsafe {
+        qemu_devices_reset(ResetType::RESET_TYPE_COLD);
+    }
+    assert_eq!(count.get(), 12);
+    assert_eq!(std::rc::Rc::strong_count(&count), 1);
+}
+
+#[test]
+/// Check that each reset phase runs once per cold reset.
+fn test_reset_phases() {
+    init_qom();
+    let p: *mut DummyResetState = unsafe { object_new(DummyResetState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyResetState = unsafe { &*p };
+    for i in 1..=2 {
+        unsafe {
+            device_cold_reset(p.cast::<DeviceState>());
+        }
+        assert_eq!(p_ref.enter_count.get(), i);
+        assert_eq!(p_ref.hold_count.get(), i);
+        assert_eq!(p_ref.exit_count.get(), i);
+    }
+    unsafe {
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+    }
+}
+
+#[test]
+/// Reset a container and check that each phase runs on both children
+/// before it runs on the container.
+fn test_reset_children() {
+    init_qom();
+    let parent = Device::<DummyResetParentState>::new(DummyResetParentState::TYPE_NAME);
+    for id in 1..=2 {
+        let child = Device::<DummyResetChildState>::new(DummyResetChildState::TYPE_NAME);
+        child.id.set(id);
+        parent.children.borrow_mut().push(child);
+    }
+    RESET_LOG.borrow_mut().clear();
+    unsafe {
+        device_cold_reset(parent.as_mut_ptr());
+    }
+    assert_eq!(
+        *RESET_LOG.borrow(),
+        [
+            ("enter", 1),
+            ("enter", 2),
+            ("enter", 0),
+            ("hold", 1),
+            ("hold", 2),
+            ("hold", 0),
+            ("exit", 1),
+            ("exit", 2),
+            ("exit", 0),
+        ]
+    );
+}
+
+#[test]
+/// Dirty the registers of a tree of devices, and check that `reset_all`
+/// on the root resets all of them.
+fn test_reset_all() {
+    init_qom();
+    let new_node = || Device::<DummyResetTreeState>::new(DummyResetTreeState::TYPE_NAME);
+    let root = new_node();
+    for _ in 0..2 {
+        let child = new_node();
+        child.children.borrow_mut().push(new_node());
+        root.children.borrow_mut().push(child);
+    }
+
+    let mut count = 0;
+    root.for_each(&mut |dev| {
+        count += 1;
+        dev.reg.set(count);
+    });
+    assert_eq!(count, 5);
+
+    reset_all(root.upcast::<DeviceState>());
+    root.for_each(&mut |dev| assert_eq!(dev.reg.get(), DummyResetTreeState::RESET_VALUE));
+
+    // a subtree can be reset on its own
+    root.for_each(&mut |dev| dev.reg.set(0));
+    let first = &root.children.borrow()[0];
+    reset_all(first.upcast::<DeviceState>());
+    first.for_each(&mut |dev| assert_eq!(dev.reg.get(), DummyResetTreeState::RESET_VALUE));
+    assert_eq!(root.reg.get(), 0);
+    assert_eq!(root.children.borrow()[1].reg.get(), 0);
+}
+
+/// Interrupt sink for `test_irq_line`; records the line number and level
+/// of each change.
+unsafe extern "C" fn record_irq(opaque: *mut c_void, n: c_int, level: c_int) {
+    let levels = unsafe { &mut *opaque.cast::<Vec<(c_int, c_int)>>() };
+    levels.push((n, level));
+}
+
+#[test]
+/// Toggle the output lines of a device and check what reaches the sink.
+fn test_irq_line() {
+    init_qom();
+    let mut levels: Vec<(c_int, c_int)> = Vec::new();
+    let p: *mut DummyIrqState = unsafe { object_new(DummyIrqState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyIrqState = unsafe { &*p };
+    let name = c_str!("irq-sink");
+    unsafe {
+        let irq = qemu_allocate_irq(Some(record_irq), addr_of_mut!(levels).cast::<c_void>(), 7);
+        object_property_add_child(object_get_root(), name.as_ptr(), irq.cast::<Object>());
+        qdev_connect_gpio_out(p.cast::<DeviceState>(), 1, irq);
+
+        // Line 0 is not connected and toggling it is a no-op.
+        assert!(!p_ref.out[0].is_connected());
+        assert!(p_ref.out[1].is_connected());
+        p_ref.toggle(0);
+        p_ref.toggle(1);
+        p_ref.toggle(1);
+        p_ref.out[1].raise();
+        p_ref.out[1].lower();
+
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+        object_unparent(irq.cast::<Object>());
+        qemu_free_irq(irq);
+    }
+    assert_eq!(levels, [(7, 1), (7, 0), (7, 1), (7, 0)]);
+}
+
+#[test]
+/// Cast a device to an interface that it implements, and call the
+/// interface's method.
+fn test_interface() {
+    init_qom();
+    let obj = unsafe { object_new(DummyIfaceState::TYPE_NAME.as_ptr()) };
+    let other = unsafe { object_new(DummyIrqState::TYPE_NAME.as_ptr()) };
+    let obj_ref = unsafe { &*obj };
+    let other_ref = unsafe { &*other };
+
+    let iface = obj_ref.dynamic_cast::<DummyInterface>().unwrap();
+    assert_eq!(iface.get_class().get_id.unwrap()(iface.as_object()), 42);
+    assert!(other_ref.dynamic_cast::<DummyInterface>().is_none());
+
+    unsafe {
+        object_unref(obj.cast::<c_void>());
+        object_unref(other.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Pulse input lines in two GPIO banks and check that the right
+/// instance received the changes.
+fn test_gpio_in() {
+    init_qom();
+    let p1: *mut DummyGpioInState =
+        unsafe { object_new(DummyGpioInState::TYPE_NAME.as_ptr()).cast() };
+    let p2: *mut DummyGpioInState =
+        unsafe { object_new(DummyGpioInState::TYPE_NAME.as_ptr()).cast() };
+    let name = c_str!("wake");
+    unsafe {
+        let irq = qdev_get_gpio_in(p2.cast::<DeviceState>(), 2);
+        qemu_set_irq(irq, 1);
+        qemu_set_irq(irq, 0);
+        let wake = qdev_get_gpio_in_named(p2.cast::<DeviceState>(), name.as_ptr(), 1);
+        qemu_set_irq(wake, 1);
+        qemu_set_irq(wake, 0);
+
+        assert_eq!((*p1).n_events.get(), 0);
+        assert_eq!((*p2).n_events.get(), 4);
+        assert_eq!(
+            (*p2).events.get(),
+            [(0, 2, true), (0, 2, false), (1, 1, true), (1, 1, false)]
+        );
+
+        object_unref(p1.cast::<c_void>());
+        object_unref(p2.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Create a named bank of eight inputs, and check that pulsing lines
+/// through the returned handle passes the right line to the callback.
+fn test_gpio_in_bank() {
+    init_qom();
+    let dev: *mut DummyGpioBankState =
+        unsafe { object_new(DummyGpioBankState::TYPE_NAME.as_ptr()).cast() };
+    let dev_ref: &DummyGpioBankState = unsafe { &*dev };
+    let name = c_str!("irq");
+
+    let bank = dev_ref.init_gpio_in_named(name, 8, &DummyGpioBankState::gpio_in);
+    assert_eq!(bank.len(), 8);
+    assert!(bank.get(8).is_none());
+    for line in [0, 3, 7] {
+        bank[line].raise();
+        bank[line].lower();
+    }
+    assert_eq!(dev_ref.n_events.get(), 6);
+    assert_eq!(
+        dev_ref.events.get()[..6],
+        [
+            (0, true),
+            (0, false),
+            (3, true),
+            (3, false),
+            (7, true),
+            (7, false)
+        ]
+    );
+
+    // Extending the bank numbers the new lines after the old ones.
+    let more = dev_ref.init_gpio_in_named(name, 2, &DummyGpioBankState::gpio_in);
+    assert!(more.get(0).is_none());
+    more[9].raise();
+    assert_eq!(dev_ref.events.get()[6], (9, true));
+
+    // Named outputs of another device can be wired to the bank.
+    let src: *mut DummyGpioBankState =
+        unsafe { object_new(DummyGpioBankState::TYPE_NAME.as_ptr()).cast() };
+    let src_ref: &DummyGpioBankState = unsafe { &*src };
+    // SAFETY: `out` is only dropped after the device is freed
+    let out = unsafe { src_ref.init_gpio_out_named(c_str!("out"), 2) };
+    src_ref.connect_gpio_out(Some(c_str!("out")), 1, &bank[3]);
+    assert!(!out[0].is_connected());
+    assert!(out[1].is_connected());
+    out[1].raise();
+    assert_eq!(dev_ref.n_events.get(), 8);
+    assert_eq!(dev_ref.events.get()[7], (3, true));
+
+    unsafe {
+        object_unref(src.cast::<c_void>());
+        object_unref(dev.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Wire the outputs of a device to both GPIO banks of another, and check
+/// that raising an output reaches the right input.
+fn test_connect_gpio_out() {
+    init_qom();
+    let src: *mut DummyIrqState = unsafe { object_new(DummyIrqState::TYPE_NAME.as_ptr()).cast() };
+    let dst: *mut DummyGpioInState =
+        unsafe { object_new(DummyGpioInState::TYPE_NAME.as_ptr()).cast() };
+    let src_ref: &DummyIrqState = unsafe { &*src };
+    let dst_ref: &DummyGpioInState = unsafe { &*dst };
+
+    src_ref.connect_gpio_out(None, 0, &dst_ref.get_gpio_in(None, 2));
+    src_ref.connect_gpio_out(None, 1, &dst_ref.get_gpio_in(Some(c_str!("wake")), 1));
+    assert!(src_ref.out[0].is_connected());
+    assert!(src_ref.out[1].is_connected());
+    src_ref.out[0].raise();
+    src_ref.out[1].raise();
+    src_ref.out[0].lower();
+
+    // The input can also be driven directly.
+    dst_ref.get_gpio_in(None, 0).raise();
+    assert_eq!(dst_ref.n_events.get(), 4);
+    assert_eq!(
+        dst_ref.events.get(),
+        [(0, 2, true), (1, 1, true), (0, 2, false), (0, 0, true)]
+    );
+
+    unsafe {
+        object_unref(src.cast::<c_void>());
+        object_unref(dst.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Do what a board does with a sysbus device: map its region at a base
+/// address and connect its interrupt to the input of another device.
+/// Then check that guest accesses reach the device and the interrupt
+/// reaches the sink.
+fn test_sysbus_map_and_connect() {
+    const BASE: hwaddr = 0x1000_0000;
+
+    init_qom();
+    let dev = Device::<DummySysBusState>::new(DummySysBusState::TYPE_NAME);
+    let bus = unsafe { &*sysbus_get_default() };
+    let dev = dev.realize_and_unref(Some(bus)).unwrap();
+    let sink: *mut DummyGpioInState =
+        unsafe { object_new(DummyGpioInState::TYPE_NAME.as_ptr()).cast() };
+    let sink_ref: &DummyGpioInState = unsafe { &*sink };
+
+    dev.mmio_map(0, BASE);
+    dev.connect_irq(0, &sink_ref.get_gpio_in(None, 1));
+    assert!(dev.irq.is_connected());
+
+    phys_mem_write(BASE, &0x1234_5678u32.to_le_bytes()).unwrap();
+    assert_eq!(dev.data.get(), 0x1234_5678);
+    let mut buf = [0u8; 4];
+    phys_mem_read(BASE, &mut buf).unwrap();
+    assert_eq!(u32::from_le_bytes(buf), 0x1234_5678);
+
+    phys_mem_write(BASE + 4, &1u32.to_le_bytes()).unwrap();
+    assert_eq!(sink_ref.n_events.get(), 1);
+    assert_eq!(sink_ref.events.get()[0], (0, 1, true));
+
+    unsafe {
+        object_unparent(dev.as_object_mut_ptr());
+        object_unref(sink.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Disable a mapped region and check that accesses no longer reach the
+/// device, then move it and enable it again.
+fn test_memory_region_remap() {
+    const BASE: hwaddr = 0x1200_0000;
+    const NEW_BASE: hwaddr = 0x1300_0000;
+
+    init_qom();
+    let dev = Device::<DummySysBusState>::new(DummySysBusState::TYPE_NAME);
+    let bus = unsafe { &*sysbus_get_default() };
+    let dev = dev.realize_and_unref(Some(bus)).unwrap();
+    dev.mmio_map(0, BASE);
+    phys_mem_write(BASE, &1u32.to_le_bytes()).unwrap();
+    assert_eq!(dev.data.get(), 1);
+
+    dev.iomem.set_enabled(false);
+    phys_mem_write(BASE, &2u32.to_le_bytes()).unwrap_err();
+    assert_eq!(dev.data.get(), 1);
+
+    // moving a disabled region does not enable it
+    dev.iomem.set_address(NEW_BASE);
+    phys_mem_write(NEW_BASE, &3u32.to_le_bytes()).unwrap_err();
+    assert_eq!(dev.data.get(), 1);
+
+    dev.iomem.set_enabled(true);
+    phys_mem_write(BASE, &4u32.to_le_bytes()).unwrap_err();
+    phys_mem_write(NEW_BASE, &5u32.to_le_bytes()).unwrap();
+    assert_eq!(dev.data.get(), 5);
+
+    unsafe {
+        object_unparent(dev.as_object_mut_ptr());
+    }
+}
+
+#[test]
+/// Map RAM for writing and reading, and check that writes through the
+/// mapping reach guest memory once it is dropped.  A mapping that crosses
+/// the end of the RAM is shorter than requested.
+fn test_dma_mapping() {
+    init_qom();
+    let dev: *mut DummyRamState = unsafe { object_new(DummyRamState::TYPE_NAME.as_ptr()).cast() };
+    let dev_ref: &DummyRamState = unsafe { &*dev };
+    let space = AddressSpace::new(&dev_ref.ram, c_str!("dummy-dma"));
+    let data: Vec<u8> = (1..=16).collect();
+
+    let mut wmap = space.map_write(0x100, 16).unwrap();
+    assert_eq!(wmap.len(), 16);
+    wmap.write(0, &data);
+    drop(wmap);
+
+    let mut buf = [0u8; 16];
+    space.read(0x100, &mut buf).unwrap();
+    assert_eq!(buf[..], data[..]);
+    let rmap = space.map_read(0x100, 16).unwrap();
+    buf = [0u8; 16];
+    rmap.read(0, &mut buf);
+    assert_eq!(buf[..], data[..]);
+    rmap.read(12, &mut buf[..4]);
+    assert_eq!(buf[..4], data[12..]);
+    drop(rmap);
+
+    let mut partial = space.map_write(0xff8, 16).unwrap();
+    assert_eq!(partial.len(), 8);
+    partial.write(0, &[0xff; 8]);
+    partial.set_access_len(4);
+    drop(partial);
+    space.read(0xff8, &mut buf[..8]).unwrap();
+    assert_eq!(buf[..8], [0xff; 8]);
+
+    drop(space);
+    unsafe {
+        object_unref(dev.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Read the initial contents of a ROM and of a ROM device, and check that
+/// writes to the ROM are dropped while writes to the ROM device reach it.
+fn test_rom_regions() {
+    init_qom();
+    let dev: *mut DummyRomState = unsafe { object_new(DummyRomState::TYPE_NAME.as_ptr()).cast() };
+    let contents: Vec<u8> = (0..=255).collect();
+    let dev_ref: &DummyRomState = unsafe { &*dev };
+    dev_ref.rom.write_ram(0, &contents);
+    dev_ref.flash.write_ram(0, &[0xee; 0x100]);
+    let mut readback = [0u8; 4];
+    dev_ref.rom.read_ram(0xfc, &mut readback);
+    assert_eq!(readback, [0xfc, 0xfd, 0xfe, 0xff]);
+    let rom_space = AddressSpace::new(&dev_ref.rom, c_str!("dummy-rom"));
+    let flash_space = AddressSpace::new(&dev_ref.flash, c_str!("dummy-flash"));
+
+    let mut buf = [0u8; 4];
+    rom_space.read(0x10, &mut buf).unwrap();
+    assert_eq!(buf, [0x10, 0x11, 0x12, 0x13]);
+    rom_space.write(0x10, &[0xaa; 4]).unwrap_err();
+    rom_space.read(0x10, &mut buf).unwrap();
+    assert_eq!(buf, [0x10, 0x11, 0x12, 0x13]);
+
+    // reads of the ROM device come from its contents, writes go to it
+    flash_space.read(0x20, &mut buf).unwrap();
+    assert_eq!(buf, [0xee; 4]);
+    flash_space
+        .write(0x20, &0x1234_5678u32.to_le_bytes())
+        .unwrap();
+    assert_eq!(dev_ref.flash_writes.get(), 1);
+    assert_eq!(dev_ref.last_flash_write.get(), (0x20, 0x1234_5678));
+    flash_space.read(0x20, &mut buf).unwrap();
+    assert_eq!(buf, [0xee; 4]);
+
+    // outside ROMD mode, reads go to the device as well
+    dev_ref.flash.set_romd(false);
+    flash_space.read(0x20, &mut buf).unwrap();
+    assert_eq!(u32::from_le_bytes(buf), 0x80);
+    dev_ref.flash.set_romd(true);
+    flash_space.read(0x20, &mut buf).unwrap();
+    assert_eq!(buf, [0xee; 4]);
+
+    drop(rom_space);
+    drop(flash_space);
+    unsafe {
+        object_unref(dev.cast::<c_void>());
+    }
+}
+
+/// The events that `dummy_iommu_notify` received, as pairs of the
+/// address and the mask of the entry.
+static IOMMU_EVENTS: BqlRefCell<Vec<(hwaddr, hwaddr)>> = BqlRefCell::new(Vec::new());
+
+unsafe extern "C" fn dummy_iommu_notify(_n: *mut IOMMUNotifier, entry: *mut IOMMUTLBEntry) {
+    let entry = unsafe { &*entry };
+    assert_eq!(entry.perm, IOMMU_NONE);
+    IOMMU_EVENTS
+        .borrow_mut()
+        .push((entry.iova, entry.addr_mask));
+}
+
+#[test]
+/// Translate an address through the class of an IOMMU region, and check
+/// that invalidations reach the notifiers that watch the address.
+fn test_iommu() {
+    init_qom();
+    let dev: *mut DummyIommuState =
+        unsafe { object_new(DummyIommuState::TYPE_NAME.as_ptr()).cast() };
+    let dev_ref: &DummyIommuState = unsafe { &*dev };
+    let iommu = dev_ref.iommu.as_mut_ptr();
+
+    let entry = unsafe {
+        let imrc = object_get_class(iommu.cast::<Object>()).cast::<IOMMUMemoryRegionClass>();
+        (*imrc).translate.unwrap()(iommu, 0x1234, IOMMU_RO, 0)
+    };
+    assert_eq!(dev_ref.translations.get(), 1);
+    assert_eq!(entry.iova, 0x1000);
+    assert_eq!(entry.translated_addr, 0x1000);
+    assert_eq!(entry.addr_mask, 0xfff);
+    assert_eq!(entry.perm, IOMMU_RW);
+
+    let mut notifier = IOMMUNotifier {
+        notify: Some(dummy_iommu_notify),
+        notifier_flags: IOMMU_NOTIFIER_UNMAP,
+        start: 0,
+        end: 0xffff,
+        iommu_idx: 0,
+        ..Default::default()
+    };
+    let mr = dev_ref.iommu.as_memory_region().as_mut_ptr();
+    unsafe {
+        memory_region_register_iommu_notifier(
+            mr,
+            addr_of_mut!(notifier),
+            addr_of_mut!(error_abort),
+        );
+    }
+
+    dev_ref.iommu.invalidate(0x2000, 0xfff);
+    // outside the range that the notifier watches
+    dev_ref.iommu.invalidate(0x10000, 0xfff);
+    // the notifier does not ask for map events
+    dev_ref.iommu.notify(&IOMMUTLBEvent {
+        type_: IOMMU_NOTIFIER_MAP,
+        entry,
+    });
+    assert_eq!(*IOMMU_EVENTS.borrow(), [(0x2000, 0xfff)]);
+
+    unsafe {
+        memory_region_unregister_iommu_notifier(mr, addr_of_mut!(notifier));
+    }
+    dev_ref.iommu.invalidate(0x3000, 0xfff);
+    assert_eq!(IOMMU_EVENTS.borrow().len(), 1);
+
+    unsafe {
+        object_unref(dev.cast::<c_void>());
+    }
+}
+
+#[derive(Default)]
+pub struct DummyTimer {
+    timer: Timer,
+    fired: BqlCell<u32>,
+}
+
+impl DummyTimer {
+    fn expired(&self) {
+        self.fired.set(self.fired.get() + 1);
+    }
+}
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+#[derive(Default)]
+pub struct DummyTimers {
+    timer: Timer,
+    boxed: Box<Timer>,
+}
+
+impl DummyTimers {
+    #[allow(clippy::missing_const_for_fn)]
+    fn expired(&self) {}
+
+    fn init(&self) {
+        let clock = QEMUClockType::QEMU_CLOCK_VIRTUAL;
+        self.timer.init(clock, &Self::expired, self);
+        self.boxed.init(clock, &Self::expired, self);
+    }
+}
+
+pub static VMSTATE_TIMERS: VMStateDescription = VMStateDescription {
+    name: c_str!("timers").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_timer!(DummyTimers, timer),
+        vmstate_timer!(DummyTimers, boxed),
+    },
+    ..Zeroable::ZERO
+};
+
+/// Create the main loop, which also initializes the clocks.
+fn init_main_loop_once() {
+    static ONCE: BqlCell<bool> = BqlCell::new(false);
+
+    if !ONCE.get() {
+        unsafe {
+            qemu_init_main_loop(addr_of_mut!(error_abort));
+            qemu_clock_enable(QEMUClockType::QEMU_CLOCK_VIRTUAL, true);
+        }
+        ONCE.set(true);
+    }
+}
+
+/// Wait for `clock` to reach `deadline` and run the expired timers.
+fn run_timers_at(clock: QEMUClockType, deadline: i64) {
+    // Outside qtest, the virtual clock follows the host clock.
+    while clock.get_ns() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    unsafe {
+        qemu_clock_run_timers(clock);
+    }
+}
+
+#[test]
+/// Let the virtual clock advance, and read the new value with
+/// `clock_get_ns`.
+fn test_clock_get_ns() {
+    init_qom();
+    init_main_loop_once();
+    assert_eq!(
+        QEMUClockType::from(ClockType::Virtual),
+        QEMUClockType::QEMU_CLOCK_VIRTUAL
+    );
+
+    let start = clock_get_ns(ClockType::Virtual);
+    run_timers_at(QEMUClockType::QEMU_CLOCK_VIRTUAL, start + 1_000_000);
+    let now = clock_get_ns(ClockType::Virtual);
+    assert!(now >= start + 1_000_000);
+    assert!(now <= QEMUClockType::QEMU_CLOCK_VIRTUAL.get_ns());
+    assert!(clock_get_ns(ClockType::Realtime) > 0);
+}
+
+#[test]
+#[allow(clippy::shadow_unrelated)]
+/// Arm a timer on the virtual clock and check that it fires once.
+fn test_timer() {
+    init_qom();
+    init_main_loop_once();
+    let clock = QEMUClockType::QEMU_CLOCK_VIRTUAL;
+    let t = Box::new(DummyTimer::default());
+    t.timer.init(clock, &DummyTimer::expired, &*t);
+
+    let deadline = clock.get_ns() + 1_000_000;
+    t.timer.mod_ns(deadline);
+    assert!(t.timer.is_pending());
+    unsafe {
+        qemu_clock_run_timers(clock);
+    }
+    assert_eq!(t.fired.get(), 0);
+
+    run_timers_at(clock, deadline);
+    assert_eq!(t.fired.get(), 1);
+    assert!(!t.timer.is_pending());
+    run_timers_at(clock, deadline);
+    assert_eq!(t.fired.get(), 1);
+
+    // del() disarms the timer.
+    let deadline = clock.get_ns() + 1_000_000;
+    t.timer.mod_ns(deadline);
+    t.timer.del();
+    run_timers_at(clock, deadline);
+    assert_eq!(t.fired.get(), 1);
+
+    // Dropping the timer removes it from the timer list, so that
+    // the callback does not access freed memory.
+    let deadline = clock.get_ns() + 1_000_000;
+    t.timer.mod_ns(deadline);
+    drop(t);
+    run_timers_at(clock, deadline);
+}
+
+#[derive(Default)]
+pub struct DummyBh {
+    bh: BottomHalf,
+    runs: BqlCell<u32>,
+}
+
+impl DummyBh {
+    fn bh_cb(&self) {
+        self.runs.set(self.runs.get() + 1);
+    }
+}
+
+#[test]
+/// Schedule a bottom half and check that the main loop runs it once.
+fn test_bottom_half() {
+    init_qom();
+    init_main_loop_once();
+    let ctx = unsafe { qemu_get_aio_context() };
+    let b = Box::new(DummyBh::default());
+    b.bh.init(&DummyBh::bh_cb, &*b);
+
+    b.bh.schedule();
+    b.bh.schedule();
+    assert_eq!(b.runs.get(), 0);
+    unsafe {
+        aio_bh_poll(ctx);
+    }
+    assert_eq!(b.runs.get(), 1);
+    unsafe {
+        aio_bh_poll(ctx);
+    }
+    assert_eq!(b.runs.get(), 1);
+
+    b.bh.schedule();
+    b.bh.cancel();
+    unsafe {
+        aio_bh_poll(ctx);
+    }
+    assert_eq!(b.runs.get(), 1);
+
+    // Dropping the bottom half deletes it, so that the callback does not
+    // access freed memory.
+    b.bh.schedule();
+    drop(b);
+    unsafe {
+        aio_bh_poll(ctx);
+    }
+}
+
+#[test]
+/// Check that the deadline of inline and boxed timers is migrated.
+fn test_vmstate_timer() {
+    init_qom();
+    init_main_loop_once();
+    let clock = QEMUClockType::QEMU_CLOCK_VIRTUAL;
+    let fields = unsafe { std::slice::from_raw_parts(VMSTATE_TIMERS.fields, 2) };
+    assert_eq!(fields[0].flags, VMStateFlags::VMS_SINGLE);
+    assert_eq!(
+        fields[1].flags,
+        VMStateFlags(VMStateFlags::VMS_SINGLE.0 | VMStateFlags::VMS_POINTER.0)
+    );
+
+    let mut saved = Box::new(DummyTimers::default());
+    saved.init();
+    let mut loaded = Box::new(DummyTimers::default());
+    loaded.init();
+
+    // the armed timer is armed on the destination with the same deadline,
+    // while the idle one disarms the destination's timer
+    let deadline = clock.get_ns() + 1_000_000_000;
+    saved.timer.mod_ns(deadline);
+    loaded.boxed.mod_ns(deadline);
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_TIMERS, &mut *saved, &mut *loaded),
+        16
+    );
+    assert!(loaded.timer.is_pending());
+    assert_eq!(
+        unsafe { (*loaded.timer.as_mut_ptr()).expire_time },
+        deadline
+    );
+    assert!(!loaded.boxed.is_pending());
+}
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+pub struct DummyFifo8 {
+    fifo: Fifo8,
+}
+
+pub static VMSTATE_FIFO8: VMStateDescription = VMStateDescription {
+    name: c_str!("fifo8").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_fifo8!(DummyFifo8, fifo),
+    },
+    ..Zeroable::ZERO
+};
+
+#[test]
+/// Check the capacity of a `Fifo8` and its behavior when wrapping around.
+fn test_fifo8() {
+    let mut fifo = Fifo8::new(4);
+    assert_eq!(fifo.capacity(), 4);
+    assert!(fifo.is_empty());
+    assert_eq!(fifo.pop(), None);
+    assert_eq!(fifo.peek(), None);
+
+    for i in 1..=4 {
+        assert!(!fifo.is_full());
+        fifo.push(i);
+    }
+    assert!(fifo.is_full());
+    assert_eq!(fifo.num_free(), 0);
+    panic::catch_unwind(panic::AssertUnwindSafe(|| fifo.push(5))).unwrap_err();
+    assert_eq!(fifo.len(), 4);
+
+    // the next pushes wrap around the end of the buffer
+    assert_eq!(fifo.pop(), Some(1));
+    assert_eq!(fifo.pop(), Some(2));
+    fifo.push(5);
+    fifo.push(6);
+    assert_eq!(fifo.peek(), Some(3));
+    let popped: Vec<u8> = std::iter::from_fn(|| fifo.pop()).collect();
+    assert_eq!(popped, [3, 4, 5, 6]);
+    assert!(fifo.is_empty());
+
+    fifo.push(7);
+    fifo.reset();
+    assert!(fifo.is_empty());
+    assert_eq!(fifo.num_free(), 4);
+}
+
+#[test]
+/// Migrate a `Fifo8` whose contents wrap around the end of the buffer,
+/// and check that the destination pops the same bytes.
+fn test_vmstate_fifo8() {
+    let mut saved = DummyFifo8 {
+        fifo: Fifo8::new(8),
+    };
+    for i in 0..8 {
+        saved.fifo.push(i);
+    }
+    for _ in 0..5 {
+        saved.fifo.pop();
+    }
+    for i in 8..12 {
+        saved.fifo.push(i);
+    }
+
+    let mut loaded = DummyFifo8 {
+        fifo: Fifo8::new(8),
+    };
+    loaded.fifo.push(0xff);
+    // the buffer, the head and the number of bytes
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_FIFO8, &mut saved, &mut loaded),
+        16
+    );
+    assert_eq!(loaded.fifo.len(), 7);
+    let expected: Vec<u8> = std::iter::from_fn(|| saved.fifo.pop()).collect();
+    let popped: Vec<u8> = std::iter::from_fn(|| loaded.fifo.pop()).collect();
+    assert_eq!(expected, [5, 6, 7, 8, 9, 10, 11]);
+    assert_eq!(popped, expected);
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct DummyExtra {
+    a: u32,
+    b: u64,
+}
+
+unsafe impl Zeroable for DummyExtra {
+    const ZERO: Self = DummyExtra { a: 0, b: 0 };
+}
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+pub struct DummyOptionalStruct {
+    extra: *mut DummyExtra,
+    ctrl: u32,
+}
+
+pub static VMSTATE_EXTRA: VMStateDescription = VMStateDescription {
+    name: c_str!("extra").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_uint32!(DummyExtra, a),
+        vmstate_uint64!(DummyExtra, b),
+    },
+    ..Zeroable::ZERO
+};
+
+pub static VMSTATE_OPTIONAL_STRUCT: VMStateDescription = VMStateDescription {
+    name: c_str!("optional-struct").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_struct_pointer!(DummyOptionalStruct, extra, addr_of!(VMSTATE_EXTRA), DummyExtra),
+        vmstate_uint32!(DummyOptionalStruct, ctrl),
+    },
+    ..Zeroable::ZERO
+};
+
+#[test]
+/// Migrate a pointer to an optional struct, both when it is set, which
+/// allocates the struct on the destination, and when it is null, which
+/// frees the struct that the destination had.
+fn test_vmstate_struct_pointer() {
+    let mut extra = DummyExtra { a: 7, b: 1 << 40 };
+    let mut saved = DummyOptionalStruct {
+        extra: addr_of_mut!(extra),
+        ctrl: 3,
+    };
+    let mut loaded = DummyOptionalStruct {
+        extra: ptr::null_mut(),
+        ctrl: 0,
+    };
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_OPTIONAL_STRUCT, &mut saved, &mut loaded),
+        1 + 4 + 8 + 4
+    );
+    assert!(!loaded.extra.is_null());
+    assert_eq!(unsafe { &*loaded.extra }, &extra);
+    assert_eq!(loaded.ctrl, 3);
+
+    // Loading into an existing struct does not allocate a new one.
+    let existing = loaded.extra;
+    unsafe {
+        (*saved.extra).a = 8;
+    }
+    vmstate_round_trip(&VMSTATE_OPTIONAL_STRUCT, &mut saved, &mut loaded);
+    assert_eq!(loaded.extra, existing);
+    assert_eq!(unsafe { (*loaded.extra).a }, 8);
+
+    // A null pointer is migrated as null.
+    saved.extra = ptr::null_mut();
+    saved.ctrl = 4;
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_OPTIONAL_STRUCT, &mut saved, &mut loaded),
+        1 + 4
+    );
+    assert!(loaded.extra.is_null());
+    assert_eq!(loaded.ctrl, 4);
+
+    let mut empty = DummyOptionalStruct {
+        extra: ptr::null_mut(),
+        ctrl: 0,
+    };
+    vmstate_round_trip(&VMSTATE_OPTIONAL_STRUCT, &mut saved, &mut empty);
+    assert!(empty.extra.is_null());
+
+    // The destination frees the struct that it allocated itself.
+    let mut allocated = DummyOptionalStruct {
+        extra: unsafe { g_malloc0(std::mem::size_of::<DummyExtra>()).cast() },
+        ctrl: 0,
+    };
+    vmstate_round_trip(&VMSTATE_OPTIONAL_STRUCT, &mut saved, &mut allocated);
+    assert!(allocated.extra.is_null());
+}
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+pub struct DummyBuffer {
+    sram: [u8; 256],
+    // only the first two elements are migrated
+    regs: [u16; 4],
+}
+
+pub static VMSTATE_BUFFER: VMStateDescription = VMStateDescription {
+    name: c_str!("buffer").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_buffer!(DummyBuffer, sram),
+        vmstate_buffer_unsafe!(DummyBuffer, regs, 4),
+    },
+    ..Zeroable::ZERO
+};
+
+#[test]
+/// Migrate a 256-byte buffer with a known pattern, and part of an array
+/// as raw bytes.
+fn test_vmstate_buffer() {
+    let mut saved = DummyBuffer {
+        sram: [0; 256],
+        regs: [0x1234, 0x5678, 0x9abc, 0xdef0],
+    };
+    for (i, b) in saved.sram.iter_mut().enumerate() {
+        *b = (i as u8).wrapping_mul(37) ^ 0xa5;
+    }
+    let mut loaded = DummyBuffer {
+        sram: [0; 256],
+        regs: [0; 4],
+    };
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_BUFFER, &mut saved, &mut loaded),
+        256 + 4
+    );
+    assert_eq!(loaded.sram, saved.sram);
+    assert_eq!(loaded.regs, [0x1234, 0x5678, 0, 0]);
+}
+
+#[derive(Default)]
+pub struct DummyChardev {
+    backend: CharBackend,
+    space: BqlCell<u32>,
+    received: BqlRefCell<Vec<u8>>,
+    events: BqlRefCell<Vec<QEMUChrEvent>>,
+}
+
+impl CharBackendHandlers for DummyChardev {
+    fn can_receive(&self) -> u32 {
+        self.space.get()
+    }
+
+    fn receive(&self, buf: &[u8]) {
+        let space = self.space.get();
+        assert!(buf.len() as u32 <= space);
+        self.space.set(space - buf.len() as u32);
+        self.received.borrow_mut().extend_from_slice(buf);
+    }
+
+    fn event(&self, event: QEMUChrEvent) {
+        self.events.borrow_mut().push(event);
+    }
+}
+
+#[test]
+/// Feed bytes and events from a null chardev into a frontend, and
+/// write bytes to the chardev.
+fn test_chardev() {
+    init_qom();
+    let chr = unsafe {
+        qemu_chardev_new(
+            c_str!("rust-null").as_ptr(),
+            TYPE_CHARDEV_NULL.as_ptr().cast::<c_char>(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            addr_of_mut!(error_abort),
+        )
+    };
+    let dev = Box::new(DummyChardev::default());
+    unsafe {
+        qemu_chr_fe_init(dev.backend.as_mut_ptr(), chr, addr_of_mut!(error_abort));
+    }
+    dev.backend.set_handlers(&*dev);
+
+    // can_receive() limits how much data the chardev sends
+    dev.space.set(3);
+    unsafe {
+        assert_eq!(qemu_chr_be_can_write(chr), 3);
+        qemu_chr_be_write(chr, b"ab".as_ptr(), 2);
+        assert_eq!(qemu_chr_be_can_write(chr), 1);
+        qemu_chr_be_write(chr, b"c".as_ptr(), 1);
+        assert_eq!(qemu_chr_be_can_write(chr), 0);
+        qemu_chr_be_event(chr, QEMUChrEvent::CHR_EVENT_BREAK);
+    }
+    assert_eq!(*dev.received.borrow(), b"abc");
+    assert_eq!(*dev.events.borrow(), [QEMUChrEvent::CHR_EVENT_BREAK]);
+
+    // the flow of data restarts after accept_input()
+    dev.space.set(1);
+    dev.backend.accept_input();
+    assert_eq!(unsafe { qemu_chr_be_can_write(chr) }, 1);
+
+    // the null chardev accepts and discards everything
+    assert_eq!(dev.backend.write(b"hello").unwrap(), 5);
+    dev.backend.write_all(b"world").unwrap();
+
+    // dropping the frontend disconnects it from the chardev
+    drop(dev);
+    assert_eq!(unsafe { qemu_chr_be_can_write(chr) }, 0);
+
+    // without a chardev, the data is dropped
+    let unconnected = CharBackend::new();
+    assert_eq!(unconnected.write(b"hello").unwrap(), 0);
+    unconnected.write_all(b"hello").unwrap();
+
+    unsafe {
+        object_unparent(chr.cast::<Object>());
+    }
+}
+
+#[test]
+/// Connect an output clock to an input clock and propagate changes
+/// from the former to the latter.
+fn test_clock() {
+    init_qom();
+    let src: *mut DummyClockState =
+        unsafe { object_new(DummyClockState::TYPE_NAME.as_ptr()).cast() };
+    let sink: *mut DummyClockState =
+        unsafe { object_new(DummyClockState::TYPE_NAME.as_ptr()).cast() };
+    let name = c_str!("clk_in");
+    unsafe {
+        let (src_ref, sink_ref) = (&*src, &*sink);
+        qdev_connect_clock_in(
+            sink.cast::<DeviceState>(),
+            name.as_ptr(),
+            src_ref.clk_out.as_ptr(),
+        );
+
+        src_ref.clk_out.as_ref().update_hz(1000);
+        assert_eq!(sink_ref.updates.get(), 1);
+        assert_eq!(sink_ref.last_period.get(), Clock::period_from_hz(1000));
+        assert_eq!(sink_ref.clk_in.as_ref().hz(), 1000);
+
+        // Setting the same period again does not call the callback.
+        src_ref.clk_out.as_ref().update_hz(1000);
+        assert_eq!(sink_ref.updates.get(), 1);
+
+        src_ref.clk_out.as_ref().update_ns(10);
+        assert_eq!(sink_ref.updates.get(), 2);
+        assert_eq!(sink_ref.last_period.get(), Clock::period_from_ns(10));
+        assert_eq!(src_ref.updates.get(), 0);
+
+        object_unref(sink.cast::<c_void>());
+        object_unref(src.cast::<c_void>());
+    }
+}
+
+pub static VMSTATE_DUMMY_CLOCK: VMStateDescription = VMStateDescription {
+    name: c_str!("dummy_clock").as_ptr(),
+    version_id: 1,
+    minimum_version_id: 1,
+    fields: vmstate_fields! {
+        vmstate_clock!(DummyClockState, clk_in),
+    },
+    ..Zeroable::ZERO
+};
+
+#[test]
+/// Migrate the period of an input clock to a device whose clock is not
+/// connected.
+fn test_vmstate_clock() {
+    init_qom();
+    let fields = unsafe { std::slice::from_raw_parts(VMSTATE_DUMMY_CLOCK.fields, 1) };
+    assert_eq!(
+        fields[0].flags,
+        VMStateFlags(VMStateFlags::VMS_STRUCT.0 | VMStateFlags::VMS_POINTER.0)
+    );
+
+    let src: *mut DummyClockState =
+        unsafe { object_new(DummyClockState::TYPE_NAME.as_ptr()).cast() };
+    let saved: *mut DummyClockState =
+        unsafe { object_new(DummyClockState::TYPE_NAME.as_ptr()).cast() };
+    let loaded: *mut DummyClockState =
+        unsafe { object_new(DummyClockState::TYPE_NAME.as_ptr()).cast() };
+    unsafe {
+        qdev_connect_clock_in(
+            saved.cast::<DeviceState>(),
+            c_str!("clk_in").as_ptr(),
+            (*src).clk_out.as_ptr(),
+        );
+        (*src).clk_out.as_ref().update_hz(1000);
+        assert_eq!((*loaded).clk_in.as_ref().hz(), 0);
+
+        vmstate_round_trip(&VMSTATE_DUMMY_CLOCK, &mut *saved, &mut *loaded);
+        assert_eq!((*loaded).clk_in.as_ref().hz(), 1000);
+        assert_eq!(
+            (*loaded).clk_in.as_ref().period(),
+            Clock::period_from_hz(1000)
+        );
+        // the callback only runs when the clock is updated
+        assert_eq!((*loaded).updates.get(), 0);
+
+        object_unref(loaded.cast::<c_void>());
+        object_unref(saved.cast::<c_void>());
+        object_unref(src.cast::<c_void>());
+    }
+}
+
+/// Release the mock BQL that is taken by `init_qom`, as if the test ran
+/// in a thread that does not hold it.
+fn bql_start_unlocked_test() {
+    cell::bql_start_test();
+    unsafe {
+        bql_unlock();
+    }
+    assert!(!cell::bql_locked());
+}
+
+#[test]
+/// Check that `BqlGuard` takes the BQL and releases it when dropped.
+fn test_bql_guard() {
+    bql_start_unlocked_test();
+    {
+        let _guard = BqlGuard::new();
+        assert!(cell::bql_locked());
+        assert_bql_locked();
+    }
+    assert!(!cell::bql_locked());
+
+    // Taking the lock again on the same thread panics, and the outer
+    // guard still owns the lock.
+    let guard = BqlGuard::new();
+    panic::catch_unwind(BqlGuard::new).unwrap_err();
+    assert!(cell::bql_locked());
+    drop(guard);
+    assert!(!cell::bql_locked());
+    cell::bql_start_test();
+}
+
+#[test]
+/// Check that `BqlGuard` releases the BQL when a panic unwinds past it.
+fn test_bql_guard_unwind() {
+    bql_start_unlocked_test();
+    let result = panic::catch_unwind(|| {
+        let _guard = BqlGuard::new();
+        assert!(cell::bql_locked());
+        panic!("unwinding with the BQL held");
+    });
+    assert!(result.is_err());
+    assert!(!cell::bql_locked());
+    cell::bql_start_test();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+/// Check that a device callback that is invoked without the BQL aborts.
+///
+/// The panic cannot unwind out of the callback, so the test runs itself
+/// again in a child process, which invokes the callback.
+fn test_callback_without_bql() {
+    const CHILD_ENV: &str = "QEMU_RUST_TEST_CALLBACK_WITHOUT_BQL";
+
+    struct Regs;
+
+    impl MmioOps for Regs {
+        fn read(&self, _offset: hwaddr, _size: u32) -> u64 {
+            0
+        }
+
+        fn write(&self, _offset: hwaddr, _size: u32, _value: u64) {}
+    }
+
+    if std::env::var_os(CHILD_ENV).is_some() {
+        bql_start_unlocked_test();
+        let mmio = Regs;
+        let read = mmio_ops::<Regs>().read.unwrap();
+        unsafe {
+            read(addr_of!(mmio) as *mut c_void, 0, 4);
+        }
+        unreachable!("callback ran without the BQL");
+    }
+
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args([
+            "--exact",
+            "test_callback_without_bql",
+            "--test-threads=1",
+            "--nocapture",
+        ])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("BQL not held"),
+        "unexpected output: {stderr}"
+    );
+}
+
+#[test]
+/// Check that the default value of a string property reaches the instance.
+fn test_string_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    unsafe {
+        let obj = p_ref.as_object_mut_ptr();
+        let value =
+            object_property_get_str(obj, c_str!("path").as_ptr(), addr_of_mut!(error_abort));
+        assert_eq!(CStr::from_ptr(value), c_str!("/dev/null"));
+        g_free(value.cast::<c_void>());
+
+        object_property_set_str(
+            obj,
+            c_str!("path").as_ptr(),
+            c_str!("/dev/zero").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(CStr::from_ptr(p_ref.path), c_str!("/dev/zero"));
+
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that a property with a range rejects values outside it.
+fn test_range_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    unsafe {
+        let obj = p_ref.as_object_mut_ptr();
+        let name = c_str!("level").as_ptr();
+        assert_eq!(
+            object_property_get_uint(obj, name, addr_of_mut!(error_abort)),
+            8
+        );
+
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_set_uint(obj, name, 16, addr_of_mut!(err)));
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(err)),
+            c_str!("Property dummy.level doesn't take value 16 (minimum: 1, maximum: 15)")
+        );
+        error_free(err);
+        assert_eq!(p_ref.level, 8);
+
+        assert!(object_property_set_uint(
+            obj,
+            name,
+            15,
+            addr_of_mut!(error_abort)
+        ));
+        assert_eq!(p_ref.level, 15);
+
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that a masked property rejects values with bits outside the mask.
+fn test_masked_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    unsafe {
+        let obj = p_ref.as_object_mut_ptr();
+        let name = c_str!("caps").as_ptr();
+        assert_eq!(
+            object_property_get_uint(obj, name, addr_of_mut!(error_abort)),
+            0x0100_0001
+        );
+
+        assert!(object_property_set_uint(
+            obj,
+            name,
+            0x8000_0080,
+            addr_of_mut!(error_abort)
+        ));
+        assert_eq!(p_ref.caps, 0x8000_0080);
+
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_set_uint(
+            obj,
+            name,
+            0x100,
+            addr_of_mut!(err)
+        ));
+        assert_eq!(
+            CStr::from_ptr(error_get_pretty(err)),
+            c_str!("Property value for 'caps' has bits outside mask '0xff0000ff'")
+        );
+        error_free(err);
+        assert_eq!(p_ref.caps, 0x8000_0080);
+
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check the typed property accessors of `DeviceMethods`.
+fn test_typed_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+
+    assert_eq!(p_ref.get_uint(c_str!("level")).unwrap(), 8);
+    p_ref.set_uint(c_str!("level"), 12).unwrap();
+    assert_eq!(p_ref.level, 12);
+    assert_eq!(p_ref.get_uint(c_str!("level")).unwrap(), 12);
+
+    let range_err = p_ref.set_uint(c_str!("level"), 16).unwrap_err();
+    assert_eq!(
+        range_err.message(),
+        c_str!("Property dummy.level doesn't take value 16 (minimum: 1, maximum: 15)")
+    );
+    assert_eq!(p_ref.level, 12);
+
+    assert!(!p_ref.get_bool(c_str!("migrate-clk")).unwrap());
+    p_ref.set_bool(c_str!("migrate-clk"), true).unwrap();
+    assert!(p_ref.migrate_clock);
+    assert!(p_ref.get_bool(c_str!("migrate-clk")).unwrap());
+
+    // unknown properties and type mismatches are errors, not aborts
+    let err = p_ref.get_uint(c_str!("no-such-prop")).unwrap_err();
+    assert_eq!(
+        err.message(),
+        c_str!("Property 'dummy.no-such-prop' not found")
+    );
+    p_ref.get_bool(c_str!("level")).unwrap_err();
+
+    unsafe {
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that defaults are encoded according to the width and sign of
+/// the property.
+fn test_property_defaults() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    assert_eq!(p_ref.bias, -1);
+    assert_eq!(p_ref.limit, u64::MAX);
+    unsafe {
+        let obj = p_ref.as_object_mut_ptr();
+        assert_eq!(
+            object_property_get_int(obj, c_str!("bias").as_ptr(), addr_of_mut!(error_abort)),
+            -1
+        );
+        assert_eq!(
+            object_property_get_uint(obj, c_str!("limit").as_ptr(), addr_of_mut!(error_abort)),
+            u64::MAX
+        );
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[derive(qemu_api_macros::offsets)]
+#[repr(C)]
+pub struct DummyWindow {
+    window: u16,
+}
+
+const WINDOW_SHIFT: u32 = 4;
+const WINDOW_BITS: u32 = 11;
+const WINDOW_MASK: u16 = ((1 << WINDOW_BITS) - 1) << WINDOW_SHIFT;
+
+#[test]
+/// Check that a default computed by a `const` expression of the field's
+/// type is stored as is.
+fn test_property_const_default() {
+    let prop = define_property!(
+        c_str!("window"),
+        DummyWindow,
+        window,
+        PropKind::U16,
+        u16,
+        default = WINDOW_MASK
+    );
+    assert!(prop.set_default);
+    assert_eq!(WINDOW_MASK, 0x7ff0);
+    assert_eq!(unsafe { prop.defval.u }, 0x7ff0);
+}
+
+#[test]
+/// Enumerate the properties of a device, and look up its path in the
+/// composition tree.
+fn test_property_iter() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    let props: Vec<(String, String)> = p_ref.property_iter().collect();
+    assert!(props.contains(&("migrate-clk".to_string(), "bool".to_string())));
+    assert!(props.contains(&("mode".to_string(), "enum".to_string())));
+    // inherited from TYPE_DEVICE
+    assert!(props.iter().any(|(name, _)| name == "realized"));
+
+    assert_eq!(p_ref.canonical_path(), None);
+    unsafe {
+        let obj = p_ref.as_object_mut_ptr();
+        object_property_add_child(object_get_root(), c_str!("dummy-path").as_ptr(), obj);
+        assert_eq!(p_ref.canonical_path().as_deref(), Some("/dummy-path"));
+        object_unparent(obj);
+        assert_eq!(p_ref.canonical_path(), None);
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that a drive property is registered, and that a device without a
+/// drive has no block backend.  The block layer is not linked into the
+/// tests, so no backend can be attached.
+fn test_drive_property() {
+    init_qom();
+    let p: *mut DummyDiskState = unsafe { object_new(DummyDiskState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyDiskState = unsafe { &*p };
+    let props: Vec<(String, String)> = p_ref.property_iter().collect();
+    assert!(props.contains(&("drive".to_string(), "str".to_string())));
+    assert!(p_ref.drive.backend().is_none());
+    unsafe {
+        object_unref(p.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that a netdev property is registered.  The network layer is not
+/// linked into the tests, so the NIC cannot be connected to a backend;
+/// its callbacks are tested in the `nic` module instead.
+fn test_netdev_property() {
+    init_qom();
+    let p: *mut DummyNicState = unsafe { object_new(DummyNicState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyNicState = unsafe { &*p };
+    let props: Vec<(String, String)> = p_ref.property_iter().collect();
+    assert!(props.contains(&("netdev".to_string(), "str".to_string())));
+    assert_eq!(p_ref.nic.mac_address(), [0; 6]);
+    unsafe {
+        object_unref(p.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that a `TypeInfo` whose `instance_size` is too small for the
+/// Rust struct is rejected before the type is registered.
+fn test_type_register_size() {
+    init_qom();
+    let result = panic::catch_unwind(qom::type_register::<DummyBadSizeState>);
+    let msg = result.unwrap_err();
+    let msg = msg.downcast_ref::<String>().unwrap();
+    assert!(msg.contains("instance_size"), "{}", msg);
+    assert!(unsafe { object_class_by_name(DummyBadSizeState::TYPE_NAME.as_ptr()) }.is_null());
+
+    // the checks pass for the types that use the default TypeInfo
+    assert_eq!(
+        DummyDiskState::TYPE_INFO.instance_size,
+        core::mem::size_of::<DummyDiskState>()
+    );
+}
+
+#[test]
+/// Check that `desc` sets the help text of a property, and that the
+/// others are left alone.
+fn test_property_description() {
+    init_qom();
+    unsafe {
+        let klass = object_class_by_name(DummyState::TYPE_NAME.as_ptr());
+        let level = &*object_class_property_find(klass, c_str!("level").as_ptr());
+        assert_eq!(
+            CStr::from_ptr(level.description),
+            c_str!("interrupt priority level (1-15)")
+        );
+        let bias = &*object_class_property_find(klass, c_str!("bias").as_ptr());
+        assert!(bias.description.is_null());
+    }
+}
+
+#[test]
+/// Check that bit properties on the same field can be toggled independently.
+fn test_bit_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    assert_eq!(p_ref.features, 1 << 0);
+    assert!(p_ref.get_bool(c_str!("feature-a")).unwrap());
+    assert!(!p_ref.get_bool(c_str!("feature-b")).unwrap());
+
+    p_ref.set_bool(c_str!("feature-b"), true).unwrap();
+    assert_eq!(p_ref.features, (1 << 0) | (1 << 5));
+    p_ref.set_bool(c_str!("feature-a"), false).unwrap();
+    assert_eq!(p_ref.features, 1 << 5);
+    assert!(!p_ref.get_bool(c_str!("feature-a")).unwrap());
+    assert!(p_ref.get_bool(c_str!("feature-b")).unwrap());
+
+    unsafe {
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that enum properties are set by name and store the discriminant.
+fn test_enum_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    assert_eq!(p_ref.mode, DummyMode::On as i32);
+    unsafe {
+        let obj = p_ref.as_object_mut_ptr();
+        let value =
+            object_property_get_str(obj, c_str!("mode").as_ptr(), addr_of_mut!(error_abort));
+        assert_eq!(CStr::from_ptr(value), c_str!("on"));
+        g_free(value.cast::<c_void>());
+
+        object_property_set_str(
+            obj,
+            c_str!("mode").as_ptr(),
+            c_str!("off").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(p_ref.mode, DummyMode::Off as i32);
+        object_property_set_str(
+            obj,
+            c_str!("mode").as_ptr(),
+            c_str!("auto").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        assert_eq!(p_ref.mode, DummyMode::Auto as i32);
+
+        // unknown names are rejected and leave the field alone
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_set_str(
+            obj,
+            c_str!("mode").as_ptr(),
+            c_str!("sometimes").as_ptr(),
+            addr_of_mut!(err)
+        ));
+        error_free(err);
+        assert_eq!(p_ref.mode, DummyMode::Auto as i32);
+
+        object_unref(obj.cast::<c_void>());
+    }
+}
+
+#[test]
+/// Check that a field exposed with `add_uint_ptr_property` is read-only
+/// and always returns the current value.
+fn test_uint_ptr_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    p_ref.add_uint_ptr_property(c_str!("counter"), &p_ref.counter);
+    assert_eq!(p_ref.get_uint(c_str!("counter")).unwrap(), 0);
+
+    p_ref.counter.set(p_ref.counter.get() + 1);
+    assert_eq!(p_ref.get_uint(c_str!("counter")).unwrap(), 1);
 
-qom_isa!(DummyState: Object, DeviceState);
+    let err = p_ref.set_uint(c_str!("counter"), 5).unwrap_err();
+    assert_eq!(
+        err.message(),
+        c_str!("Property 'dummy.counter' is not writable")
+    );
+    assert_eq!(p_ref.counter.get(), 1);
 
-pub struct DummyClass {
-    parent_class: <DeviceState as ObjectType>::Class,
+    unsafe {
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+    }
 }
 
-declare_properties! {
-    DUMMY_PROPERTIES,
-        define_property!(
-            c_str!("migrate-clk"),
-            DummyState,
-            migrate_clock,
-            unsafe { &qdev_prop_bool },
-            bool
-        ),
+#[test]
+/// Check that adding a property with an existing name fails instead of
+/// aborting, and leaves the existing property alone.
+fn test_try_add_uint_ptr_property() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    p_ref
+        .try_add_uint_ptr_property(c_str!("counter"), &p_ref.counter)
+        .unwrap();
+    let err = p_ref
+        .try_add_uint_ptr_property(c_str!("counter"), &p_ref.counter)
+        .unwrap_err();
+    assert_eq!(err.message(), c_str!("property 'counter' already exists"));
+
+    // class properties are found too
+    p_ref
+        .try_add_uint_ptr_property(c_str!("level"), &p_ref.counter)
+        .unwrap_err();
+
+    p_ref.counter.set(3);
+    assert_eq!(p_ref.get_uint(c_str!("counter")).unwrap(), 3);
+
+    unsafe {
+        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+    }
 }
 
-unsafe impl ObjectType for DummyState {
-    type Class = DummyClass;
-    const TYPE_NAME: &'static CStr = c_str!("dummy");
+#[test]
+#[should_panic(expected = "property field must be part of the object")]
+/// Check that `add_uint_ptr_property` refuses fields outside the object.
+fn test_uint_ptr_property_outside() {
+    init_qom();
+    let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
+    let p_ref: &DummyState = unsafe { &*p };
+    let outside = 0u32;
+    p_ref.add_uint_ptr_property(c_str!("outside"), &outside);
 }
 
-impl ObjectImpl for DummyState {
-    type ParentType = DeviceState;
-    const ABSTRACT: bool = false;
+#[test]
+/// Check that a link property only accepts objects of the right type.
+fn test_link_property() {
+    init_qom();
+    unsafe {
+        let obj = object_new(DummyState::TYPE_NAME.as_ptr());
+        let child = object_new(DummyChildState::TYPE_NAME.as_ptr());
+        let other = object_new(TYPE_RUST_DUMMY.as_ptr());
+        object_property_add_child(object_get_root(), c_str!("link-child").as_ptr(), child);
+        object_property_add_child(object_get_root(), c_str!("link-other").as_ptr(), other);
+
+        let name = c_str!("child").as_ptr();
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_set_link(
+            obj,
+            name,
+            other,
+            addr_of_mut!(err)
+        ));
+        assert!(!err.is_null());
+        error_free(err);
+        assert!((*obj.cast::<DummyState>()).child.is_null());
+
+        assert!(object_property_set_link(
+            obj,
+            name,
+            child,
+            addr_of_mut!(error_abort)
+        ));
+        assert_eq!((*obj.cast::<DummyState>()).child, child);
+        assert_eq!(
+            object_property_get_link(obj, name, addr_of_mut!(error_abort)),
+            child
+        );
+
+        object_unref(obj.cast::<c_void>());
+        object_unparent(other);
+        object_unparent(child);
+        object_unref(other.cast::<c_void>());
+        object_unref(child.cast::<c_void>());
+    }
 }
 
-impl DeviceImpl for DummyState {
-    fn properties() -> &'static [Property] {
-        &DUMMY_PROPERTIES
+#[test]
+/// Connect a device to a chardev by id, as `-device dummy,chardev=...`
+/// would do, and check that the connection is in place at realize time.
+fn test_chardev_property() {
+    init_qom();
+    let chr = unsafe {
+        qemu_chardev_new(
+            c_str!("rust-chr-prop").as_ptr(),
+            TYPE_CHARDEV_NULL.as_ptr().cast::<c_char>(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            addr_of_mut!(error_abort),
+        )
+    };
+    let dev = Device::<DummyState>::new(DummyState::TYPE_NAME);
+    let obj = unsafe { dev.as_object_mut_ptr() };
+    let name = c_str!("chardev").as_ptr();
+    assert!(!dev.chr.is_connected());
+    unsafe {
+        // unknown ids are rejected
+        let mut err: *mut Error = ptr::null_mut();
+        assert!(!object_property_set_str(
+            obj,
+            name,
+            c_str!("rust-chr-missing").as_ptr(),
+            addr_of_mut!(err)
+        ));
+        assert!(!err.is_null());
+        error_free(err);
+        assert!(!dev.chr.is_connected());
+
+        object_property_set_str(
+            obj,
+            name,
+            c_str!("rust-chr-prop").as_ptr(),
+            addr_of_mut!(error_abort),
+        );
+        assert!(dev.chr.is_connected());
+        assert_eq!((*dev.chr.as_mut_ptr()).chr, chr);
+        let value = object_property_get_str(obj, name, addr_of_mut!(error_abort));
+        assert_eq!(CStr::from_ptr(value), c_str!("rust-chr-prop"));
+        g_free(value.cast::<c_void>());
+
+        object_property_add_child(object_get_root(), c_str!("dummy-chr").as_ptr(), obj);
     }
-    fn vmsd() -> Option<&'static VMStateDescription> {
-        Some(&VMSTATE)
+
+    let owned = dev.realize_and_unref(None).unwrap();
+    assert!(owned.chr.is_connected());
+    drop(owned);
+    unsafe {
+        object_unparent(obj);
+        object_unparent(chr.cast::<Object>());
     }
 }
 
-// `impl<T> ClassInitImpl<DummyClass> for T` doesn't work since it violates
-// orphan rule.
-impl ClassInitImpl<DummyClass> for DummyState {
-    fn class_init(klass: &mut DummyClass) {
-        <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+#[test]
+/// Check that each `PropKind` maps to the corresponding `PropertyInfo`.
+fn test_prop_kind() {
+    #[allow(unused_unsafe)]
+    unsafe {
+        assert_eq!(PropKind::Bool.info(), addr_of!(qdev_prop_bool));
+        assert_eq!(PropKind::U8.info(), addr_of!(qdev_prop_uint8));
+        assert_eq!(PropKind::U16.info(), addr_of!(qdev_prop_uint16));
+        assert_eq!(PropKind::U32.info(), addr_of!(qdev_prop_uint32));
+        assert_eq!(PropKind::U64.info(), addr_of!(qdev_prop_uint64));
+        assert_eq!(PropKind::I32.info(), addr_of!(qdev_prop_int32));
+        assert_eq!(PropKind::Size.info(), addr_of!(qdev_prop_size));
+        assert_eq!(PropKind::String.info(), addr_of!(qdev_prop_string));
     }
+    assert_eq!(DUMMY_PROPERTIES[0].info, PropKind::Bool.info());
+    assert_eq!(DUMMY_PROPERTIES[1].info, PropKind::String.info());
 }
 
-#[derive(qemu_api_macros::offsets)]
-#[repr(C)]
-#[derive(qemu_api_macros::Object)]
-pub struct DummyChildState {
-    parent: ParentField<DummyState>,
+#[test]
+/// Check that `declare_properties!` accepts an empty list.
+fn test_empty_properties() {
+    assert!(EMPTY_PROPERTIES.is_empty());
+    assert!(EMPTY_PROPERTIES_COMMA.is_empty());
 }
 
-qom_isa!(DummyChildState: Object, DeviceState, DummyState);
+/// Save `saved` with `save_vmsd`, load the stream into `loaded` with
+/// `load_vmsd` and return the number of bytes that were written, or the
+/// error returned by the save or the load.  The stream is loaded as
+/// coming from version `save_vmsd.version_id`.
+fn vmstate_try_migrate<S, L>(
+    save_vmsd: &VMStateDescription,
+    load_vmsd: &VMStateDescription,
+    saved: &mut S,
+    loaded: &mut L,
+) -> Result<usize, c_int> {
+    init_qom();
+    unsafe {
+        let bioc = qio_channel_buffer_new(4096);
+        let ioc = bioc.cast::<QIOChannel>();
 
-pub struct DummyChildClass {
-    parent_class: <DummyState as ObjectType>::Class,
+        let fsave = qemu_file_new_output(ioc);
+        let save_ret = vmstate_save_state(
+            fsave,
+            save_vmsd,
+            (saved as *mut S).cast::<c_void>(),
+            ptr::null_mut(),
+        );
+        let mut result = Err(save_ret);
+        if save_ret == 0 {
+            assert_eq!(qemu_fflush(fsave), 0);
+            let usage = (*bioc).usage;
+
+            (*bioc).offset = 0;
+            let fload = qemu_file_new_input(ioc);
+            let load_ret = vmstate_load_state(
+                fload,
+                load_vmsd,
+                (loaded as *mut L).cast::<c_void>(),
+                save_vmsd.version_id,
+            );
+            result = if load_ret == 0 {
+                Ok(usage)
+            } else {
+                Err(load_ret)
+            };
+            qemu_fclose(fload);
+        }
+
+        qemu_fclose(fsave);
+        object_unref(bioc.cast::<c_void>());
+        result
+    }
 }
 
-unsafe impl ObjectType for DummyChildState {
-    type Class = DummyChildClass;
-    const TYPE_NAME: &'static CStr = c_str!("dummy_child");
+/// Save `saved` with `vmsd` and load the stream back into `loaded`.
+fn vmstate_try_round_trip<T>(
+    vmsd: &VMStateDescription,
+    saved: &mut T,
+    loaded: &mut T,
+) -> Result<usize, c_int> {
+    vmstate_try_migrate(vmsd, vmsd, saved, loaded)
 }
 
-impl ObjectImpl for DummyChildState {
-    type ParentType = DummyState;
-    const ABSTRACT: bool = false;
+/// Same as `vmstate_try_round_trip`, but both the save and the load
+/// must succeed.
+fn vmstate_round_trip<T>(vmsd: &VMStateDescription, saved: &mut T, loaded: &mut T) -> usize {
+    vmstate_try_round_trip(vmsd, saved, loaded).unwrap()
 }
 
-impl DeviceImpl for DummyChildState {}
+/// A device that migrates a list of variable-length records with custom
+/// handlers: the number of records, then the length and contents of each.
+#[derive(Default)]
+struct DummySaveVmState {
+    records: BqlRefCell<Vec<Vec<u8>>>,
+    generation: BqlCell<u64>,
+    savevm: SaveVmRegistration,
+}
 
-impl ClassInitImpl<DummyClass> for DummyChildState {
-    fn class_init(klass: &mut DummyClass) {
-        <Self as ClassInitImpl<DeviceClass>>::class_init(&mut klass.parent_class);
+impl SaveVmImpl for DummySaveVmState {
+    fn save_state(&self, f: &QemuFile) {
+        let records = self.records.borrow();
+        f.put_be64(self.generation.get());
+        f.put_be32(records.len() as u32);
+        for record in records.iter() {
+            f.put_byte(record.len() as u8);
+            f.put_buffer(record);
+        }
     }
-}
 
-impl ClassInitImpl<DummyChildClass> for DummyChildState {
-    fn class_init(klass: &mut DummyChildClass) {
-        <Self as ClassInitImpl<DummyClass>>::class_init(&mut klass.parent_class);
+    fn load_state(&self, f: &QemuFile, _version_id: u32) -> Result<(), i32> {
+        let generation = f.get_be64();
+        let count = f.get_be32();
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let mut record = vec![0; f.get_byte().into()];
+            f.get_buffer(&mut record);
+            records.push(record);
+        }
+        f.error()?;
+        self.generation.set(generation);
+        *self.records.borrow_mut() = records;
+        Ok(())
     }
 }
 
-fn init_qom() {
-    static ONCE: BqlCell<bool> = BqlCell::new(false);
+/// Run the save handler of `saved`, truncate the stream to `len` bytes if
+/// given, and run the load handler of `loaded` on it.
+fn savevm_try_round_trip<T: SaveVmImpl>(
+    saved: &T,
+    loaded: &T,
+    len: Option<usize>,
+) -> Result<usize, i32> {
+    init_qom();
+    unsafe {
+        let bioc = qio_channel_buffer_new(4096);
+        let ioc = bioc.cast::<QIOChannel>();
 
-    cell::bql_start_test();
-    if !ONCE.get() {
-        unsafe {
-            module_call_init(module_init_type::MODULE_INIT_QOM);
+        let fsave = qemu_file_new_output(ioc);
+        saved.save_state(QemuFile::from_raw(fsave));
+        assert_eq!(qemu_fflush(fsave), 0);
+        let usage = (*bioc).usage;
+        if let Some(len) = len {
+            (*bioc).usage = len;
         }
-        ONCE.set(true);
+
+        (*bioc).offset = 0;
+        let fload = qemu_file_new_input(ioc);
+        let result = loaded
+            .load_state(QemuFile::from_raw(fload), 1)
+            .map(|()| usage);
+        qemu_fclose(fload);
+        qemu_fclose(fsave);
+        object_unref(bioc.cast::<c_void>());
+        result
     }
 }
 
 #[test]
-/// Create and immediately drop an instance.
-fn test_object_new() {
+/// Migrate variable-length records through the `QemuFile` interface.
+fn test_savevm_round_trip() {
     init_qom();
+    let saved = DummySaveVmState::default();
+    saved.generation.set(0x0123_4567_89ab_cdef);
+    *saved.records.borrow_mut() = vec![b"first".to_vec(), Vec::new(), vec![0xa5; 200]];
+    let loaded = DummySaveVmState::default();
+    assert_eq!(
+        savevm_try_round_trip(&saved, &loaded, None),
+        Ok(8 + 4 + (1 + 5) + 1 + (1 + 200))
+    );
+    assert_eq!(loaded.generation.get(), 0x0123_4567_89ab_cdef);
+    assert_eq!(*loaded.records.borrow(), *saved.records.borrow());
+
+    // a truncated stream is rejected, and the state is left untouched
+    let truncated = DummySaveVmState::default();
+    *truncated.records.borrow_mut() = vec![b"old".to_vec()];
+    savevm_try_round_trip(&saved, &truncated, Some(20)).unwrap_err();
+    assert_eq!(truncated.generation.get(), 0);
+    assert_eq!(*truncated.records.borrow(), [b"old".to_vec()]);
+
+    // registering and unregistering the handlers
     unsafe {
-        object_unref(object_new(DummyState::TYPE_NAME.as_ptr()).cast());
-        object_unref(object_new(DummyChildState::TYPE_NAME.as_ptr()).cast());
+        saved.savevm.register(&saved, c_str!("dummy-savevm"), 0, 1);
+    }
+    saved.savevm.unregister();
+    // VMSTATE_INSTANCE_ID_ANY; unregistered when `saved` is dropped
+    unsafe {
+        saved
+            .savevm
+            .register(&saved, c_str!("dummy-savevm"), u32::MAX, 1);
     }
 }
 
 #[test]
-/// Try invoking a method on an object.
-fn test_typename() {
+/// Save a struct with `VMSTATE_REGS` and load it back into another one.
+fn test_vmstate_round_trip() {
+    let mut saved = DummyRegs {
+        ctrl: 0x1234_5678,
+        count: 0xdead_beef_0bad_cafe,
+        flags: 0x5a,
+    };
+    let mut loaded = DummyRegs::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_REGS, &mut saved, &mut loaded),
+        13
+    );
+    assert_eq!(loaded, saved);
+}
+
+#[test]
+/// Load a stream from version 1 of a description into version 2, which
+/// added a field; the new field keeps the value it had before the load.
+/// A stream from version 2 cannot be loaded by version 1.
+fn test_vmstate_versions() {
+    let mut saved = DummyVersioned {
+        ctrl: 0x1234_5678,
+        extra: 0xdead_beef,
+    };
+    let mut loaded = DummyVersioned { ctrl: 0, extra: 42 };
+    assert_eq!(
+        vmstate_try_migrate(
+            &VMSTATE_VERSIONED_V1,
+            &VMSTATE_VERSIONED_V2,
+            &mut saved,
+            &mut loaded
+        ),
+        Ok(4)
+    );
+    assert_eq!(
+        loaded,
+        DummyVersioned {
+            ctrl: 0x1234_5678,
+            extra: 42,
+        }
+    );
+
+    loaded = DummyVersioned::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_VERSIONED_V2, &mut saved, &mut loaded),
+        8
+    );
+    assert_eq!(loaded, saved);
+
+    vmstate_try_migrate(
+        &VMSTATE_VERSIONED_V2,
+        &VMSTATE_VERSIONED_V1,
+        &mut saved,
+        &mut loaded,
+    )
+    .unwrap_err();
+}
+
+#[test]
+/// Check the width of `vmstate_bool!` and `vmstate_bool_as_int!` fields,
+/// and that the latter is compatible with a `u32` on the other side.
+fn test_vmstate_bool_widths() {
+    for (narrow, wide) in [(false, true), (true, false)] {
+        let mut saved = DummyFlags { narrow, wide };
+        let mut loaded = DummyFlags::default();
+        assert_eq!(
+            vmstate_round_trip(&VMSTATE_FLAGS, &mut saved, &mut loaded),
+            5
+        );
+        assert_eq!(loaded, saved);
+    }
+
+    // any nonzero integer is true
+    let mut legacy = DummyLegacyFlags {
+        narrow: true,
+        wide: 7,
+    };
+    let mut flags = DummyFlags::default();
+    assert_eq!(
+        vmstate_try_migrate(
+            &VMSTATE_LEGACY_FLAGS,
+            &VMSTATE_FLAGS,
+            &mut legacy,
+            &mut flags
+        ),
+        Ok(5)
+    );
+    assert_eq!(
+        flags,
+        DummyFlags {
+            narrow: true,
+            wide: true
+        }
+    );
+
+    legacy = DummyLegacyFlags::default();
+    assert_eq!(
+        vmstate_try_migrate(
+            &VMSTATE_FLAGS,
+            &VMSTATE_LEGACY_FLAGS,
+            &mut flags,
+            &mut legacy
+        ),
+        Ok(5)
+    );
+    assert_eq!(
+        legacy,
+        DummyLegacyFlags {
+            narrow: true,
+            wide: 1
+        }
+    );
+}
+
+#[test]
+/// Check that `vmstate_unused!` keeps the stream compatible after a field
+/// is removed, in both directions.
+fn test_vmstate_unused() {
+    let mut old = DummyUntrimmed {
+        ctrl: 0x1234_5678,
+        legacy: 0xdead_beef,
+        flags: 0x5a,
+    };
+    let mut new = DummyTrimmed::default();
+    assert_eq!(
+        vmstate_try_migrate(&VMSTATE_UNTRIMMED, &VMSTATE_TRIMMED, &mut old, &mut new),
+        Ok(9)
+    );
+    assert_eq!(
+        new,
+        DummyTrimmed {
+            ctrl: 0x1234_5678,
+            flags: 0x5a
+        }
+    );
+
+    // the unused bytes are sent as zeros
+    new.ctrl = 0x0bad_cafe;
+    assert_eq!(
+        vmstate_try_migrate(&VMSTATE_TRIMMED, &VMSTATE_UNTRIMMED, &mut new, &mut old),
+        Ok(9)
+    );
+    assert_eq!(
+        old,
+        DummyUntrimmed {
+            ctrl: 0x0bad_cafe,
+            legacy: 0,
+            flags: 0x5a
+        }
+    );
+}
+
+#[test]
+/// Check that a nested struct is migrated together with its parent.
+fn test_vmstate_struct() {
+    let fields = unsafe { std::slice::from_raw_parts(VMSTATE_PARENT.fields, 3) };
+    assert_eq!(fields[1].flags, VMStateFlags::VMS_STRUCT);
+    assert_eq!(fields[1].size, std::mem::size_of::<DummyFifo>());
+    assert_eq!(fields[1].version_id, 2);
+    assert_eq!(fields[1].vmsd, addr_of!(VMSTATE_FIFO));
+
+    let mut saved = DummyParent {
+        ctrl: 0xcafe_f00d,
+        fifo: DummyFifo {
+            head: 0x0102_0304,
+            tail: 0x0506_0708,
+        },
+    };
+    let mut loaded = DummyParent::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_PARENT, &mut saved, &mut loaded),
+        12
+    );
+    assert_eq!(loaded, saved);
+}
+
+#[test]
+/// Check that a fixed-size register bank is migrated element by element.
+fn test_vmstate_array() {
+    let fields = unsafe { std::slice::from_raw_parts(VMSTATE_BANK.fields, 1) };
+    assert_eq!(fields[0].flags, VMStateFlags::VMS_ARRAY);
+    assert_eq!(fields[0].num, 8);
+    assert_eq!(fields[0].size, std::mem::size_of::<u32>());
+    #[allow(unused_unsafe)]
+    unsafe {
+        assert_eq!(fields[0].info, addr_of!(vmstate_info_uint32));
+    }
+
+    let mut saved = DummyBank {
+        regs: [
+            1,
+            2,
+            3,
+            4,
+            0xffff_fff8,
+            0xffff_fff9,
+            0xffff_fffa,
+            0xffff_fffb,
+        ],
+    };
+    let mut loaded = DummyBank::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_BANK, &mut saved, &mut loaded),
+        32
+    );
+    assert_eq!(loaded, saved);
+}
+
+#[test]
+/// Check that a heap buffer is migrated with the length found in another
+/// field, including when it is empty.
+fn test_vmstate_varray() {
+    let fields = unsafe { std::slice::from_raw_parts(VMSTATE_VARRAY.fields, 2) };
+    assert_eq!(
+        fields[1].flags,
+        VMStateFlags(VMStateFlags::VMS_VARRAY_UINT32.0 | VMStateFlags::VMS_POINTER.0)
+    );
+    assert_eq!(fields[1].num_offset, qemu_api::offset_of!(DummyVarray, len));
+    assert_eq!(fields[1].size, std::mem::size_of::<u32>());
+
+    let mut src = vec![0x0102_0304u32, 0x0506_0708, 0xdead_beef];
+    let mut dst = vec![0u32; 3];
+    let mut saved = DummyVarray {
+        len: 0,
+        buf: src.as_mut_ptr(),
+    };
+    let mut loaded = DummyVarray {
+        len: 3,
+        buf: dst.as_mut_ptr(),
+    };
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_VARRAY, &mut saved, &mut loaded),
+        4
+    );
+    assert_eq!(loaded.len, 0);
+    assert_eq!(dst, [0, 0, 0]);
+
+    saved.len = 3;
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_VARRAY, &mut saved, &mut loaded),
+        16
+    );
+    assert_eq!(loaded.len, 3);
+    assert_eq!(dst, src);
+}
+
+#[test]
+/// Check that a subsection is only sent when its `needed` callback
+/// returns true, and that a stream without it can still be loaded.
+fn test_vmstate_subsection() {
+    // needed: the subsection adds its header and the field
+    let mut saved = DummyOptional {
+        ctrl: 0x1234_5678,
+        has_extra: true,
+        extra: 0xcafe_f00d,
+    };
+    let mut loaded = DummyOptional::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_OPTIONAL, &mut saved, &mut loaded),
+        29
+    );
+    assert_eq!(loaded, saved);
+
+    // not needed: the subsection is absent and `extra` is not loaded
+    saved.has_extra = false;
+    loaded = DummyOptional::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_OPTIONAL, &mut saved, &mut loaded),
+        5
+    );
+    assert_eq!(loaded.ctrl, saved.ctrl);
+    assert!(!loaded.has_extra);
+    assert_eq!(loaded.extra, 0);
+
+    // the stream produced by the old description is the same
+    loaded = DummyOptional::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_OPTIONAL_OLD, &mut saved, &mut loaded),
+        5
+    );
+    assert_eq!(loaded.ctrl, saved.ctrl);
+}
+
+#[test]
+/// Check that a field whose `field_exists` callback returns false is
+/// neither saved nor loaded.
+fn test_vmstate_cond() {
+    let mut saved = DummyConditional {
+        fifo_mode: true,
+        ctrl: 0x1234_5678,
+        fifo: 0xcafe_f00d,
+    };
+    let mut loaded = DummyConditional {
+        fifo_mode: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_CONDITIONAL, &mut saved, &mut loaded),
+        8
+    );
+    assert_eq!(loaded, saved);
+
+    // without the mode flag, `fifo` is absent from the stream
+    saved.fifo_mode = false;
+    loaded = DummyConditional::default();
+    assert_eq!(
+        vmstate_round_trip(&VMSTATE_CONDITIONAL, &mut saved, &mut loaded),
+        4
+    );
+    assert_eq!(loaded.ctrl, saved.ctrl);
+    assert_eq!(loaded.fifo, 0);
+}
+
+#[test]
+/// Check that `post_load` can rebuild derived state, and that errors from
+/// `pre_save` and `post_load` make the migration fail.
+fn test_vmstate_hooks() {
+    let mut saved = DummyHooks {
+        value: 21,
+        ..Default::default()
+    };
+    let mut loaded = DummyHooks::default();
+    assert_eq!(
+        vmstate_try_round_trip(&VMSTATE_HOOKS, &mut saved, &mut loaded),
+        Ok(4)
+    );
+    assert_eq!(loaded.value, 21);
+    assert_eq!(loaded.doubled.get(), 42);
+
+    // pre_save rejects the state, nothing is loaded
+    saved.value = 0;
+    loaded = DummyHooks::default();
+    assert_eq!(
+        vmstate_try_round_trip(&VMSTATE_HOOKS, &mut saved, &mut loaded),
+        Err(-22)
+    );
+    assert_eq!(loaded.doubled.get(), 0);
+
+    // post_load rejects the state
+    saved.value = u32::MAX;
+    loaded = DummyHooks::default();
+    assert_eq!(
+        vmstate_try_round_trip(&VMSTATE_HOOKS, &mut saved, &mut loaded),
+        Err(-22)
+    );
+    assert_eq!(loaded.doubled.get(), 0);
+}
+
+#[derive(qemu_api_macros::Migrate)]
+#[migrate(name = "migrate-iommu", priority = MIG_PRI_IOMMU)]
+#[derive(Debug, Default)]
+pub struct DummyMigrateIommu {
+    root: u64,
+}
+
+#[derive(qemu_api_macros::Migrate)]
+#[migrate(name = "migrate-pci-bus", priority = MIG_PRI_PCI_BUS)]
+#[derive(Debug, Default)]
+pub struct DummyMigratePciBus {
+    config: u32,
+}
+
+#[test]
+/// Check that `#[migrate(priority = ...)]` sets the priority of the
+/// description.  savevm.c is not linked into the tests, so the order in
+/// which the descriptions are saved cannot be checked directly; it sorts
+/// them by decreasing priority, and the PCI bus must come first.
+fn test_derive_migrate_priority() {
+    assert_eq!(
+        DummyMigrate::vmsd().priority,
+        MigrationPriority::MIG_PRI_DEFAULT
+    );
+    let iommu = DummyMigrateIommu::vmsd();
+    let pci_bus = DummyMigratePciBus::vmsd();
+    assert_eq!(iommu.priority, MigrationPriority::MIG_PRI_IOMMU);
+    assert_eq!(pci_bus.priority, MigrationPriority::MIG_PRI_PCI_BUS);
+
+    let mut order = [iommu, pci_bus, DummyMigrate::vmsd()];
+    order.sort_by_key(|vmsd| std::cmp::Reverse(vmsd.priority as u32));
+    let names: Vec<&CStr> = order
+        .iter()
+        .map(|vmsd| unsafe { CStr::from_ptr(vmsd.name) })
+        .collect();
+    assert_eq!(
+        names,
+        [
+            c_str!("migrate-pci-bus"),
+            c_str!("migrate-iommu"),
+            c_str!("migrate")
+        ]
+    );
+}
+
+#[derive(qemu_api_macros::Migrate)]
+#[migrate(name = "migrate-early", early_setup)]
+#[derive(Debug, Default)]
+pub struct DummyMigrateEarly {
+    config: u32,
+}
+
+#[test]
+/// Check that `#[migrate(early_setup)]` sets the flag of the description.
+/// As for priorities, savevm.c is not linked into the tests, so the phase
+/// in which the description is sent cannot be checked directly.
+fn test_derive_migrate_early_setup() {
+    assert!(!DummyMigrate::vmsd().early_setup);
+    assert!(!DummyMigrateIommu::vmsd().early_setup);
+    let early = DummyMigrateEarly::vmsd();
+    assert!(early.early_setup);
+    assert_eq!(early.priority, MigrationPriority::MIG_PRI_DEFAULT);
+
+    // the contents are migrated like any other description
+    let mut saved = DummyMigrateEarly { config: 0x1234 };
+    let mut loaded = DummyMigrateEarly::default();
+    assert_eq!(vmstate_round_trip(early, &mut saved, &mut loaded), 4);
+    assert_eq!(loaded.config, 0x1234);
+}
+
+#[test]
+/// Check that `#[derive(Migrate)]` matches a hand-written description.
+fn test_derive_migrate() {
+    let vmsd = DummyMigrate::vmsd();
+    unsafe {
+        assert_eq!(
+            CStr::from_ptr(vmsd.name),
+            CStr::from_ptr(VMSTATE_MIGRATE.name)
+        );
+    }
+    assert_eq!(vmsd.version_id, VMSTATE_MIGRATE.version_id);
+    assert_eq!(vmsd.minimum_version_id, VMSTATE_MIGRATE.minimum_version_id);
+
+    let derived = unsafe { std::slice::from_raw_parts(vmsd.fields, 4) };
+    let expected = unsafe { std::slice::from_raw_parts(VMSTATE_MIGRATE.fields, 4) };
+    for (d, e) in derived.iter().zip(expected) {
+        assert_eq!(d.flags, e.flags);
+        assert_eq!(d.offset, e.offset);
+        assert_eq!(d.size, e.size);
+        assert_eq!(d.num, e.num);
+        assert_eq!(d.version_id, e.version_id);
+        assert_eq!(d.info, e.info);
+        if !e.name.is_null() {
+            unsafe {
+                assert_eq!(CStr::from_ptr(d.name), CStr::from_ptr(e.name));
+            }
+        }
+    }
+    assert_eq!(derived[3].flags, VMStateFlags::VMS_END);
+
+    let mut saved = DummyMigrate {
+        ctrl: 0x1234_5678,
+        fifo: [1, 2, 3, 4],
+        scratch: 0xdead_beef,
+        enabled: true,
+    };
+    let mut loaded = DummyMigrate::default();
+    assert_eq!(vmstate_round_trip(vmsd, &mut saved, &mut loaded), 13);
+    assert_eq!(loaded.scratch, 0);
+    loaded.scratch = saved.scratch;
+    assert_eq!(loaded, saved);
+}
+
+#[test]
+/// Test conversion of raw pointers with `qom::downcast`.
+fn test_downcast_ptr() {
     init_qom();
     let p: *mut DummyState = unsafe { object_new(DummyState::TYPE_NAME.as_ptr()).cast() };
-    let p_ref: &DummyState = unsafe { &*p };
-    assert_eq!(p_ref.typename(), "dummy");
+    let obj = p.cast::<Object>();
     unsafe {
-        object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
+        let dummy: Option<&mut DummyState> = qom::downcast(obj);
+        assert_eq!(dummy.map(|d| addr_of_mut!(*d)), Some(p));
+
+        let dev: Option<&mut DeviceState> = qom::downcast(obj);
+        assert_eq!(dev.map(|d| addr_of_mut!(*d)), Some(p.cast()));
+
+        let sbd: Option<&mut SysBusDevice> = qom::downcast(obj);
+        assert!(sbd.is_none());
+
+        let null: Option<&mut DummyState> = qom::downcast(ptr::null_mut());
+        assert!(null.is_none());
+
+        object_unref(obj.cast::<c_void>());
     }
 }
 
@@ -209,3 +5237,233 @@ fn test_cast_mut() {
         object_unref(p_ref.as_object_mut_ptr().cast::<c_void>());
     }
 }
+
+#[cfg(feature = "test-utils")]
+mod harness {
+    use qemu_api::{memory::DeviceEndian, test_util::DeviceHarness};
+
+    use super::*;
+
+    dummy_type! {
+        /// A device with a data register at offset 0 that reads back what was
+        /// written, and an interrupt register at offset 4 that drives its only
+        /// output line.
+        pub struct DummyEchoState: DeviceState, Object = "dummy_echo" {
+            out: [InterruptSource; 1],
+            data: BqlCell<u32>,
+        }
+        impl ObjectImpl {
+            const INSTANCE_POST_INIT: Option<fn(&Self)> = Some(Self::post_init);
+        }
+    }
+
+    impl DeviceImpl for DummyEchoState {}
+
+    impl ResettablePhasesImpl for DummyEchoState {}
+
+    impl DummyEchoState {
+        fn post_init(&self) {
+            self.init_gpio_out(&self.out);
+        }
+    }
+
+    impl MmioOps for DummyEchoState {
+        const CONFIG: MmioConfig = MmioConfig::DEFAULT.with_impl_sizes(4, 4);
+
+        fn read(&self, offset: hwaddr, _size: u32) -> u64 {
+            match offset {
+                0 => self.data.get().into(),
+                _ => 0,
+            }
+        }
+
+        fn write(&self, offset: hwaddr, _size: u32, value: u64) {
+            match offset {
+                0 => self.data.set(value as u32),
+                4 => self.out[0].set(value != 0),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    /// Drive a trivial device through `DeviceHarness`.
+    fn test_device_harness() {
+        let mut h = DeviceHarness::<DummyEchoState>::new(DummyEchoState::TYPE_NAME);
+        let irq = h.observe_gpio_out(None, 0);
+        assert_eq!(h.readl(0), 0);
+        h.writel(0, 0x1234_5678);
+        assert_eq!(h.readl(0), 0x1234_5678);
+        assert_eq!(h.device().data.get(), 0x1234_5678);
+
+        assert!(!irq.level());
+        h.writel(4, 1);
+        assert!(irq.level());
+        h.writel(4, 0);
+        h.writel(4, 1);
+        assert!(irq.level());
+        assert_eq!(irq.raise_count(), 2);
+
+        drop(h);
+        // the probe outlives the harness
+        assert!(irq.level());
+    }
+
+    dummy_type! {
+        /// A device whose write to offset 8 is a DMA request that copies its
+        /// value into the data register at offset 0, through the harness
+        /// which stands in for the address space.
+        pub struct DummyLoopbackState: DeviceState, Object = "dummy_loopback" {
+            harness: std::cell::Cell<*const DeviceHarness<DummyLoopbackState>>,
+            data: BqlCell<u32>,
+            dma_read: BqlCell<u32>,
+        }
+    }
+
+    impl DeviceImpl for DummyLoopbackState {}
+
+    impl ResettablePhasesImpl for DummyLoopbackState {}
+
+    impl MmioOps for DummyLoopbackState {
+        const CONFIG: MmioConfig = MmioConfig::DEFAULT.with_impl_sizes(4, 4);
+
+        fn read(&self, offset: hwaddr, _size: u32) -> u64 {
+            match offset {
+                0 => self.data.get().into(),
+                _ => 0,
+            }
+        }
+
+        fn write(&self, offset: hwaddr, _size: u32, value: u64) {
+            match offset {
+                0 => self.data.set(value as u32),
+                8 => {
+                    // SAFETY: the test sets the harness before writing here
+                    let h = unsafe { &*self.harness.get() };
+                    h.writel(0, value as u32);
+                    self.dma_read.set(h.readl(0));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    /// Check that the harness blocks accesses that a device performs on
+    /// its own registers while handling another access, as `memory.c`
+    /// does, and logs them as guest errors.
+    fn test_device_harness_reentrancy() {
+        let h = DeviceHarness::<DummyLoopbackState>::new(DummyLoopbackState::TYPE_NAME);
+        h.device().harness.set(addr_of!(h));
+        h.writel(0, 5);
+
+        let path =
+            std::env::temp_dir().join(format!("qemu-rust-reentrancy-{}.txt", std::process::id()));
+        let name = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            qemu_set_log_filename_flags(
+                name.as_ptr(),
+                LOG_GUEST_ERROR as c_int,
+                addr_of_mut!(error_abort),
+            );
+        }
+        h.writel(8, 7);
+        unsafe {
+            qemu_set_log_filename_flags(ptr::null(), 0, addr_of_mut!(error_abort));
+        }
+
+        // both the write and the read of the DMA were blocked
+        assert_eq!(h.device().data.get(), 5);
+        assert_eq!(h.device().dma_read.get(), 0);
+        assert_eq!(h.readl(0), 5);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "Blocked re-entrant IO on dummy_loopback at offset 0x0\n".repeat(2)
+        );
+    }
+
+    dummy_type! {
+        /// A sysbus device with a 32-bit data register at offset 0, whose
+        /// byte order is selected by its `big-endian` property.
+        pub struct DummyEndianState: SysBusDevice, DeviceState, Object = "dummy_endian" {
+            iomem: MemoryRegion,
+            big_endian: bool,
+            data: BqlCell<u32>,
+        }
+        impl ObjectImpl {
+            const INSTANCE_INIT: Option<unsafe fn(&mut Self)> = Some(Self::init);
+        }
+    }
+
+    declare_properties! {
+        DUMMY_ENDIAN_PROPERTIES,
+            define_property!(
+                c_str!("big-endian"),
+                DummyEndianState,
+                big_endian,
+                PropKind::Bool,
+                bool
+            ),
+    }
+
+    impl DeviceImpl for DummyEndianState {
+        fn properties() -> &'static [Property] {
+            &DUMMY_ENDIAN_PROPERTIES
+        }
+        const REALIZE: Option<fn(&Self) -> qemu_api::Result<()>> = Some(Self::realize);
+    }
+
+    impl ResettablePhasesImpl for DummyEndianState {}
+
+    impl SysBusDeviceImpl for DummyEndianState {}
+
+    impl DummyEndianState {
+        unsafe fn init(&mut self) {
+            let owner = addr_of_mut!(*self);
+            // SAFETY: the region is a field of `owner`
+            unsafe {
+                self.iomem.init_io(owner, Self::TYPE_NAME, 4);
+            }
+            self.init_mmio(&self.iomem);
+        }
+
+        fn realize(&self) -> qemu_api::Result<()> {
+            let endian = DeviceEndian::from_big_endian(self.big_endian);
+            self.iomem.set_endianness(self, endian);
+            Ok(())
+        }
+    }
+
+    impl MmioOps for DummyEndianState {
+        const CONFIG: MmioConfig = MmioConfig::DEFAULT.with_impl_sizes(4, 4);
+
+        fn read(&self, _offset: hwaddr, _size: u32) -> u64 {
+            self.data.get().into()
+        }
+
+        fn write(&self, _offset: hwaddr, _size: u32, value: u64) {
+            self.data.set(value as u32);
+        }
+    }
+
+    #[test]
+    /// Check that the `big-endian` property selects the byte order of the
+    /// MMIO region when the device is realized.
+    fn test_device_endian_property() {
+        let le = DeviceHarness::<DummyEndianState>::new_sysbus(DummyEndianState::TYPE_NAME);
+        le.writel(0, 0x1122_3344);
+        assert_eq!(le.device().data.get(), 0x1122_3344);
+        assert_eq!(le.readl(0), 0x1122_3344);
+
+        let be = DeviceHarness::<DummyEndianState>::new_sysbus_with_props(
+            DummyEndianState::TYPE_NAME,
+            &[(c_str!("big-endian"), c_str!("on"))],
+        );
+        be.writel(0, 0x1122_3344);
+        assert_eq!(be.device().data.get(), 0x4433_2211);
+        assert_eq!(be.readl(0), 0x1122_3344);
+    }
+}
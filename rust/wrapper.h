@@ -48,9 +48,17 @@ typedef enum memory_order {
 #endif /* __CLANG_STDATOMIC_H */
 
 #include "qemu/osdep.h"
+#include "qemu/log.h"
+#include "qemu/main-loop.h"
 #include "qemu/module.h"
+#include "qemu/fifo8.h"
+#include "qemu/guest-random.h"
 #include "qemu-io.h"
 #include "system/system.h"
+#include "system/reset.h"
+#include "system/replay.h"
+#include "system/runstate.h"
+#include "system/cpu-timers.h"
 #include "hw/sysbus.h"
 #include "exec/memory.h"
 #include "chardev/char-fe.h"
@@ -58,7 +66,23 @@ typedef enum memory_order {
 #include "hw/qdev-clock.h"
 #include "hw/qdev-properties.h"
 #include "hw/qdev-properties-system.h"
+#include "system/block-backend.h"
+#include "net/net.h"
 #include "hw/irq.h"
+#include "hw/ptimer.h"
+#include "hw/nvram/fw_cfg.h"
+#include "hw/pci/pci_device.h"
 #include "qapi/error.h"
+#include "qapi/visitor.h"
+#include "qapi/qmp/dispatch.h"
+#include "qapi/qmp/qbool.h"
+#include "qapi/qmp/qdict.h"
+#include "qapi/qmp/qjson.h"
+#include "qapi/qmp/qnum.h"
+#include "qapi/qmp/qstring.h"
+#include "qom/object_interfaces.h"
 #include "migration/vmstate.h"
+#include "migration/qemu-file-types.h"
+#include "migration/register.h"
+#include "io/channel-buffer.h"
 #include "chardev/char-serial.h"
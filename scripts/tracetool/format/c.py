@@ -64,3 +64,20 @@ def generate(events, backend, group):
     for event in active_events:
         backend.generate(event, group)
     backend.generate_end(active_events, group)
+
+    # out-of-line wrappers for the events emitted by Rust code
+    out('',
+        '#ifdef CONFIG_HAVE_RUST')
+    for e in events:
+        if "rust" not in e.properties:
+            continue
+        out('',
+            'void %(api_rust)s(%(args)s)',
+            '{',
+            '    %(api)s(%(names)s);',
+            '}',
+            api_rust=e.api(e.QEMU_TRACE_RUST),
+            api=e.api(),
+            args=e.args,
+            names=", ".join(e.args.names()))
+    out('#endif')
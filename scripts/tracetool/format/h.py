@@ -88,4 +88,16 @@ def generate(events, backend, group):
 
     backend.generate_end(events, group)
 
+    # out-of-line wrappers, called by Rust code
+    out('',
+        '#ifdef CONFIG_HAVE_RUST')
+    for e in events:
+        if "rust" not in e.properties:
+            continue
+        out('void %(api)s(%(args)s);',
+            api=e.api(e.QEMU_TRACE_RUST),
+            args=e.args)
+    out('#endif',
+        '')
+
     out('#endif /* TRACE_%s_GENERATED_TRACERS_H */' % group.upper())
@@ -0,0 +1,125 @@
+# -*- coding: utf-8 -*-
+
+"""
+trace-<group>.rs (Rust bindings for the events with the "rust" property)
+"""
+
+__copyright__  = "Copyright 2024, Red Hat, Inc."
+__license__    = "GPL version 2 or (at your option) any later version"
+
+__maintainer__ = "Stefan Hajnoczi"
+__email__      = "stefanha@redhat.com"
+
+
+from tracetool import out
+
+
+# C types of the arguments, and the Rust types they are passed as.  The
+# types are those in tracetool.ALLOWED_TYPES, with qualifiers removed.
+RUST_TYPES = {
+    'bool': 'bool',
+    'char': '::std::os::raw::c_char',
+    'signed char': '::std::os::raw::c_schar',
+    'unsigned char': '::std::os::raw::c_uchar',
+    'short': '::std::os::raw::c_short',
+    'signed short': '::std::os::raw::c_short',
+    'unsigned short': '::std::os::raw::c_ushort',
+    'int': '::std::os::raw::c_int',
+    'signed': '::std::os::raw::c_int',
+    'signed int': '::std::os::raw::c_int',
+    'unsigned': '::std::os::raw::c_uint',
+    'unsigned int': '::std::os::raw::c_uint',
+    'long': '::std::os::raw::c_long',
+    'signed long': '::std::os::raw::c_long',
+    'unsigned long': '::std::os::raw::c_ulong',
+    'long long': '::std::os::raw::c_longlong',
+    'signed long long': '::std::os::raw::c_longlong',
+    'unsigned long long': '::std::os::raw::c_ulonglong',
+    'int8_t': 'i8',
+    'uint8_t': 'u8',
+    'int16_t': 'i16',
+    'uint16_t': 'u16',
+    'int32_t': 'i32',
+    'uint32_t': 'u32',
+    'int64_t': 'i64',
+    'uint64_t': 'u64',
+    'size_t': 'usize',
+    'ssize_t': 'isize',
+    'uintptr_t': 'usize',
+    'ptrdiff_t': 'isize',
+}
+
+# Rust keywords that can appear as argument names in trace-events
+RESERVED_WORDS = (
+    'as', 'box', 'crate', 'dyn', 'enum', 'fn', 'impl', 'in', 'let', 'loop',
+    'match', 'mod', 'move', 'mut', 'priv', 'ref', 'self', 'struct', 'super',
+    'trait', 'type', 'unsafe', 'use', 'where', 'yield',
+)
+
+
+def extern_type(type_):
+    """Rust type with the same ABI as the C type of an argument."""
+    if '*' in type_:
+        pointee = type_.replace('*', '').replace('const', '').split()
+        if pointee == ['char']:
+            return '*const ::std::os::raw::c_char'
+        return '*const ::std::os::raw::c_void'
+
+    key = ' '.join(type_.replace('const', '').split())
+    if key not in RUST_TYPES:
+        raise ValueError("Argument type '%s' cannot be passed from Rust" % type_)
+    return RUST_TYPES[key]
+
+
+def rust_type(type_):
+    """Rust type of an argument in the safe function."""
+    if '*' in type_:
+        # C strings and other pointers are converted by TraceArg
+        return ('impl ::qemu_api::trace::TraceArg<CType = %s>'
+                % extern_type(type_))
+    return extern_type(type_)
+
+
+def rust_name(name):
+    if name in RESERVED_WORDS:
+        name += '_'
+    return name
+
+
+def generate(events, backend, group):
+    events = [e for e in events
+              if "rust" in e.properties]
+
+    out('// This file is autogenerated by tracetool, do not edit.',
+        '')
+
+    for e in events:
+        names = [rust_name(name) for name in e.args.names()]
+        types = e.args.types()
+        convert = [name if '*' not in type_
+                   else '::qemu_api::trace::TraceArg::into_trace_arg(%s)' % name
+                   for type_, name in zip(types, names)]
+
+        out('/// Emit the `%(name)s` trace event.',
+            name=e.name)
+        if len(e.args) > 7:
+            out('#[allow(clippy::too_many_arguments)]')
+        out('#[inline]',
+            'pub fn %(name)s(%(args)s) {',
+            '    extern "C" {',
+            '        fn %(api)s(%(extern_args)s);',
+            '    }',
+            '    // SAFETY: the declaration of %(api)s is generated from',
+            '    // trace-events, together with its definition',
+            '    unsafe {',
+            '        %(api)s(%(names)s);',
+            '    }',
+            '}',
+            '',
+            name=e.name,
+            api=e.api(e.QEMU_TRACE_RUST),
+            args=', '.join('%s: %s' % (name, rust_type(type_))
+                           for type_, name in zip(types, names)),
+            extern_args=', '.join('%s: %s' % (name, extern_type(type_))
+                                  for type_, name in zip(types, names)),
+            names=', '.join(convert))
@@ -2,6 +2,7 @@
 #include "qemu/main-loop.h"
 
 static bool bql_is_locked = false;
+static bool bql_is_mocked = false;
 static uint32_t bql_unlock_blocked;
 
 bool bql_locked(void)
@@ -11,16 +12,29 @@ bool bql_locked(void)
 
 void rust_bql_mock_lock(void)
 {
+    bql_is_mocked = true;
     bql_is_locked = true;
 }
 
+/*
+ * Once Rust tests have mocked the BQL, lock and unlock track its state
+ * so that they can check guards; otherwise they do nothing.
+ */
 void bql_lock_impl(const char *file, int line)
 {
+    if (bql_is_mocked) {
+        assert(!bql_is_locked);
+        bql_is_locked = true;
+    }
 }
 
 void bql_unlock(void)
 {
     assert(!bql_unlock_blocked);
+    if (bql_is_mocked) {
+        assert(bql_is_locked);
+        bql_is_locked = false;
+    }
 }
 
 void bql_block_unlock(bool increase)